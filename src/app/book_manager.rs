@@ -2,10 +2,20 @@ use egui::Context;
 use std::sync::Arc;
 
 use crate::config::constants::*;
+use crate::config::settings::ZoomMode;
 use crate::library::scanner::LibraryScanner;
-use crate::ui::toolbar::ToolbarAction;
+use crate::ui::toolbar::{PageLayout, PageView, ToolbarAction};
 
+use super::bookmark_manager;
+use super::crop_manager;
+use super::highlight_manager;
+use super::kosync_manager;
+use super::reflow_manager;
 use super::render_manager;
+use super::rotation_manager;
+use super::scroll_manager;
+use super::spread_manager;
+use super::zoom_manager;
 use super::DocReaderApp;
 
 pub(crate) fn select_book(app: &mut DocReaderApp, _ctx: &Context, book_hash: &str) {
@@ -13,9 +23,18 @@ pub(crate) fn select_book(app: &mut DocReaderApp, _ctx: &Context, book_hash: &st
         return;
     }
 
+    // Send the position in the previous book before leaving it
+    kosync_manager::push_now(app);
+
     app.selected_book_hash = Some(book_hash.to_string());
     app.settings.last_opened_book = Some(book_hash.to_string());
     app.current_document_bytes = None;
+    app.current_page_sections.clear();
+    app.current_page_positions.clear();
+    app.current_page_size = None;
+    app.current_page_sizes.clear();
+    highlight_manager::clear_page_text(app);
+    app.export_status = None;
 
     // Find book and load saved page
     if let Some(bp) = app.progress.books.get(book_hash) {
@@ -48,7 +67,10 @@ pub(crate) fn select_book(app: &mut DocReaderApp, _ctx: &Context, book_hash: &st
         }
     }
 
+    zoom_manager::restore_book_zoom(app);
+    scroll_manager::restore_scroll(app);
     render_manager::request_render(app);
+    kosync_manager::pull_current(app);
 }
 
 pub(crate) fn go_to_page(app: &mut DocReaderApp, page: u32) {
@@ -63,56 +85,108 @@ pub(crate) fn go_to_page(app: &mut DocReaderApp, page: u32) {
         .map(|b| b.total_pages)
         .unwrap_or(1);
 
-    let new_page = page.clamp(1, total_pages.max(1));
+    let new_page = spread_manager::first_page(app, page.clamp(1, total_pages.max(1)));
     if new_page != app.current_page {
+        let position = reflow_manager::text_position(app, new_page);
         app.current_page = new_page;
         app.page_input = new_page.to_string();
-        app.progress.update_book_progress(book_hash, new_page);
+        let progress_page = reflow_manager::progress_page(app, new_page);
+        app.progress.update_book_progress(book_hash, progress_page);
+        app.progress.set_book_position(book_hash, position);
         app.needs_save = true;
+        highlight_manager::clear_page_text(app);
+        kosync_manager::mark_dirty(app);
+        scroll_manager::scroll_to_page(app, new_page);
         render_manager::request_render(app);
     }
 }
 
+/// Moves forward by a page, or by a spread in two-page mode.
+pub(crate) fn next_page(app: &mut DocReaderApp) {
+    let step = spread_manager::spread_pages(app).len() as u32;
+    go_to_page(app, app.current_page + step);
+}
+
+/// Moves back by a page, or to the previous spread in two-page mode.
+pub(crate) fn prev_page(app: &mut DocReaderApp) {
+    go_to_page(app, app.current_page.saturating_sub(1));
+}
+
+/// How the pages of the open book are shown, `None` for reflowable books
+/// and reflowed PDFs.
+pub(crate) fn page_view(app: &DocReaderApp) -> Option<PageView> {
+    let book = app.selected_book()?;
+    if reflow_manager::reflows(app) {
+        return None;
+    }
+    let progress = app.progress.books.get(&book.file_hash);
+    Some(match progress {
+        Some(bp) if bp.spread.is_some() => PageView::Spread(bp.spread.unwrap_or_default()),
+        Some(bp) if bp.scroll.is_some() => PageView::Continuous,
+        _ => PageView::Single,
+    })
+}
+
+/// How the pages of the open book are arranged, cropped and turned, `None`
+/// for reflowable books and reflowed PDFs.
+pub(crate) fn page_layout(app: &DocReaderApp) -> Option<PageLayout> {
+    Some(PageLayout {
+        view: page_view(app)?,
+        crop: crop_manager::book_crop(app),
+        rotation: rotation_manager::page_rotation(app, app.current_page),
+    })
+}
+
 pub(crate) fn handle_toolbar_action(app: &mut DocReaderApp, action: ToolbarAction) {
     match action {
-        ToolbarAction::PrevPage => {
-            go_to_page(app, app.current_page.saturating_sub(1));
-        }
-        ToolbarAction::NextPage => {
-            go_to_page(app, app.current_page + 1);
-        }
+        ToolbarAction::PrevPage => prev_page(app),
+        ToolbarAction::NextPage => next_page(app),
         ToolbarAction::GoToPage(page) => {
             go_to_page(app, page);
         }
         ToolbarAction::ZoomIn => {
-            app.zoom = (app.zoom + ZOOM_STEP).min(ZOOM_MAX);
-            app.page_cache.clear();
-            render_manager::request_render(app);
+            zoom_manager::set_zoom(app, ZoomMode::Fixed, app.zoom + ZOOM_STEP);
         }
         ToolbarAction::ZoomOut => {
-            app.zoom = (app.zoom - ZOOM_STEP).max(ZOOM_MIN);
-            app.page_cache.clear();
-            render_manager::request_render(app);
+            zoom_manager::set_zoom(app, ZoomMode::Fixed, app.zoom - ZOOM_STEP);
         }
         ToolbarAction::ZoomReset => {
-            app.zoom = ZOOM_DEFAULT;
-            app.page_cache.clear();
-            render_manager::request_render(app);
+            zoom_manager::set_zoom(app, ZoomMode::Fixed, ZOOM_DEFAULT);
+        }
+        ToolbarAction::FitWidth => {
+            zoom_manager::set_zoom(app, ZoomMode::FitWidth, app.zoom);
         }
+        ToolbarAction::FitPage => {
+            zoom_manager::set_zoom(app, ZoomMode::FitPage, app.zoom);
+        }
+        ToolbarAction::ToggleBookmark => bookmark_manager::toggle_bookmark(app),
+        ToolbarAction::ToggleBookmarksPanel => {
+            app.show_bookmarks = !app.show_bookmarks;
+        }
+        ToolbarAction::ToggleContinuous => scroll_manager::toggle_continuous(app),
+        ToolbarAction::SetSpread(spread) => spread_manager::set_spread(app, spread),
+        ToolbarAction::SetCrop(crop) => crop_manager::set_crop(app, crop),
+        ToolbarAction::ToggleReflow => reflow_manager::toggle_reflow(app),
+        ToolbarAction::RotatePage(turns) => rotation_manager::rotate_page(app, turns),
+        ToolbarAction::RotateDocument(turns) => rotation_manager::rotate_document(app, turns),
+        ToolbarAction::ResetRotation => rotation_manager::reset_rotation(app),
     }
 }
 
 pub(crate) fn rescan_library(app: &mut DocReaderApp) {
-    let mut books =
+    app.books =
         LibraryScanner::scan_and_load_books(&app.settings.library_path, None).unwrap_or_default();
+    restore_total_pages(app);
+}
 
-    for book in &mut books {
+/// Takes page counts from saved progress, so books need not be opened to
+/// know them.
+pub(crate) fn restore_total_pages(app: &mut DocReaderApp) {
+    for book in &mut app.books {
         if let Some(bp) = app.progress.books.get(&book.file_hash) {
             if bp.total_pages > 0 {
                 book.total_pages = bp.total_pages;
             }
         }
     }
-
-    app.books = books;
 }
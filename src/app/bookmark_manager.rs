@@ -0,0 +1,95 @@
+use crate::library::bookmarks::Bookmark;
+use crate::ui::bookmarks_panel::BookmarkAction;
+
+use super::book_manager;
+use super::highlight_manager::{self, ExportFormat};
+use super::reflow_manager;
+use super::DocReaderApp;
+
+/// Adds a bookmark on the current page, or removes the one already there.
+/// Bookmarks of reflowed PDFs are kept on the page of the file, those of
+/// EPUB and FB2 books at the position of the page in the text.
+pub(crate) fn toggle_bookmark(app: &mut DocReaderApp) {
+    let Some(hash) = app.selected_book_hash.clone() else {
+        return;
+    };
+    let page = reflow_manager::progress_page(app, app.current_page);
+    let position = reflow_manager::text_position(app, app.current_page);
+    let existing = current_bookmark(app).map(|b| b.id.clone());
+    let Some(book) = app.progress.books.get_mut(&hash) else {
+        return;
+    };
+
+    match existing {
+        Some(id) => book.remove_bookmark(&id),
+        None => book.add_bookmark(page, position, format!("Стр. {}", page)),
+    }
+    mark_changed(app);
+}
+
+pub(crate) fn handle_bookmark_action(app: &mut DocReaderApp, action: BookmarkAction) {
+    let export = match action {
+        BookmarkAction::Jump(page) => {
+            book_manager::go_to_page(app, reflow_manager::reflowed_page(app, page));
+            return;
+        }
+        BookmarkAction::ExportMarkdown => Some(ExportFormat::Markdown),
+        BookmarkAction::ExportJson => Some(ExportFormat::Json),
+        BookmarkAction::ExportPdf => {
+            highlight_manager::export_pdf_annotations(app);
+            return;
+        }
+        BookmarkAction::ImportPdf => {
+            highlight_manager::import_pdf_annotations(app);
+            return;
+        }
+        _ => None,
+    };
+    if let Some(format) = export {
+        app.export_status = Some(match highlight_manager::export_highlights(app, format) {
+            Ok(path) => format!("Сохранено: {}", path.display()),
+            Err(e) => format!("Ошибка экспорта: {}", e),
+        });
+        return;
+    }
+
+    let Some(book) = app
+        .selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get_mut(hash))
+    else {
+        return;
+    };
+    match action {
+        BookmarkAction::Rename { id, label } if !label.is_empty() => {
+            book.rename_bookmark(&id, label)
+        }
+        BookmarkAction::Delete(id) => book.remove_bookmark(&id),
+        _ => return,
+    }
+    mark_changed(app);
+}
+
+/// Whether the current page has a bookmark.
+pub(crate) fn is_bookmarked(app: &DocReaderApp) -> bool {
+    current_bookmark(app).is_some()
+}
+
+fn current_bookmark(app: &DocReaderApp) -> Option<&Bookmark> {
+    let page = reflow_manager::progress_page(app, app.current_page);
+    let book = app
+        .selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get(hash))?;
+    if reflow_manager::keeps_positions(app) {
+        book.bookmarks()
+            .find(|b| reflow_manager::saved_page(app, b.page, b.position) == page)
+    } else {
+        book.bookmark_at(page)
+    }
+}
+
+fn mark_changed(app: &mut DocReaderApp) {
+    app.progress.last_modified = chrono::Utc::now();
+    app.needs_save = true;
+}
@@ -0,0 +1,36 @@
+use crate::library::progress::Crop;
+use crate::renderer::crop::PageCrop;
+
+use super::book_manager;
+use super::render_manager;
+use super::DocReaderApp;
+
+/// How the margins of the open book are cropped. Only pages of
+/// fixed-layout books have margins to crop.
+pub(crate) fn book_crop(app: &DocReaderApp) -> Option<Crop> {
+    book_manager::page_view(app)?;
+    app.selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get(hash))
+        .and_then(|book| book.crop)
+}
+
+/// How `page` of the open book is cropped.
+pub(crate) fn page_crop(app: &DocReaderApp, page: u32) -> Option<PageCrop> {
+    book_crop(app).map(|crop| crop.for_page(page))
+}
+
+/// Crops the margins of the open book's pages, or stops for `None`. The
+/// pages are rendered again; a fitted zoom follows their new size.
+pub(crate) fn set_crop(app: &mut DocReaderApp, crop: Option<Crop>) {
+    if book_manager::page_view(app).is_none() {
+        return;
+    }
+    let Some(hash) = app.selected_book_hash.clone() else {
+        return;
+    };
+    app.progress.update_book_view(&hash, |bp| bp.crop = crop);
+    app.needs_save = true;
+    app.page_cache.clear();
+    render_manager::request_render(app);
+}
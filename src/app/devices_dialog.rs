@@ -0,0 +1,85 @@
+use egui::Context;
+
+use crate::library::devices;
+
+use super::progress_manager;
+use super::DocReaderApp;
+
+pub(crate) fn show_devices_window(app: &mut DocReaderApp, ctx: &Context) {
+    let mut show = app.show_devices;
+    let mut retire = None;
+
+    egui::Window::new("Устройства")
+        .open(&mut show)
+        .default_width(460.0)
+        .show(ctx, |ui| {
+            let own_id = app.settings.device_id.clone();
+            let active = app.progress.active_devices();
+            if active.is_empty() {
+                ui.label("Устройства появятся после первого сохранения прогресса");
+                return;
+            }
+
+            egui::Grid::new("devices_grid")
+                .num_columns(4)
+                .striped(true)
+                .spacing([12.0, 6.0])
+                .show(ui, |ui| {
+                    ui.strong("Устройство");
+                    ui.strong("Активность");
+                    ui.strong("Последняя книга");
+                    ui.end_row();
+
+                    for (id, device) in active {
+                        let mut name = devices::display_name(&device.name, id);
+                        if *id == own_id {
+                            name.push_str(" (это устройство)");
+                        }
+                        ui.label(name).on_hover_text(id.as_str());
+                        ui.label(
+                            device
+                                .last_seen
+                                .with_timezone(&chrono::Local)
+                                .format("%d.%m.%Y %H:%M")
+                                .to_string(),
+                        );
+                        let book = device
+                            .last_book
+                            .as_ref()
+                            .and_then(|hash| app.progress.books.get(hash))
+                            .map(|b| b.file_name.as_str())
+                            .unwrap_or("—");
+                        ui.label(book);
+
+                        if *id == own_id {
+                            ui.label("");
+                        } else if app.devices_confirm_retire.as_ref() == Some(id) {
+                            ui.horizontal(|ui| {
+                                if ui.button("Удалить").clicked() {
+                                    retire = Some(id.clone());
+                                }
+                                if ui.button("Отмена").clicked() {
+                                    app.devices_confirm_retire = None;
+                                }
+                            });
+                        } else if ui
+                            .button("Вывести из использования")
+                            .on_hover_text("Скрыть устройство и удалить его данные синхронизации")
+                            .clicked()
+                        {
+                            app.devices_confirm_retire = Some(id.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+
+    if let Some(id) = retire {
+        app.devices_confirm_retire = None;
+        if let Err(e) = progress_manager::retire_device(app, &id) {
+            app.error_message = Some(format!("Ошибка удаления устройства: {}", e));
+        }
+    }
+
+    app.show_devices = show;
+}
@@ -0,0 +1,416 @@
+use anyhow::{Context as _, Result};
+use egui::{Context, Pos2};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::config::constants::*;
+use crate::library::annotations::{self, Highlight, HighlightColor};
+use crate::library::progress::BookProgress;
+use crate::renderer::markup::Markup;
+use crate::ui::document_viewer::ViewerAction;
+
+use super::reflow_manager;
+use super::render_thread::{AnnotationJob, AnnotationResult, RenderCommand};
+use super::DocReaderApp;
+
+/// Colour and note editor shown next to a selection or a clicked highlight.
+pub(crate) struct HighlightPopup {
+    pub pos: Pos2,
+    /// Highlight being edited; `None` for a new one from the selection.
+    pub editing: Option<String>,
+    pub color: HighlightColor,
+    pub note: String,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum ExportFormat {
+    Markdown,
+    Json,
+}
+
+/// Forgets the text layer of the page being left, so nothing is drawn or
+/// selected on the old texture until the new page arrives.
+pub(crate) fn clear_page_text(app: &mut DocReaderApp) {
+    app.current_page_text = Arc::default();
+    app.current_page_images = Arc::default();
+    app.selection = None;
+    app.highlight_popup = None;
+}
+
+pub(crate) fn handle_viewer_action(app: &mut DocReaderApp, action: ViewerAction) {
+    match action {
+        ViewerAction::SelectStart(index) => {
+            app.selection = Some((index, index));
+            app.highlight_popup = None;
+        }
+        ViewerAction::SelectTo(index) => {
+            if let Some((anchor, _)) = app.selection {
+                app.selection = Some((anchor, index));
+            }
+        }
+        ViewerAction::SelectEnd(pos) => {
+            let has_text = app
+                .selection
+                .is_some_and(|(a, b)| !app.current_page_text.text(a, b).is_empty());
+            // Reflowed text can be copied, but highlights keep to the pages of the file
+            if has_text && reflow_manager::is_reflowed(app) {
+                return;
+            }
+            if has_text {
+                app.highlight_popup = Some(HighlightPopup {
+                    pos,
+                    editing: None,
+                    color: HighlightColor::default(),
+                    note: String::new(),
+                });
+            } else {
+                app.selection = None;
+            }
+        }
+        ViewerAction::Click { char_index, pos } => {
+            app.selection = None;
+            app.highlight_popup = char_index
+                .and_then(|index| {
+                    current_highlights(app)
+                        .into_iter()
+                        .find(|h| h.contains(index as u32))
+                })
+                .map(|h| HighlightPopup {
+                    pos,
+                    editing: Some(h.id),
+                    color: h.color,
+                    note: h.note,
+                });
+        }
+        // Opened by the image viewer, which needs the egui context
+        ViewerAction::OpenImage(_) => {}
+    }
+}
+
+/// Highlights of the page currently shown, none for a reflowed PDF. In
+/// EPUB and FB2 books they are found by their positions in the text, with
+/// `start` and `end` counting the characters of the page as laid out now.
+pub(crate) fn current_highlights(app: &DocReaderApp) -> Vec<Highlight> {
+    let page = app.current_page;
+    let text = &app.current_page_text;
+    let by_position = reflow_manager::keeps_positions(app) && !text.positions.is_empty();
+    app.selected_book_hash
+        .as_ref()
+        .filter(|_| !reflow_manager::is_reflowed(app))
+        .and_then(|hash| app.progress.books.get(hash))
+        .into_iter()
+        .flat_map(|book| book.highlights())
+        .filter_map(|h| match h.anchor {
+            Some([start, end]) if by_position => {
+                let (start, end) = text.range_of(start, end)?;
+                Some(Highlight {
+                    page,
+                    start: start as u32,
+                    end: end as u32,
+                    ..h.clone()
+                })
+            }
+            _ => (h.page == page).then(|| h.clone()),
+        })
+        .collect()
+}
+
+/// Text of the selection, or of the highlight open in the popup.
+pub(crate) fn selected_text(app: &DocReaderApp) -> Option<String> {
+    if let Some(id) = app
+        .highlight_popup
+        .as_ref()
+        .and_then(|p| p.editing.as_ref())
+    {
+        return app
+            .selected_book_hash
+            .as_ref()
+            .and_then(|hash| app.progress.books.get(hash))
+            .and_then(|book| book.highlight(id))
+            .map(|h| h.text.clone());
+    }
+    app.selection
+        .map(|(a, b)| app.current_page_text.text(a, b))
+        .filter(|text| !text.is_empty())
+}
+
+/// Copies the selected text to the clipboard; returns false if nothing is selected.
+pub(crate) fn copy_selection(app: &DocReaderApp, ctx: &Context) -> bool {
+    match selected_text(app) {
+        Some(text) => {
+            ctx.copy_text(text);
+            true
+        }
+        None => false,
+    }
+}
+
+pub(crate) fn show_highlight_popup(app: &mut DocReaderApp, ctx: &Context) {
+    if app.highlight_popup.is_none() {
+        return;
+    }
+    let preview = selected_text(app).unwrap_or_default();
+    let Some(popup) = &mut app.highlight_popup else {
+        return;
+    };
+
+    let mut copy = false;
+    let mut save = false;
+    let mut delete = false;
+    let mut close = ctx.input(|i| i.key_pressed(egui::Key::Escape));
+
+    egui::Area::new(egui::Id::new("highlight_popup"))
+        .fixed_pos(popup.pos)
+        .order(egui::Order::Foreground)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.set_width(HIGHLIGHT_POPUP_WIDTH);
+                let mut shown: String = preview.chars().take(HIGHLIGHT_PREVIEW_CHARS).collect();
+                if shown.len() < preview.len() {
+                    shown.push('…');
+                }
+                ui.label(egui::RichText::new(shown).italics());
+
+                ui.horizontal(|ui| {
+                    for color in HighlightColor::ALL {
+                        let [r, g, b] = color.rgb();
+                        let swatch =
+                            egui::RichText::new("⬛").color(egui::Color32::from_rgb(r, g, b));
+                        if ui
+                            .selectable_label(popup.color == color, swatch)
+                            .on_hover_text(color.display_name())
+                            .clicked()
+                        {
+                            popup.color = color;
+                        }
+                    }
+                });
+
+                ui.add(
+                    egui::TextEdit::multiline(&mut popup.note)
+                        .hint_text("Заметка")
+                        .desired_rows(2)
+                        .desired_width(f32::INFINITY),
+                );
+
+                ui.horizontal(|ui| {
+                    if ui.button("Сохранить").clicked() {
+                        save = true;
+                    }
+                    if popup.editing.is_some() && ui.button("Удалить").clicked() {
+                        delete = true;
+                    }
+                    if ui.button("Копировать").on_hover_text("Ctrl+C").clicked() {
+                        copy = true;
+                    }
+                    if ui.button("Отмена").clicked() {
+                        close = true;
+                    }
+                });
+            });
+        });
+
+    if copy {
+        copy_selection(app, ctx);
+        app.highlight_popup = None;
+        app.selection = None;
+    } else if save {
+        save_popup(app);
+    } else if delete {
+        delete_popup(app);
+    } else if close {
+        app.highlight_popup = None;
+        app.selection = None;
+    }
+}
+
+fn save_popup(app: &mut DocReaderApp) {
+    let Some(popup) = app.highlight_popup.take() else {
+        return;
+    };
+    let selection = app.selection.take();
+    let page = app.current_page;
+    let text = selection.map(|(a, b)| app.current_page_text.text(a, b));
+    let anchor = selection
+        .filter(|_| reflow_manager::keeps_positions(app))
+        .and_then(|(a, b)| {
+            let position = |index| app.current_page_text.position(index);
+            Some([position(a.min(b))?, position(a.max(b))?])
+        });
+    let Some(book) = app
+        .selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get_mut(hash))
+    else {
+        return;
+    };
+
+    let note = popup.note.trim().to_string();
+    match (popup.editing, selection, text) {
+        (Some(id), _, _) => book.edit_highlight(&id, popup.color, note),
+        (None, Some((a, b)), Some(text)) => {
+            let mut highlight = Highlight::new(page, a as u32, b as u32, text, popup.color);
+            highlight.anchor = anchor;
+            highlight.note = note;
+            book.add_highlight(highlight);
+        }
+        _ => return,
+    }
+    mark_changed(app);
+}
+
+fn delete_popup(app: &mut DocReaderApp) {
+    let Some(id) = app.highlight_popup.take().and_then(|p| p.editing) else {
+        return;
+    };
+    if let Some(book) = app
+        .selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get_mut(hash))
+    {
+        book.remove_highlight(&id);
+        mark_changed(app);
+    }
+}
+
+/// Writes the open book's highlights to the documents folder and returns the file path.
+pub(crate) fn export_highlights(app: &DocReaderApp, format: ExportFormat) -> Result<PathBuf> {
+    let hash = app.selected_book_hash.as_ref().context("No book is open")?;
+    let book = app.progress.books.get(hash).context("No book is open")?;
+    let title = book_title(book);
+
+    let (content, extension) = match format {
+        ExportFormat::Markdown => (
+            annotations::export_markdown(&title, book.highlights()),
+            "md",
+        ),
+        ExportFormat::Json => (annotations::export_json(&title, book.highlights())?, "json"),
+    };
+
+    let path = export_path(&title, HIGHLIGHTS_EXPORT_SUFFIX, extension)?;
+    std::fs::write(&path, content).context("Failed to write export file")?;
+    Ok(path)
+}
+
+/// Asks the render thread to save a copy of the open PDF with the highlights
+/// and notes stored as PDF annotations.
+pub(crate) fn export_pdf_annotations(app: &mut DocReaderApp) {
+    let sent = pdf_export_job(app).and_then(|job| send_job(app, job));
+    app.export_status = Some(match sent {
+        Ok(()) => "Сохранение аннотаций…".to_string(),
+        Err(e) => format!("Ошибка экспорта: {}", e),
+    });
+}
+
+/// Asks the render thread to read the annotations of the open PDF, and of
+/// its annotated copy if one was exported and edited elsewhere.
+pub(crate) fn import_pdf_annotations(app: &mut DocReaderApp) {
+    let sent = pdf_import_job(app).and_then(|job| send_job(app, job));
+    app.export_status = Some(match sent {
+        Ok(()) => "Чтение аннотаций…".to_string(),
+        Err(e) => format!("Ошибка импорта: {}", e),
+    });
+}
+
+pub(crate) fn apply_annotation_result(app: &mut DocReaderApp, result: AnnotationResult) {
+    let status = match result {
+        AnnotationResult::Exported(path) => format!("Сохранено: {}", path.display()),
+        AnnotationResult::Failed(e) => format!("Ошибка аннотаций PDF: {}", e),
+        AnnotationResult::Imported { book_hash, markups } => {
+            let Some(book) = app.progress.books.get_mut(&book_hash) else {
+                return;
+            };
+            let (highlights, notes) = book.import_markups(markups);
+            if highlights + notes > 0 {
+                mark_changed(app);
+            }
+            format!(
+                "Импортировано выделений: {}, заметок: {}",
+                highlights, notes
+            )
+        }
+    };
+    app.export_status = Some(status);
+}
+
+fn pdf_export_job(app: &DocReaderApp) -> Result<AnnotationJob> {
+    let book = app.selected_book().context("No book is open")?;
+    let progress = app
+        .progress
+        .books
+        .get(&book.file_hash)
+        .context("No book is open")?;
+    let bytes = app
+        .current_document_bytes
+        .clone()
+        .context("The book is not loaded")?;
+
+    let markups = progress
+        .highlights()
+        .map(|h| Markup {
+            page_index: h.page.saturating_sub(1),
+            start: h.start as usize,
+            end: h.end as usize,
+            text: h.text.clone(),
+            color: h.color.rgb(),
+            note: h.note.clone(),
+            created: h.created,
+            updated: h.updated,
+        })
+        .collect();
+    Ok(AnnotationJob::Export {
+        bytes,
+        format: book.format,
+        markups,
+        path: export_path(&book_title(progress), PDF_ANNOTATED_SUFFIX, "pdf")?,
+    })
+}
+
+fn pdf_import_job(app: &DocReaderApp) -> Result<AnnotationJob> {
+    let book = app.selected_book().context("No book is open")?;
+    let progress = app
+        .progress
+        .books
+        .get(&book.file_hash)
+        .context("No book is open")?;
+    let bytes = app
+        .current_document_bytes
+        .clone()
+        .context("The book is not loaded")?;
+
+    let mut sources = vec![bytes];
+    let copy = export_path(&book_title(progress), PDF_ANNOTATED_SUFFIX, "pdf")?;
+    if let Ok(copy) = std::fs::read(copy) {
+        sources.push(Arc::new(copy));
+    }
+    Ok(AnnotationJob::Import {
+        book_hash: book.file_hash.clone(),
+        sources,
+        format: book.format,
+    })
+}
+
+fn send_job(app: &DocReaderApp, job: AnnotationJob) -> Result<()> {
+    app.render_tx
+        .send(RenderCommand::Annotations(job))
+        .map_err(|_| anyhow::anyhow!("Render thread has stopped"))
+}
+
+fn book_title(book: &BookProgress) -> String {
+    std::path::Path::new(&book.file_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| book.file_name.clone())
+}
+
+/// `<title><suffix>.<extension>` in the user's documents folder.
+fn export_path(title: &str, suffix: &str, extension: &str) -> Result<PathBuf> {
+    let dirs = directories::UserDirs::new().context("Failed to get user directories")?;
+    let dir = dirs.document_dir().unwrap_or_else(|| dirs.home_dir());
+    Ok(dir.join(format!("{}{}.{}", title, suffix, extension)))
+}
+
+fn mark_changed(app: &mut DocReaderApp) {
+    app.progress.last_modified = chrono::Utc::now();
+    app.needs_save = true;
+}
@@ -0,0 +1,118 @@
+use egui::Context;
+
+use crate::sync::backup::{self, BookChange};
+
+use super::progress_manager;
+use super::sync_thread::SyncRequest;
+use super::DocReaderApp;
+
+/// Asks for the snapshot list again, e.g. when the window is opened.
+pub(crate) fn refresh(app: &mut DocReaderApp) {
+    app.history_snapshots.clear();
+    app.history_selected = None;
+    app.history_preview = None;
+    let _ = app.sync_tx.send(SyncRequest::Snapshots);
+}
+
+pub(crate) fn show_history_window(app: &mut DocReaderApp, ctx: &Context) {
+    let mut show = app.show_history;
+    let mut restore = None;
+
+    egui::Window::new("История прогресса")
+        .open(&mut show)
+        .default_width(520.0)
+        .show(ctx, |ui| {
+            if app.history_snapshots.is_empty() {
+                ui.label("Резервных копий пока нет");
+                return;
+            }
+
+            ui.horizontal_top(|ui| {
+                egui::ScrollArea::vertical()
+                    .id_salt("history_snapshots")
+                    .max_height(360.0)
+                    .show(ui, |ui| {
+                        ui.set_min_width(180.0);
+                        for (i, snapshot) in app.history_snapshots.iter().enumerate() {
+                            let label = snapshot
+                                .created
+                                .with_timezone(&chrono::Local)
+                                .format("%d.%m.%Y %H:%M:%S")
+                                .to_string();
+                            let selected = app.history_selected == Some(i);
+                            if ui.selectable_label(selected, label).clicked() && !selected {
+                                app.history_selected = Some(i);
+                                app.history_preview = None;
+                                let _ = app
+                                    .sync_tx
+                                    .send(SyncRequest::ReadSnapshot(snapshot.clone()));
+                            }
+                        }
+                    });
+
+                ui.separator();
+
+                ui.vertical(|ui| match &app.history_preview {
+                    None if app.history_selected.is_some() => {
+                        ui.label("Чтение копии...");
+                    }
+                    None => {
+                        ui.label("Выберите копию, чтобы увидеть отличия");
+                    }
+                    Some(Err(e)) => {
+                        ui.colored_label(egui::Color32::RED, format!("Копия не читается: {}", e));
+                    }
+                    Some(Ok(snapshot)) => {
+                        let diffs = backup::diff_progress(&app.progress, snapshot);
+                        if diffs.is_empty() {
+                            ui.label("Совпадает с текущим прогрессом");
+                        } else {
+                            egui::ScrollArea::vertical()
+                                .id_salt("history_diff")
+                                .max_height(320.0)
+                                .show(ui, |ui| {
+                                    for diff in &diffs {
+                                        let text = match diff.change {
+                                            BookChange::PageChanged { from, to } => {
+                                                format!(
+                                                    "{}: стр. {} → {}",
+                                                    diff.file_name, from, to
+                                                )
+                                            }
+                                            BookChange::Added { page } => {
+                                                format!(
+                                                    "{}: появится (стр. {})",
+                                                    diff.file_name, page
+                                                )
+                                            }
+                                            BookChange::Removed { page } => {
+                                                format!(
+                                                    "{}: исчезнет (стр. {})",
+                                                    diff.file_name, page
+                                                )
+                                            }
+                                        };
+                                        ui.label(text);
+                                    }
+                                });
+                        }
+
+                        ui.separator();
+                        if ui.button("Восстановить эту копию").clicked() {
+                            restore = app.history_selected;
+                        }
+                    }
+                });
+            });
+        });
+
+    if let Some(index) = restore {
+        let snapshot = app.history_snapshots[index].clone();
+        // The window closes once the sync thread restored it
+        if let Err(e) = progress_manager::restore_snapshot(app, &snapshot) {
+            app.error_message = Some(format!("Ошибка восстановления: {}", e));
+        }
+    }
+
+    app.show_history = show;
+}
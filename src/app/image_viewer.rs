@@ -0,0 +1,58 @@
+use egui::Context;
+
+use super::DocReaderApp;
+
+/// Uploads the illustration at full resolution, or as large as the GPU
+/// takes textures, and opens its window.
+pub(crate) fn open_image(app: &mut DocReaderApp, ctx: &Context, index: usize) {
+    let Some(page_image) = app.current_page_images.get(index) else {
+        return;
+    };
+    let image = &page_image.image;
+    let max_side = ctx.input(|i| i.max_texture_side) as u32;
+    let scale = (max_side as f32 / image.width().max(image.height()) as f32).min(1.0);
+    let fitted;
+    let image = if scale < 1.0 {
+        fitted = image::imageops::resize(
+            image.as_ref(),
+            ((image.width() as f32 * scale) as u32).clamp(1, max_side),
+            ((image.height() as f32 * scale) as u32).clamp(1, max_side),
+            image::imageops::FilterType::Triangle,
+        );
+        &fitted
+    } else {
+        image.as_ref()
+    };
+    let size = [image.width() as usize, image.height() as usize];
+    let color_image = egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+    app.image_viewer = Some(ctx.load_texture(
+        format!("illustration_{}_{}", app.current_page, index),
+        color_image,
+        egui::TextureOptions::LINEAR,
+    ));
+}
+
+pub(crate) fn show_image_window(app: &mut DocReaderApp, ctx: &Context) {
+    let Some(texture) = &app.image_viewer else {
+        return;
+    };
+    let mut open = true;
+    // One image pixel per screen pixel
+    let size = texture.size_vec2() / ctx.pixels_per_point();
+
+    egui::Window::new("Иллюстрация")
+        .open(&mut open)
+        .default_size(size.min(ctx.screen_rect().size() * 0.9))
+        .resizable(true)
+        .show(ctx, |ui| {
+            egui::ScrollArea::both()
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    ui.image((texture.id(), size));
+                });
+        });
+
+    if !open || ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+        app.image_viewer = None;
+    }
+}
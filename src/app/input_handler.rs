@@ -1,15 +1,44 @@
 use egui::Context;
 
 use super::book_manager;
+use super::bookmark_manager;
+use super::highlight_manager;
+use super::rotation_manager;
+use super::spread_manager;
 use super::DocReaderApp;
 
 pub(crate) fn handle_keyboard_input(app: &mut DocReaderApp, ctx: &Context) {
+    // Keys typed into a text field are not shortcuts
+    if ctx.wants_keyboard_input() {
+        return;
+    }
+
+    // egui turns Ctrl+C into a copy event rather than a key press
+    if ctx.input(|i| i.events.iter().any(|e| matches!(e, egui::Event::Copy))) {
+        highlight_manager::copy_selection(app, ctx);
+    }
     ctx.input(|i| {
-        if i.key_pressed(egui::Key::ArrowLeft) || i.key_pressed(egui::Key::PageUp) {
-            book_manager::go_to_page(app, app.current_page.saturating_sub(1));
+        if i.modifiers.command && i.key_pressed(egui::Key::B) {
+            bookmark_manager::toggle_bookmark(app);
+        }
+        if i.modifiers.command && i.key_pressed(egui::Key::R) {
+            if i.modifiers.shift {
+                rotation_manager::rotate_document(app, 1);
+            } else {
+                rotation_manager::rotate_page(app, 1);
+            }
+        }
+        // Right to left books turn forward with the left arrow
+        let (back, forward) = if spread_manager::is_rtl(app) {
+            (egui::Key::ArrowRight, egui::Key::ArrowLeft)
+        } else {
+            (egui::Key::ArrowLeft, egui::Key::ArrowRight)
+        };
+        if i.key_pressed(back) || i.key_pressed(egui::Key::PageUp) {
+            book_manager::prev_page(app);
         }
-        if i.key_pressed(egui::Key::ArrowRight) || i.key_pressed(egui::Key::PageDown) {
-            book_manager::go_to_page(app, app.current_page + 1);
+        if i.key_pressed(forward) || i.key_pressed(egui::Key::PageDown) {
+            book_manager::next_page(app);
         }
         if i.key_pressed(egui::Key::Home) {
             book_manager::go_to_page(app, 1);
@@ -0,0 +1,220 @@
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::config::constants::*;
+use crate::config::settings::KosyncSettings;
+use crate::sync::kosync::{self, KosyncClient, KosyncProgress};
+
+use super::book_manager;
+use super::kosync_thread::{self, KosyncRequest, KosyncResponse};
+use super::reflow_manager;
+use super::DocReaderApp;
+
+/// Connection to a kosync server plus the sync state of the open book.
+pub(crate) struct KosyncSession {
+    tx: mpsc::Sender<KosyncRequest>,
+    rx: mpsc::Receiver<KosyncResponse>,
+    dirty: bool,
+    last_push: Instant,
+    last_pull: Instant,
+    // Pulled position waiting until the book is paginated
+    pending: Option<(String, KosyncProgress)>,
+}
+
+pub(crate) fn start(settings: &KosyncSettings) -> Option<KosyncSession> {
+    if !settings.enabled || settings.username.is_empty() || settings.server.trim().is_empty() {
+        return None;
+    }
+
+    let client = KosyncClient::new(&settings.server, &settings.username, &settings.userkey);
+    let (tx, rx) = kosync_thread::spawn_kosync_thread(client);
+    Some(KosyncSession {
+        tx,
+        rx,
+        dirty: false,
+        last_push: Instant::now(),
+        last_pull: Instant::now(),
+        pending: None,
+    })
+}
+
+/// Restarts the session after a settings change. The old thread still
+/// sends the position handed to it.
+pub(crate) fn restart(app: &mut DocReaderApp) {
+    push_now(app);
+    app.kosync = start(&app.settings.kosync);
+    pull_current(app);
+}
+
+/// Asks the server for the position of the open book.
+pub(crate) fn pull_current(app: &mut DocReaderApp) {
+    let Some(book_hash) = app.selected_book_hash.clone() else {
+        return;
+    };
+    let Some(document) = app
+        .books
+        .iter()
+        .find(|b| b.file_hash == book_hash)
+        .and_then(|b| b.koreader_digest.clone())
+    else {
+        return;
+    };
+    let Some(session) = &mut app.kosync else {
+        return;
+    };
+
+    session.last_pull = Instant::now();
+    let _ = session.tx.send(KosyncRequest::Pull {
+        book_hash,
+        document,
+    });
+}
+
+pub(crate) fn mark_dirty(app: &mut DocReaderApp) {
+    if let Some(session) = &mut app.kosync {
+        session.dirty = true;
+    }
+}
+
+/// Sends the current position right away, e.g. before switching books.
+pub(crate) fn push_now(app: &mut DocReaderApp) {
+    let Some(progress) = current_position(app) else {
+        return;
+    };
+    if let Some(session) = &mut app.kosync {
+        if session.dirty && session.tx.send(KosyncRequest::Push(progress)).is_ok() {
+            session.dirty = false;
+            session.last_push = Instant::now();
+        }
+    }
+}
+
+/// Sends the position on exit and waits a little for the background
+/// thread to deliver it.
+pub(crate) fn flush(app: &mut DocReaderApp) {
+    push_now(app);
+    let Some(session) = app.kosync.take() else {
+        return;
+    };
+
+    // The thread ends, closing the channel, once it sent what it was handed
+    drop(session.tx);
+    let deadline = Instant::now() + Duration::from_secs(KOSYNC_EXIT_TIMEOUT_SECS);
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        if session.rx.recv_timeout(left).is_err() {
+            break;
+        }
+    }
+}
+
+pub(crate) fn poll(app: &mut DocReaderApp) {
+    let Some(session) = &mut app.kosync else {
+        return;
+    };
+
+    while let Ok(response) = session.rx.try_recv() {
+        match response {
+            KosyncResponse::Pulled {
+                book_hash,
+                progress: Some(progress),
+            } => session.pending = Some((book_hash, progress)),
+            KosyncResponse::Pulled { progress: None, .. } | KosyncResponse::Pushed => {}
+            KosyncResponse::Err(e) => app.error_message = Some(e),
+        }
+    }
+
+    apply_pending(app);
+
+    let Some(session) = &app.kosync else {
+        return;
+    };
+    let push_due = session.dirty
+        && session.last_push.elapsed() >= Duration::from_secs(KOSYNC_PUSH_INTERVAL_SECS);
+    let pull_due = session.last_pull.elapsed() >= Duration::from_secs(KOSYNC_PULL_INTERVAL_SECS);
+
+    if push_due {
+        push_now(app);
+    }
+    if pull_due {
+        pull_current(app);
+    }
+}
+
+fn apply_pending(app: &mut DocReaderApp) {
+    let reflowable = reflow_manager::reflows(app);
+    let Some(session) = &mut app.kosync else {
+        return;
+    };
+    let Some((book_hash, remote)) = session.pending.take() else {
+        return;
+    };
+
+    if app.selected_book_hash.as_deref() != Some(&book_hash)
+        || remote.device_id == app.settings.device_id
+    {
+        return;
+    }
+    let Some(book) = app.books.iter().find(|b| b.file_hash == book_hash) else {
+        return;
+    };
+    if book.total_pages == 0 || (reflowable && app.current_page_sections.is_empty()) {
+        // Not paginated yet, try again on the next frame
+        session.pending = Some((book_hash, remote));
+        return;
+    }
+
+    // Only take positions newer than our own last move in the book;
+    // opening it or laying it out again does not count
+    let moved = app
+        .progress
+        .books
+        .get(&book_hash)
+        .and_then(|bp| bp.moved)
+        .map(|moved| moved.timestamp())
+        .unwrap_or(0);
+    if remote.timestamp.unwrap_or(0) <= moved {
+        return;
+    }
+
+    let page = kosync::from_kosync_position(
+        &remote.progress,
+        remote.percentage,
+        reflow_manager::file_total_pages(app),
+        &app.current_page_sections,
+    );
+    book_manager::go_to_page(app, reflow_manager::reflowed_page(app, page));
+
+    // The position came from the server, no need to send it back
+    if let Some(session) = &mut app.kosync {
+        session.dirty = false;
+    }
+}
+
+fn current_position(app: &DocReaderApp) -> Option<KosyncProgress> {
+    let book = app.selected_book()?;
+    let document = book.koreader_digest.clone()?;
+    let reflowable = reflow_manager::reflows(app);
+    if book.total_pages == 0 || (reflowable && app.current_page_sections.is_empty()) {
+        return None;
+    }
+
+    // Other readers know the pages of the PDF, not the reflowed ones
+    let (progress, percentage) = kosync::to_kosync_position(
+        book.format,
+        reflow_manager::progress_page(app, app.current_page),
+        reflow_manager::file_total_pages(app),
+        &app.current_page_sections,
+    );
+    Some(KosyncProgress {
+        document,
+        progress,
+        percentage,
+        device: if app.settings.device_name.trim().is_empty() {
+            KOSYNC_DEVICE_NAME.to_string()
+        } else {
+            app.settings.device_name.clone()
+        },
+        device_id: app.settings.device_id.clone(),
+        timestamp: None,
+    })
+}
@@ -0,0 +1,50 @@
+use std::sync::mpsc;
+
+use crate::sync::kosync::{KosyncClient, KosyncProgress};
+
+pub(crate) enum KosyncRequest {
+    Pull { book_hash: String, document: String },
+    Push(KosyncProgress),
+}
+
+pub(crate) enum KosyncResponse {
+    Pulled {
+        book_hash: String,
+        progress: Option<KosyncProgress>,
+    },
+    Pushed,
+    Err(String),
+}
+
+pub(crate) fn spawn_kosync_thread(
+    client: KosyncClient,
+) -> (mpsc::Sender<KosyncRequest>, mpsc::Receiver<KosyncResponse>) {
+    let (request_tx, request_rx) = mpsc::channel::<KosyncRequest>();
+    let (response_tx, response_rx) = mpsc::channel::<KosyncResponse>();
+
+    std::thread::spawn(move || {
+        while let Ok(req) = request_rx.recv() {
+            let response = match req {
+                KosyncRequest::Pull {
+                    book_hash,
+                    document,
+                } => match client.get_progress(&document) {
+                    Ok(progress) => KosyncResponse::Pulled {
+                        book_hash,
+                        progress,
+                    },
+                    Err(e) => KosyncResponse::Err(format!("Ошибка KOReader sync: {}", e)),
+                },
+                KosyncRequest::Push(progress) => match client.update_progress(&progress) {
+                    Ok(()) => KosyncResponse::Pushed,
+                    Err(e) => KosyncResponse::Err(format!("Ошибка KOReader sync: {}", e)),
+                },
+            };
+            if response_tx.send(response).is_err() {
+                break;
+            }
+        }
+    });
+
+    (request_tx, response_rx)
+}
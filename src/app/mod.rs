@@ -1,28 +1,59 @@
 mod book_manager;
+mod bookmark_manager;
+mod crop_manager;
+mod devices_dialog;
+mod highlight_manager;
+mod history_dialog;
+mod image_viewer;
 mod input_handler;
+mod kosync_manager;
+mod kosync_thread;
 mod progress_manager;
+mod reflow_manager;
 mod render_manager;
 mod render_thread;
+mod rotation_manager;
+mod scroll_manager;
 mod settings_dialog;
+mod spread_manager;
+mod sync_thread;
+mod theme_manager;
+mod typography_manager;
+mod zoom_manager;
 
 use egui::{Context, TextureHandle};
+use once_cell::sync::Lazy;
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crate::config::constants::*;
-use crate::config::settings::AppSettings;
+use crate::config::settings::{
+    AppSettings, KosyncSettings, SyncBackendSettings, ThemeSettings, TypographySettings, ZoomMode,
+};
+use crate::library::annotations::Highlight;
 use crate::library::book::Book;
+use crate::library::bookmarks::Bookmark;
+use crate::library::devices;
 use crate::library::progress::ReadingProgress;
 use crate::library::scanner::LibraryScanner;
 use crate::renderer::cache::PageCache;
-use crate::sync::storage::ProgressStorage;
+use crate::renderer::fonts::SYSTEM_FONTS;
+use crate::renderer::format::DocumentFormat;
+use crate::renderer::page_text::{PageImage, PageText};
+use crate::sync::backup::Snapshot;
 use crate::sync::watcher::SyncWatcher;
-use crate::ui::document_viewer::DocumentViewer;
+use crate::ui::bookmarks_panel::BookmarksPanel;
+use crate::ui::document_viewer::{DocumentViewer, PageOverlay, ViewerAction};
 use crate::ui::sidebar::Sidebar;
 use crate::ui::toolbar::Toolbar;
 
-use render_thread::{RenderRequest, RenderResponse};
+use highlight_manager::HighlightPopup;
+use kosync_manager::KosyncSession;
+use render_thread::{RenderCommand, RenderResponse};
+use sync_thread::{SyncRequest, SyncResponse};
 
 pub struct DocReaderApp {
     // Settings
@@ -37,28 +68,96 @@ pub struct DocReaderApp {
     pub(crate) current_page: u32,
     pub(crate) current_texture: Option<TextureHandle>,
     pub(crate) current_document_bytes: Option<Arc<Vec<u8>>>,
+    pub(crate) current_page_sections: Vec<u32>,
+    pub(crate) current_page_positions: Vec<u64>,
+    pub(crate) current_page_text: Arc<PageText>,
+    pub(crate) current_page_images: Arc<Vec<PageImage>>,
+    // Size of the current page at 100% zoom, once rendered
+    pub(crate) current_page_size: Option<egui::Vec2>,
+    // Size of every page at 100% zoom (fixed-layout formats)
+    pub(crate) current_page_sizes: Vec<egui::Vec2>,
 
     // Services
-    pub(crate) storage: ProgressStorage,
+    // Where progress is stored, for the settings dialog
+    pub(crate) storage_description: String,
     pub(crate) watcher: Option<SyncWatcher>,
+    // The sync thread owns the storage and loads and saves progress
+    pub(crate) sync_tx: mpsc::Sender<SyncRequest>,
+    pub(crate) sync_rx: mpsc::Receiver<SyncResponse>,
+    // Writes handed to the sync thread that have not finished
+    pub(crate) pending_saves: usize,
     pub(crate) page_cache: PageCache,
+    pub(crate) kosync: Option<KosyncSession>,
 
     // Async rendering
-    pub(crate) render_tx: mpsc::Sender<RenderRequest>,
+    pub(crate) render_tx: mpsc::Sender<RenderCommand>,
     pub(crate) result_rx: mpsc::Receiver<RenderResponse>,
     pub(crate) is_rendering: bool,
+    // Counter of render batches; the render thread drops older ones
+    pub(crate) render_view: u64,
+    // Typography of the last render request
+    pub(crate) layout_typography: TypographySettings,
     first_frame: bool,
 
     // UI state
     pub(crate) zoom: f32,
+    pub(crate) zoom_mode: ZoomMode,
     pub(crate) pixels_per_point: f32,
+    // Size of the central panel, and when it last changed
+    pub(crate) viewer_size: egui::Vec2,
+    pub(crate) viewer_resized: Option<Instant>,
+    // Continuous scroll: pages in view or close to it, where to scroll
+    // (page and offset into it) and the zoom of the last layout
+    pub(crate) continuous_pages: RangeInclusive<u32>,
+    pub(crate) scroll_to: Option<(u32, f32)>,
+    pub(crate) scroll_zoom: f32,
     pub(crate) last_save: Instant,
     pub(crate) needs_save: bool,
+    // Progress came from storage; nothing is saved before it did
+    pub(crate) progress_loaded: bool,
+    // Why the progress file must not be written (wrong passphrase, newer schema)
+    pub(crate) progress_read_only: Option<String>,
 
     // Settings dialog
     pub(crate) show_settings: bool,
     pub(crate) settings_library_path: String,
     pub(crate) settings_progress_path: String,
+    pub(crate) settings_sync_backend: SyncBackendSettings,
+    pub(crate) settings_kosync: KosyncSettings,
+    pub(crate) settings_kosync_password: String,
+    pub(crate) settings_passphrase: String,
+    pub(crate) settings_device_name: String,
+    pub(crate) settings_auto_save_interval: u64,
+    pub(crate) settings_local_overrides: BTreeSet<String>,
+    pub(crate) settings_typography: TypographySettings,
+    // Whether the typography edited is the open book's own
+    pub(crate) settings_book_typography: bool,
+    pub(crate) settings_theme: ThemeSettings,
+
+    // Devices window
+    pub(crate) show_devices: bool,
+    // Device awaiting confirmation of retirement
+    pub(crate) devices_confirm_retire: Option<String>,
+
+    // Progress history window
+    pub(crate) show_history: bool,
+    pub(crate) history_snapshots: Vec<Snapshot>,
+    pub(crate) history_selected: Option<usize>,
+    pub(crate) history_preview: Option<Result<ReadingProgress, String>>,
+
+    // Bookmarks panel
+    pub(crate) show_bookmarks: bool,
+    // Id and label buffer of the bookmark being renamed
+    pub(crate) bookmark_editing: Option<(String, String)>,
+
+    // Text selection on the current page, as an inclusive character range
+    pub(crate) selection: Option<(usize, usize)>,
+    pub(crate) highlight_popup: Option<HighlightPopup>,
+    // Result of the last highlights export
+    pub(crate) export_status: Option<String>,
+
+    // Illustration opened at full size
+    pub(crate) image_viewer: Option<TextureHandle>,
 
     // Page navigation input
     pub(crate) page_input: String,
@@ -71,30 +170,41 @@ impl DocReaderApp {
     pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
         let settings = AppSettings::load().unwrap_or_default();
 
-        let storage = ProgressStorage::new(settings.progress_file_path.clone());
-        let progress = storage
-            .load_or_create(&settings.device_id)
-            .unwrap_or_else(|_| ReadingProgress::new(settings.device_id.clone()));
+        // Progress is loaded in the background; the last book is opened
+        // once it arrives
+        let storage = progress_manager::open_storage(&settings);
+        let watcher = progress_manager::open_watcher(&settings, &storage);
+        let storage_description = storage.backend().describe();
+        let (sync_tx, sync_rx) = sync_thread::spawn_sync_thread(storage);
+        let _ = sync_tx.send(SyncRequest::Load {
+            device_id: settings.device_id.clone(),
+        });
+        let progress = ReadingProgress::new(settings.device_id.clone());
 
-        let mut books =
+        let books =
             LibraryScanner::scan_and_load_books(&settings.library_path, None).unwrap_or_default();
 
-        // Restore total_pages from saved progress
-        for book in &mut books {
-            if let Some(bp) = progress.books.get(&book.file_hash) {
-                if bp.total_pages > 0 {
-                    book.total_pages = bp.total_pages;
-                }
-            }
-        }
-
-        let watcher = SyncWatcher::new(&settings.progress_file_path).ok();
-
         let (render_tx, result_rx) = render_thread::spawn_render_thread();
+        // Scan fonts now rather than when the font picker is first opened
+        std::thread::spawn(|| Lazy::force(&SYSTEM_FONTS));
+        let kosync = kosync_manager::start(&settings.kosync);
 
         Self {
             settings_library_path: settings.library_path.to_string_lossy().to_string(),
             settings_progress_path: settings.progress_file_path.to_string_lossy().to_string(),
+            settings_sync_backend: settings.sync_backend.clone(),
+            settings_kosync: settings.kosync.clone(),
+            settings_kosync_password: String::new(),
+            settings_passphrase: settings.encryption_passphrase.clone(),
+            settings_device_name: settings.device_name.clone(),
+            settings_auto_save_interval: settings.auto_save_interval_secs,
+            settings_local_overrides: settings.local_overrides.clone(),
+            settings_typography: settings.typography.clone(),
+            settings_book_typography: false,
+            settings_theme: settings.theme,
+            zoom: settings.zoom_level,
+            scroll_zoom: settings.zoom_level,
+            zoom_mode: ZoomMode::Fixed,
             settings,
             books,
             progress,
@@ -102,18 +212,47 @@ impl DocReaderApp {
             current_page: 1,
             current_texture: None,
             current_document_bytes: None,
-            storage,
+            current_page_sections: Vec::new(),
+            current_page_positions: Vec::new(),
+            current_page_text: Arc::default(),
+            current_page_images: Arc::default(),
+            current_page_size: None,
+            current_page_sizes: Vec::new(),
+            storage_description,
             watcher,
+            sync_tx,
+            sync_rx,
+            pending_saves: 0,
             page_cache: PageCache::new(PAGE_CACHE_CAPACITY),
+            kosync,
             render_tx,
             result_rx,
             is_rendering: false,
+            render_view: 0,
+            layout_typography: TypographySettings::default(),
             first_frame: true,
-            zoom: ZOOM_DEFAULT,
             pixels_per_point: 1.0,
+            viewer_size: egui::Vec2::ZERO,
+            viewer_resized: None,
+            continuous_pages: 1..=1,
+            scroll_to: None,
             last_save: Instant::now(),
             needs_save: false,
+            progress_loaded: false,
+            progress_read_only: None,
             show_settings: false,
+            show_devices: false,
+            devices_confirm_retire: None,
+            show_history: false,
+            history_snapshots: Vec::new(),
+            history_selected: None,
+            history_preview: None,
+            show_bookmarks: false,
+            bookmark_editing: None,
+            selection: None,
+            highlight_popup: None,
+            export_status: None,
+            image_viewer: None,
             page_input: "1".to_string(),
             error_message: None,
         }
@@ -132,11 +271,7 @@ impl eframe::App for DocReaderApp {
         if self.first_frame {
             self.first_frame = false;
             self.pixels_per_point = ctx.pixels_per_point();
-            if let Some(book_hash) = self.settings.last_opened_book.clone() {
-                if self.books.iter().any(|b| b.file_hash == book_hash) {
-                    book_manager::select_book(self, ctx, &book_hash);
-                }
-            }
+            theme_manager::apply_visuals(self, ctx);
         }
 
         // Track HiDPI scale factor; re-render if it changed
@@ -148,8 +283,9 @@ impl eframe::App for DocReaderApp {
         }
 
         // Background tasks
-        progress_manager::check_sync(self);
+        progress_manager::check_sync(self, ctx);
         render_manager::poll_render_results(self, ctx);
+        kosync_manager::poll(self);
 
         // Keyboard input
         input_handler::handle_keyboard_input(self, ctx);
@@ -158,18 +294,37 @@ impl eframe::App for DocReaderApp {
         egui::TopBottomPanel::top("toolbar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 if ui.button("Настройки").clicked() {
+                    settings_dialog::refresh(self);
                     self.show_settings = true;
                 }
+                if ui.button("Устройства").clicked() {
+                    self.devices_confirm_retire = None;
+                    self.show_devices = true;
+                }
+                if ui.button("История").clicked() {
+                    history_dialog::refresh(self);
+                    self.show_history = true;
+                }
                 ui.separator();
 
                 let total_pages = self.selected_book().map(|b| b.total_pages).unwrap_or(0);
+                let bookmarked = bookmark_manager::is_bookmarked(self);
+                let layout = book_manager::page_layout(self);
+                let reflow = self
+                    .selected_book()
+                    .is_some_and(|b| b.format == DocumentFormat::Pdf)
+                    .then(|| reflow_manager::is_reflowed(self));
 
                 if let Some(action) = Toolbar::show(
                     ui,
-                    self.current_page,
                     total_pages,
                     self.zoom,
+                    self.zoom_mode,
                     &mut self.page_input,
+                    bookmarked,
+                    self.show_bookmarks,
+                    layout,
+                    reflow,
                 ) {
                     book_manager::handle_toolbar_action(self, action);
                 }
@@ -179,8 +334,12 @@ impl eframe::App for DocReaderApp {
         // Bottom panel (status bar)
         egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                if let Some(err) = &self.error_message {
+                if let Some(err) = &self.progress_read_only {
+                    ui.colored_label(egui::Color32::RED, format!("🔒 {}", err));
+                } else if let Some(err) = &self.error_message {
                     ui.colored_label(egui::Color32::RED, err);
+                } else if !self.progress_loaded {
+                    ui.label("Загрузка прогресса...");
                 } else if self.is_rendering {
                     ui.label("Загрузка страницы...");
                 } else if self.needs_save {
@@ -198,8 +357,7 @@ impl eframe::App for DocReaderApp {
 
                 ui.label(format!(
                     "Устройство: {}",
-                    &self.settings.device_id
-                        [..DEVICE_ID_DISPLAY_LEN.min(self.settings.device_id.len())]
+                    devices::display_name(&self.settings.device_name, &self.settings.device_id)
                 ));
                 ui.separator();
                 ui.label(format!("Книг: {}", self.books.len()));
@@ -230,23 +388,100 @@ impl eframe::App for DocReaderApp {
                 }
             });
 
+        // Right panel (bookmarks of the open book)
+        if self.show_bookmarks {
+            egui::SidePanel::right("bookmarks")
+                .resizable(true)
+                .default_width(SIDEBAR_DEFAULT_WIDTH)
+                .min_width(SIDEBAR_MIN_WIDTH)
+                .show(ctx, |ui| {
+                    let is_pdf = self
+                        .selected_book()
+                        .is_some_and(|b| b.format == DocumentFormat::Pdf);
+                    let current_page = reflow_manager::progress_page(self, self.current_page);
+                    let book = self
+                        .selected_book_hash
+                        .as_ref()
+                        .and_then(|hash| self.progress.books.get(hash));
+                    let bookmarks: Vec<(u32, &Bookmark)> = book
+                        .into_iter()
+                        .flat_map(|b| b.bookmarks())
+                        .map(|b| (reflow_manager::saved_page(self, b.page, b.position), b))
+                        .collect();
+                    let highlights: Vec<(u32, &Highlight)> = book
+                        .into_iter()
+                        .flat_map(|b| b.highlights())
+                        .map(|h| {
+                            let position = h.anchor.map(|[start, _]| start);
+                            (reflow_manager::saved_page(self, h.page, position), h)
+                        })
+                        .collect();
+                    let action = BookmarksPanel::show(
+                        ui,
+                        &bookmarks,
+                        &highlights,
+                        current_page,
+                        &mut self.bookmark_editing,
+                        self.export_status.as_deref(),
+                        is_pdf,
+                    );
+                    if let Some(action) = action {
+                        bookmark_manager::handle_bookmark_action(self, action);
+                    }
+                });
+        }
+
         // Central panel (viewer)
         egui::CentralPanel::default().show(ctx, |ui| {
+            typography_manager::track_viewer_size(self, ui.available_size());
+            zoom_manager::update_fit(self);
+            if scroll_manager::is_continuous(self) && scroll_manager::show_continuous(self, ui) {
+                return;
+            }
+            if spread_manager::book_spread(self).is_some() {
+                spread_manager::show_spread(self, ui);
+                return;
+            }
             let total_pages = self.selected_book().map(|b| b.total_pages).unwrap_or(0);
 
-            DocumentViewer::show(
+            let highlights = highlight_manager::current_highlights(self);
+            let overlay = PageOverlay {
+                text: &self.current_page_text,
+                images: &self.current_page_images,
+                highlights: &highlights,
+                selection: self.selection,
+            };
+            let action = DocumentViewer::show(
                 ui,
                 self.current_texture.as_ref(),
+                overlay,
                 self.current_page,
                 total_pages,
             );
+            match action {
+                Some(ViewerAction::OpenImage(index)) => image_viewer::open_image(self, ctx, index),
+                Some(action) => highlight_manager::handle_viewer_action(self, action),
+                None => {}
+            }
         });
+        highlight_manager::show_highlight_popup(self, ctx);
+        image_viewer::show_image_window(self, ctx);
 
         // Settings window
         if self.show_settings {
             settings_dialog::show_settings_window(self, ctx);
         }
 
+        // Devices window
+        if self.show_devices {
+            devices_dialog::show_devices_window(self, ctx);
+        }
+
+        // Progress history window
+        if self.show_history {
+            history_dialog::show_history_window(self, ctx);
+        }
+
         // Auto-save progress
         progress_manager::maybe_save_progress(self);
 
@@ -255,7 +490,8 @@ impl eframe::App for DocReaderApp {
     }
 
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
-        let _ = self.storage.save(&self.progress);
+        kosync_manager::flush(self);
+        progress_manager::flush(self);
         let _ = self.settings.save();
     }
 }
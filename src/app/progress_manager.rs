@@ -1,19 +1,325 @@
-use std::time::Duration;
+use anyhow::Result;
+use egui::Context;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::config::constants::*;
+use crate::config::roaming::{self, RoamingPreferences};
+use crate::config::settings::{AppSettings, SyncBackendSettings};
+use crate::library::migration;
+use crate::library::progress::ReadingProgress;
+use crate::sync::backup::{BackupStore, Snapshot};
+use crate::sync::crypto::ProgressCipher;
 use crate::sync::merge::ProgressMerger;
-use crate::sync::watcher::SyncEvent;
+use crate::sync::s3::{S3Backend, S3Config};
+use crate::sync::storage::ProgressStorage;
+use crate::sync::watcher::{SyncEvent, SyncWatcher};
+use crate::sync::webdav::WebDavBackend;
 
+use super::book_manager;
+use super::highlight_manager;
+use super::render_manager;
+use super::sync_thread::{self, SyncRequest, SyncResponse, NEWER_SCHEMA_MESSAGE};
+use super::theme_manager;
+use super::typography_manager;
+use super::zoom_manager;
 use super::DocReaderApp;
 
-pub(crate) fn check_sync(app: &mut DocReaderApp) {
+/// Creates the progress storage for the configured backend.
+pub(crate) fn open_storage(settings: &AppSettings) -> ProgressStorage {
+    let mut storage = open_backend(settings);
+    if !settings.encryption_passphrase.is_empty() {
+        storage = storage.with_cipher(ProgressCipher::new(&settings.encryption_passphrase));
+    }
+    if let Ok(dir) = BackupStore::default_dir() {
+        storage = storage.with_backups(BackupStore::new(dir));
+    }
+    storage
+}
+
+/// Watches the storage for changes made by other devices.
+pub(crate) fn open_watcher(
+    settings: &AppSettings,
+    storage: &ProgressStorage,
+) -> Option<SyncWatcher> {
+    match &settings.sync_backend {
+        SyncBackendSettings::LocalFile => SyncWatcher::new(&settings.progress_file_path).ok(),
+        SyncBackendSettings::WebDav { .. } | SyncBackendSettings::S3 { .. } => {
+            Some(SyncWatcher::polling(
+                storage.backend(),
+                Duration::from_secs(SYNC_POLL_INTERVAL_SECS),
+            ))
+        }
+    }
+}
+
+fn open_backend(settings: &AppSettings) -> ProgressStorage {
+    match &settings.sync_backend {
+        SyncBackendSettings::LocalFile => ProgressStorage::new(settings.progress_file_path.clone()),
+        SyncBackendSettings::WebDav {
+            url,
+            username,
+            password,
+        } => {
+            let backend = Arc::new(WebDavBackend::new(url, username, password));
+            ProgressStorage::with_backend(backend, PROGRESS_FILENAME)
+        }
+        SyncBackendSettings::S3 {
+            endpoint,
+            bucket,
+            prefix,
+            region,
+            access_key,
+            secret_key,
+        } => {
+            let backend = Arc::new(S3Backend::new(S3Config {
+                endpoint: endpoint.clone(),
+                bucket: bucket.clone(),
+                prefix: prefix.clone(),
+                region: region.clone(),
+                access_key: access_key.clone(),
+                secret_key: secret_key.clone(),
+            }));
+            ProgressStorage::per_device(backend, &settings.device_id)
+        }
+    }
+}
+
+/// Connects to the storage of the current settings, restarting the sync
+/// thread that owns it. A save still running on the old one is not waited for.
+fn connect(app: &mut DocReaderApp) {
+    let storage = open_storage(&app.settings);
+    app.watcher = open_watcher(&app.settings, &storage);
+    app.storage_description = storage.backend().describe();
+    (app.sync_tx, app.sync_rx) = sync_thread::spawn_sync_thread(storage);
+    app.pending_saves = 0;
+}
+
+/// Reopens storage after a settings change and reloads progress from it.
+pub(crate) fn reopen_storage(app: &mut DocReaderApp) {
+    connect(app);
+    let device_id = app.settings.device_id.clone();
+    if app.sync_tx.send(SyncRequest::Load { device_id }).is_ok() {
+        app.progress_loaded = false;
+    }
+}
+
+/// Reopens storage with a new passphrase and rewrites this device's progress with it.
+pub(crate) fn change_passphrase(app: &mut DocReaderApp) {
+    if app.progress_read_only.is_some() || !app.progress_loaded {
+        // Nothing trustworthy to re-encrypt: just try the new passphrase
+        reopen_storage(app);
+        return;
+    }
+
+    connect(app);
+    let progress = Box::new(app.progress.clone());
+    if send(app, SyncRequest::Replace(progress)).is_ok() {
+        app.needs_save = false;
+    }
+}
+
+/// Asks the sync thread to replace the current progress with a snapshot.
+/// The current state is snapshotted first, so a restore can itself be undone.
+pub(crate) fn restore_snapshot(app: &mut DocReaderApp, snapshot: &Snapshot) -> Result<()> {
+    if let Some(reason) = &app.progress_read_only {
+        anyhow::bail!("{}", reason);
+    }
+
+    send(
+        app,
+        SyncRequest::Restore {
+            snapshot: snapshot.clone(),
+            current: Box::new(app.progress.clone()),
+        },
+    )
+}
+
+/// Shows the open book at its position in progress loaded or restored just now.
+fn reload_open_book(app: &mut DocReaderApp) {
+    let Some(hash) = app.selected_book_hash.clone() else {
+        return;
+    };
+    match app.progress.books.get(&hash) {
+        Some(bp) => {
+            app.current_page = bp.current_page;
+            app.page_input = bp.current_page.to_string();
+            // Laid out again from the restored position in the text
+            app.current_page_positions.clear();
+            highlight_manager::clear_page_text(app);
+            render_manager::request_render(app);
+        }
+        None => {
+            // Opened before the progress arrived
+            if let Some(book) = app.books.iter().find(|b| b.file_hash == hash) {
+                app.progress.add_book(
+                    hash.clone(),
+                    book.file_name.clone(),
+                    book.file_path.to_string_lossy().to_string(),
+                    book.total_pages,
+                );
+                app.needs_save = true;
+            }
+        }
+    }
+}
+
+/// Applies roaming preferences from the progress document to local settings.
+pub(crate) fn apply_preferences(app: &mut DocReaderApp, ctx: &Context) {
+    let changed = app.progress.preferences.apply_to(&mut app.settings);
+    preferences_applied(app, ctx, &changed);
+}
+
+/// Gives settings no longer overridden locally their roaming value again.
+pub(crate) fn release_overrides(app: &mut DocReaderApp, ctx: &Context, keys: &BTreeSet<String>) {
+    let changed = app.progress.preferences.apply_keys(&mut app.settings, keys);
+    preferences_applied(app, ctx, &changed);
+}
+
+/// Shows the open book with preferences that came from another device.
+fn preferences_applied(app: &mut DocReaderApp, ctx: &Context, changed: &[&str]) {
+    if changed.is_empty() {
+        return;
+    }
+    if changed.contains(&roaming::ZOOM_LEVEL) {
+        // Books read before keep their own zoom
+        zoom_manager::restore_book_zoom(app);
+        app.page_cache.clear();
+        render_manager::request_render(app);
+    }
+    if changed.contains(&roaming::THEME) {
+        theme_manager::apply_theme(app, ctx);
+    }
+    let typography = [
+        roaming::READING_FONT,
+        roaming::TEXT_LAYOUT,
+        roaming::HYPHENATION,
+        roaming::JUSTIFY,
+    ];
+    if changed.iter().any(|key| typography.contains(key)) {
+        typography_manager::relayout(app);
+    }
+    let _ = app.settings.save();
+}
+
+/// Publishes locally changed preferences to other devices.
+pub(crate) fn record_preferences(app: &mut DocReaderApp) {
+    if app.progress.preferences.record_from(&app.settings) {
+        app.progress.last_modified = chrono::Utc::now();
+        app.needs_save = true;
+    }
+}
+
+/// Retires another device: hides it from the device list everywhere and
+/// deletes its own progress object where the backend keeps one.
+pub(crate) fn retire_device(app: &mut DocReaderApp, device_id: &str) -> Result<()> {
+    if let Some(reason) = &app.progress_read_only {
+        anyhow::bail!("{}", reason);
+    }
+    // Its positions are already merged into ours, so nothing is lost
+    app.progress.retire_device(device_id);
+    app.progress
+        .touch_device(&app.settings.device_name, app.selected_book_hash.as_deref());
+    send(
+        app,
+        SyncRequest::Retire {
+            device_id: device_id.to_string(),
+            progress: Box::new(app.progress.clone()),
+        },
+    )?;
+    app.needs_save = false;
+    Ok(())
+}
+
+/// Hands a request that writes progress to the sync thread.
+fn send(app: &mut DocReaderApp, request: SyncRequest) -> Result<()> {
+    app.sync_tx
+        .send(request)
+        .map_err(|_| anyhow::anyhow!("Поток синхронизации остановлен"))?;
+    app.pending_saves += 1;
+    Ok(())
+}
+
+/// Applies what the sync thread loaded or saved, and asks it to load the
+/// remote progress when the watcher saw it change.
+pub(crate) fn check_sync(app: &mut DocReaderApp, ctx: &Context) {
+    while let Ok(response) = app.sync_rx.try_recv() {
+        match response {
+            SyncResponse::Loaded(progress, read_only) => {
+                // Keep preferences edited while it loaded, e.g. in the
+                // settings dialog that reopened the storage
+                let edited = std::mem::replace(&mut app.progress, *progress).preferences;
+                let merged = RoamingPreferences::merge(&app.progress.preferences, &edited);
+                app.needs_save = merged != app.progress.preferences;
+                if app.needs_save {
+                    app.progress.preferences = merged;
+                    app.progress.last_modified = chrono::Utc::now();
+                }
+                app.progress_read_only = read_only;
+                app.progress_loaded = true;
+                book_manager::restore_total_pages(app);
+                apply_preferences(app, ctx);
+                if app.selected_book_hash.is_some() {
+                    reload_open_book(app);
+                } else if let Some(book_hash) = app.settings.last_opened_book.clone() {
+                    if app.books.iter().any(|b| b.file_hash == book_hash) {
+                        book_manager::select_book(app, ctx, &book_hash);
+                    }
+                }
+            }
+            SyncResponse::Pulled(remote) => {
+                app.needs_save = true;
+                merge_remote(app, ctx, &remote);
+            }
+            SyncResponse::Saved(remote) => {
+                app.pending_saves = app.pending_saves.saturating_sub(1);
+                // What was saved already has it; later changes are saved next time
+                if let Some(remote) = remote {
+                    merge_remote(app, ctx, &remote);
+                }
+            }
+            SyncResponse::SaveFailed(e) => {
+                app.pending_saves = app.pending_saves.saturating_sub(1);
+                app.error_message = Some(format!("Ошибка сохранения: {}", e));
+                // Retried at the next interval rather than every frame
+                app.needs_save = true;
+            }
+            SyncResponse::Snapshots(snapshots) => {
+                app.history_snapshots = snapshots;
+            }
+            SyncResponse::Snapshot(snapshot, progress) => {
+                let selected = app
+                    .history_selected
+                    .and_then(|i| app.history_snapshots.get(i));
+                if selected == Some(&snapshot) {
+                    app.history_preview = Some(progress.map(|p| *p));
+                }
+            }
+            SyncResponse::Restored(result) => {
+                app.pending_saves = app.pending_saves.saturating_sub(1);
+                match result {
+                    Ok(restored) => {
+                        app.progress = *restored;
+                        app.needs_save = false;
+                        apply_preferences(app, ctx);
+                        reload_open_book(app);
+                        app.show_history = false;
+                    }
+                    Err(e) => app.error_message = Some(format!("Ошибка восстановления: {}", e)),
+                }
+            }
+        }
+    }
+
+    if app.progress_read_only.is_some() || !app.progress_loaded {
+        return;
+    }
     if let Some(watcher) = &mut app.watcher {
         if let Some(event) = watcher.poll_changes() {
             match event {
                 SyncEvent::FileModified => {
-                    if let Ok(remote) = app.storage.load() {
-                        app.progress = ProgressMerger::merge(&app.progress, &remote);
-                        app.needs_save = true;
-                    }
+                    let _ = app.sync_tx.send(SyncRequest::Pull);
                 }
                 SyncEvent::FileDeleted => {
                     app.needs_save = true;
@@ -23,18 +329,65 @@ pub(crate) fn check_sync(app: &mut DocReaderApp) {
     }
 }
 
+/// Merges progress another device wrote into ours. Progress from a newer
+/// major schema turns saving off, as this app cannot write it without loss.
+fn merge_remote(app: &mut DocReaderApp, ctx: &Context, remote: &ReadingProgress) {
+    app.progress = ProgressMerger::merge(&app.progress, remote);
+    if migration::ensure_writable(&app.progress).is_err() {
+        app.progress_read_only = Some(NEWER_SCHEMA_MESSAGE.to_string());
+        app.needs_save = false;
+    }
+    apply_preferences(app, ctx);
+}
+
+/// Saves progress on exit through the sync thread, waiting a little for it
+/// and for saves already handed to it.
+pub(crate) fn flush(app: &mut DocReaderApp) {
+    // Never overwrite progress we could not decrypt or do not fully understand
+    if app.progress_read_only.is_some() || !app.progress_loaded {
+        return;
+    }
+
+    app.progress
+        .touch_device(&app.settings.device_name, app.selected_book_hash.as_deref());
+    let progress = Box::new(app.progress.clone());
+    let _ = send(app, SyncRequest::Save(progress));
+
+    let deadline = Instant::now() + Duration::from_secs(SYNC_EXIT_TIMEOUT_SECS);
+    while app.pending_saves > 0 {
+        let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+            break;
+        };
+        match app.sync_rx.recv_timeout(left) {
+            Ok(
+                SyncResponse::Saved(_) | SyncResponse::SaveFailed(_) | SyncResponse::Restored(_),
+            ) => {
+                app.pending_saves -= 1;
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+/// Hands progress to the sync thread once the auto-save interval passed.
 pub(crate) fn maybe_save_progress(app: &mut DocReaderApp) {
-    if !app.needs_save {
+    if !app.needs_save
+        || app.pending_saves > 0
+        || !app.progress_loaded
+        || app.progress_read_only.is_some()
+    {
         return;
     }
 
     let save_interval = Duration::from_secs(app.settings.auto_save_interval_secs);
     if app.last_save.elapsed() >= save_interval {
-        if let Err(e) = app.storage.save(&app.progress) {
-            app.error_message = Some(format!("Ошибка сохранения: {}", e));
-        } else {
+        app.progress
+            .touch_device(&app.settings.device_name, app.selected_book_hash.as_deref());
+        let progress = Box::new(app.progress.clone());
+        if send(app, SyncRequest::Save(progress)).is_ok() {
             app.needs_save = false;
-            app.last_save = std::time::Instant::now();
         }
+        app.last_save = Instant::now();
     }
 }
@@ -0,0 +1,142 @@
+use crate::renderer::format::DocumentFormat;
+use crate::renderer::pdf_reflow;
+
+use super::highlight_manager;
+use super::render_manager;
+use super::scroll_manager;
+use super::DocReaderApp;
+
+/// Whether the open book is a PDF read with its text reflowed.
+pub(crate) fn is_reflowed(app: &DocReaderApp) -> bool {
+    app.selected_book().is_some_and(|book| {
+        book.format == DocumentFormat::Pdf
+            && app
+                .progress
+                .books
+                .get(&book.file_hash)
+                .is_some_and(|bp| bp.reflow)
+    })
+}
+
+/// Whether the pages of the open book are laid out from its text with the
+/// typography settings: EPUB and FB2 books, and reflowed PDFs.
+pub(crate) fn reflows(app: &DocReaderApp) -> bool {
+    app.selected_book()
+        .is_some_and(|book| book.format.is_reflowable())
+        || is_reflowed(app)
+}
+
+/// Page of the file that `page` of the open book starts on, as kept in
+/// the reading progress and in bookmarks.
+pub(crate) fn progress_page(app: &DocReaderApp, page: u32) -> u32 {
+    if !is_reflowed(app) {
+        return page;
+    }
+    let index = page.saturating_sub(1) as usize;
+    app.current_page_sections
+        .get(index)
+        .map_or(page, |&section| section + 1)
+}
+
+/// Page of the open book showing the start of `page` of the file.
+pub(crate) fn reflowed_page(app: &DocReaderApp, page: u32) -> u32 {
+    if !is_reflowed(app) || app.current_page_sections.is_empty() {
+        return page;
+    }
+    pdf_reflow::reflowed_page_index(&app.current_page_sections, page.saturating_sub(1)) + 1
+}
+
+/// Whether the open book keeps its reading position, bookmarks and
+/// highlights as positions in its text: EPUB and FB2 books, whose pages
+/// change with the typography.
+pub(crate) fn keeps_positions(app: &DocReaderApp) -> bool {
+    app.selected_book()
+        .is_some_and(|book| book.format.is_reflowable())
+}
+
+/// Position in the text of the open EPUB or FB2 book to keep for `page`:
+/// the saved reading position while it lies on the page, else where the
+/// page starts. Until the layout is known only the saved position of the
+/// current page is.
+pub(crate) fn text_position(app: &DocReaderApp, page: u32) -> Option<u64> {
+    if !keeps_positions(app) {
+        return None;
+    }
+    let saved = app
+        .selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get(hash))
+        .and_then(|bp| bp.position);
+    let index = page.saturating_sub(1) as usize;
+    let Some(&start) = app.current_page_positions.get(index) else {
+        return saved.filter(|_| page == app.current_page);
+    };
+    let end = app
+        .current_page_positions
+        .get(index + 1)
+        .copied()
+        .unwrap_or(u64::MAX);
+    saved.filter(|p| (start..end).contains(p)).or(Some(start))
+}
+
+/// Page of the open book that a bookmark or highlight saved on `page` is
+/// on, counted like [`progress_page`]. In EPUB and FB2 books it is found
+/// by its position in the text once the layout is known.
+pub(crate) fn saved_page(app: &DocReaderApp, page: u32, position: Option<u64>) -> u32 {
+    match position {
+        Some(position) if keeps_positions(app) && !app.current_page_positions.is_empty() => {
+            app.current_page_positions
+                .partition_point(|&p| p <= position)
+                .max(1) as u32
+        }
+        _ => page,
+    }
+}
+
+/// Number of pages of the open book's file, which reflowed PDFs do not show.
+pub(crate) fn file_total_pages(app: &DocReaderApp) -> u32 {
+    let Some(book) = app.selected_book() else {
+        return 0;
+    };
+    if !is_reflowed(app) {
+        return book.total_pages;
+    }
+    app.progress
+        .books
+        .get(&book.file_hash)
+        .map_or(0, |bp| bp.total_pages)
+}
+
+/// Switches the open PDF between its pages and its text reflowed to the
+/// window, keeping the reader on the same page of the file.
+pub(crate) fn toggle_reflow(app: &mut DocReaderApp) {
+    let Some(book) = app.selected_book() else {
+        return;
+    };
+    if book.format != DocumentFormat::Pdf {
+        return;
+    }
+    let hash = book.file_hash.clone();
+    let reflow = !is_reflowed(app);
+
+    // Until the new layout is known, the current page counts pages of the file
+    let page = progress_page(app, app.current_page);
+    app.progress
+        .update_book_view(&hash, |bp| bp.reflow = reflow);
+    app.needs_save = true;
+    app.current_page = page;
+    app.page_input = page.to_string();
+    app.current_page_sections.clear();
+    app.current_page_positions.clear();
+    app.current_page_size = None;
+    if !reflow {
+        let total_pages = app.progress.books.get(&hash).map_or(0, |bp| bp.total_pages);
+        if let Some(book) = app.books.iter_mut().find(|b| b.file_hash == hash) {
+            book.total_pages = total_pages;
+        }
+    }
+    highlight_manager::clear_page_text(app);
+    app.page_cache.clear();
+    scroll_manager::restore_scroll(app);
+    render_manager::request_render(app);
+}
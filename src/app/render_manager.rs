@@ -1,10 +1,34 @@
 use egui::Context;
 use std::sync::Arc;
 
-use super::render_thread::{RenderRequest, RenderResponse};
+use crate::config::constants::CONTINUOUS_SIZE_EPSILON;
+use crate::renderer::cache::CachedPage;
+
+use super::crop_manager;
+use super::highlight_manager;
+use super::reflow_manager;
+use super::render_thread::{RenderCommand, RenderRequest, RenderResponse};
+use super::rotation_manager;
+use super::scroll_manager;
+use super::typography_manager;
 use super::DocReaderApp;
 
+/// Pixel density pages are rendered at for the current zoom.
+pub(crate) fn page_dpi(app: &DocReaderApp) -> u32 {
+    // Account for HiDPI: render at native pixel density
+    (96.0 * app.zoom * app.pixels_per_point) as u32
+}
+
 pub(crate) fn request_render(app: &mut DocReaderApp) {
+    let pages = scroll_manager::pages_to_render(app);
+    let dpi = page_dpi(app);
+    let typography = typography_manager::book_typography(app);
+    let reflow = reflow_manager::is_reflowed(app);
+    let reflows = reflow_manager::reflows(app);
+    let rotation = rotation_manager::book_rotation(app)
+        .cloned()
+        .unwrap_or_default();
+
     let Some(book_hash) = &app.selected_book_hash else {
         return;
     };
@@ -17,36 +41,93 @@ pub(crate) fn request_render(app: &mut DocReaderApp) {
         return;
     };
 
-    // Account for HiDPI: render at native pixel density
-    let dpi = (96.0 * app.zoom * app.pixels_per_point) as u32;
-    if let Some(texture) = app.page_cache.get(book_hash, app.current_page, dpi) {
-        app.current_texture = Some(texture.clone());
+    let colors = app.settings.theme.colors();
+    let current_rotation = rotation.for_page(app.current_page);
+    if let Some(cached) =
+        app.page_cache
+            .get(book_hash, app.current_page, dpi, colors, current_rotation)
+    {
+        app.current_texture = Some(cached.texture.clone());
+        app.current_page_text = Arc::clone(&cached.text);
+        app.current_page_images = Arc::clone(&cached.images);
+        app.current_page_size = Some(cached.size);
+    }
+    // Page sizes come with a render of the current page, even if cached
+    let mut page_sizes = app.current_page_sizes.is_empty() && !reflows;
+    let pages: Vec<u32> = pages
+        .into_iter()
+        .filter(|&page| {
+            (page_sizes && page == app.current_page)
+                || app
+                    .page_cache
+                    .get(book_hash, page, dpi, colors, rotation.for_page(page))
+                    .is_none()
+        })
+        .collect();
+    if pages.is_empty() {
         app.is_rendering = false;
         return;
     }
 
-    // Send render request to background thread
-    let page_index = app.current_page.saturating_sub(1);
-    let request = RenderRequest {
-        book_hash: book_hash.clone(),
-        page: app.current_page,
-        page_index,
-        zoom: app.zoom * app.pixels_per_point,
-        dpi,
-        bytes: Arc::clone(bytes),
-        format: book.format,
-    };
+    // Send render requests to background thread, replacing any pending
+    app.render_view += 1;
+    let mut sent = false;
+    for page in pages {
+        let page_index = page.saturating_sub(1);
+        let request = RenderRequest {
+            view: app.render_view,
+            book_hash: book_hash.clone(),
+            page,
+            page_index,
+            zoom: app.zoom * app.pixels_per_point,
+            dpi,
+            bytes: Arc::clone(bytes),
+            format: book.format,
+            typography: typography.clone(),
+            position: reflow_manager::text_position(app, page)
+                .or_else(|| app.current_page_positions.get(page_index as usize).copied()),
+            colors,
+            page_sizes,
+            rotation: rotation.for_page(page),
+            crop: crop_manager::page_crop(app, page),
+            reflow,
+            // The current page counts pages of the file until the layout is known
+            source_page: (reflow && app.current_page_sections.is_empty()).then_some(page),
+        };
+        page_sizes = false;
+        sent |= app.render_tx.send(RenderCommand::Render(request)).is_ok();
+    }
+    app.layout_typography = typography;
 
-    if app.render_tx.send(request).is_ok() {
+    if sent {
         app.is_rendering = true;
     }
 }
 
 pub(crate) fn poll_render_results(app: &mut DocReaderApp, ctx: &Context) {
     while let Ok(response) = app.result_rx.try_recv() {
-        app.is_rendering = false;
+        if !matches!(response, RenderResponse::Annotations(_)) {
+            app.is_rendering = false;
+        }
         match response {
-            RenderResponse::Ok(result) => {
+            RenderResponse::Ok(mut result) => {
+                // Pages laid out with typography since replaced would be
+                // numbered differently from the ones shown now
+                if !result.page_positions.is_empty()
+                    && app.selected_book_hash.as_deref() == Some(&result.book_hash)
+                    && result.typography != app.layout_typography
+                {
+                    continue;
+                }
+                // Pages cropped with margins or turned since changed, or
+                // reflowed since switched on or off
+                if app.selected_book_hash.as_deref() == Some(&result.book_hash)
+                    && (result.crop != crop_manager::page_crop(app, result.page)
+                        || result.rotation != rotation_manager::page_rotation(app, result.page)
+                        || result.reflow != reflow_manager::is_reflowed(app))
+                {
+                    continue;
+                }
                 let size = [
                     result.image.width() as usize,
                     result.image.height() as usize,
@@ -60,8 +141,22 @@ pub(crate) fn poll_render_results(app: &mut DocReaderApp, ctx: &Context) {
                     egui::TextureOptions::NEAREST,
                 );
 
-                app.page_cache
-                    .insert(&result.book_hash, result.page, result.dpi, texture.clone());
+                let page_size = egui::Vec2::from(result.page_size);
+                let text = Arc::new(result.text);
+                let images = Arc::new(result.images);
+                app.page_cache.insert(
+                    &result.book_hash,
+                    result.page,
+                    result.dpi,
+                    result.colors,
+                    result.rotation,
+                    CachedPage {
+                        texture: texture.clone(),
+                        text: Arc::clone(&text),
+                        images: Arc::clone(&images),
+                        size: page_size,
+                    },
+                );
 
                 // Update total_pages if we learned it from render thread
                 if result.total_pages > 0 {
@@ -72,7 +167,13 @@ pub(crate) fn poll_render_results(app: &mut DocReaderApp, ctx: &Context) {
                     {
                         book.total_pages = result.total_pages;
                     }
-                    if let Some(bp) = app.progress.books.get_mut(&result.book_hash) {
+                    // Progress counts pages of the file, not reflowed ones
+                    let progress = app
+                        .progress
+                        .books
+                        .get_mut(&result.book_hash)
+                        .filter(|_| !result.reflow);
+                    if let Some(bp) = progress {
                         if bp.total_pages != result.total_pages {
                             bp.total_pages = result.total_pages;
                             app.needs_save = true;
@@ -80,17 +181,60 @@ pub(crate) fn poll_render_results(app: &mut DocReaderApp, ctx: &Context) {
                     }
                 }
 
+                if app.selected_book_hash.as_deref() == Some(&result.book_hash) {
+                    if !result.page_sizes.is_empty() {
+                        // Pages turned sideways are as wide as they were high
+                        let sizes = result.page_sizes.iter().enumerate().map(|(i, &[w, h])| {
+                            match rotation_manager::page_rotation(app, i as u32 + 1) % 2 {
+                                1 => egui::vec2(h, w),
+                                _ => egui::vec2(w, h),
+                            }
+                        });
+                        app.current_page_sizes = sizes.collect();
+                    }
+                    // Cropped pages are smaller than reported up front
+                    let index = result.page.saturating_sub(1) as usize;
+                    if let Some(size) = app.current_page_sizes.get_mut(index) {
+                        if (*size - page_size).length() > CONTINUOUS_SIZE_EPSILON {
+                            *size = page_size;
+                            if result.page < app.current_page {
+                                scroll_manager::hold_position(app);
+                            }
+                        }
+                    }
+                    app.current_page_sections = std::mem::take(&mut result.page_sections);
+                    app.current_page_positions = std::mem::take(&mut result.page_positions);
+
+                    // The new layout put the requested text on another page
+                    if result.moved_from == Some(app.current_page) {
+                        app.current_page = result.page;
+                        app.page_input = result.page.to_string();
+                        let progress_page = reflow_manager::progress_page(app, result.page);
+                        let position = reflow_manager::text_position(app, result.page);
+                        app.progress
+                            .update_book_layout(&result.book_hash, progress_page);
+                        app.progress.set_book_position(&result.book_hash, position);
+                        app.needs_save = true;
+                    }
+                }
+
                 // Only update if this is still the page we want
                 if app.selected_book_hash.as_deref() == Some(&result.book_hash)
                     && app.current_page == result.page
                 {
                     app.current_texture = Some(texture);
+                    app.current_page_size = Some(page_size);
+                    app.current_page_text = text;
+                    app.current_page_images = images;
                     app.error_message = None;
                 }
             }
             RenderResponse::Err(e) => {
                 app.error_message = Some(e);
             }
+            RenderResponse::Annotations(result) => {
+                highlight_manager::apply_annotation_result(app, result);
+            }
         }
     }
 }
@@ -1,12 +1,24 @@
 use image::RgbaImage;
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::Arc;
 
 use crate::config::constants::PDF_SCALE_MULTIPLIER;
+use crate::config::settings::{PageColors, TypographySettings};
+use crate::renderer::crop::{self, PageCrop};
 use crate::renderer::format::DocumentFormat;
+use crate::renderer::markup::{DocumentMarkups, Markup};
+use crate::renderer::page_text::{PageImage, PageText};
+use crate::renderer::pdf_reflow;
+use crate::renderer::rotation;
+use crate::renderer::theme;
 use crate::renderer::RendererRegistry;
 
 pub(crate) struct RenderRequest {
+    /// Pages shown together (in continuous scroll mode) share a view and
+    /// are rendered in turn; requests of an older view are dropped.
+    pub view: u64,
     pub book_hash: String,
     pub page: u32,
     pub page_index: u32,
@@ -14,6 +26,22 @@ pub(crate) struct RenderRequest {
     pub dpi: u32,
     pub bytes: Arc<Vec<u8>>,
     pub format: DocumentFormat,
+    pub typography: TypographySettings,
+    /// Start of the page in the layout the app knows (reflowable formats
+    /// only). If the typography changed, the page holding it is rendered.
+    pub position: Option<u64>,
+    pub colors: PageColors,
+    /// Whether to report the size of every page.
+    pub page_sizes: bool,
+    /// Quarter turns clockwise to turn the page by, before it is cropped.
+    pub rotation: u8,
+    /// Margins to cut off the page, fixed-layout formats only.
+    pub crop: Option<PageCrop>,
+    /// Whether to lay out the text of a fixed-layout document again.
+    pub reflow: bool,
+    /// Page of the file to find in the reflowed layout, while the app does
+    /// not know that layout yet.
+    pub source_page: Option<u32>,
 }
 
 pub(crate) struct RenderResult {
@@ -21,59 +49,269 @@ pub(crate) struct RenderResult {
     pub page: u32,
     pub dpi: u32,
     pub total_pages: u32,
+    /// Section each page starts in (reflowable formats only)
+    pub page_sections: Vec<u32>,
+    /// Start position of each page (reflowable formats only)
+    pub page_positions: Vec<u64>,
+    /// Typography the pages were laid out with
+    pub typography: TypographySettings,
+    /// Page requested, if the new layout moved its position to `page`.
+    pub moved_from: Option<u32>,
+    pub colors: PageColors,
+    pub rotation: u8,
+    pub crop: Option<PageCrop>,
+    pub reflow: bool,
+    /// Size the page is shown at at 100% zoom, in points.
+    pub page_size: [f32; 2],
+    /// Size of every page at 100% zoom, in points, if requested and known.
+    pub page_sizes: Vec<[f32; 2]>,
     pub image: RgbaImage,
+    pub text: PageText,
+    /// Illustrations on the page, to open at full size.
+    pub images: Vec<PageImage>,
+}
+
+/// Work on the document file itself, done on the render thread because
+/// the renderers (and the pdfium library) live there.
+pub(crate) enum AnnotationJob {
+    /// Save a copy of the document with the markups stored in it.
+    Export {
+        bytes: Arc<Vec<u8>>,
+        format: DocumentFormat,
+        markups: Vec<Markup>,
+        path: PathBuf,
+    },
+    /// Read the annotations stored in each of the files.
+    Import {
+        book_hash: String,
+        sources: Vec<Arc<Vec<u8>>>,
+        format: DocumentFormat,
+    },
+}
+
+pub(crate) enum AnnotationResult {
+    Exported(PathBuf),
+    Imported {
+        book_hash: String,
+        markups: DocumentMarkups,
+    },
+    Failed(String),
+}
+
+pub(crate) enum RenderCommand {
+    Render(RenderRequest),
+    Annotations(AnnotationJob),
 }
 
 pub(crate) enum RenderResponse {
-    Ok(RenderResult),
+    Ok(Box<RenderResult>),
     Err(String),
+    Annotations(AnnotationResult),
 }
 
-pub(crate) fn spawn_render_thread() -> (mpsc::Sender<RenderRequest>, mpsc::Receiver<RenderResponse>)
+pub(crate) fn spawn_render_thread() -> (mpsc::Sender<RenderCommand>, mpsc::Receiver<RenderResponse>)
 {
-    let (render_tx, render_rx) = mpsc::channel::<RenderRequest>();
+    let (render_tx, render_rx) = mpsc::channel::<RenderCommand>();
     let (result_tx, result_rx) = mpsc::channel::<RenderResponse>();
 
     std::thread::spawn(move || {
         let registry = RendererRegistry::new();
 
-        while let Ok(mut req) = render_rx.recv() {
-            // Drain queue -- skip stale requests, keep only the latest
-            while let Ok(newer) = render_rx.try_recv() {
-                req = newer;
+        let mut pending = VecDeque::new();
+        loop {
+            // Wait for work only when none is left, then drain the queue
+            let first = if pending.is_empty() {
+                match render_rx.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return,
+                }
+            } else {
+                None
+            };
+            let queued = first
+                .into_iter()
+                .chain(std::iter::from_fn(|| render_rx.try_recv().ok()));
+
+            // Skip stale render requests, keep only those of the latest view;
+            // annotation jobs are all run
+            let mut jobs = Vec::new();
+            for command in queued {
+                match command {
+                    RenderCommand::Render(req) => {
+                        if pending
+                            .back()
+                            .is_some_and(|last: &RenderRequest| last.view != req.view)
+                        {
+                            pending.clear();
+                        }
+                        pending.push_back(req);
+                    }
+                    RenderCommand::Annotations(job) => jobs.push(job),
+                }
             }
 
-            let Some(renderer) = registry.get(&req.format) else {
-                let msg = format!("Формат {} не поддерживается", req.format.display_name());
-                if result_tx.send(RenderResponse::Err(msg)).is_err() {
-                    break;
+            // One page at a time, so newer requests are seen between pages
+            let responses = jobs
+                .into_iter()
+                .map(|job| RenderResponse::Annotations(run_annotation_job(&registry, job)))
+                .chain(pending.pop_front().map(|req| render(&registry, req)));
+            for response in responses {
+                if result_tx.send(response).is_err() {
+                    return;
                 }
-                continue;
-            };
+            }
+        }
+    });
+
+    (render_tx, result_rx)
+}
 
-            let total_pages = renderer.get_page_count(&req.bytes).unwrap_or(0);
+fn render(registry: &RendererRegistry, req: RenderRequest) -> RenderResponse {
+    let renderer = if req.reflow {
+        registry.get_reflowed(&req.format)
+    } else {
+        registry.get(&req.format)
+    };
+    let Some(renderer) = renderer else {
+        let msg = format!("Формат {} не поддерживается", req.format.display_name());
+        return RenderResponse::Err(msg);
+    };
 
-            let scale = if req.format == DocumentFormat::Pdf {
-                req.zoom * PDF_SCALE_MULTIPLIER
-            } else {
-                req.zoom
-            };
+    renderer.set_typography(req.typography.clone());
+    let native_colors = renderer.set_colors(req.colors);
+    let native_rotation = renderer.set_rotation(req.rotation);
+    let total_pages = renderer.get_page_count(&req.bytes).unwrap_or(0);
+    let page_sections = renderer.page_sections(&req.bytes).unwrap_or_default();
+    let page_positions = renderer.page_positions(&req.bytes).unwrap_or_default();
+
+    let page_index = match (req.source_page, req.position) {
+        (Some(source), _) if !page_sections.is_empty() => {
+            pdf_reflow::reflowed_page_index(&page_sections, source.saturating_sub(1))
+        }
+        (_, Some(position)) if !page_positions.is_empty() => page_positions
+            .partition_point(|&p| p <= position)
+            .saturating_sub(1) as u32,
+        _ => req.page_index,
+    };
+    let moved_from = (page_index != req.page_index).then_some(req.page);
 
-            let response = match renderer.render_page(&req.bytes, req.page_index, scale) {
-                Ok(image) => RenderResponse::Ok(RenderResult {
-                    book_hash: req.book_hash,
-                    page: req.page,
-                    dpi: req.dpi,
-                    total_pages,
-                    image,
-                }),
-                Err(e) => RenderResponse::Err(format!("Ошибка рендеринга: {}", e)),
+    // Reflowed text is set in points like EPUB and FB2
+    let scale = if req.format == DocumentFormat::Pdf && !req.reflow {
+        req.zoom * PDF_SCALE_MULTIPLIER
+    } else {
+        req.zoom
+    };
+    let page_sizes = if req.page_sizes {
+        renderer.page_sizes(&req.bytes).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let page_sizes = page_sizes
+        .into_iter()
+        .map(|[width, height]| [width * scale / req.zoom, height * scale / req.zoom])
+        .collect();
+
+    match renderer.render_page(&req.bytes, page_index, scale) {
+        Ok(mut image) => {
+            let mut text = renderer
+                .page_text(&req.bytes, page_index)
+                .unwrap_or_default();
+            let mut images = renderer
+                .page_images(&req.bytes, page_index)
+                .unwrap_or_default();
+            if !req.rotation.is_multiple_of(4) {
+                if !native_rotation {
+                    image = rotation::rotate(&image, req.rotation);
+                }
+                // Text and images are found on the page as the file has it
+                for c in &mut text.chars {
+                    c.rect = rotation::rotate_rect(c.rect, req.rotation);
+                }
+                for i in &mut images {
+                    i.rect = rotation::rotate_rect(i.rect, req.rotation);
+                }
+            }
+            // Found on the page as rendered, before it is recoloured
+            let margins = match req.crop {
+                Some(PageCrop::Auto) => crop::content_margins(&image),
+                Some(PageCrop::Margins(margins)) => Some(margins.clamped()),
+                None => None,
             };
-            if result_tx.send(response).is_err() {
-                break;
+            if let Some(margins) = margins {
+                image = crop::crop(&image, margins);
+                for c in &mut text.chars {
+                    c.rect = margins.map_rect(c.rect);
+                }
+                for i in &mut images {
+                    i.rect = margins.map_rect(i.rect);
+                }
+            }
+            if !native_colors {
+                theme::recolor(&mut image, req.colors);
             }
+            RenderResponse::Ok(Box::new(RenderResult {
+                text,
+                images,
+                book_hash: req.book_hash,
+                page: page_index + 1,
+                dpi: req.dpi,
+                total_pages,
+                page_sections,
+                page_positions,
+                typography: req.typography,
+                moved_from,
+                colors: req.colors,
+                rotation: req.rotation,
+                crop: req.crop,
+                reflow: req.reflow,
+                page_size: [
+                    image.width() as f32 / req.zoom,
+                    image.height() as f32 / req.zoom,
+                ],
+                page_sizes,
+                image,
+            }))
         }
-    });
+        Err(e) => RenderResponse::Err(format!("Ошибка рендеринга: {}", e)),
+    }
+}
 
-    (render_tx, result_rx)
+fn run_annotation_job(registry: &RendererRegistry, job: AnnotationJob) -> AnnotationResult {
+    let format = match &job {
+        AnnotationJob::Export { format, .. } | AnnotationJob::Import { format, .. } => *format,
+    };
+    let Some(renderer) = registry.get(&format) else {
+        return AnnotationResult::Failed(format!(
+            "Формат {} не поддерживается",
+            format.display_name()
+        ));
+    };
+
+    let result = match job {
+        AnnotationJob::Export {
+            bytes,
+            markups,
+            path,
+            ..
+        } => renderer
+            .write_markups(&bytes, &markups)
+            .and_then(|annotated| {
+                std::fs::write(&path, annotated)
+                    .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))
+            })
+            .map(|()| AnnotationResult::Exported(path)),
+        AnnotationJob::Import {
+            book_hash, sources, ..
+        } => sources
+            .iter()
+            .map(|bytes| renderer.read_markups(bytes))
+            .try_fold(DocumentMarkups::default(), |mut all, read| {
+                let read = read?;
+                all.markups.extend(read.markups);
+                all.notes.extend(read.notes);
+                anyhow::Ok(all)
+            })
+            .map(|markups| AnnotationResult::Imported { book_hash, markups }),
+    };
+    result.unwrap_or_else(|e| AnnotationResult::Failed(e.to_string()))
 }
@@ -0,0 +1,69 @@
+use crate::library::progress::Rotation;
+
+use super::book_manager;
+use super::highlight_manager;
+use super::render_manager;
+use super::scroll_manager;
+use super::DocReaderApp;
+
+/// How the pages of the open book are turned. Only pages of fixed-layout
+/// books can be turned.
+pub(crate) fn book_rotation(app: &DocReaderApp) -> Option<&Rotation> {
+    book_manager::page_view(app)?;
+    app.selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get(hash))
+        .and_then(|book| book.rotation.as_ref())
+}
+
+/// Quarter turns clockwise `page` of the open book is shown at.
+pub(crate) fn page_rotation(app: &DocReaderApp, page: u32) -> u8 {
+    book_rotation(app).map_or(0, |rotation| rotation.for_page(page))
+}
+
+/// Turns the current page by `quarter_turns` quarter turns clockwise.
+pub(crate) fn rotate_page(app: &mut DocReaderApp, quarter_turns: u8) {
+    let page = app.current_page;
+    update(app, |rotation| rotation.rotate_page(page, quarter_turns));
+}
+
+/// Turns every page by `quarter_turns` quarter turns clockwise.
+pub(crate) fn rotate_document(app: &mut DocReaderApp, quarter_turns: u8) {
+    update(app, |rotation| rotation.rotate_document(quarter_turns));
+}
+
+/// Shows every page upright again.
+pub(crate) fn reset_rotation(app: &mut DocReaderApp) {
+    update(app, |rotation| *rotation = Rotation::default());
+}
+
+/// Changes how the open book's pages are turned and remembers it. Turned
+/// pages are rendered again; pages turned sideways swap width and height.
+fn update(app: &mut DocReaderApp, change: impl FnOnce(&mut Rotation)) {
+    if book_manager::page_view(app).is_none() {
+        return;
+    }
+    let Some(hash) = app.selected_book_hash.clone() else {
+        return;
+    };
+    let before = book_rotation(app).cloned().unwrap_or_default();
+    let mut rotation = before.clone();
+    change(&mut rotation);
+    if rotation == before {
+        return;
+    }
+
+    for (index, size) in app.current_page_sizes.iter_mut().enumerate() {
+        let page = index as u32 + 1;
+        if (before.for_page(page) + rotation.for_page(page)) % 2 == 1 {
+            *size = egui::vec2(size.y, size.x);
+        }
+    }
+    let rotation = (!rotation.is_none()).then_some(rotation);
+    app.progress
+        .update_book_view(&hash, |bp| bp.rotation = rotation);
+    app.needs_save = true;
+    highlight_manager::clear_page_text(app);
+    scroll_manager::hold_position(app);
+    render_manager::request_render(app);
+}
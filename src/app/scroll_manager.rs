@@ -0,0 +1,165 @@
+use egui::Ui;
+
+use crate::config::constants::CONTINUOUS_OFFSET_EPSILON;
+use crate::library::annotations::Highlight;
+use crate::library::progress::ScrollPosition;
+use crate::ui::document_viewer::{ContinuousView, DocumentViewer};
+use crate::ui::toolbar::PageView;
+
+use super::book_manager;
+use super::highlight_manager;
+use super::kosync_manager;
+use super::render_manager;
+use super::rotation_manager;
+use super::spread_manager;
+use super::DocReaderApp;
+
+pub(crate) fn is_continuous(app: &DocReaderApp) -> bool {
+    book_manager::page_view(app) == Some(PageView::Continuous)
+}
+
+fn book_scroll(app: &DocReaderApp) -> Option<ScrollPosition> {
+    app.selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get(hash))
+        .and_then(|book| book.scroll)
+}
+
+/// Switches the open book between page by page and continuous scroll,
+/// starting at the top of the current page. Continuous scroll replaces
+/// spreads.
+pub(crate) fn toggle_continuous(app: &mut DocReaderApp) {
+    let Some(view) = book_manager::page_view(app) else {
+        return;
+    };
+    let Some(hash) = app.selected_book_hash.clone() else {
+        return;
+    };
+    let scroll = (view != PageView::Continuous).then_some(ScrollPosition { page_offset: 0.0 });
+    app.progress.update_book_view(&hash, |bp| {
+        bp.scroll = scroll;
+        if scroll.is_some() {
+            bp.spread = None;
+        }
+    });
+    app.needs_save = true;
+    highlight_manager::clear_page_text(app);
+    restore_scroll(app);
+    render_manager::request_render(app);
+}
+
+/// Scrolls back to where the open book was left in continuous mode.
+pub(crate) fn restore_scroll(app: &mut DocReaderApp) {
+    app.scroll_to = book_scroll(app).map(|scroll| (app.current_page, scroll.page_offset));
+    app.continuous_pages = app.current_page..=app.current_page;
+}
+
+/// Scrolls to the top of `page` after it was chosen by the reader.
+pub(crate) fn scroll_to_page(app: &mut DocReaderApp, page: u32) {
+    if !is_continuous(app) {
+        return;
+    }
+    app.scroll_to = Some((page, 0.0));
+    if let Some(hash) = &app.selected_book_hash {
+        app.progress.update_book_scroll(hash, 0.0);
+    }
+}
+
+/// Keeps the current place in view while the pages before it change size.
+pub(crate) fn hold_position(app: &mut DocReaderApp) {
+    if is_continuous(app) && app.scroll_to.is_none() {
+        let offset = book_scroll(app).map_or(0.0, |s| s.page_offset);
+        app.scroll_to = Some((app.current_page, offset));
+    }
+}
+
+/// Pages to render: those shown with the current one first, then in
+/// continuous mode the others in view or close to it, nearest first.
+pub(crate) fn pages_to_render(app: &DocReaderApp) -> Vec<u32> {
+    let mut pages = spread_manager::spread_pages(app);
+    if is_continuous(app) {
+        let mut others: Vec<u32> = app
+            .continuous_pages
+            .clone()
+            .filter(|&page| page != app.current_page)
+            .collect();
+        others.sort_by_key(|page| page.abs_diff(app.current_page));
+        pages.extend(others);
+    }
+    pages
+}
+
+/// Shows the open book as one scroll of pages. The page at the top of the
+/// viewer becomes the current page. Returns `false` while the page sizes
+/// needed for the layout are not known.
+pub(crate) fn show_continuous(app: &mut DocReaderApp, ui: &mut Ui) -> bool {
+    let Some(hash) = app.selected_book_hash.clone() else {
+        return false;
+    };
+    if app.current_page_sizes.is_empty() {
+        return false;
+    }
+    // Pages grew or shrank: keep the same place in view
+    if app.zoom != app.scroll_zoom {
+        app.scroll_zoom = app.zoom;
+        hold_position(app);
+    }
+
+    let dpi = render_manager::page_dpi(app);
+    let colors = app.settings.theme.colors();
+    let rotation = rotation_manager::book_rotation(app)
+        .cloned()
+        .unwrap_or_default();
+    let highlights: Vec<&Highlight> = app
+        .progress
+        .books
+        .get(&hash)
+        .map(|book| book.highlights().collect())
+        .unwrap_or_default();
+    let page_cache = &mut app.page_cache;
+    let view = DocumentViewer::show_continuous(
+        ui,
+        &app.current_page_sizes,
+        app.zoom,
+        app.scroll_to,
+        &highlights,
+        &mut |page| {
+            page_cache
+                .get(&hash, page, dpi, colors, rotation.for_page(page))
+                .cloned()
+        },
+    );
+    if let Some(view) = view {
+        track_scroll(app, &hash, view);
+    }
+    true
+}
+
+fn track_scroll(app: &mut DocReaderApp, hash: &str, view: ContinuousView) {
+    app.scroll_to = None;
+    let mut render = false;
+
+    if view.page != app.current_page {
+        app.current_page = view.page;
+        app.page_input = view.page.to_string();
+        app.progress.update_book_progress(hash, view.page);
+        app.needs_save = true;
+        highlight_manager::clear_page_text(app);
+        kosync_manager::mark_dirty(app);
+        render = true;
+    }
+
+    let saved = book_scroll(app).map_or(0.0, |s| s.page_offset);
+    if render || (view.offset - saved).abs() > CONTINUOUS_OFFSET_EPSILON {
+        app.progress.update_book_scroll(hash, view.offset);
+        app.needs_save = true;
+    }
+
+    if view.pages != app.continuous_pages {
+        app.continuous_pages = view.pages;
+        render = true;
+    }
+    if render {
+        render_manager::request_render(app);
+    }
+}
@@ -1,11 +1,21 @@
 use egui::Context;
+use once_cell::sync::Lazy;
+use std::collections::BTreeSet;
 use std::path::PathBuf;
 
-use crate::library::progress::ReadingProgress;
-use crate::sync::storage::ProgressStorage;
-use crate::sync::watcher::SyncWatcher;
+use crate::config::constants::*;
+use crate::config::roaming;
+use crate::config::settings::{AppSettings, ReadingTheme, SyncBackendSettings};
+use crate::renderer::fonts::SYSTEM_FONTS;
+
+use crate::sync::kosync;
 
 use super::book_manager;
+use super::kosync_manager;
+use super::progress_manager;
+use super::reflow_manager;
+use super::theme_manager;
+use super::typography_manager;
 use super::DocReaderApp;
 
 pub(crate) fn show_settings_window(app: &mut DocReaderApp, ctx: &Context) {
@@ -19,10 +29,180 @@ pub(crate) fn show_settings_window(app: &mut DocReaderApp, ctx: &Context) {
             });
 
             ui.horizontal(|ui| {
-                ui.label("Файл прогресса:");
-                ui.text_edit_singleline(&mut app.settings_progress_path);
+                ui.label("Синхронизация:");
+                egui::ComboBox::from_id_salt("sync_backend")
+                    .selected_text(app.settings_sync_backend.display_name())
+                    .show_ui(ui, |ui| {
+                        let is_local =
+                            matches!(app.settings_sync_backend, SyncBackendSettings::LocalFile);
+                        if ui
+                            .selectable_label(
+                                is_local,
+                                SyncBackendSettings::LocalFile.display_name(),
+                            )
+                            .clicked()
+                            && !is_local
+                        {
+                            app.settings_sync_backend = SyncBackendSettings::LocalFile;
+                        }
+                        let is_webdav = matches!(
+                            app.settings_sync_backend,
+                            SyncBackendSettings::WebDav { .. }
+                        );
+                        if ui.selectable_label(is_webdav, "WebDAV").clicked() && !is_webdav {
+                            app.settings_sync_backend = SyncBackendSettings::WebDav {
+                                url: String::new(),
+                                username: String::new(),
+                                password: String::new(),
+                            };
+                        }
+                        let is_s3 =
+                            matches!(app.settings_sync_backend, SyncBackendSettings::S3 { .. });
+                        if ui.selectable_label(is_s3, "S3").clicked() && !is_s3 {
+                            app.settings_sync_backend = SyncBackendSettings::S3 {
+                                endpoint: String::new(),
+                                bucket: String::new(),
+                                prefix: String::new(),
+                                region: S3_DEFAULT_REGION.to_string(),
+                                access_key: String::new(),
+                                secret_key: String::new(),
+                            };
+                        }
+                    });
+            });
+
+            match &mut app.settings_sync_backend {
+                SyncBackendSettings::LocalFile => {
+                    ui.horizontal(|ui| {
+                        ui.label("Файл прогресса:");
+                        ui.text_edit_singleline(&mut app.settings_progress_path);
+                    });
+                }
+                SyncBackendSettings::WebDav {
+                    url,
+                    username,
+                    password,
+                } => {
+                    ui.horizontal(|ui| {
+                        ui.label("Адрес папки WebDAV:");
+                        ui.text_edit_singleline(url);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Логин:");
+                        ui.text_edit_singleline(username);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Пароль:");
+                        ui.add(egui::TextEdit::singleline(password).password(true));
+                    });
+                }
+                SyncBackendSettings::S3 {
+                    endpoint,
+                    bucket,
+                    prefix,
+                    region,
+                    access_key,
+                    secret_key,
+                } => {
+                    ui.horizontal(|ui| {
+                        ui.label("Адрес сервера:");
+                        ui.text_edit_singleline(endpoint);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Бакет:");
+                        ui.text_edit_singleline(bucket);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Префикс:");
+                        ui.text_edit_singleline(prefix);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Регион:");
+                        ui.text_edit_singleline(region);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Access key:");
+                        ui.text_edit_singleline(access_key);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Secret key:");
+                        ui.add(egui::TextEdit::singleline(secret_key).password(true));
+                    });
+                }
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Парольная фраза шифрования:");
+                ui.add(
+                    egui::TextEdit::singleline(&mut app.settings_passphrase)
+                        .password(true)
+                        .hint_text("без шифрования"),
+                );
             });
+            if let Some(err) = &app.progress_read_only {
+                ui.colored_label(egui::Color32::RED, err);
+            }
 
+            ui.horizontal(|ui| {
+                ui.label("Текущее хранилище:");
+                ui.label(&app.storage_description);
+            });
+
+            ui.separator();
+            ui.checkbox(&mut app.settings_kosync.enabled, "Синхронизация с KOReader");
+            if app.settings_kosync.enabled {
+                ui.horizontal(|ui| {
+                    ui.label("Сервер kosync:");
+                    ui.text_edit_singleline(&mut app.settings_kosync.server);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Логин:");
+                    ui.text_edit_singleline(&mut app.settings_kosync.username);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Пароль:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut app.settings_kosync_password)
+                            .password(true)
+                            .hint_text(if app.settings_kosync.userkey.is_empty() {
+                                ""
+                            } else {
+                                "сохранён"
+                            }),
+                    );
+                });
+            }
+            ui.separator();
+
+            ui.label("Общие настройки синхронизируются между устройствами:");
+            ui.horizontal(|ui| {
+                ui.label("Автосохранение, с:");
+                ui.add(
+                    egui::DragValue::new(&mut app.settings_auto_save_interval)
+                        .range(AUTO_SAVE_INTERVAL_SECS_MIN..=AUTO_SAVE_INTERVAL_SECS_MAX),
+                );
+                local_override_checkbox(
+                    ui,
+                    &mut app.settings_local_overrides,
+                    roaming::AUTO_SAVE_INTERVAL,
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label(format!("Масштаб: {:.0}%", app.settings.zoom_level * 100.0));
+                local_override_checkbox(ui, &mut app.settings_local_overrides, roaming::ZOOM_LEVEL);
+            });
+            ui.separator();
+
+            theme_settings(ui, app);
+            ui.separator();
+
+            typography_settings(ui, app);
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Имя устройства:");
+                ui.text_edit_singleline(&mut app.settings_device_name);
+            });
             ui.horizontal(|ui| {
                 ui.label("ID устройства:");
                 ui.label(&app.settings.device_id);
@@ -31,18 +211,59 @@ pub(crate) fn show_settings_window(app: &mut DocReaderApp, ctx: &Context) {
             ui.separator();
 
             if ui.button("Сохранить настройки").clicked() {
+                let storage_changed = app.settings.sync_backend != app.settings_sync_backend
+                    || app.settings.progress_file_path.as_path()
+                        != std::path::Path::new(&app.settings_progress_path);
                 app.settings.library_path = PathBuf::from(&app.settings_library_path);
                 app.settings.progress_file_path = PathBuf::from(&app.settings_progress_path);
+                app.settings.sync_backend = app.settings_sync_backend.clone();
+                if !app.settings_kosync_password.is_empty() {
+                    app.settings_kosync.userkey =
+                        kosync::hash_password(&app.settings_kosync_password);
+                    app.settings_kosync_password.clear();
+                }
+                let passphrase_changed =
+                    app.settings.encryption_passphrase != app.settings_passphrase;
+                app.settings.encryption_passphrase = app.settings_passphrase.clone();
+                let kosync_changed = app.settings.kosync != app.settings_kosync;
+                app.settings.kosync = app.settings_kosync.clone();
+                let device_name = app.settings_device_name.trim().to_string();
+                let device_renamed = app.settings.device_name != device_name;
+                app.settings.device_name = device_name;
+                let theme_changed = app.settings.theme != app.settings_theme;
+                app.settings.theme = app.settings_theme;
 
                 if let Err(e) = app.settings.save() {
                     app.error_message = Some(format!("Ошибка сохранения настроек: {}", e));
                 } else {
-                    app.storage = ProgressStorage::new(app.settings.progress_file_path.clone());
-                    app.progress = app
-                        .storage
-                        .load_or_create(&app.settings.device_id)
-                        .unwrap_or_else(|_| ReadingProgress::new(app.settings.device_id.clone()));
-                    app.watcher = SyncWatcher::new(&app.settings.progress_file_path).ok();
+                    if passphrase_changed && !storage_changed {
+                        progress_manager::change_passphrase(app);
+                    } else {
+                        progress_manager::reopen_storage(app);
+                    }
+                    if kosync_changed {
+                        kosync_manager::restart(app);
+                    }
+                    app.settings.auto_save_interval_secs = app.settings_auto_save_interval;
+                    typography_manager::save_typography(app);
+                    if theme_changed {
+                        theme_manager::apply_theme(app, ctx);
+                    }
+                    // Settings no longer overridden take the roaming value,
+                    // values edited just now are published instead
+                    let released = app
+                        .settings
+                        .local_overrides
+                        .difference(&app.settings_local_overrides)
+                        .cloned()
+                        .collect();
+                    app.settings.local_overrides = app.settings_local_overrides.clone();
+                    progress_manager::release_overrides(app, ctx, &released);
+                    progress_manager::record_preferences(app);
+                    if device_renamed {
+                        app.needs_save = true;
+                    }
+                    let _ = app.settings.save();
                     book_manager::rescan_library(app);
                     app.show_settings = false;
                 }
@@ -54,3 +275,155 @@ pub(crate) fn show_settings_window(app: &mut DocReaderApp, ctx: &Context) {
         });
     app.show_settings = show;
 }
+
+/// Loads the settings to edit when the dialog is opened; roaming ones may
+/// have come from another device since.
+pub(crate) fn refresh(app: &mut DocReaderApp) {
+    app.settings_auto_save_interval = app.settings.auto_save_interval_secs;
+    app.settings_local_overrides = app.settings.local_overrides.clone();
+    app.settings_theme = app.settings.theme;
+    typography_manager::edit_typography(app);
+}
+
+/// Colours pages are shown in.
+fn theme_settings(ui: &mut egui::Ui, app: &mut DocReaderApp) {
+    let theme = &mut app.settings_theme;
+    ui.horizontal(|ui| {
+        ui.label("Тема:");
+        for option in ReadingTheme::ALL {
+            ui.radio_value(&mut theme.theme, option, option.display_name());
+        }
+        local_override_checkbox(ui, &mut app.settings_local_overrides, roaming::THEME);
+    });
+    if theme.theme == ReadingTheme::Custom {
+        ui.horizontal(|ui| {
+            ui.label("Текст:");
+            ui.color_edit_button_srgb(&mut theme.custom.foreground);
+            ui.label("Фон:");
+            ui.color_edit_button_srgb(&mut theme.custom.background);
+        });
+    }
+}
+
+/// Typography of EPUB and FB2 books and reflowed PDFs, for all of them or
+/// the open one.
+fn typography_settings(ui: &mut egui::Ui, app: &mut DocReaderApp) {
+    ui.label("Текст EPUB, FB2 и перекомпонованных PDF:");
+    if reflow_manager::reflows(app) {
+        ui.horizontal(|ui| {
+            if ui
+                .radio_value(&mut app.settings_book_typography, false, "Для всех книг")
+                .clicked()
+            {
+                app.settings_typography = app.settings.typography.clone();
+            }
+            ui.radio_value(&mut app.settings_book_typography, true, "Для этой книги");
+        });
+    }
+
+    // Settings of one book travel with its progress anyway
+    let mut overrides =
+        (!app.settings_book_typography).then_some(&mut app.settings_local_overrides);
+    let mut local_override = |ui: &mut egui::Ui, key: &str| {
+        if let Some(overrides) = overrides.as_deref_mut() {
+            local_override_checkbox(ui, overrides, key);
+        }
+    };
+    let typography = &mut app.settings_typography;
+    ui.horizontal(|ui| {
+        ui.label("Шрифт:");
+        let family = &mut typography.font_family;
+        let selected = family
+            .clone()
+            .unwrap_or_else(|| EMBEDDED_FONT_NAME.to_string());
+        egui::ComboBox::from_id_salt("font_family")
+            .selected_text(selected)
+            .height(FONT_PICKER_HEIGHT)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(family, None, EMBEDDED_FONT_NAME);
+                // Fonts are scanned in the background at startup
+                match Lazy::get(&SYSTEM_FONTS) {
+                    Some(library) => {
+                        for name in library.families() {
+                            ui.selectable_value(family, Some(name.clone()), name);
+                        }
+                    }
+                    None => {
+                        ui.label("Поиск шрифтов…");
+                    }
+                }
+            });
+        local_override(ui, roaming::READING_FONT);
+    });
+    if let Ok(dir) = AppSettings::fonts_dir() {
+        ui.label(format!("Папка своих шрифтов: {}", dir.display()))
+            .on_hover_text("Шрифты .ttf и .otf из этой папки появятся в списке после перезапуска");
+    }
+    ui.horizontal(|ui| {
+        ui.label("Размер шрифта:");
+        ui.add(
+            egui::DragValue::new(&mut typography.font_size)
+                .range(TEXT_FONT_SIZE_MIN..=TEXT_FONT_SIZE_MAX)
+                .speed(0.5),
+        );
+        ui.label("Межстрочный интервал:");
+        ui.add(
+            egui::DragValue::new(&mut typography.line_spacing)
+                .range(TEXT_LINE_SPACING_MIN..=TEXT_LINE_SPACING_MAX)
+                .speed(0.05)
+                .fixed_decimals(2),
+        );
+    });
+    ui.horizontal(|ui| {
+        ui.label("Отступ между абзацами, строк:");
+        ui.add(
+            egui::DragValue::new(&mut typography.paragraph_spacing)
+                .range(0.0..=TEXT_PARAGRAPH_SPACING_MAX)
+                .speed(0.05)
+                .fixed_decimals(2),
+        );
+        ui.label("Поля:");
+        ui.add(egui::DragValue::new(&mut typography.margin).range(0..=TEXT_PAGE_MARGIN_MAX));
+    });
+    ui.checkbox(&mut typography.fit_window, "Страница по размеру окна");
+    ui.add_enabled_ui(!typography.fit_window, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Размер страницы:");
+            ui.add(
+                egui::DragValue::new(&mut typography.page_width)
+                    .range(TEXT_PAGE_SIZE_MIN..=TEXT_PAGE_SIZE_MAX),
+            );
+            ui.label("×");
+            ui.add(
+                egui::DragValue::new(&mut typography.page_height)
+                    .range(TEXT_PAGE_SIZE_MIN..=TEXT_PAGE_SIZE_MAX),
+            );
+        });
+    });
+    ui.horizontal(|ui| {
+        ui.label("Размеры, интервалы и поля:");
+        local_override(ui, roaming::TEXT_LAYOUT);
+    });
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut typography.hyphenation, "Переносы слов");
+        local_override(ui, roaming::HYPHENATION);
+    });
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut typography.justify, "Выравнивание по ширине");
+        local_override(ui, roaming::JUSTIFY);
+    });
+}
+
+fn local_override_checkbox(ui: &mut egui::Ui, overrides: &mut BTreeSet<String>, key: &str) {
+    let mut local = overrides.contains(key);
+    if ui
+        .checkbox(&mut local, "только на этом устройстве")
+        .changed()
+    {
+        if local {
+            overrides.insert(key.to_string());
+        } else {
+            overrides.remove(key);
+        }
+    }
+}
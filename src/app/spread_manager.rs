@@ -0,0 +1,99 @@
+use egui::Ui;
+
+use crate::library::annotations::Highlight;
+use crate::library::progress::Spread;
+use crate::renderer::cache::CachedPage;
+use crate::ui::document_viewer::DocumentViewer;
+use crate::ui::toolbar::PageView;
+
+use super::book_manager;
+use super::highlight_manager;
+use super::render_manager;
+use super::rotation_manager;
+use super::DocReaderApp;
+
+/// Spread settings of the open book, if it is shown in two-page spreads.
+pub(crate) fn book_spread(app: &DocReaderApp) -> Option<Spread> {
+    match book_manager::page_view(app) {
+        Some(PageView::Spread(spread)) => Some(spread),
+        _ => None,
+    }
+}
+
+/// Pages shown together with the current one, which comes first.
+pub(crate) fn spread_pages(app: &DocReaderApp) -> Vec<u32> {
+    let total_pages = app.selected_book().map_or(0, |b| b.total_pages);
+    match book_spread(app) {
+        Some(spread) => spread.pages(app.current_page, total_pages),
+        None => vec![app.current_page],
+    }
+}
+
+/// Page the spread holding `page` starts on: the page that is current
+/// while the spread is shown.
+pub(crate) fn first_page(app: &DocReaderApp, page: u32) -> u32 {
+    book_spread(app).map_or(page, |spread| spread.first_page(page))
+}
+
+/// Whether pages go from right to left, so that the arrow keys swap.
+pub(crate) fn is_rtl(app: &DocReaderApp) -> bool {
+    book_spread(app).is_some_and(|spread| spread.rtl)
+}
+
+/// Shows the open book in spreads, or in single pages for `None`.
+/// Spreads replace continuous scroll.
+pub(crate) fn set_spread(app: &mut DocReaderApp, spread: Option<Spread>) {
+    if book_manager::page_view(app).is_none() {
+        return;
+    }
+    let Some(hash) = app.selected_book_hash.clone() else {
+        return;
+    };
+    app.progress.update_book_view(&hash, |bp| {
+        bp.spread = spread;
+        if spread.is_some() {
+            bp.scroll = None;
+        }
+    });
+    app.needs_save = true;
+    highlight_manager::clear_page_text(app);
+
+    // Current page moves to the start of its spread
+    book_manager::go_to_page(app, app.current_page);
+    render_manager::request_render(app);
+}
+
+/// Shows the pages of the current spread side by side.
+pub(crate) fn show_spread(app: &mut DocReaderApp, ui: &mut Ui) {
+    let Some(spread) = book_spread(app) else {
+        return;
+    };
+    let Some(hash) = app.selected_book_hash.clone() else {
+        return;
+    };
+
+    let dpi = render_manager::page_dpi(app);
+    let colors = app.settings.theme.colors();
+    let mut pages = spread_pages(app);
+    if spread.rtl {
+        pages.reverse();
+    }
+    let pages: Vec<(u32, Option<CachedPage>)> = pages
+        .into_iter()
+        .map(|page| {
+            let rotation = rotation_manager::page_rotation(app, page);
+            let cached = app
+                .page_cache
+                .get(&hash, page, dpi, colors, rotation)
+                .cloned();
+            (page, cached)
+        })
+        .collect();
+    let highlights: Vec<&Highlight> = app
+        .progress
+        .books
+        .get(&hash)
+        .map(|book| book.highlights().collect())
+        .unwrap_or_default();
+    DocumentViewer::show_spread(ui, &pages, &app.current_page_sizes, app.zoom, &highlights);
+}
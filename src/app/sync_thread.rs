@@ -0,0 +1,219 @@
+use anyhow::Result;
+use std::sync::mpsc;
+
+use crate::config::constants::SYNC_SAVE_MAX_RETRIES;
+use crate::library::migration::{self, SchemaError};
+use crate::library::progress::ReadingProgress;
+use crate::sync::backend::SyncError;
+use crate::sync::backup::Snapshot;
+use crate::sync::crypto::CryptoError;
+use crate::sync::merge::ProgressMerger;
+use crate::sync::storage::ProgressStorage;
+
+pub(crate) const NEWER_SCHEMA_MESSAGE: &str =
+    "Файл прогресса создан более новой версией программы: сохранение отключено";
+
+pub(crate) enum SyncRequest {
+    /// Load the progress, creating it when there is none.
+    Load {
+        device_id: String,
+    },
+    /// Load the remote progress if another device changed it.
+    Pull,
+    Save(Box<ReadingProgress>),
+    /// Overwrite this device's progress, e.g. to re-encrypt it with a new
+    /// passphrase.
+    Replace(Box<ReadingProgress>),
+    /// Delete the progress object of a retired device, then save.
+    Retire {
+        device_id: String,
+        progress: Box<ReadingProgress>,
+    },
+    Snapshots,
+    ReadSnapshot(Snapshot),
+    /// Make a snapshot the progress of this device, snapshotting the
+    /// current progress first so the restore can be undone.
+    Restore {
+        snapshot: Snapshot,
+        current: Box<ReadingProgress>,
+    },
+}
+
+pub(crate) enum SyncResponse {
+    /// The progress, and why it must not be written if it must not.
+    Loaded(Box<ReadingProgress>, Option<String>),
+    Pulled(Box<ReadingProgress>),
+    /// Saved; carries the remote progress merged in first when another
+    /// device wrote since we last read.
+    Saved(Option<Box<ReadingProgress>>),
+    SaveFailed(String),
+    Snapshots(Vec<Snapshot>),
+    Snapshot(Snapshot, Result<Box<ReadingProgress>, String>),
+    Restored(Result<Box<ReadingProgress>, String>),
+}
+
+/// Starts the thread that owns the progress storage: every load and save
+/// goes through it, in the order requested.
+pub(crate) fn spawn_sync_thread(
+    storage: ProgressStorage,
+) -> (mpsc::Sender<SyncRequest>, mpsc::Receiver<SyncResponse>) {
+    let (request_tx, request_rx) = mpsc::channel::<SyncRequest>();
+    let (response_tx, response_rx) = mpsc::channel::<SyncResponse>();
+
+    std::thread::spawn(move || {
+        // Remote progress sent to the app but perhaps not merged into what
+        // it asks to save next
+        let mut pulled: Option<ReadingProgress> = None;
+        while let Ok(req) = request_rx.recv() {
+            let response = match req {
+                SyncRequest::Load { device_id } => {
+                    let (progress, read_only) = load_progress(&storage, &device_id);
+                    SyncResponse::Loaded(Box::new(progress), read_only)
+                }
+                SyncRequest::Pull => match storage.load_if_changed() {
+                    Ok(Some(remote)) => {
+                        pulled = Some(remote.clone());
+                        SyncResponse::Pulled(Box::new(remote))
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        eprintln!("Sync load error: {:#}", e);
+                        continue;
+                    }
+                },
+                SyncRequest::Save(mut progress) => {
+                    save_response(&storage, &mut progress, pulled.take())
+                }
+                SyncRequest::Replace(progress) => match storage.replace(&progress) {
+                    Ok(()) => SyncResponse::Saved(None),
+                    Err(e) => SyncResponse::SaveFailed(e.to_string()),
+                },
+                SyncRequest::Retire {
+                    device_id,
+                    mut progress,
+                } => match storage.remove_device(&device_id) {
+                    Ok(()) => save_response(&storage, &mut progress, pulled.take()),
+                    Err(e) => SyncResponse::SaveFailed(e.to_string()),
+                },
+                SyncRequest::Snapshots => {
+                    SyncResponse::Snapshots(storage.snapshots().unwrap_or_default())
+                }
+                SyncRequest::ReadSnapshot(snapshot) => {
+                    let progress = storage
+                        .read_snapshot(&snapshot)
+                        .map(Box::new)
+                        .map_err(|e| format!("{:#}", e));
+                    SyncResponse::Snapshot(snapshot, progress)
+                }
+                SyncRequest::Restore { snapshot, current } => SyncResponse::Restored(
+                    restore(&storage, &snapshot, &current)
+                        .map(Box::new)
+                        .map_err(|e| e.to_string()),
+                ),
+            };
+            if response_tx.send(response).is_err() {
+                break;
+            }
+        }
+    });
+
+    (request_tx, response_rx)
+}
+
+/// Loads progress from storage. The second value is set when the file must
+/// not be written (it could not be decrypted or comes from a newer schema);
+/// saving stays off until that is resolved.
+fn load_progress(storage: &ProgressStorage, device_id: &str) -> (ReadingProgress, Option<String>) {
+    match storage.load_or_create(device_id) {
+        Ok(progress) => {
+            let read_only = migration::ensure_writable(&progress)
+                .err()
+                .map(|_| NEWER_SCHEMA_MESSAGE.to_string());
+            (progress, read_only)
+        }
+        Err(e) => {
+            let read_only = if let Some(err) = CryptoError::find(&e) {
+                Some(match err {
+                    CryptoError::WrongPassphrase => {
+                        "Неверная парольная фраза: прогресс не расшифрован".to_string()
+                    }
+                    CryptoError::PassphraseRequired => {
+                        "Прогресс зашифрован: укажите парольную фразу в настройках".to_string()
+                    }
+                    other => format!("Ошибка расшифровки прогресса: {}", other),
+                })
+            } else {
+                SchemaError::find(&e).map(|_| NEWER_SCHEMA_MESSAGE.to_string())
+            };
+            (ReadingProgress::new(device_id.to_string()), read_only)
+        }
+    }
+}
+
+/// Saves progress with the remote progress pulled last merged in, so a save
+/// asked for before the app merged it does not overwrite it.
+fn save_response(
+    storage: &ProgressStorage,
+    progress: &mut ReadingProgress,
+    pulled: Option<ReadingProgress>,
+) -> SyncResponse {
+    if let Some(remote) = pulled {
+        *progress = ProgressMerger::merge(progress, &remote);
+    }
+    match save_merging(storage, progress) {
+        Ok(remote) => SyncResponse::Saved(remote.map(Box::new)),
+        Err(e) => SyncResponse::SaveFailed(e.to_string()),
+    }
+}
+
+/// Saves progress, merging in remote changes when another device wrote
+/// first. Returns the remote progress that was merged in, if any.
+fn save_merging(
+    storage: &ProgressStorage,
+    progress: &mut ReadingProgress,
+) -> Result<Option<ReadingProgress>> {
+    let mut merged = None;
+    let mut attempt = 0;
+    loop {
+        if migration::ensure_writable(progress).is_err() {
+            // Merged with progress of a newer version, which the app turns
+            // saving off for once it merges it too
+            return Ok(merged);
+        }
+        match storage.save(progress) {
+            Ok(()) => return Ok(merged),
+            Err(e) if SyncError::is_precondition_failed(&e) && attempt < SYNC_SAVE_MAX_RETRIES => {
+                attempt += 1;
+                let remote = storage.load()?;
+                *progress = ProgressMerger::merge(progress, &remote);
+                merged = Some(remote);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Replaces the progress of this device with a snapshot of it.
+fn restore(
+    storage: &ProgressStorage,
+    snapshot: &Snapshot,
+    current: &ReadingProgress,
+) -> Result<ReadingProgress> {
+    let mut restored = storage.read_snapshot(snapshot)?;
+    migration::ensure_writable(&restored)?;
+    storage.snapshot_now(current)?;
+
+    // Restored positions must win the next merge with other devices
+    let now = chrono::Utc::now();
+    for (hash, book) in restored.books.iter_mut() {
+        let current_page = current.books.get(hash).map(|b| b.current_page);
+        if current_page != Some(book.current_page) {
+            book.last_read = now;
+        }
+    }
+    restored.device_id = current.device_id.clone();
+    restored.last_modified = now;
+
+    storage.replace(&restored)?;
+    Ok(restored)
+}
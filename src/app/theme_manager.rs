@@ -0,0 +1,21 @@
+use egui::{Context, Visuals};
+
+use super::render_manager;
+use super::DocReaderApp;
+
+/// Switches the window to dark or light visuals to match the page colours.
+pub(crate) fn apply_visuals(app: &DocReaderApp, ctx: &Context) {
+    let visuals = if app.settings.theme.colors().is_dark() {
+        Visuals::dark()
+    } else {
+        Visuals::light()
+    };
+    ctx.set_visuals(visuals);
+}
+
+/// Shows the open page in the colours of the current theme. Pages are
+/// cached per colours, so switching back is instant.
+pub(crate) fn apply_theme(app: &mut DocReaderApp, ctx: &Context) {
+    apply_visuals(app, ctx);
+    render_manager::request_render(app);
+}
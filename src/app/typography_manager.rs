@@ -0,0 +1,118 @@
+use egui::Vec2;
+use std::time::{Duration, Instant};
+
+use crate::config::constants::*;
+use crate::config::settings::TypographySettings;
+
+use super::reflow_manager;
+use super::render_manager;
+use super::DocReaderApp;
+
+/// Typography the open book is laid out with: its own settings if it has
+/// them, the global ones otherwise, with the page sized to the viewer if
+/// the settings ask for it.
+pub(crate) fn book_typography(app: &DocReaderApp) -> TypographySettings {
+    let mut typography = app
+        .selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get(hash))
+        .and_then(|book| book.typography.clone())
+        .unwrap_or_else(|| app.settings.typography.clone());
+    if typography.fit_window {
+        if let Some((width, height)) = fitted_page_size(app) {
+            typography.page_width = width;
+            typography.page_height = height;
+        }
+    }
+    typography
+}
+
+/// Page size that fills the viewer at the current zoom, rounded down to
+/// whole steps so that small resizes do not lay the book out again.
+fn fitted_page_size(app: &DocReaderApp) -> Option<(u32, u32)> {
+    let size = app.viewer_size;
+    if size.x <= 0.0 || size.y <= 0.0 {
+        return None;
+    }
+    let fit = |points: f32| {
+        let pixels = (points / app.zoom / TEXT_PAGE_SIZE_STEP).floor() * TEXT_PAGE_SIZE_STEP;
+        (pixels as u32).clamp(TEXT_PAGE_SIZE_MIN, TEXT_PAGE_SIZE_MAX)
+    };
+    Some((fit(size.x), fit(size.y)))
+}
+
+/// Records the size of the viewer. Once it has stayed put for a moment,
+/// lays the open book out again if its pages follow the window.
+pub(crate) fn track_viewer_size(app: &mut DocReaderApp, size: Vec2) {
+    if size != app.viewer_size {
+        app.viewer_size = size;
+        app.viewer_resized = Some(Instant::now());
+        return;
+    }
+    let Some(resized) = app.viewer_resized else {
+        return;
+    };
+    if resized.elapsed() < Duration::from_millis(TEXT_RELAYOUT_DELAY_MS) {
+        return;
+    }
+    app.viewer_resized = None;
+
+    if reflow_manager::reflows(app) && book_typography(app) != app.layout_typography {
+        relayout(app);
+    }
+}
+
+/// Renders the open book again with the current typography. The render
+/// thread keeps the reader on the page holding the same text.
+pub(crate) fn relayout(app: &mut DocReaderApp) {
+    app.page_cache.clear();
+    render_manager::request_render(app);
+}
+
+/// Hash of the open book if its typography can be set on its own.
+fn reflowable_book(app: &DocReaderApp) -> Option<String> {
+    app.selected_book()
+        .filter(|_| reflow_manager::reflows(app))
+        .map(|book| book.file_hash.clone())
+}
+
+/// Loads the typography to edit in the settings dialog: the open book's
+/// own settings if it has them, the global ones otherwise.
+pub(crate) fn edit_typography(app: &mut DocReaderApp) {
+    let own = reflowable_book(app)
+        .and_then(|hash| app.progress.books.get(&hash))
+        .and_then(|book| book.typography.clone());
+    app.settings_book_typography = own.is_some();
+    app.settings_typography = own.unwrap_or_else(|| app.settings.typography.clone());
+}
+
+/// Saves the typography edited in the settings dialog, for the open book
+/// alone or for all books, and lays the book out again if it changed.
+pub(crate) fn save_typography(app: &mut DocReaderApp) {
+    let before = book_typography(app);
+    let book = reflowable_book(app);
+    let edited = app.settings_typography.clone();
+    if let Some(hash) = book.as_ref().filter(|_| app.settings_book_typography) {
+        app.progress
+            .update_book_view(hash, |bp| bp.typography = Some(edited));
+        app.needs_save = true;
+    } else {
+        app.settings.typography = edited;
+        // The book follows the global settings again
+        if let Some(hash) = &book {
+            if app
+                .progress
+                .books
+                .get(hash)
+                .is_some_and(|b| b.typography.is_some())
+            {
+                app.progress
+                    .update_book_view(hash, |bp| bp.typography = None);
+                app.needs_save = true;
+            }
+        }
+    }
+    if book_typography(app) != before {
+        relayout(app);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::config::constants::*;
+use crate::config::settings::ZoomMode;
+use crate::library::progress::BookZoom;
+
+use super::progress_manager;
+use super::reflow_manager;
+use super::render_manager;
+use super::spread_manager;
+use super::typography_manager;
+use super::DocReaderApp;
+
+/// Takes the zoom the open book was last read at, or the default zoom
+/// for books without one.
+pub(crate) fn restore_book_zoom(app: &mut DocReaderApp) {
+    let saved = app
+        .selected_book_hash
+        .as_ref()
+        .and_then(|hash| app.progress.books.get(hash))
+        .and_then(|book| book.zoom);
+    let (mode, level) = match saved {
+        Some(zoom) => (zoom.mode, zoom.level),
+        None => (ZoomMode::Fixed, app.settings.zoom_level),
+    };
+    app.zoom_mode = mode;
+    app.zoom = level.clamp(ZOOM_MIN, ZOOM_MAX);
+}
+
+/// Sets the zoom chosen by the reader and remembers it for the open book.
+/// The level is also the default for books not opened yet.
+pub(crate) fn set_zoom(app: &mut DocReaderApp, mode: ZoomMode, level: f32) {
+    app.zoom_mode = mode;
+    let level = fit_level(app).unwrap_or(level).clamp(ZOOM_MIN, ZOOM_MAX);
+    apply_level(app, level);
+
+    if let Some(hash) = &app.selected_book_hash {
+        app.progress
+            .update_book_view(hash, |bp| bp.zoom = Some(BookZoom { mode, level }));
+        app.needs_save = true;
+    }
+    app.settings.zoom_level = level;
+    progress_manager::record_preferences(app);
+}
+
+/// Zoom at which the current page fits the viewer in the current mode,
+/// `None` for a fixed zoom or while the page size is not known.
+fn fit_level(app: &DocReaderApp) -> Option<f32> {
+    let mut page = app.current_page_size?;
+    // Pages of a spread are taken to be of the same size
+    if spread_manager::spread_pages(app).len() > 1 {
+        page.x *= 2.0;
+    }
+    // Pages laid out to the size of the window already fit it
+    if reflow_manager::reflows(app) && typography_manager::book_typography(app).fit_window {
+        return None;
+    }
+    let viewer = app.viewer_size - egui::Vec2::splat(ZOOM_FIT_MARGIN);
+    if page.x <= 0.0 || page.y <= 0.0 || viewer.x <= 0.0 || viewer.y <= 0.0 {
+        return None;
+    }
+    let width = viewer.x / page.x;
+    match app.zoom_mode {
+        ZoomMode::FitWidth => Some(width),
+        ZoomMode::FitPage => Some(width.min(viewer.y / page.y)),
+        ZoomMode::Fixed => None,
+    }
+}
+
+/// Fits the page to the viewer again in a fit mode, once a resize has
+/// settled or a page of another size is shown.
+pub(crate) fn update_fit(app: &mut DocReaderApp) {
+    if app.viewer_resized.is_some() {
+        return;
+    }
+    let Some(level) = fit_level(app) else {
+        return;
+    };
+    let level = level.clamp(ZOOM_MIN, ZOOM_MAX);
+    if (level - app.zoom).abs() > app.zoom * ZOOM_FIT_TOLERANCE {
+        apply_level(app, level);
+    }
+}
+
+fn apply_level(app: &mut DocReaderApp, level: f32) {
+    if level != app.zoom {
+        app.zoom = level;
+        app.page_cache.clear();
+        render_manager::request_render(app);
+    }
+}
@@ -16,17 +16,144 @@ pub const ZOOM_DEFAULT: f32 = 1.0;
 pub const ZOOM_MIN: f32 = 0.3;
 pub const ZOOM_MAX: f32 = 3.0;
 pub const ZOOM_STEP: f32 = 0.1;
+/// Room left beside a fitted page for the scroll bar, in points
+pub const ZOOM_FIT_MARGIN: f32 = 16.0;
+/// Relative change of a fitted zoom below which the page is not re-rendered
+pub const ZOOM_FIT_TOLERANCE: f32 = 0.01;
+
+// ── Непрерывная прокрутка ───────────────────────────────────────
+/// Space between pages, in points
+pub const CONTINUOUS_PAGE_GAP: f32 = 8.0;
+/// Share of the viewer height rendered ahead above and below it
+pub const CONTINUOUS_RENDER_MARGIN: f32 = 0.5;
+/// Most pages rendered at once, so they never push each other out of the cache
+pub const CONTINUOUS_MAX_PAGES: u32 = (PAGE_CACHE_CAPACITY / 2) as u32;
+/// Change of a page's size, in points, for which pages are laid out again
+pub const CONTINUOUS_SIZE_EPSILON: f32 = 1.0;
+/// Change of the offset into a page, in page heights, worth saving
+pub const CONTINUOUS_OFFSET_EPSILON: f32 = 0.001;
 
 // ── PDF рендеринг ───────────────────────────────────────────────
 pub const PDF_SCALE_MULTIPLIER: f32 = 1.5;
 
+// ── Перекомпоновка PDF ──────────────────────────────────────────
+/// Gap between lines, in median line heights, that starts a new paragraph
+pub const PDF_REFLOW_PARAGRAPH_GAP: f32 = 0.8;
+/// Height of a line, in median line heights, from which it is set as a heading
+pub const PDF_REFLOW_HEADING_SCALE: f32 = 1.3;
+/// Share of the paragraph width a line may fall short by without ending it
+pub const PDF_REFLOW_SHORT_LINE: f32 = 0.15;
+/// Width of an image, as a share of the page width, from which it is kept as a figure
+pub const PDF_REFLOW_FIGURE_MIN: f32 = 0.1;
+
+// ── Обрезка полей ───────────────────────────────────────────────
+/// Difference in brightness from the paper that counts as content
+pub const CROP_CONTENT_THRESHOLD: i32 = 40;
+/// Share of a row or column that must be content, so specks of scan noise are ignored
+pub const CROP_NOISE_SHARE: f32 = 0.002;
+/// Room left around the content, as a share of the page size
+pub const CROP_PADDING: f32 = 0.02;
+/// Largest margin cut off one side, as a share of the page size
+pub const CROP_MARGIN_MAX: f32 = 0.45;
+
 // ── Текстовый рендеринг (EPUB / FB2) ───────────────────────────
-pub const TEXT_PAGE_WIDTH: u32 = 800;
-pub const TEXT_PAGE_HEIGHT: u32 = 1100;
-pub const TEXT_PAGE_MARGIN: u32 = 40;
-pub const TEXT_FONT_SIZE: f32 = 20.0;
-pub const TEXT_LINE_HEIGHT: f32 = 28.0;
-pub const TEXT_PARAGRAPH_SPACING: f32 = 14.0;
+pub const TEXT_PAGE_WIDTH_DEFAULT: u32 = 800;
+pub const TEXT_PAGE_HEIGHT_DEFAULT: u32 = 1100;
+pub const TEXT_PAGE_SIZE_MIN: u32 = 200;
+pub const TEXT_PAGE_SIZE_MAX: u32 = 4000;
+pub const TEXT_PAGE_MARGIN_DEFAULT: u32 = 40;
+pub const TEXT_PAGE_MARGIN_MAX: u32 = 200;
+pub const TEXT_FONT_SIZE_DEFAULT: f32 = 20.0;
+pub const TEXT_FONT_SIZE_MIN: f32 = 8.0;
+pub const TEXT_FONT_SIZE_MAX: f32 = 72.0;
+/// Distance between baselines, in font sizes
+pub const TEXT_LINE_SPACING_DEFAULT: f32 = 1.4;
+pub const TEXT_LINE_SPACING_MIN: f32 = 1.0;
+pub const TEXT_LINE_SPACING_MAX: f32 = 3.0;
+/// Space after a paragraph, in lines
+pub const TEXT_PARAGRAPH_SPACING_DEFAULT: f32 = 0.5;
+pub const TEXT_PARAGRAPH_SPACING_MAX: f32 = 2.0;
+/// Viewer sizes are rounded down to this step for pages that follow the window
+pub const TEXT_PAGE_SIZE_STEP: f32 = 10.0;
+/// The viewer has to keep its new size this long before pages are laid out again
+pub const TEXT_RELAYOUT_DELAY_MS: u64 = 300;
+/// Font size of headings h1..h6 relative to body text
+pub const TEXT_HEADING_SCALES: [f32; 6] = [1.6, 1.4, 1.25, 1.1, 1.0, 1.0];
+/// Extra space above a heading that does not start a page
+pub const TEXT_HEADING_SPACING: f32 = 20.0;
+/// Left shift per level of quote nesting
+pub const TEXT_INDENT_STEP: f32 = 30.0;
+/// Epigraphs start this far into the text width
+pub const TEXT_EPIGRAPH_INDENT: f32 = 0.35;
+/// Images taller than this part of the text height get a page of their own
+pub const TEXT_IMAGE_OWN_PAGE: f32 = 0.6;
+/// Characters looked at to guess the language of a book without metadata
+pub const TEXT_LANGUAGE_SAMPLE: usize = 5000;
+/// Families tried in order for characters of these scripts (ISO 15924
+/// codes) that the reading font lacks
+pub const TEXT_FALLBACK_FONTS: &[(&[&str], &[&str])] = &[
+    (
+        &["Grek", "Cyrl", "Latn"],
+        &["Noto Sans", "Segoe UI", "Arial", "DejaVu Sans"],
+    ),
+    (
+        &["Hebr"],
+        &["Noto Sans Hebrew", "Segoe UI", "Arial", "DejaVu Sans"],
+    ),
+    (
+        &["Arab"],
+        &[
+            "Noto Naskh Arabic",
+            "Noto Sans Arabic",
+            "Segoe UI",
+            "Arial",
+            "DejaVu Sans",
+        ],
+    ),
+    (
+        &["Armn", "Geor"],
+        &[
+            "Noto Sans Armenian",
+            "Noto Sans Georgian",
+            "Sylfaen",
+            "DejaVu Sans",
+        ],
+    ),
+    (&["Deva"], &["Noto Sans Devanagari", "Nirmala UI", "Mangal"]),
+    (&["Thai"], &["Noto Sans Thai", "Leelawadee UI", "Tahoma"]),
+    (
+        &["Hani", "Hira", "Kana", "Bopo"],
+        &[
+            "Noto Sans CJK SC",
+            "Noto Sans CJK JP",
+            "Source Han Sans SC",
+            "Microsoft YaHei",
+            "Yu Gothic",
+            "MS Gothic",
+            "SimSun",
+            "PingFang SC",
+            "Hiragino Sans",
+            "WenQuanYi Micro Hei",
+        ],
+    ),
+    (
+        &["Hang"],
+        &["Noto Sans CJK KR", "Malgun Gothic", "Apple SD Gothic Neo"],
+    ),
+];
+/// Name of the embedded font in the font picker
+pub const EMBEDDED_FONT_NAME: &str = "Встроенный (Noto Sans)";
+/// Height of the font picker list
+pub const FONT_PICKER_HEIGHT: f32 = 300.0;
+/// Families tried for characters of any script, e.g. symbols
+pub const TEXT_FALLBACK_FONTS_ANY: &[&str] = &[
+    "Segoe UI Symbol",
+    "Noto Sans Symbols",
+    "Noto Sans Symbols 2",
+    "DejaVu Sans",
+    "Arial Unicode MS",
+    "Symbola",
+];
 
 // ── Кэш ─────────────────────────────────────────────────────────
 pub const PAGE_CACHE_CAPACITY: usize = 20;
@@ -37,6 +164,64 @@ pub const HASH_BUFFER_SIZE: usize = 256;
 pub const REPAINT_INTERVAL_MS: u64 = 100;
 pub const FILE_WATCHER_POLL_SECS: u64 = 2;
 pub const AUTO_SAVE_INTERVAL_SECS_DEFAULT: u64 = 5;
+pub const AUTO_SAVE_INTERVAL_SECS_MIN: u64 = 1;
+pub const AUTO_SAVE_INTERVAL_SECS_MAX: u64 = 300;
+pub const SYNC_POLL_INTERVAL_SECS: u64 = 30;
+
+// ── Сетевая синхронизация ───────────────────────────────────────
+pub const HTTP_TIMEOUT_SECS: u64 = 15;
+pub const SYNC_EXIT_TIMEOUT_SECS: u64 = 5;
+pub const SYNC_SAVE_MAX_RETRIES: u32 = 3;
+pub const S3_DEFAULT_REGION: &str = "us-east-1";
+
+// ── Шифрование прогресса (Argon2id) ─────────────────────────────
+pub const ENCRYPTION_KDF_MEMORY_KIB: u32 = 19456;
+pub const ENCRYPTION_KDF_ITERATIONS: u32 = 2;
+pub const ENCRYPTION_KDF_PARALLELISM: u32 = 1;
+/// Largest memory cost accepted from an envelope header, so a crafted file
+/// cannot make key derivation exhaust memory
+pub const ENCRYPTION_KDF_MAX_MEMORY_KIB: u32 = 64 * 1024;
+/// Largest number of passes accepted from an envelope header, so a crafted
+/// file cannot make key derivation run for hours
+pub const ENCRYPTION_KDF_MAX_ITERATIONS: u32 = 10;
+/// Largest number of lanes accepted from an envelope header
+pub const ENCRYPTION_KDF_MAX_PARALLELISM: u32 = 8;
+/// Derived keys kept at once, one per device object in the per-device layout
+pub const ENCRYPTION_KEY_CACHE_SIZE: usize = 16;
+
+// ── KOReader (kosync) ───────────────────────────────────────────
+pub const KOSYNC_DEFAULT_SERVER: &str = "https://sync.koreader.rocks";
+pub const KOSYNC_DEVICE_NAME: &str = "DocReader";
+pub const KOSYNC_PUSH_INTERVAL_SECS: u64 = 10;
+pub const KOSYNC_PULL_INTERVAL_SECS: u64 = 60;
+pub const KOSYNC_EXIT_TIMEOUT_SECS: u64 = 2;
+pub const KOREADER_DIGEST_SAMPLE_SIZE: usize = 1024;
+
+// ── Резервные копии прогресса ───────────────────────────────────
+pub const BACKUP_DIR_NAME: &str = "backups";
+pub const BACKUP_KEEP_COUNT: usize = 30;
+pub const BACKUP_INTERVAL_SECS: i64 = 600;
+
+// ── Выделения ───────────────────────────────────────────────────
+pub const HIGHLIGHT_ALPHA: u8 = 90;
+pub const SELECTION_COLOR: [u8; 4] = [80, 140, 255, 80];
+pub const HIGHLIGHT_POPUP_WIDTH: f32 = 260.0;
+pub const HIGHLIGHT_PREVIEW_CHARS: usize = 120;
+pub const HIGHLIGHTS_EXPORT_SUFFIX: &str = ".highlights";
+pub const PDF_ANNOTATED_SUFFIX: &str = ".annotated";
+/// Side of the sticky-note icon written next to a highlight with a note, in PDF points
+pub const PDF_NOTE_ICON_SIZE: f32 = 20.0;
+/// Highlight colour assumed when a PDF annotation has none
+pub const PDF_HIGHLIGHT_DEFAULT_RGB: [u8; 3] = [255, 255, 0];
+
+// ── Темы оформления ─────────────────────────────────────────────
+/// Text and background colours of the built-in reading themes
+pub const THEME_DAY_FOREGROUND: [u8; 3] = [0, 0, 0];
+pub const THEME_DAY_BACKGROUND: [u8; 3] = [255, 255, 255];
+pub const THEME_NIGHT_FOREGROUND: [u8; 3] = [200, 200, 200];
+pub const THEME_NIGHT_BACKGROUND: [u8; 3] = [28, 28, 30];
+pub const THEME_SEPIA_FOREGROUND: [u8; 3] = [91, 70, 50];
+pub const THEME_SEPIA_BACKGROUND: [u8; 3] = [244, 236, 216];
 
 // ── HiDPI ───────────────────────────────────────────────────────
 pub const HIDPI_CHANGE_THRESHOLD: f32 = 0.01;
@@ -52,6 +237,27 @@ pub const PDFIUM_DLL_FILENAME: &str = "pdfium.dll";
 // ── Файлы конфигурации и прогресса ──────────────────────────────
 pub const PROJECT_NAME: &str = "docreader-cloud";
 pub const SETTINGS_FILENAME: &str = "settings.json";
+/// Folder next to the settings file for the user's own fonts
+pub const FONTS_DIR_NAME: &str = "fonts";
 pub const PROGRESS_FILENAME: &str = "reading_progress.json";
+pub const DEVICE_PROGRESS_PREFIX: &str = "progress-";
+pub const QUARANTINE_SUFFIX: &str = ".corrupt-";
+pub const PROGRESS_SCHEMA_MAJOR: u32 = 1;
+// 1: unknown fields are preserved on save
+// 2: roaming preferences
+// 3: device registry
+// 4: bookmarks
+// 5: highlights
+// 6: per-book typography
+// 7: per-book zoom
+// 8: continuous scroll position
+// 9: two-page spreads
+// 10: margin cropping
+// 11: PDF reflow
+// 12: page rotation
+// 13: view settings timestamp
+// 14: positions in the text of EPUB and FB2 books
+// 15: time of the last move in a book
+pub const PROGRESS_SCHEMA_MINOR: u32 = 15;
 pub const DEFAULT_CLOUD_DIR: &str = "YandexDisk";
 pub const DEFAULT_BOOKS_DIR: &str = "Books";
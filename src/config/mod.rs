@@ -1,2 +1,3 @@
 pub mod constants;
+pub mod roaming;
 pub mod settings;
@@ -0,0 +1,361 @@
+//! Preferences that follow the user across devices.
+//!
+//! They travel inside the progress document, so they share its storage,
+//! encryption, backups and merging. Every value carries the time it was
+//! changed; merging keeps the newest one per setting. Which settings roam
+//! is listed in [`SETTINGS`].
+
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+
+use super::settings::{AppSettings, TypographySettings};
+
+pub const ZOOM_LEVEL: &str = "zoom_level";
+pub const AUTO_SAVE_INTERVAL: &str = "auto_save_interval_secs";
+pub const THEME: &str = "theme";
+pub const READING_FONT: &str = "reading_font";
+pub const TEXT_LAYOUT: &str = "text_layout";
+pub const HYPHENATION: &str = "hyphenation";
+pub const JUSTIFY: &str = "justify";
+
+/// A setting that roams, and where it lives in [`AppSettings`].
+pub struct RoamingSetting {
+    pub key: &'static str,
+    read: fn(&AppSettings) -> Value,
+    /// Returns false if the value does not fit the setting.
+    write: fn(&mut AppSettings, Value) -> bool,
+}
+
+/// Settings that roam. Adding one here is all it takes for it to be
+/// published, merged and applied.
+pub const SETTINGS: &[RoamingSetting] = &[
+    RoamingSetting {
+        key: ZOOM_LEVEL,
+        read: |settings| to_value(&settings.zoom_level),
+        write: |settings, value| assign(&mut settings.zoom_level, value),
+    },
+    RoamingSetting {
+        key: AUTO_SAVE_INTERVAL,
+        read: |settings| to_value(&settings.auto_save_interval_secs),
+        write: |settings, value| assign(&mut settings.auto_save_interval_secs, value),
+    },
+    RoamingSetting {
+        key: THEME,
+        read: |settings| to_value(&settings.theme),
+        write: |settings, value| assign(&mut settings.theme, value),
+    },
+    RoamingSetting {
+        key: READING_FONT,
+        read: |settings| to_value(&settings.typography.font_family),
+        write: |settings, value| assign(&mut settings.typography.font_family, value),
+    },
+    RoamingSetting {
+        key: TEXT_LAYOUT,
+        read: |settings| to_value(&TextLayout::of(&settings.typography)),
+        write: |settings, value| {
+            let mut layout = TextLayout::of(&settings.typography);
+            let fits = assign(&mut layout, value);
+            layout.set(&mut settings.typography);
+            fits
+        },
+    },
+    RoamingSetting {
+        key: HYPHENATION,
+        read: |settings| to_value(&settings.typography.hyphenation),
+        write: |settings, value| assign(&mut settings.typography.hyphenation, value),
+    },
+    RoamingSetting {
+        key: JUSTIFY,
+        read: |settings| to_value(&settings.typography.justify),
+        write: |settings, value| assign(&mut settings.typography.justify, value),
+    },
+];
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn assign<T: DeserializeOwned>(field: &mut T, value: Value) -> bool {
+    match serde_json::from_value(value) {
+        Ok(value) => {
+            *field = value;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Sizes and spacing of text pages, which roam together.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TextLayout {
+    font_size: f32,
+    line_spacing: f32,
+    paragraph_spacing: f32,
+    margin: u32,
+    fit_window: bool,
+    page_width: u32,
+    page_height: u32,
+}
+
+impl TextLayout {
+    fn of(typography: &TypographySettings) -> Self {
+        Self {
+            font_size: typography.font_size,
+            line_spacing: typography.line_spacing,
+            paragraph_spacing: typography.paragraph_spacing,
+            margin: typography.margin,
+            fit_window: typography.fit_window,
+            page_width: typography.page_width,
+            page_height: typography.page_height,
+        }
+    }
+
+    fn set(self, typography: &mut TypographySettings) {
+        typography.font_size = self.font_size;
+        typography.line_spacing = self.line_spacing;
+        typography.paragraph_spacing = self.paragraph_spacing;
+        typography.margin = self.margin;
+        typography.fit_window = self.fit_window;
+        typography.page_width = self.page_width;
+        typography.page_height = self.page_height;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Roaming<T> {
+    pub value: T,
+    pub updated: DateTime<Utc>,
+}
+
+impl<T> Roaming<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            updated: Utc::now(),
+        }
+    }
+}
+
+/// Roaming values by key, including keys known only to newer versions.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RoamingPreferences {
+    values: Map<String, Value>,
+}
+
+impl RoamingPreferences {
+    pub fn get(&self, key: &str) -> Option<Roaming<Value>> {
+        self.values
+            .get(key)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+
+    pub fn set(&mut self, key: &str, value: Value) {
+        self.values
+            .insert(key.to_string(), to_value(&Roaming::new(value)));
+    }
+
+    /// Per setting, the newer of the two values wins.
+    pub fn merge(local: &Self, remote: &Self) -> Self {
+        let mut merged = local.clone();
+        for (key, value) in &remote.values {
+            let newer = match (local.get(key), remote.get(key)) {
+                (Some(l), Some(r)) => r.updated > l.updated,
+                // Not a value this version understands: keep ours if any
+                _ => !local.values.contains_key(key),
+            };
+            if newer {
+                merged.values.insert(key.clone(), value.clone());
+            }
+        }
+        merged
+    }
+
+    /// Copies roaming values into `settings`, skipping local overrides.
+    /// Returns the keys that changed.
+    pub fn apply_to(&self, settings: &mut AppSettings) -> Vec<&'static str> {
+        self.apply(settings, |_| true)
+    }
+
+    /// Like [`Self::apply_to`], for the given keys only.
+    pub fn apply_keys(
+        &self,
+        settings: &mut AppSettings,
+        keys: &BTreeSet<String>,
+    ) -> Vec<&'static str> {
+        self.apply(settings, |key| keys.contains(key))
+    }
+
+    fn apply(
+        &self,
+        settings: &mut AppSettings,
+        wanted: impl Fn(&str) -> bool,
+    ) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        for setting in SETTINGS {
+            if !wanted(setting.key) || settings.is_local_override(setting.key) {
+                continue;
+            }
+            let Some(roaming) = self.get(setting.key) else {
+                continue;
+            };
+            if (setting.read)(settings) != roaming.value && (setting.write)(settings, roaming.value)
+            {
+                changed.push(setting.key);
+            }
+        }
+        changed
+    }
+
+    /// Records local values that differ from the roaming ones, skipping
+    /// local overrides. Returns true if anything was recorded.
+    pub fn record_from(&mut self, settings: &AppSettings) -> bool {
+        let mut changed = false;
+        for setting in SETTINGS {
+            if settings.is_local_override(setting.key) {
+                continue;
+            }
+            let value = (setting.read)(settings);
+            if self.get(setting.key).map(|r| r.value) != Some(value.clone()) {
+                self.set(setting.key, value);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::settings::{ReadingTheme, ThemeSettings};
+    use chrono::Duration;
+
+    fn at(value: Value, minutes_ago: i64) -> Value {
+        to_value(&Roaming {
+            value,
+            updated: Utc::now() - Duration::minutes(minutes_ago),
+        })
+    }
+
+    fn prefs(entries: &[(&str, Value)]) -> RoamingPreferences {
+        RoamingPreferences {
+            values: entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_merge_newest_per_setting() {
+        let local = prefs(&[
+            (ZOOM_LEVEL, at(1.5.into(), 1)),
+            (AUTO_SAVE_INTERVAL, at(10.into(), 0)),
+        ]);
+        let remote = prefs(&[(ZOOM_LEVEL, at(2.0.into(), 5))]);
+
+        let merged = RoamingPreferences::merge(&local, &remote);
+        assert_eq!(merged.get(ZOOM_LEVEL).unwrap().value, 1.5);
+        assert_eq!(merged.get(AUTO_SAVE_INTERVAL).unwrap().value, 10);
+
+        let merged = RoamingPreferences::merge(&remote, &local);
+        assert_eq!(merged.get(ZOOM_LEVEL).unwrap().value, 1.5);
+    }
+
+    #[test]
+    fn test_apply_respects_local_override() {
+        let prefs = prefs(&[
+            (ZOOM_LEVEL, at(2.0.into(), 0)),
+            (AUTO_SAVE_INTERVAL, at(30.into(), 0)),
+        ]);
+        let mut settings = AppSettings::default();
+        settings.local_overrides.insert(ZOOM_LEVEL.to_string());
+
+        let changed = prefs.apply_to(&mut settings);
+        assert_eq!(changed, vec![AUTO_SAVE_INTERVAL]);
+        assert_eq!(settings.auto_save_interval_secs, 30);
+        assert_ne!(settings.zoom_level, 2.0);
+    }
+
+    #[test]
+    fn test_apply_keys_only() {
+        let prefs = prefs(&[
+            (ZOOM_LEVEL, at(2.0.into(), 0)),
+            (AUTO_SAVE_INTERVAL, at(30.into(), 0)),
+        ]);
+        let mut settings = AppSettings::default();
+        let keys = BTreeSet::from([ZOOM_LEVEL.to_string()]);
+
+        assert_eq!(prefs.apply_keys(&mut settings, &keys), vec![ZOOM_LEVEL]);
+        assert_eq!(settings.zoom_level, 2.0);
+        assert_ne!(settings.auto_save_interval_secs, 30);
+    }
+
+    #[test]
+    fn test_record_from_settings() {
+        let mut settings = AppSettings {
+            zoom_level: 1.25,
+            ..Default::default()
+        };
+        let mut prefs = RoamingPreferences::default();
+        assert!(prefs.record_from(&settings));
+        assert_eq!(prefs.get(ZOOM_LEVEL).unwrap().value, 1.25);
+        assert!(!prefs.record_from(&settings));
+
+        settings.local_overrides.insert(ZOOM_LEVEL.to_string());
+        settings.zoom_level = 3.0;
+        assert!(!prefs.record_from(&settings));
+        assert_eq!(prefs.get(ZOOM_LEVEL).unwrap().value, 1.25);
+    }
+
+    #[test]
+    fn test_theme_and_typography_roam() {
+        let mut laptop = AppSettings {
+            theme: ThemeSettings {
+                theme: ReadingTheme::Sepia,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        laptop.typography.font_family = Some("PT Serif".to_string());
+        laptop.typography.font_size = 22.0;
+        laptop.typography.hyphenation = false;
+        let mut prefs = RoamingPreferences::default();
+        prefs.record_from(&laptop);
+
+        let mut phone = AppSettings::default();
+        let changed = prefs.apply_to(&mut phone);
+        assert!(changed.contains(&THEME));
+        assert!(changed.contains(&READING_FONT));
+        assert!(changed.contains(&TEXT_LAYOUT));
+        assert!(changed.contains(&HYPHENATION));
+        assert!(!changed.contains(&JUSTIFY));
+        assert_eq!(phone.theme, laptop.theme);
+        assert_eq!(phone.typography, laptop.typography);
+    }
+
+    #[test]
+    fn test_value_that_does_not_fit_is_skipped() {
+        let prefs = prefs(&[(THEME, at("plaid".into(), 0))]);
+        let mut settings = AppSettings::default();
+        assert!(prefs.apply_to(&mut settings).is_empty());
+        assert_eq!(settings.theme, ThemeSettings::default());
+    }
+
+    #[test]
+    fn test_unknown_preferences_roundtrip() {
+        let json =
+            r#"{"zoom_level":{"value":1.5,"updated":"2024-01-01T00:00:00Z"},"key_bindings":"vim"}"#;
+        let prefs: RoamingPreferences = serde_json::from_str(json).unwrap();
+        assert_eq!(prefs.get(ZOOM_LEVEL).unwrap().value, 1.5);
+        let saved = serde_json::to_value(&prefs).unwrap();
+        assert_eq!(saved["key_bindings"], "vim");
+
+        let merged = RoamingPreferences::merge(&RoamingPreferences::default(), &prefs);
+        assert_eq!(merged, prefs);
+    }
+}
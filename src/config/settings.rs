@@ -1,20 +1,252 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
 use std::fs;
 use std::path::PathBuf;
 use uuid::Uuid;
 
 use super::constants::*;
 
+/// Transport used to sync the progress file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SyncBackendSettings {
+    /// `progress_file_path` in a folder synced by a desktop cloud client.
+    #[default]
+    LocalFile,
+    WebDav {
+        url: String,
+        username: String,
+        password: String,
+    },
+    /// S3-compatible bucket; every device writes its own object under `prefix`.
+    S3 {
+        endpoint: String,
+        bucket: String,
+        #[serde(default)]
+        prefix: String,
+        #[serde(default = "default_s3_region")]
+        region: String,
+        access_key: String,
+        secret_key: String,
+    },
+}
+
+fn default_s3_region() -> String {
+    S3_DEFAULT_REGION.to_string()
+}
+
+impl SyncBackendSettings {
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::LocalFile => "Локальный файл",
+            Self::WebDav { .. } => "WebDAV",
+            Self::S3 { .. } => "S3",
+        }
+    }
+}
+
+/// KOReader progress sync (kosync) account.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KosyncSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_kosync_server")]
+    pub server: String,
+    #[serde(default)]
+    pub username: String,
+    /// MD5 of the password, the form KOReader stores and sends.
+    #[serde(default)]
+    pub userkey: String,
+}
+
+fn default_kosync_server() -> String {
+    KOSYNC_DEFAULT_SERVER.to_string()
+}
+
+impl Default for KosyncSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            server: default_kosync_server(),
+            username: String::new(),
+            userkey: String::new(),
+        }
+    }
+}
+
+/// How EPUB and FB2 text is set, by default or for one book. Lengths are
+/// pixels of the page at 100% zoom.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TypographySettings {
+    /// Split long words at the end of a line, by the book's language.
+    pub hyphenation: bool,
+    /// Stretch the spaces so lines of a paragraph end flush right.
+    pub justify: bool,
+    /// Reading font family; `None` for the embedded font.
+    pub font_family: Option<String>,
+    pub font_size: f32,
+    /// Distance between baselines, in font sizes.
+    pub line_spacing: f32,
+    /// Space after a paragraph, in lines.
+    pub paragraph_spacing: f32,
+    /// Space around the text on each side.
+    pub margin: u32,
+    /// Pages take the size of the viewer instead of `page_width` by
+    /// `page_height`.
+    pub fit_window: bool,
+    pub page_width: u32,
+    pub page_height: u32,
+}
+
+impl Default for TypographySettings {
+    fn default() -> Self {
+        Self {
+            hyphenation: true,
+            justify: true,
+            font_family: None,
+            font_size: TEXT_FONT_SIZE_DEFAULT,
+            line_spacing: TEXT_LINE_SPACING_DEFAULT,
+            paragraph_spacing: TEXT_PARAGRAPH_SPACING_DEFAULT,
+            margin: TEXT_PAGE_MARGIN_DEFAULT,
+            fit_window: false,
+            page_width: TEXT_PAGE_WIDTH_DEFAULT,
+            page_height: TEXT_PAGE_HEIGHT_DEFAULT,
+        }
+    }
+}
+
+/// How the page scale is chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoomMode {
+    /// The page is as wide as the viewer.
+    FitWidth,
+    /// The whole page fits in the viewer.
+    FitPage,
+    /// The zoom level set by the reader.
+    #[default]
+    Fixed,
+}
+
+/// Text and background colours of a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PageColors {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+}
+
+impl PageColors {
+    /// Black on white, the colours documents are rendered in.
+    pub const DAY: Self = Self {
+        foreground: THEME_DAY_FOREGROUND,
+        background: THEME_DAY_BACKGROUND,
+    };
+
+    /// Light text on a darker background.
+    pub fn is_dark(&self) -> bool {
+        let luma = |[r, g, b]: [u8; 3]| 299 * r as u32 + 587 * g as u32 + 114 * b as u32;
+        luma(self.background) < luma(self.foreground)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingTheme {
+    #[default]
+    Day,
+    Night,
+    Sepia,
+    Custom,
+}
+
+impl ReadingTheme {
+    pub const ALL: [Self; 4] = [Self::Day, Self::Night, Self::Sepia, Self::Custom];
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Day => "День",
+            Self::Night => "Ночь",
+            Self::Sepia => "Сепия",
+            Self::Custom => "Своя",
+        }
+    }
+}
+
+/// Colours pages are shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeSettings {
+    pub theme: ReadingTheme,
+    /// Colours of [`ReadingTheme::Custom`].
+    pub custom: PageColors,
+}
+
+impl Default for ThemeSettings {
+    fn default() -> Self {
+        Self {
+            theme: ReadingTheme::Day,
+            custom: PageColors::DAY,
+        }
+    }
+}
+
+impl ThemeSettings {
+    pub fn colors(&self) -> PageColors {
+        match self.theme {
+            ReadingTheme::Day => PageColors::DAY,
+            ReadingTheme::Night => PageColors {
+                foreground: THEME_NIGHT_FOREGROUND,
+                background: THEME_NIGHT_BACKGROUND,
+            },
+            ReadingTheme::Sepia => PageColors {
+                foreground: THEME_SEPIA_FOREGROUND,
+                background: THEME_SEPIA_BACKGROUND,
+            },
+            ReadingTheme::Custom => self.custom,
+        }
+    }
+}
+
+/// Host name of this machine, if the environment reports it.
+fn default_device_name() -> String {
+    ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok())
+        .unwrap_or_default()
+}
+
+/// Local settings. Paths, credentials and `device_id` stay on this device;
+/// preferences such as zoom, theme and typography also roam through the
+/// progress document (see [`super::roaming`]) unless listed in
+/// `local_overrides`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppSettings {
     pub library_path: PathBuf,
     pub progress_file_path: PathBuf,
     pub device_id: String,
+    /// Human-readable name shown to other devices.
+    #[serde(default = "default_device_name")]
+    pub device_name: String,
     pub zoom_level: f32,
     pub auto_save_interval_secs: u64,
+    /// Roaming preference keys whose local value always wins.
+    #[serde(default)]
+    pub local_overrides: BTreeSet<String>,
     #[serde(default)]
     pub last_opened_book: Option<String>,
+    #[serde(default)]
+    pub sync_backend: SyncBackendSettings,
+    #[serde(default)]
+    pub kosync: KosyncSettings,
+    #[serde(default)]
+    pub typography: TypographySettings,
+    #[serde(default)]
+    pub theme: ThemeSettings,
+    /// Passphrase for encrypting synced progress; empty disables encryption.
+    #[serde(default)]
+    pub encryption_passphrase: String,
 }
 
 impl Default for AppSettings {
@@ -33,14 +265,25 @@ impl Default for AppSettings {
                 .join(DEFAULT_BOOKS_DIR)
                 .join(PROGRESS_FILENAME),
             device_id,
+            device_name: default_device_name(),
             zoom_level: ZOOM_DEFAULT,
             auto_save_interval_secs: AUTO_SAVE_INTERVAL_SECS_DEFAULT,
+            local_overrides: BTreeSet::new(),
             last_opened_book: None,
+            sync_backend: SyncBackendSettings::LocalFile,
+            kosync: KosyncSettings::default(),
+            typography: TypographySettings::default(),
+            theme: ThemeSettings::default(),
+            encryption_passphrase: String::new(),
         }
     }
 }
 
 impl AppSettings {
+    pub fn is_local_override(&self, key: &str) -> bool {
+        self.local_overrides.contains(key)
+    }
+
     pub fn config_path() -> Result<PathBuf> {
         let proj_dirs = directories::ProjectDirs::from("", "", PROJECT_NAME)
             .context("Failed to get project directories")?;
@@ -48,6 +291,15 @@ impl AppSettings {
         Ok(proj_dirs.config_dir().join(SETTINGS_FILENAME))
     }
 
+    /// Folder the user can put fonts into, next to the settings file.
+    pub fn fonts_dir() -> Result<PathBuf> {
+        let config_path = Self::config_path()?;
+        let config_dir = config_path
+            .parent()
+            .context("Settings file has no folder")?;
+        Ok(config_dir.join(FONTS_DIR_NAME))
+    }
+
     pub fn load() -> Result<Self> {
         let config_path = Self::config_path()?;
 
@@ -103,10 +355,12 @@ mod tests {
 
     #[test]
     fn test_serialization_roundtrip() {
-        let mut settings = AppSettings::default();
-        settings.zoom_level = 1.5;
-        settings.auto_save_interval_secs = 10;
-        settings.last_opened_book = Some("abc".to_string());
+        let settings = AppSettings {
+            zoom_level: 1.5,
+            auto_save_interval_secs: 10,
+            last_opened_book: Some("abc".to_string()),
+            ..Default::default()
+        };
 
         let json = serde_json::to_string(&settings).unwrap();
         let deserialized: AppSettings = serde_json::from_str(&json).unwrap();
@@ -138,5 +392,72 @@ mod tests {
         let settings: AppSettings = serde_json::from_str(json).unwrap();
         assert!(settings.last_opened_book.is_none());
         assert_eq!(settings.device_id, "test-device");
+        assert_eq!(settings.sync_backend, SyncBackendSettings::LocalFile);
+        assert!(!settings.kosync.enabled);
+        assert_eq!(settings.kosync.server, KOSYNC_DEFAULT_SERVER);
+    }
+
+    #[test]
+    fn test_webdav_backend_roundtrip() {
+        let settings = AppSettings {
+            sync_backend: SyncBackendSettings::WebDav {
+                url: "https://cloud.example.com/remote.php/dav/files/me/Books".to_string(),
+                username: "me".to_string(),
+                password: "secret".to_string(),
+            },
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(json.contains(r#""kind":"web_dav""#));
+        let deserialized: AppSettings = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.sync_backend, settings.sync_backend);
+    }
+
+    #[test]
+    fn test_s3_backend_defaults() {
+        let json = r#"{
+            "kind": "s3",
+            "endpoint": "http://minio.local:9000",
+            "bucket": "books",
+            "access_key": "key",
+            "secret_key": "secret"
+        }"#;
+        let backend: SyncBackendSettings = serde_json::from_str(json).unwrap();
+        match backend {
+            SyncBackendSettings::S3 { prefix, region, .. } => {
+                assert!(prefix.is_empty());
+                assert_eq!(region, S3_DEFAULT_REGION);
+            }
+            other => panic!("unexpected backend {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_kosync_partial_settings() {
+        let json = r#"{"enabled": true, "username": "reader"}"#;
+        let kosync: KosyncSettings = serde_json::from_str(json).unwrap();
+        assert!(kosync.enabled);
+        assert_eq!(kosync.server, KOSYNC_DEFAULT_SERVER);
+        assert!(kosync.userkey.is_empty());
+    }
+
+    #[test]
+    fn test_theme_colors() {
+        let theme: ThemeSettings = serde_json::from_str(r#"{"theme": "night"}"#).unwrap();
+        assert_eq!(theme.theme, ReadingTheme::Night);
+        assert!(theme.colors().is_dark());
+        assert!(!PageColors::DAY.is_dark());
+
+        let custom = PageColors {
+            foreground: [255, 255, 0],
+            background: [0, 0, 128],
+        };
+        let theme = ThemeSettings {
+            theme: ReadingTheme::Custom,
+            custom,
+        };
+        assert_eq!(theme.colors(), custom);
+        assert!(custom.is_dark());
     }
 }
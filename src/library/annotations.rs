@@ -0,0 +1,267 @@
+//! Text highlights with notes, and the merge rule shared by all annotations.
+//!
+//! Annotations have a stable id and an edit time. Devices merge them by set
+//! union on the id; when both sides have one, the newer edit wins. Deleted
+//! annotations stay as tombstones so merges do not bring them back.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Something a user attaches to a place in a book.
+pub trait Annotation: Clone {
+    fn id(&self) -> &str;
+    /// Last edit, including deletion.
+    fn updated(&self) -> DateTime<Utc>;
+    /// Order in the book.
+    fn sort_key(&self) -> (u32, u32, DateTime<Utc>);
+}
+
+/// Union of both sides by id; for annotations on both, the newer edit wins.
+pub fn merge_annotations<T: Annotation>(local: &[T], remote: &[T]) -> Vec<T> {
+    let mut merged: HashMap<&str, &T> = local.iter().map(|a| (a.id(), a)).collect();
+    for annotation in remote {
+        match merged.get(annotation.id()) {
+            Some(existing) if existing.updated() >= annotation.updated() => {}
+            _ => {
+                merged.insert(annotation.id(), annotation);
+            }
+        }
+    }
+
+    let mut merged: Vec<T> = merged.into_values().cloned().collect();
+    merged.sort_by_key(|a| a.sort_key());
+    merged
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightColor {
+    #[default]
+    Yellow,
+    Green,
+    Blue,
+    Pink,
+}
+
+impl HighlightColor {
+    pub const ALL: [HighlightColor; 4] = [Self::Yellow, Self::Green, Self::Blue, Self::Pink];
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Self::Yellow => "жёлтый",
+            Self::Green => "зелёный",
+            Self::Blue => "синий",
+            Self::Pink => "розовый",
+        }
+    }
+
+    pub fn rgb(&self) -> [u8; 3] {
+        match self {
+            Self::Yellow => [255, 221, 0],
+            Self::Green => [92, 214, 92],
+            Self::Blue => [80, 160, 255],
+            Self::Pink => [255, 105, 180],
+        }
+    }
+
+    /// The palette colour closest to an arbitrary one, e.g. from a PDF annotation.
+    pub fn nearest(rgb: [u8; 3]) -> Self {
+        let distance = |color: &Self| {
+            color
+                .rgb()
+                .iter()
+                .zip(rgb)
+                .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+                .sum::<i32>()
+        };
+        Self::ALL
+            .into_iter()
+            .min_by_key(distance)
+            .unwrap_or_default()
+    }
+}
+
+/// A highlighted range of characters on one page, `start..=end` in the
+/// page's text order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Highlight {
+    pub id: String,
+    pub page: u32,
+    pub start: u32,
+    pub end: u32,
+    /// Positions of the first and last character in the text of an EPUB or
+    /// FB2 book, see [`PageText::positions`](crate::renderer::page_text::PageText::positions).
+    /// They hold when the typography changes the pages; `page`, `start`
+    /// and `end` are the layout the highlight was made in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<[u64; 2]>,
+    /// Highlighted text, kept for export and for finding it again.
+    pub text: String,
+    #[serde(default)]
+    pub color: HighlightColor,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Highlight {
+    pub fn new(page: u32, start: u32, end: u32, text: String, color: HighlightColor) -> Self {
+        let now = Utc::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            page,
+            start: start.min(end),
+            end: start.max(end),
+            anchor: None,
+            text,
+            color,
+            note: String::new(),
+            created: now,
+            updated: now,
+            deleted: false,
+            extra: Map::new(),
+        }
+    }
+
+    pub fn contains(&self, char_index: u32) -> bool {
+        (self.start..=self.end).contains(&char_index)
+    }
+}
+
+impl Annotation for Highlight {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn updated(&self) -> DateTime<Utc> {
+        self.updated
+    }
+
+    fn sort_key(&self) -> (u32, u32, DateTime<Utc>) {
+        (self.page, self.start, self.created)
+    }
+}
+
+/// Markdown with one section per highlight, in book order.
+pub fn export_markdown<'a>(title: &str, highlights: impl Iterator<Item = &'a Highlight>) -> String {
+    let mut out = format!("# {}\n", title);
+    for h in highlights {
+        out.push_str(&format!(
+            "\n## Стр. {} ({})\n\n",
+            h.page,
+            h.color.display_name()
+        ));
+        for line in h.text.lines() {
+            out.push_str(&format!("> {}\n", line));
+        }
+        if !h.note.is_empty() {
+            out.push('\n');
+            out.push_str(&h.note);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[derive(Serialize)]
+struct ExportedHighlight<'a> {
+    page: u32,
+    text: &'a str,
+    color: HighlightColor,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    note: &'a str,
+    created: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct ExportedBook<'a> {
+    title: &'a str,
+    highlights: Vec<ExportedHighlight<'a>>,
+}
+
+pub fn export_json<'a>(
+    title: &str,
+    highlights: impl Iterator<Item = &'a Highlight>,
+) -> Result<String> {
+    let book = ExportedBook {
+        title,
+        highlights: highlights
+            .map(|h| ExportedHighlight {
+                page: h.page,
+                text: &h.text,
+                color: h.color,
+                note: &h.note,
+                created: h.created,
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&book).context("Failed to serialize highlights")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_merge_union_and_newer_edit() {
+        let a = Highlight::new(3, 0, 4, "alpha".into(), HighlightColor::Yellow);
+        let b = Highlight::new(1, 5, 9, "beta".into(), HighlightColor::Green);
+        let mut a_edited = a.clone();
+        a_edited.note = "заметка".into();
+        a_edited.updated = a.updated + Duration::seconds(1);
+
+        let merged = merge_annotations(&[a, b.clone()], &[a_edited]);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0], b);
+        assert_eq!(merged[1].note, "заметка");
+    }
+
+    #[test]
+    fn test_new_orders_range() {
+        let h = Highlight::new(1, 9, 2, "x".into(), HighlightColor::Blue);
+        assert_eq!((h.start, h.end), (2, 9));
+        assert!(h.contains(5));
+        assert!(!h.contains(10));
+    }
+
+    #[test]
+    fn test_nearest_color() {
+        assert_eq!(
+            HighlightColor::nearest([255, 255, 0]),
+            HighlightColor::Yellow
+        );
+        assert_eq!(HighlightColor::nearest([0, 128, 255]), HighlightColor::Blue);
+        assert_eq!(HighlightColor::nearest([255, 0, 0]), HighlightColor::Pink);
+    }
+
+    #[test]
+    fn test_export_markdown() {
+        let mut h = Highlight::new(12, 0, 3, "Важная мысль".into(), HighlightColor::Yellow);
+        h.note = "Проверить".into();
+        let md = export_markdown("Книга", [&h].into_iter());
+        assert_eq!(
+            md,
+            "# Книга\n\n## Стр. 12 (жёлтый)\n\n> Важная мысль\n\nПроверить\n"
+        );
+    }
+
+    #[test]
+    fn test_export_json() {
+        let h = Highlight::new(7, 0, 3, "text".into(), HighlightColor::Pink);
+        let json: Value =
+            serde_json::from_str(&export_json("Book", [&h].into_iter()).unwrap()).unwrap();
+        assert_eq!(json["title"], "Book");
+        assert_eq!(json["highlights"][0]["page"], 7);
+        assert_eq!(json["highlights"][0]["color"], "pink");
+        assert!(json["highlights"][0].get("note").is_none());
+    }
+}
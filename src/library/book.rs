@@ -7,6 +7,8 @@ pub struct Book {
     pub file_path: PathBuf,
     pub file_name: String,
     pub file_hash: String,
+    /// KOReader partial-MD5 digest, the document key for kosync.
+    pub koreader_digest: Option<String>,
     pub total_pages: u32,
     pub format: DocumentFormat,
 }
@@ -27,6 +29,7 @@ impl Book {
             file_path,
             file_name,
             file_hash,
+            koreader_digest: None,
             total_pages,
             format,
         }
@@ -0,0 +1,105 @@
+//! Named bookmarks inside a book.
+//!
+//! Bookmarks point at pages, like reading progress, and merge like all
+//! annotations (see [`super::annotations`]).
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::annotations::Annotation;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub id: String,
+    pub page: u32,
+    /// Where the page started in the text of an EPUB or FB2 book, which
+    /// finds it again when the typography changes the pages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u64>,
+    pub label: String,
+    pub created: DateTime<Utc>,
+    /// Last rename or deletion.
+    pub updated: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Bookmark {
+    pub fn new(page: u32, label: String) -> Self {
+        let now = Utc::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            page,
+            position: None,
+            label,
+            created: now,
+            updated: now,
+            deleted: false,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl Annotation for Bookmark {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn updated(&self) -> DateTime<Utc> {
+        self.updated
+    }
+
+    fn sort_key(&self) -> (u32, u32, DateTime<Utc>) {
+        (self.page, 0, self.created)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::annotations::merge_annotations;
+    use chrono::Duration;
+
+    #[test]
+    fn test_merge_is_union() {
+        let a = Bookmark::new(5, "A".to_string());
+        let b = Bookmark::new(2, "B".to_string());
+
+        let merged = merge_annotations(std::slice::from_ref(&a), std::slice::from_ref(&b));
+        assert_eq!(merged, vec![b.clone(), a.clone()]);
+        assert_eq!(merge_annotations(&[b.clone(), a.clone()], &[a]).len(), 2);
+    }
+
+    #[test]
+    fn test_merge_newer_rename_wins() {
+        let original = Bookmark::new(5, "Глава".to_string());
+        let mut renamed = original.clone();
+        renamed.label = "Кульминация".to_string();
+        renamed.updated = original.updated + Duration::seconds(1);
+        let (original, renamed) = (vec![original], vec![renamed]);
+
+        assert_eq!(
+            merge_annotations(&original, &renamed)[0].label,
+            "Кульминация"
+        );
+        assert_eq!(
+            merge_annotations(&renamed, &original)[0].label,
+            "Кульминация"
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_deletion() {
+        let original = Bookmark::new(5, "A".to_string());
+        let mut deleted = original.clone();
+        deleted.deleted = true;
+        deleted.updated = original.updated + Duration::seconds(1);
+        let (original, deleted) = (vec![original], vec![deleted]);
+
+        assert!(merge_annotations(&original, &deleted)[0].deleted);
+        assert!(merge_annotations(&deleted, &original)[0].deleted);
+    }
+}
@@ -0,0 +1,122 @@
+//! Registry of devices that share the progress document.
+//!
+//! Every device records its name, when it was last active and which book it
+//! read. Retiring a device leaves a tombstone so that merges with older
+//! copies do not bring it back; a retired device that syncs again reappears.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::config::constants::DEVICE_ID_DISPLAY_LEN;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    #[serde(default)]
+    pub name: String,
+    pub last_seen: DateTime<Utc>,
+    /// Hash of the book read last on this device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_book: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retired: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl DeviceInfo {
+    /// Retired and not active since.
+    pub fn is_retired(&self) -> bool {
+        self.retired.is_some_and(|t| t >= self.last_seen)
+    }
+
+    /// Time of the latest change to this entry.
+    fn updated(&self) -> DateTime<Utc> {
+        self.retired
+            .map_or(self.last_seen, |t| t.max(self.last_seen))
+    }
+}
+
+/// Device name for display, falling back to the start of its id.
+pub fn display_name(name: &str, device_id: &str) -> String {
+    let name = name.trim();
+    if name.is_empty() {
+        device_id.chars().take(DEVICE_ID_DISPLAY_LEN).collect()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Per device, the most recently changed entry wins.
+pub fn merge_devices(
+    local: &HashMap<String, DeviceInfo>,
+    remote: &HashMap<String, DeviceInfo>,
+) -> HashMap<String, DeviceInfo> {
+    let mut merged = local.clone();
+    for (id, remote_device) in remote {
+        match merged.get(id) {
+            Some(local_device) if local_device.updated() >= remote_device.updated() => {}
+            _ => {
+                merged.insert(id.clone(), remote_device.clone());
+            }
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn device(name: &str, minutes_ago: i64) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_string(),
+            last_seen: Utc::now() - Duration::minutes(minutes_ago),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_display_name_falls_back_to_id() {
+        assert_eq!(display_name("Ноутбук", "0123456789"), "Ноутбук");
+        assert_eq!(display_name("  ", "0123456789"), "01234567");
+        assert_eq!(display_name("", "abc"), "abc");
+        // Ids synced from elsewhere need not be ASCII
+        assert_eq!(display_name("", "устройство-1"), "устройст");
+    }
+
+    #[test]
+    fn test_merge_newest_entry_wins() {
+        let local = HashMap::from([("a".to_string(), device("old", 10))]);
+        let remote = HashMap::from([
+            ("a".to_string(), device("new", 1)),
+            ("b".to_string(), device("other", 5)),
+        ]);
+
+        let merged = merge_devices(&local, &remote);
+        assert_eq!(merged["a"].name, "new");
+        assert_eq!(merged["b"].name, "other");
+    }
+
+    #[test]
+    fn test_retirement_survives_stale_copy() {
+        let stale = device("tablet", 10);
+        let mut retired = stale.clone();
+        retired.retired = Some(Utc::now());
+        assert!(retired.is_retired());
+
+        let local = HashMap::from([("t".to_string(), retired)]);
+        let remote = HashMap::from([("t".to_string(), stale)]);
+        assert!(merge_devices(&local, &remote)["t"].is_retired());
+        assert!(merge_devices(&remote, &local)["t"].is_retired());
+    }
+
+    #[test]
+    fn test_retired_device_reappears_when_active() {
+        let mut device = device("tablet", 1);
+        device.retired = Some(Utc::now() - Duration::minutes(5));
+        assert!(!device.is_retired());
+    }
+}
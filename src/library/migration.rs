@@ -0,0 +1,175 @@
+//! Schema versioning of the progress file.
+//!
+//! `version` is the major schema version: a major bump means older apps
+//! cannot safely write the file. `minor_version` marks additive changes that
+//! older apps keep intact through the unknown-field passthrough.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde_json::{Map, Value};
+
+use super::progress::ReadingProgress;
+use crate::config::constants::{PROGRESS_SCHEMA_MAJOR, PROGRESS_SCHEMA_MINOR};
+
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// `MIGRATIONS[n]` upgrades a file from major version `n` to `n + 1`.
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error(
+        "Progress file uses schema {found}, this version supports up to {supported}; refusing to overwrite it"
+    )]
+    NewerMajor { found: u32, supported: u32 },
+}
+
+impl SchemaError {
+    pub fn find(err: &anyhow::Error) -> Option<&SchemaError> {
+        err.downcast_ref::<SchemaError>()
+    }
+}
+
+/// Parses a progress file of any known version, upgrading it step by step.
+///
+/// Files from a newer major version are parsed on a best-effort basis and keep
+/// their version, so [`ensure_writable`] stops us from saving over them.
+pub fn parse_progress(content: &str) -> Result<ReadingProgress> {
+    let mut value: Value =
+        serde_json::from_str(content).context("Failed to parse progress file")?;
+    let object = value
+        .as_object_mut()
+        .context("Progress file is not a JSON object")?;
+
+    let found = object.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    let mut major = found;
+    while let Some(migration) = MIGRATIONS.get(major as usize) {
+        migration(object).with_context(|| format!("Failed to migrate schema {}", major))?;
+        major += 1;
+        object.insert("version".to_string(), major.into());
+    }
+
+    let mut progress: ReadingProgress = match serde_json::from_value(value) {
+        Ok(progress) => progress,
+        Err(e) if found > PROGRESS_SCHEMA_MAJOR => {
+            return Err(anyhow::Error::new(e).context(SchemaError::NewerMajor {
+                found,
+                supported: PROGRESS_SCHEMA_MAJOR,
+            }))
+        }
+        Err(e) => return Err(e).context("Failed to parse progress file"),
+    };
+
+    if progress.version == PROGRESS_SCHEMA_MAJOR {
+        progress.minor_version = progress.minor_version.max(PROGRESS_SCHEMA_MINOR);
+    }
+    Ok(progress)
+}
+
+/// Fails if `progress` came from a newer major schema than this app writes.
+pub fn ensure_writable(progress: &ReadingProgress) -> Result<()> {
+    if progress.version > PROGRESS_SCHEMA_MAJOR {
+        return Err(SchemaError::NewerMajor {
+            found: progress.version,
+            supported: PROGRESS_SCHEMA_MAJOR,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Hand-written or pre-release files without a version field.
+fn migrate_v0_to_v1(object: &mut Map<String, Value>) -> Result<()> {
+    object
+        .entry("last_modified")
+        .or_insert_with(|| serde_json::to_value(Utc::now()).unwrap_or(Value::Null));
+    object
+        .entry("device_id")
+        .or_insert_with(|| Value::String(String::new()));
+    object
+        .entry("books")
+        .or_insert_with(|| Value::Object(Map::new()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_current_version() {
+        let json = serde_json::to_string(&ReadingProgress::new("d1".to_string())).unwrap();
+        let progress = parse_progress(&json).unwrap();
+        assert_eq!(progress.version, PROGRESS_SCHEMA_MAJOR);
+        assert_eq!(progress.device_id, "d1");
+        assert!(ensure_writable(&progress).is_ok());
+    }
+
+    #[test]
+    fn test_migrates_unversioned_file() {
+        let json = r#"{"books": {}}"#;
+        let progress = parse_progress(json).unwrap();
+        assert_eq!(progress.version, 1);
+        assert_eq!(progress.minor_version, PROGRESS_SCHEMA_MINOR);
+        assert!(progress.device_id.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_fields_survive_roundtrip() {
+        let json = format!(
+            r#"{{
+                "version": {major},
+                "minor_version": {minor},
+                "last_modified": "2024-01-01T00:00:00Z",
+                "device_id": "d1",
+                "future_setting": {{"a": 1}},
+                "books": {{
+                    "h1": {{
+                        "file_name": "A.pdf",
+                        "file_path": "/a.pdf",
+                        "file_hash": "h1",
+                        "total_pages": 10,
+                        "current_page": 3,
+                        "last_read": "2024-01-01T00:00:00Z",
+                        "reading_speed": 1.5
+                    }}
+                }}
+            }}"#,
+            major = PROGRESS_SCHEMA_MAJOR,
+            minor = PROGRESS_SCHEMA_MINOR + 1
+        );
+        let progress = parse_progress(&json).unwrap();
+        assert_eq!(progress.minor_version, PROGRESS_SCHEMA_MINOR + 1);
+        assert!(ensure_writable(&progress).is_ok());
+
+        let saved: Value = serde_json::to_value(&progress).unwrap();
+        assert_eq!(saved["future_setting"]["a"], 1);
+        assert_eq!(saved["books"]["h1"]["reading_speed"], 1.5);
+        assert_eq!(saved["minor_version"], PROGRESS_SCHEMA_MINOR + 1);
+    }
+
+    #[test]
+    fn test_newer_major_is_read_only() {
+        let mut progress = ReadingProgress::new("d1".to_string());
+        progress.version = PROGRESS_SCHEMA_MAJOR + 1;
+        let json = serde_json::to_string(&progress).unwrap();
+
+        let parsed = parse_progress(&json).unwrap();
+        assert_eq!(parsed.version, PROGRESS_SCHEMA_MAJOR + 1);
+        let err = ensure_writable(&parsed).unwrap_err();
+        assert!(SchemaError::find(&err).is_some());
+    }
+
+    #[test]
+    fn test_newer_major_incompatible_layout() {
+        let json = format!(
+            r#"{{"version": {}, "books": []}}"#,
+            PROGRESS_SCHEMA_MAJOR + 1
+        );
+        let err = parse_progress(&json).unwrap_err();
+        assert!(matches!(
+            SchemaError::find(&err),
+            Some(SchemaError::NewerMajor { .. })
+        ));
+    }
+}
@@ -1,3 +1,7 @@
+pub mod annotations;
 pub mod book;
+pub mod bookmarks;
+pub mod devices;
+pub mod migration;
 pub mod progress;
 pub mod scanner;
@@ -1,22 +1,49 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::config::constants::{PROGRESS_SCHEMA_MAJOR, PROGRESS_SCHEMA_MINOR};
+use crate::config::roaming::RoamingPreferences;
+use crate::config::settings::{TypographySettings, ZoomMode};
+use crate::renderer::crop::{CropMargins, PageCrop};
+use crate::renderer::markup::DocumentMarkups;
+
+use super::annotations::{Annotation, Highlight, HighlightColor};
+use super::bookmarks::Bookmark;
+use super::devices::DeviceInfo;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReadingProgress {
+    /// Major schema version, see [`super::migration`].
     pub version: u32,
+    #[serde(default)]
+    pub minor_version: u32,
     pub last_modified: DateTime<Utc>,
     pub device_id: String,
     pub books: HashMap<String, BookProgress>,
+    /// Preferences shared by all devices.
+    #[serde(default)]
+    pub preferences: RoamingPreferences,
+    /// Devices that share this document, by device id.
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceInfo>,
+    /// Fields written by newer versions, kept so saving does not drop them.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl ReadingProgress {
     pub fn new(device_id: String) -> Self {
         Self {
-            version: 1,
+            version: PROGRESS_SCHEMA_MAJOR,
+            minor_version: PROGRESS_SCHEMA_MINOR,
             last_modified: Utc::now(),
             device_id,
             books: HashMap::new(),
+            preferences: RoamingPreferences::default(),
+            devices: HashMap::new(),
+            extra: Map::new(),
         }
     }
 
@@ -24,10 +51,82 @@ impl ReadingProgress {
         if let Some(bp) = self.books.get_mut(book_hash) {
             bp.current_page = current_page;
             bp.last_read = Utc::now();
+            bp.moved = Some(bp.last_read);
+        }
+        self.last_modified = Utc::now();
+    }
+
+    /// Keeps a book at the page its position landed on when it was laid
+    /// out again. The reader did not go anywhere, so `moved` stays.
+    pub fn update_book_layout(&mut self, book_hash: &str, current_page: u32) {
+        if let Some(bp) = self.books.get_mut(book_hash) {
+            bp.current_page = current_page;
+            bp.last_read = Utc::now();
+        }
+        self.last_modified = Utc::now();
+    }
+
+    /// Changes the view settings of a book, such as its zoom. Unlike the
+    /// reading position this does not count as reading the book.
+    pub fn update_book_view(&mut self, book_hash: &str, change: impl FnOnce(&mut BookProgress)) {
+        if let Some(bp) = self.books.get_mut(book_hash) {
+            change(bp);
+            bp.view_modified = Some(Utc::now());
+        }
+        self.last_modified = Utc::now();
+    }
+
+    /// Sets where the current page of an EPUB or FB2 book starts in its
+    /// text, after [`update_book_progress`](Self::update_book_progress).
+    pub fn set_book_position(&mut self, book_hash: &str, position: Option<u64>) {
+        if let Some(bp) = self.books.get_mut(book_hash) {
+            bp.position = position;
+        }
+    }
+
+    /// Moves the continuous scroll position of a book within its current
+    /// page. Like turning pages this is reading the book.
+    pub fn update_book_scroll(&mut self, book_hash: &str, page_offset: f32) {
+        if let Some(bp) = self.books.get_mut(book_hash) {
+            if let Some(scroll) = &mut bp.scroll {
+                scroll.page_offset = page_offset;
+                bp.last_read = Utc::now();
+                bp.moved = Some(bp.last_read);
+            }
         }
         self.last_modified = Utc::now();
     }
 
+    /// Marks this device as active under `name`, reading `book_hash`.
+    pub fn touch_device(&mut self, name: &str, book_hash: Option<&str>) {
+        let device = self.devices.entry(self.device_id.clone()).or_default();
+        device.name = name.to_string();
+        device.last_seen = Utc::now();
+        if let Some(hash) = book_hash {
+            device.last_book = Some(hash.to_string());
+        }
+        self.last_modified = Utc::now();
+    }
+
+    /// Leaves a tombstone for `device_id` so merges do not bring it back.
+    pub fn retire_device(&mut self, device_id: &str) {
+        if let Some(device) = self.devices.get_mut(device_id) {
+            device.retired = Some(Utc::now());
+            self.last_modified = Utc::now();
+        }
+    }
+
+    /// Devices that are not retired, most recently active first.
+    pub fn active_devices(&self) -> Vec<(&String, &DeviceInfo)> {
+        let mut devices: Vec<_> = self
+            .devices
+            .iter()
+            .filter(|(_, d)| !d.is_retired())
+            .collect();
+        devices.sort_by_key(|(_, d)| std::cmp::Reverse(d.last_seen));
+        devices
+    }
+
     pub fn add_book(
         &mut self,
         book_hash: String,
@@ -41,21 +140,326 @@ impl ReadingProgress {
             file_hash: book_hash.clone(),
             total_pages,
             current_page: 1,
+            position: None,
             last_read: Utc::now(),
+            moved: None,
+            view_modified: None,
+            bookmarks: Vec::new(),
+            highlights: Vec::new(),
+            typography: None,
+            zoom: None,
+            scroll: None,
+            spread: None,
+            crop: None,
+            reflow: false,
+            rotation: None,
+            extra: Map::new(),
         };
         self.books.insert(book_hash, book_progress);
         self.last_modified = Utc::now();
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Zoom mode of a book and its scale, last computed one for fit modes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BookZoom {
+    pub mode: ZoomMode,
+    pub level: f32,
+}
+
+/// Where the viewer stands in a book read in continuous scroll mode.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScrollPosition {
+    /// How far the top of the viewer is into the current page, in page heights.
+    pub page_offset: f32,
+}
+
+/// How a book is shown in two-page spreads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Spread {
+    /// The first page is shown alone, so that spreads line up as in the
+    /// printed book.
+    #[serde(default)]
+    pub cover_alone: bool,
+    /// Pages go from right to left (manga).
+    #[serde(default)]
+    pub rtl: bool,
+}
+
+impl Spread {
+    /// First page of the spread holding `page`.
+    pub fn first_page(&self, page: u32) -> u32 {
+        let page = page.max(1);
+        if self.cover_alone && page == 1 {
+            return 1;
+        }
+        // Spreads start on odd pages, or on even ones after a lone cover
+        if (page % 2 == 1) != self.cover_alone {
+            page
+        } else {
+            page - 1
+        }
+    }
+
+    /// Pages of the spread holding `page`, in reading order. While the
+    /// page count is not known (zero) the spread is taken to be full.
+    pub fn pages(&self, page: u32, total_pages: u32) -> Vec<u32> {
+        let first = self.first_page(page);
+        let alone = self.cover_alone && first == 1;
+        if alone || (total_pages > 0 && first >= total_pages) {
+            vec![first]
+        } else {
+            vec![first, first + 1]
+        }
+    }
+}
+
+/// Quarter turns clockwise a book's pages are shown at.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rotation {
+    /// Turns of every page.
+    #[serde(default)]
+    pub document: u8,
+    /// Turns of single pages on top of the document's, by page number.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pages: BTreeMap<u32, u8>,
+}
+
+impl Rotation {
+    pub fn for_page(&self, page: u32) -> u8 {
+        let own = self.pages.get(&page).copied().unwrap_or(0);
+        (self.document % 4 + own % 4) % 4
+    }
+
+    /// Turns `page` by `quarter_turns` more.
+    pub fn rotate_page(&mut self, page: u32, quarter_turns: u8) {
+        let own = self.pages.get(&page).copied().unwrap_or(0);
+        match (own % 4 + quarter_turns % 4) % 4 {
+            0 => self.pages.remove(&page),
+            turns => self.pages.insert(page, turns),
+        };
+    }
+
+    /// Turns every page by `quarter_turns` more.
+    pub fn rotate_document(&mut self, quarter_turns: u8) {
+        self.document = (self.document % 4 + quarter_turns % 4) % 4;
+    }
+
+    /// Whether no page is turned.
+    pub fn is_none(&self) -> bool {
+        self.document.is_multiple_of(4) && self.pages.is_empty()
+    }
+}
+
+/// How the margins of a book's pages are cropped.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Crop {
+    /// Each page to the bounds of its content.
+    Auto,
+    /// The same margins on every odd and on every even page.
+    Manual { odd: CropMargins, even: CropMargins },
+}
+
+impl Crop {
+    pub fn for_page(&self, page: u32) -> PageCrop {
+        match self {
+            Crop::Auto => PageCrop::Auto,
+            Crop::Manual { odd, .. } if page % 2 == 1 => PageCrop::Margins(*odd),
+            Crop::Manual { even, .. } => PageCrop::Margins(*even),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BookProgress {
     pub file_name: String,
     pub file_path: String,
     pub file_hash: String,
     pub total_pages: u32,
     pub current_page: u32,
+    /// Where the current page starts in the text of an EPUB or FB2 book;
+    /// it is opened at that position whatever the typography.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u64>,
     pub last_read: DateTime<Utc>,
+    /// When the reader last went to another place in the book. Unlike
+    /// `last_read` it stays when the book is only laid out again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved: Option<DateTime<Utc>>,
+    /// When the view settings below last changed; they merge apart from the
+    /// reading position, see [`BookProgress::take_view`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view_modified: Option<DateTime<Utc>>,
+    /// Including deleted ones, see [`super::bookmarks`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bookmarks: Vec<Bookmark>,
+    /// Including deleted ones, see [`super::annotations`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub highlights: Vec<Highlight>,
+    /// Typography for this book, overriding the global settings (EPUB/FB2).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typography: Option<TypographySettings>,
+    /// Zoom the book was last read at.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zoom: Option<BookZoom>,
+    /// Set while the book is read in continuous scroll mode (PDF).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll: Option<ScrollPosition>,
+    /// Set while the book is shown in two-page spreads (PDF).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spread: Option<Spread>,
+    /// Set while the margins of the pages are cropped (PDF).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Crop>,
+    /// Set while the text of the pages is reflowed to the window (PDF).
+    /// `current_page` and bookmarks still count pages of the file.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reflow: bool,
+    /// Set while pages are turned (PDF).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Rotation>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl BookProgress {
+    /// Takes the view settings of `other`. A scroll offset is part of the
+    /// reading position, so it stays while both books are scrolled.
+    pub fn take_view(&mut self, other: &BookProgress) {
+        self.view_modified = other.view_modified;
+        self.typography = other.typography.clone();
+        self.zoom = other.zoom;
+        self.scroll = match (self.scroll, other.scroll) {
+            (Some(scroll), Some(_)) => Some(scroll),
+            (_, scroll) => scroll,
+        };
+        self.spread = other.spread;
+        self.crop = other.crop;
+        self.reflow = other.reflow;
+        self.rotation = other.rotation.clone();
+    }
+
+    /// Bookmarks that are not deleted, by page.
+    pub fn bookmarks(&self) -> impl Iterator<Item = &Bookmark> {
+        self.bookmarks.iter().filter(|b| !b.deleted)
+    }
+
+    pub fn bookmark_at(&self, page: u32) -> Option<&Bookmark> {
+        self.bookmarks().find(|b| b.page == page)
+    }
+
+    pub fn add_bookmark(&mut self, page: u32, position: Option<u64>, label: String) {
+        let mut bookmark = Bookmark::new(page, label);
+        bookmark.position = position;
+        self.bookmarks.push(bookmark);
+        self.bookmarks.sort_by_key(|b| (b.page, b.created));
+    }
+
+    pub fn rename_bookmark(&mut self, id: &str, label: String) {
+        if let Some(bookmark) = self.bookmarks.iter_mut().find(|b| b.id == id) {
+            bookmark.label = label;
+            bookmark.updated = Utc::now();
+        }
+    }
+
+    pub fn remove_bookmark(&mut self, id: &str) {
+        if let Some(bookmark) = self.bookmarks.iter_mut().find(|b| b.id == id) {
+            bookmark.deleted = true;
+            bookmark.updated = Utc::now();
+        }
+    }
+
+    /// Highlights that are not deleted, in book order.
+    pub fn highlights(&self) -> impl Iterator<Item = &Highlight> {
+        self.highlights.iter().filter(|h| !h.deleted)
+    }
+
+    pub fn highlight(&self, id: &str) -> Option<&Highlight> {
+        self.highlights().find(|h| h.id == id)
+    }
+
+    pub fn add_highlight(&mut self, highlight: Highlight) {
+        self.highlights.push(highlight);
+        self.highlights.sort_by_key(|h| h.sort_key());
+    }
+
+    pub fn edit_highlight(&mut self, id: &str, color: HighlightColor, note: String) {
+        if let Some(highlight) = self.highlights.iter_mut().find(|h| h.id == id) {
+            highlight.color = color;
+            highlight.note = note;
+            highlight.updated = Utc::now();
+        }
+    }
+
+    pub fn remove_highlight(&mut self, id: &str) {
+        if let Some(highlight) = self.highlights.iter_mut().find(|h| h.id == id) {
+            highlight.deleted = true;
+            highlight.updated = Utc::now();
+        }
+    }
+
+    /// Takes in annotations read from a PDF: highlights as highlights,
+    /// free-standing notes as bookmarks. Ones the book already has, deleted
+    /// or not, are not added again; a highlight edited later in the file
+    /// takes its colour and note. Returns how many highlights were added or
+    /// changed and how many notes were added.
+    pub fn import_markups(&mut self, markups: DocumentMarkups) -> (usize, usize) {
+        let mut highlights = 0;
+        for markup in markups.markups {
+            let page = markup.page_index + 1;
+            let (start, end) = (markup.start as u32, markup.end as u32);
+            let color = HighlightColor::nearest(markup.color);
+            let existing = self
+                .highlights
+                .iter_mut()
+                .find(|h| h.page == page && h.start == start && h.end == end);
+            match existing {
+                Some(h) if h.deleted || h.updated >= markup.updated => {}
+                Some(h) => {
+                    if h.color != color || h.note != markup.note {
+                        h.color = color;
+                        h.note = markup.note;
+                        h.updated = markup.updated;
+                        highlights += 1;
+                    }
+                }
+                None => {
+                    let mut highlight = Highlight::new(page, start, end, markup.text, color);
+                    highlight.note = markup.note;
+                    highlight.created = markup.created;
+                    highlight.updated = markup.updated;
+                    self.add_highlight(highlight);
+                    highlights += 1;
+                }
+            }
+        }
+
+        let mut notes = 0;
+        for note in markups.notes {
+            let page = note.page_index + 1;
+            let label = note
+                .text
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            if self
+                .bookmarks
+                .iter()
+                .any(|b| b.page == page && b.label == label)
+            {
+                continue;
+            }
+            let mut bookmark = Bookmark::new(page, label);
+            bookmark.created = note.created;
+            bookmark.updated = note.updated;
+            self.bookmarks.push(bookmark);
+            notes += 1;
+        }
+        (highlights, notes)
+    }
 }
 
 #[allow(dead_code)]
@@ -81,6 +485,7 @@ mod tests {
             total_pages,
             current_page,
             last_read: Utc::now(),
+            ..Default::default()
         }
     }
 
@@ -153,6 +558,25 @@ mod tests {
         assert!(bp.last_read >= before);
     }
 
+    #[test]
+    fn test_book_position_roundtrip() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "Book.epub".to_string(),
+            "/books/Book.epub".to_string(),
+            100,
+        );
+        let json = serde_json::to_string(&rp).unwrap();
+        assert!(!json.contains("position"));
+
+        rp.update_book_progress("h1", 12);
+        rp.set_book_position("h1", Some((3 << 32) | 120));
+        let json = serde_json::to_string(&rp).unwrap();
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.books["h1"].position, Some((3 << 32) | 120));
+    }
+
     #[test]
     fn test_update_nonexistent_book() {
         let mut rp = ReadingProgress::new("device1".to_string());
@@ -200,4 +624,365 @@ mod tests {
         assert_eq!(deserialized.books.len(), 1);
         assert_eq!(deserialized.books.get("h1").unwrap().current_page, 25);
     }
+
+    #[test]
+    fn test_book_typography() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.epub".to_string(),
+            "/a.epub".to_string(),
+            50,
+        );
+        let json = serde_json::to_string(&rp).unwrap();
+        assert!(!json.contains("typography"));
+
+        let typography = TypographySettings {
+            font_size: 28.0,
+            ..TypographySettings::default()
+        };
+        rp.update_book_view("h1", |bp| bp.typography = Some(typography.clone()));
+        let json = serde_json::to_string(&rp).unwrap();
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.books["h1"].typography, Some(typography));
+
+        rp.update_book_view("h1", |bp| bp.typography = None);
+        assert!(rp.books["h1"].typography.is_none());
+    }
+
+    #[test]
+    fn test_book_zoom() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.pdf".to_string(),
+            "/a.pdf".to_string(),
+            50,
+        );
+        let zoom = BookZoom {
+            mode: ZoomMode::FitWidth,
+            level: 1.25,
+        };
+        rp.update_book_view("h1", |bp| bp.zoom = Some(zoom));
+        let json = serde_json::to_string(&rp).unwrap();
+        assert!(json.contains(r#""mode":"fit_width""#));
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.books["h1"].zoom, Some(zoom));
+    }
+
+    #[test]
+    fn test_book_scroll() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.pdf".to_string(),
+            "/a.pdf".to_string(),
+            50,
+        );
+        let json = serde_json::to_string(&rp).unwrap();
+        assert!(!json.contains("scroll"));
+
+        let scroll = ScrollPosition { page_offset: 0.375 };
+        rp.update_book_view("h1", |bp| bp.scroll = Some(scroll));
+        let json = serde_json::to_string(&rp).unwrap();
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.books["h1"].scroll, Some(scroll));
+
+        rp.update_book_view("h1", |bp| bp.scroll = None);
+        assert!(rp.books["h1"].scroll.is_none());
+    }
+
+    #[test]
+    fn test_spread_pages() {
+        let spread = Spread::default();
+        assert_eq!(spread.pages(1, 5), vec![1, 2]);
+        assert_eq!(spread.pages(2, 5), vec![1, 2]);
+        assert_eq!(spread.pages(4, 5), vec![3, 4]);
+        assert_eq!(spread.pages(5, 5), vec![5]);
+
+        let cover = Spread {
+            cover_alone: true,
+            rtl: false,
+        };
+        assert_eq!(cover.pages(1, 5), vec![1]);
+        assert_eq!(cover.pages(2, 5), vec![2, 3]);
+        assert_eq!(cover.pages(3, 5), vec![2, 3]);
+        assert_eq!(cover.pages(5, 5), vec![4, 5]);
+        assert_eq!(cover.pages(6, 6), vec![6]);
+
+        // Unknown page count
+        assert_eq!(spread.pages(3, 0), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_book_spread() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.pdf".to_string(),
+            "/a.pdf".to_string(),
+            50,
+        );
+        let spread = Spread {
+            cover_alone: true,
+            rtl: true,
+        };
+        rp.update_book_view("h1", |bp| bp.spread = Some(spread));
+        let json = serde_json::to_string(&rp).unwrap();
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.books["h1"].spread, Some(spread));
+
+        // Options missing from older files are off
+        let json = json.replace(r#""rtl":true"#, "").replace(",}", "}");
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert!(!deserialized.books["h1"].spread.unwrap().rtl);
+    }
+
+    #[test]
+    fn test_book_crop() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.pdf".to_string(),
+            "/a.pdf".to_string(),
+            50,
+        );
+        let odd = CropMargins {
+            left: 0.1,
+            right: 0.2,
+            ..CropMargins::default()
+        };
+        let even = CropMargins {
+            left: 0.2,
+            right: 0.1,
+            ..CropMargins::default()
+        };
+        let crop = Crop::Manual { odd, even };
+        rp.update_book_view("h1", |bp| bp.crop = Some(crop));
+        let json = serde_json::to_string(&rp).unwrap();
+        assert!(json.contains(r#""mode":"manual""#));
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.books["h1"].crop, Some(crop));
+
+        assert_eq!(crop.for_page(3), PageCrop::Margins(odd));
+        assert_eq!(crop.for_page(4), PageCrop::Margins(even));
+        assert_eq!(Crop::Auto.for_page(4), PageCrop::Auto);
+    }
+
+    #[test]
+    fn test_book_rotation() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.pdf".to_string(),
+            "/a.pdf".to_string(),
+            50,
+        );
+        let mut rotation = Rotation::default();
+        rotation.rotate_document(1);
+        rotation.rotate_page(3, 3);
+        rotation.rotate_page(5, 1);
+        assert_eq!(rotation.for_page(1), 1);
+        assert_eq!(rotation.for_page(3), 0);
+        assert_eq!(rotation.for_page(5), 2);
+
+        // Turning a page back upright forgets it
+        rotation.rotate_page(5, 3);
+        assert_eq!(rotation.pages.len(), 1);
+        rotation.rotate_document(3);
+        assert!(!rotation.is_none());
+
+        rp.update_book_view("h1", |bp| bp.rotation = Some(rotation.clone()));
+        let json = serde_json::to_string(&rp).unwrap();
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.books["h1"].rotation, Some(rotation));
+    }
+
+    #[test]
+    fn test_book_reflow() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.pdf".to_string(),
+            "/a.pdf".to_string(),
+            50,
+        );
+        let json = serde_json::to_string(&rp).unwrap();
+        assert!(!json.contains("reflow"));
+
+        rp.update_book_view("h1", |bp| bp.reflow = true);
+        let json = serde_json::to_string(&rp).unwrap();
+        let deserialized: ReadingProgress = serde_json::from_str(&json).unwrap();
+        assert!(deserialized.books["h1"].reflow);
+    }
+
+    #[test]
+    fn test_layout_is_not_a_move() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.epub".to_string(),
+            "/a.epub".to_string(),
+            50,
+        );
+        assert!(rp.books["h1"].moved.is_none());
+
+        rp.update_book_layout("h1", 7);
+        assert_eq!(rp.books["h1"].current_page, 7);
+        assert!(rp.books["h1"].moved.is_none());
+
+        rp.update_book_progress("h1", 8);
+        assert_eq!(rp.books["h1"].moved, Some(rp.books["h1"].last_read));
+    }
+
+    #[test]
+    fn test_view_change_is_not_reading() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.add_book(
+            "h1".to_string(),
+            "A.pdf".to_string(),
+            "/a.pdf".to_string(),
+            50,
+        );
+        let last_read = rp.books["h1"].last_read;
+        assert!(rp.books["h1"].view_modified.is_none());
+
+        let zoom = BookZoom {
+            mode: ZoomMode::FitPage,
+            level: 1.0,
+        };
+        rp.update_book_view("h1", |bp| bp.zoom = Some(zoom));
+        assert_eq!(rp.books["h1"].last_read, last_read);
+        assert!(rp.books["h1"].view_modified.is_some());
+    }
+
+    #[test]
+    fn test_touch_and_retire_device() {
+        let mut rp = ReadingProgress::new("device1".to_string());
+        rp.touch_device("Ноутбук", Some("hash1"));
+        rp.devices.insert(
+            "device2".to_string(),
+            DeviceInfo {
+                name: "Планшет".to_string(),
+                last_seen: Utc::now() - chrono::Duration::hours(1),
+                ..Default::default()
+            },
+        );
+
+        let active = rp.active_devices();
+        assert_eq!(active.len(), 2);
+        assert_eq!(active[0].1.name, "Ноутбук");
+        assert_eq!(active[0].1.last_book.as_deref(), Some("hash1"));
+
+        rp.retire_device("device2");
+        let active = rp.active_devices();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].0, "device1");
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let mut bp = make_book_progress(1, 100);
+        bp.add_bookmark(30, None, "B".to_string());
+        bp.add_bookmark(10, None, "A".to_string());
+        let labels: Vec<_> = bp.bookmarks().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, vec!["A", "B"]);
+
+        let id = bp.bookmark_at(30).unwrap().id.clone();
+        bp.rename_bookmark(&id, "C".to_string());
+        assert_eq!(bp.bookmark_at(30).unwrap().label, "C");
+
+        bp.remove_bookmark(&id);
+        assert!(bp.bookmark_at(30).is_none());
+        assert_eq!(bp.bookmarks().count(), 1);
+        // The tombstone stays for merging
+        assert_eq!(bp.bookmarks.len(), 2);
+    }
+
+    #[test]
+    fn test_highlights() {
+        let mut bp = make_book_progress(1, 100);
+        let h = Highlight::new(5, 10, 20, "text".into(), HighlightColor::Yellow);
+        let id = h.id.clone();
+        bp.add_highlight(h);
+
+        bp.edit_highlight(&id, HighlightColor::Blue, "note".into());
+        let h = bp.highlight(&id).unwrap();
+        assert_eq!(h.color, HighlightColor::Blue);
+        assert_eq!(h.note, "note");
+
+        bp.remove_highlight(&id);
+        assert!(bp.highlight(&id).is_none());
+        assert_eq!(bp.highlights.len(), 1);
+    }
+
+    #[test]
+    fn test_import_markups() {
+        use crate::renderer::markup::{Markup, PageNote};
+        use chrono::Duration;
+
+        let mut bp = make_book_progress(1, 100);
+        let kept = Highlight::new(1, 0, 4, "kept".into(), HighlightColor::Yellow);
+        let edited = Highlight::new(1, 10, 14, "edited".into(), HighlightColor::Yellow);
+        let deleted = Highlight::new(2, 0, 4, "deleted".into(), HighlightColor::Yellow);
+        let deleted_id = deleted.id.clone();
+        let edited_at = edited.updated;
+        for h in [kept.clone(), edited.clone(), deleted] {
+            bp.add_highlight(h);
+        }
+        bp.remove_highlight(&deleted_id);
+
+        let markup = |page_index, start, text: &str, updated| Markup {
+            page_index,
+            start,
+            end: start + 4,
+            text: text.to_string(),
+            color: HighlightColor::Blue.rgb(),
+            note: "заметка".to_string(),
+            created: edited_at - Duration::days(1),
+            updated,
+        };
+        let old = edited_at - Duration::hours(1);
+        let new = edited_at + Duration::hours(1);
+        let markups = DocumentMarkups {
+            markups: vec![
+                markup(0, 0, "kept", old),
+                markup(0, 10, "edited", new),
+                markup(1, 0, "deleted", new),
+                markup(2, 0, "added", old),
+            ],
+            notes: vec![PageNote {
+                page_index: 3,
+                text: "Поля\nвторая строка".to_string(),
+                created: old,
+                updated: old,
+            }],
+        };
+
+        assert_eq!(bp.import_markups(markups.clone()), (2, 1));
+        // Edited in the app after the file was written
+        assert_eq!(
+            bp.highlight(&kept.id).unwrap().color,
+            HighlightColor::Yellow
+        );
+        // Edited in the file later
+        let h = bp.highlight(&edited.id).unwrap();
+        assert_eq!(h.color, HighlightColor::Blue);
+        assert_eq!(h.note, "заметка");
+        assert_eq!(h.updated, new);
+        // Deleted ones stay deleted
+        assert!(bp.highlight(&deleted_id).is_none());
+        let added = bp.highlights().find(|h| h.page == 3).unwrap();
+        assert_eq!(
+            (added.created, added.updated),
+            (edited_at - Duration::days(1), old)
+        );
+        let note = bp.bookmark_at(4).unwrap();
+        assert_eq!(note.label, "Поля");
+        assert_eq!(note.updated, old);
+
+        // Importing the same file again changes nothing
+        assert_eq!(bp.import_markups(markups), (0, 0));
+        assert_eq!(bp.highlights.len(), 4);
+    }
 }
@@ -1,10 +1,12 @@
 use anyhow::{Context, Result};
+use md5::Md5;
 use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
 use super::book::Book;
+use crate::config::constants::KOREADER_DIGEST_SAMPLE_SIZE;
 use crate::renderer::format::DocumentFormat;
 use crate::renderer::RendererRegistry;
 
@@ -61,6 +63,36 @@ impl LibraryScanner {
         Ok(format!("{:x}", result))
     }
 
+    /// KOReader's partial MD5: 1 KiB samples at offsets 0 and 1024 * 4^i for i in 0..=10.
+    /// Used as the document key by kosync servers.
+    pub fn compute_koreader_digest(path: &Path) -> Result<String> {
+        let mut file = File::open(path).context("Failed to open file for hashing")?;
+        let file_size = file.metadata()?.len();
+
+        let mut hasher = Md5::new();
+        let mut buffer = vec![0u8; KOREADER_DIGEST_SAMPLE_SIZE];
+        let offsets = std::iter::once(0u64)
+            .chain((0..=10).map(|i| (KOREADER_DIGEST_SAMPLE_SIZE as u64) << (2 * i)));
+
+        for offset in offsets {
+            if offset >= file_size {
+                break;
+            }
+            file.seek(SeekFrom::Start(offset))?;
+            let mut filled = 0;
+            while filled < buffer.len() {
+                let n = file.read(&mut buffer[filled..])?;
+                if n == 0 {
+                    break;
+                }
+                filled += n;
+            }
+            hasher.update(&buffer[..filled]);
+        }
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
     pub fn scan_and_load_books(
         library_path: &Path,
         registry: Option<&RendererRegistry>,
@@ -95,12 +127,9 @@ impl LibraryScanner {
             0 // Will be loaded later when opening
         };
 
-        Ok(Book::new(
-            path.to_path_buf(),
-            file_hash,
-            total_pages,
-            format,
-        ))
+        let mut book = Book::new(path.to_path_buf(), file_hash, total_pages, format);
+        book.koreader_digest = Self::compute_koreader_digest(path).ok();
+        Ok(book)
     }
 }
 
@@ -192,6 +221,46 @@ mod tests {
         let _ = fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn test_koreader_digest_small_file_is_plain_md5() {
+        let dir = std::env::temp_dir().join("docreader_test_koreader_small");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let file = dir.join("small.bin");
+        fs::write(&file, b"hello world").unwrap();
+
+        let digest = LibraryScanner::compute_koreader_digest(&file).unwrap();
+        assert_eq!(digest, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_koreader_digest_samples() {
+        let dir = std::env::temp_dir().join("docreader_test_koreader_samples");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // 5000 bytes: samples at 0, 1024 and 4096 (the last one partial)
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let file = dir.join("book.pdf");
+        fs::write(&file, &data).unwrap();
+
+        let mut expected = Md5::new();
+        expected.update(&data[0..1024]);
+        expected.update(&data[1024..2048]);
+        expected.update(&data[4096..5000]);
+        let expected = format!("{:x}", expected.finalize());
+
+        assert_eq!(
+            LibraryScanner::compute_koreader_digest(&file).unwrap(),
+            expected
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_scan_and_load_books() {
         let dir = std::env::temp_dir().join("docreader_test_load");
@@ -208,6 +277,7 @@ mod tests {
         for book in &books {
             assert_eq!(book.total_pages, 0);
             assert!(!book.file_hash.is_empty());
+            assert!(book.koreader_digest.is_some());
         }
 
         let _ = fs::remove_dir_all(&dir);
@@ -1,16 +1,34 @@
 use egui::TextureHandle;
 use lru::LruCache;
 use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use crate::config::settings::PageColors;
+
+use super::page_text::{PageImage, PageText};
 
 #[derive(Hash, Eq, PartialEq, Clone)]
 struct CacheKey {
     book_hash: String,
     page: u32,
     dpi: u32,
+    colors: PageColors,
+    /// Quarter turns clockwise.
+    rotation: u8,
+}
+
+/// A rendered page with its text layer and illustrations.
+#[derive(Clone)]
+pub struct CachedPage {
+    pub texture: TextureHandle,
+    pub text: Arc<PageText>,
+    pub images: Arc<Vec<PageImage>>,
+    /// Size the page is shown at at 100% zoom, in points.
+    pub size: egui::Vec2,
 }
 
 pub struct PageCache {
-    cache: LruCache<CacheKey, TextureHandle>,
+    cache: LruCache<CacheKey, CachedPage>,
 }
 
 impl PageCache {
@@ -20,22 +38,41 @@ impl PageCache {
         }
     }
 
-    pub fn get(&mut self, book_hash: &str, page: u32, dpi: u32) -> Option<&TextureHandle> {
+    pub fn get(
+        &mut self,
+        book_hash: &str,
+        page: u32,
+        dpi: u32,
+        colors: PageColors,
+        rotation: u8,
+    ) -> Option<&CachedPage> {
         let key = CacheKey {
             book_hash: book_hash.to_string(),
             page,
             dpi,
+            colors,
+            rotation,
         };
         self.cache.get(&key)
     }
 
-    pub fn insert(&mut self, book_hash: &str, page: u32, dpi: u32, texture: TextureHandle) {
+    pub fn insert(
+        &mut self,
+        book_hash: &str,
+        page: u32,
+        dpi: u32,
+        colors: PageColors,
+        rotation: u8,
+        page_data: CachedPage,
+    ) {
         let key = CacheKey {
             book_hash: book_hash.to_string(),
             page,
             dpi,
+            colors,
+            rotation,
         };
-        self.cache.put(key, texture);
+        self.cache.put(key, page_data);
     }
 
     #[allow(dead_code)]
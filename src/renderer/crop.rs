@@ -0,0 +1,194 @@
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use crate::config::constants::*;
+
+/// Margins cut off a page, as shares of its width (left, right) and its
+/// height (top, bottom).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CropMargins {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+/// How a page is cropped when rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageCrop {
+    /// To the bounds of its content, found in the rendered bitmap.
+    Auto,
+    Margins(CropMargins),
+}
+
+impl CropMargins {
+    /// Margins that always leave part of the page, as files may be edited
+    /// by hand.
+    pub fn clamped(self) -> Self {
+        let clamp = |m: f32| {
+            if m.is_finite() {
+                m.clamp(0.0, CROP_MARGIN_MAX)
+            } else {
+                0.0
+            }
+        };
+        Self {
+            left: clamp(self.left),
+            top: clamp(self.top),
+            right: clamp(self.right),
+            bottom: clamp(self.bottom),
+        }
+    }
+
+    /// Maps a rectangle in page fractions to fractions of the cropped page.
+    pub fn map_rect(&self, rect: [f32; 4]) -> [f32; 4] {
+        let width = 1.0 - self.left - self.right;
+        let height = 1.0 - self.top - self.bottom;
+        [
+            (rect[0] - self.left) / width,
+            (rect[1] - self.top) / height,
+            (rect[2] - self.left) / width,
+            (rect[3] - self.top) / height,
+        ]
+    }
+}
+
+/// Margins around the content of a rendered page, leaving a little room
+/// around it. `None` for a blank page.
+///
+/// The paper colour is taken from the edges of the page; rows and columns
+/// count as content when enough of their pixels differ from it.
+pub fn content_margins(image: &RgbaImage) -> Option<CropMargins> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let luma = |x: u32, y: u32| {
+        let [r, g, b, _] = image.get_pixel(x, y).0;
+        (r as i32 * 299 + g as i32 * 587 + b as i32 * 114) / 1000
+    };
+
+    let mut edge: Vec<i32> = (0..width)
+        .flat_map(|x| [luma(x, 0), luma(x, height - 1)])
+        .chain((0..height).flat_map(|y| [luma(0, y), luma(width - 1, y)]))
+        .collect();
+    let middle = edge.len() / 2;
+    let paper = *edge.select_nth_unstable(middle).1;
+    let is_content = |x: u32, y: u32| (luma(x, y) - paper).abs() > CROP_CONTENT_THRESHOLD;
+
+    // A lone pixel is never content
+    let min_count = |length: u32| ((length as f32 * CROP_NOISE_SHARE) as usize).max(2);
+    let row_has_content =
+        |y: u32| (0..width).filter(|&x| is_content(x, y)).count() >= min_count(width);
+    let top = (0..height).find(|&y| row_has_content(y))?;
+    let bottom = (0..height).rev().find(|&y| row_has_content(y))?;
+
+    let rows = bottom - top + 1;
+    let column_has_content =
+        |x: u32| (top..=bottom).filter(|&y| is_content(x, y)).count() >= min_count(rows);
+    let left = (0..width).find(|&x| column_has_content(x))?;
+    let right = (0..width).rev().find(|&x| column_has_content(x))?;
+
+    let (width, height) = (width as f32, height as f32);
+    let margins = CropMargins {
+        left: left as f32 / width - CROP_PADDING,
+        top: top as f32 / height - CROP_PADDING,
+        right: (width - right as f32 - 1.0) / width - CROP_PADDING,
+        bottom: (height - bottom as f32 - 1.0) / height - CROP_PADDING,
+    };
+    Some(margins.clamped())
+}
+
+/// The part of a page left inside the margins.
+pub fn crop(image: &RgbaImage, margins: CropMargins) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let x = (margins.left * width as f32).round() as u32;
+    let y = (margins.top * height as f32).round() as u32;
+    let right = (margins.right * width as f32).round() as u32;
+    let bottom = (margins.bottom * height as f32).round() as u32;
+    let cropped_width = width.saturating_sub(x + right).max(1);
+    let cropped_height = height.saturating_sub(y + bottom).max(1);
+    image::imageops::crop_imm(image, x, y, cropped_width, cropped_height).to_image()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    /// A 100x200 white page with a black block at x 20..60, y 50..150.
+    fn page() -> RgbaImage {
+        RgbaImage::from_fn(100, 200, |x, y| {
+            if (20..60).contains(&x) && (50..150).contains(&y) {
+                BLACK
+            } else {
+                WHITE
+            }
+        })
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn test_content_margins() {
+        let margins = content_margins(&page()).unwrap();
+        assert_near(margins.left, 0.2 - CROP_PADDING);
+        assert_near(margins.right, 0.4 - CROP_PADDING);
+        assert_near(margins.top, 0.25 - CROP_PADDING);
+        assert_near(margins.bottom, 0.25 - CROP_PADDING);
+    }
+
+    #[test]
+    fn test_content_margins_ignore_specks() {
+        let mut image = page();
+        image.put_pixel(95, 5, BLACK);
+        let margins = content_margins(&image).unwrap();
+        assert_near(margins.top, 0.25 - CROP_PADDING);
+        assert_near(margins.right, 0.4 - CROP_PADDING);
+    }
+
+    #[test]
+    fn test_blank_page_is_not_cropped() {
+        let blank = RgbaImage::from_pixel(50, 50, WHITE);
+        assert_eq!(content_margins(&blank), None);
+    }
+
+    #[test]
+    fn test_crop() {
+        let margins = CropMargins {
+            left: 0.2,
+            top: 0.25,
+            right: 0.4,
+            bottom: 0.25,
+        };
+        let cropped = crop(&page(), margins);
+        assert_eq!(cropped.dimensions(), (40, 100));
+        assert!(cropped.pixels().all(|p| *p == BLACK));
+
+        // The block now fills the page
+        let rect = margins.map_rect([0.2, 0.25, 0.6, 0.75]);
+        for (actual, expected) in rect.into_iter().zip([0.0, 0.0, 1.0, 1.0]) {
+            assert_near(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_clamped() {
+        let margins = CropMargins {
+            left: -0.1,
+            top: 0.9,
+            right: f32::NAN,
+            bottom: 0.1,
+        };
+        let clamped = margins.clamped();
+        assert_eq!(clamped.left, 0.0);
+        assert_eq!(clamped.top, CROP_MARGIN_MAX);
+        assert_eq!(clamped.right, 0.0);
+        assert_eq!(clamped.bottom, 0.1);
+    }
+}
@@ -3,8 +3,10 @@ use image::RgbaImage;
 
 use super::traits::DocumentRenderer;
 
+#[allow(dead_code)]
 pub struct DjvuRenderer;
 
+#[allow(dead_code)]
 impl DjvuRenderer {
     pub fn new() -> Result<Self> {
         anyhow::bail!("Формат DJVU пока не поддерживается")
@@ -1,36 +1,37 @@
 use anyhow::{Context, Result};
 use image::RgbaImage;
+use rbook::read::{Content, ContentType};
 use rbook::Ebook;
 use std::collections::HashMap;
 use std::io::Write;
-use std::sync::Mutex;
+use std::sync::Arc;
 
 use crate::config::constants::*;
 
-use super::text_render::TextPageRenderer;
-use super::traits::DocumentRenderer;
+use super::page_text::{PageImage, PageText};
+use crate::config::settings::{PageColors, TypographySettings};
 
-struct CachedDocument {
-    pages: Vec<Vec<String>>,
-    scale: f32,
-}
+use super::reflow_cache::ReflowCache;
+use super::rich_text::{
+    decode_image, Align, Block, BlockBuilder, BlockFormat, BlockKind, Document,
+};
+use super::traits::DocumentRenderer;
 
 pub struct EpubRenderer {
-    text_renderer: TextPageRenderer,
-    cache: Mutex<HashMap<u64, CachedDocument>>,
+    reflow: ReflowCache,
 }
 
 impl Default for EpubRenderer {
     fn default() -> Self {
         Self {
-            text_renderer: TextPageRenderer::new(),
-            cache: Mutex::new(HashMap::new()),
+            reflow: ReflowCache::new(Box::new(Self::extract_blocks)),
         }
     }
 }
 
 impl EpubRenderer {
-    fn extract_paragraphs(bytes: &[u8]) -> Result<Vec<String>> {
+    /// Sections are the spine items.
+    fn extract_blocks(bytes: &[u8]) -> Result<Document> {
         // Write bytes to a temp file since rbook requires a file path
         let temp_dir = std::env::temp_dir().join(TEMP_DIR_NAME);
         std::fs::create_dir_all(&temp_dir)?;
@@ -42,161 +43,361 @@ impl EpubRenderer {
 
         let epub = rbook::Epub::new(&temp_path).context("Не удалось открыть EPUB")?;
 
-        let mut paragraphs = Vec::new();
+        let mut blocks = Vec::new();
+        let mut sections = Vec::new();
         let mut reader = epub.reader();
+        let mut spine_index = 0u32;
+        // Decoded images by archive path; books often repeat ornaments
+        let mut images: HashMap<String, Option<Arc<RgbaImage>>> = HashMap::new();
+
+        let mut parse = |content: &Content| {
+            let item_path = content.get_content(ContentType::Path).unwrap_or_default();
+            Self::parse_html_blocks(&content.as_lossy_str(), |src| {
+                let path = resolve_href(item_path, src);
+                images
+                    .entry(path)
+                    .or_insert_with_key(|path| {
+                        epub.read_bytes_file(path)
+                            .ok()
+                            .and_then(|bytes| decode_image(&bytes))
+                    })
+                    .clone()
+            })
+        };
 
         // Read first page
         if let Ok(content) = reader.current_page() {
-            let text_paragraphs = Self::strip_html_to_paragraphs(&content.as_lossy_str());
-            paragraphs.extend(text_paragraphs);
+            let item_blocks = parse(&content);
+            sections.extend(std::iter::repeat_n(spine_index, item_blocks.len()));
+            blocks.extend(item_blocks);
         }
 
         // Read remaining pages
         while let Some(content) = reader.next_page() {
-            let text_paragraphs = Self::strip_html_to_paragraphs(&content.as_lossy_str());
-            paragraphs.extend(text_paragraphs);
+            spine_index += 1;
+            let item_blocks = parse(&content);
+            sections.extend(std::iter::repeat_n(spine_index, item_blocks.len()));
+            blocks.extend(item_blocks);
         }
 
+        let language = epub
+            .metadata()
+            .language()
+            .map(|l| l.value().trim().to_string())
+            .filter(|l| !l.is_empty());
+
         // Clean up temp file (best effort)
         let _ = std::fs::remove_file(&temp_path);
 
-        Ok(paragraphs)
+        Ok(Document {
+            blocks,
+            sections,
+            language,
+        })
     }
 
-    /// Simple HTML tag stripper that extracts text content, splitting by block elements.
-    fn strip_html_to_paragraphs(html: &str) -> Vec<String> {
-        let mut paragraphs = Vec::new();
-        let mut current = String::new();
+    /// Simple HTML scanner that turns block elements into blocks and keeps
+    /// emphasis, headings, quotes, alignment, poem/epigraph classes and
+    /// images, which `load_image` fetches by their `src`.
+    fn parse_html_blocks(
+        html: &str,
+        mut load_image: impl FnMut(&str) -> Option<Arc<RgbaImage>>,
+    ) -> Vec<Block> {
+        let mut builder = BlockBuilder::default();
+        let mut text = String::new();
+        let mut tag = String::new();
         let mut in_tag = false;
-        let mut tag_name = String::new();
-
-        let block_tags = [
-            "p",
-            "div",
-            "h1",
-            "h2",
-            "h3",
-            "h4",
-            "h5",
-            "h6",
-            "br",
-            "li",
-            "tr",
-            "blockquote",
-        ];
+        // Inside <head>, <style> or <script>, whose text is not shown
+        let mut hidden = 0u32;
 
         for ch in html.chars() {
             match ch {
-                '<' => {
+                '<' if !in_tag => {
                     in_tag = true;
-                    tag_name.clear();
+                    tag.clear();
+                    if hidden == 0 {
+                        builder.text(&decode_entities(&text));
+                    }
+                    text.clear();
                 }
                 '>' if in_tag => {
                     in_tag = false;
-                    let tag_lower = tag_name.to_lowercase();
-                    // Strip leading '/' for closing tags
-                    let tag_base = tag_lower
-                        .trim_start_matches('/')
-                        .split_whitespace()
-                        .next()
-                        .unwrap_or("")
-                        .to_string();
-
-                    if block_tags.contains(&tag_base.as_str()) {
-                        let trimmed = current.trim().to_string();
-                        if !trimmed.is_empty() {
-                            paragraphs.push(trimmed);
+                    if hidden == 0 {
+                        if let Some(image) = Self::image_source(&tag).and_then(&mut load_image) {
+                            builder.image(image);
                         }
-                        current.clear();
                     }
+                    Self::apply_tag(&mut builder, &tag, &mut hidden);
                 }
-                _ if in_tag => {
-                    tag_name.push(ch);
-                }
-                _ => {
-                    current.push(ch);
-                }
+                _ if in_tag => tag.push(ch),
+                _ => text.push(ch),
             }
         }
-
-        let trimmed = current.trim().to_string();
-        if !trimmed.is_empty() {
-            paragraphs.push(trimmed);
+        if hidden == 0 {
+            builder.text(&decode_entities(&text));
         }
 
-        // Decode basic HTML entities
-        paragraphs
-            .into_iter()
-            .map(|p| {
-                p.replace("&amp;", "&")
-                    .replace("&lt;", "<")
-                    .replace("&gt;", ">")
-                    .replace("&quot;", "\"")
-                    .replace("&apos;", "'")
-                    .replace("&nbsp;", " ")
-            })
-            .filter(|p| !p.is_empty())
-            .collect()
+        builder.finish()
     }
 
-    fn bytes_hash(bytes: &[u8]) -> u64 {
-        use std::hash::{Hash, Hasher};
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        bytes.len().hash(&mut hasher);
-        if bytes.len() >= HASH_BUFFER_SIZE {
-            bytes[..HASH_BUFFER_SIZE].hash(&mut hasher);
-        } else {
-            bytes.hash(&mut hasher);
+    /// Source of an `<img>` or SVG `<image>` tag.
+    fn image_source(tag: &str) -> Option<&str> {
+        let name = tag.split_whitespace().next()?.to_lowercase();
+        match name.trim_end_matches('/') {
+            "img" => html_attribute(tag, "src"),
+            "image" => html_attribute(tag, "xlink:href").or_else(|| html_attribute(tag, "href")),
+            _ => None,
         }
-        hasher.finish()
     }
 
-    fn get_pages(&self, bytes: &[u8], scale: f32) -> Result<Vec<Vec<String>>> {
-        let hash = Self::bytes_hash(bytes);
+    fn apply_tag(builder: &mut BlockBuilder, tag: &str, hidden: &mut u32) {
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .trim_end_matches('/')
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
 
-        {
-            let cache = self.cache.lock().unwrap();
-            if let Some(cached) = cache.get(&hash) {
-                if (cached.scale - scale).abs() < SCALE_COMPARE_EPSILON {
-                    return Ok(cached.pages.clone());
+        match name.as_str() {
+            "head" | "style" | "script" if !self_closing => {
+                *hidden = if closing {
+                    hidden.saturating_sub(1)
+                } else {
+                    *hidden + 1
+                };
+            }
+            "b" | "strong" => builder.set_bold(!closing),
+            "i" | "em" | "cite" | "var" => builder.set_italic(!closing),
+            "br" | "hr" => builder.line_break(),
+            "p" | "div" | "li" | "tr" | "dd" | "dt" | "center" | "blockquote" | "h1" | "h2"
+            | "h3" | "h4" | "h5" | "h6" => {
+                if closing {
+                    builder.close();
+                } else if self_closing {
+                    builder.line_break();
+                } else {
+                    let format = Self::block_format(builder.format(), &name, tag);
+                    builder.open(format);
                 }
             }
+            _ => {}
         }
+    }
 
-        let paragraphs = Self::extract_paragraphs(bytes)?;
-        let pages = self.text_renderer.paginate(&paragraphs, scale);
+    /// Format of a block element nested in `parent`.
+    fn block_format(parent: BlockFormat, name: &str, tag: &str) -> BlockFormat {
+        let mut format = parent;
+        match name {
+            "blockquote" => format.indent += 1,
+            "center" => format.align = Align::Center,
+            _ => {
+                if let Some(level) = name.strip_prefix('h').and_then(|l| l.parse().ok()) {
+                    format.kind = BlockKind::Heading(level);
+                }
+            }
+        }
 
+        let class = html_attribute(tag, "class").unwrap_or("").to_lowercase();
+        if class.contains("epigraph") {
+            format.kind = BlockKind::Epigraph;
+        } else if ["poem", "stanza", "verse"]
+            .iter()
+            .any(|c| class.contains(c))
         {
-            let mut cache = self.cache.lock().unwrap();
-            cache.insert(
-                hash,
-                CachedDocument {
-                    pages: pages.clone(),
-                    scale,
-                },
-            );
+            format.kind = BlockKind::Verse;
         }
 
-        Ok(pages)
+        let style = html_attribute(tag, "style").unwrap_or("").to_lowercase();
+        let align = html_attribute(tag, "align")
+            .map(str::to_lowercase)
+            .or_else(|| {
+                let (_, rest) = style.split_once("text-align")?;
+                let value = rest.trim_start().strip_prefix(':')?;
+                Some(value.split(';').next()?.trim().to_string())
+            });
+        match align.as_deref() {
+            Some("center") => format.align = Align::Center,
+            Some("right") => format.align = Align::Right,
+            Some("left") | Some("justify") => format.align = Align::Left,
+            _ => {}
+        }
+        format
     }
 }
 
 impl DocumentRenderer for EpubRenderer {
+    fn set_typography(&self, typography: TypographySettings) {
+        self.reflow.set_typography(typography);
+    }
+
+    fn set_colors(&self, colors: PageColors) -> bool {
+        self.reflow.set_colors(colors)
+    }
+
     fn get_page_count(&self, bytes: &[u8]) -> Result<u32> {
-        let pages = self.get_pages(bytes, 1.0)?;
-        Ok(pages.len() as u32)
+        self.reflow.page_count(bytes)
     }
 
     fn render_page(&self, bytes: &[u8], page_index: u32, scale: f32) -> Result<RgbaImage> {
-        let pages = self.get_pages(bytes, scale)?;
-        let idx = page_index as usize;
-        if idx >= pages.len() {
-            anyhow::bail!(
-                "Страница {} за пределами документа ({} стр.)",
-                page_index + 1,
-                pages.len()
-            );
+        self.reflow.render_page(bytes, page_index, scale)
+    }
+
+    fn page_sections(&self, bytes: &[u8]) -> Result<Vec<u32>> {
+        self.reflow.page_sections(bytes)
+    }
+
+    fn page_positions(&self, bytes: &[u8]) -> Result<Vec<u64>> {
+        self.reflow.page_positions(bytes)
+    }
+
+    fn page_text(&self, bytes: &[u8], page_index: u32) -> Result<PageText> {
+        self.reflow.page_text(bytes, page_index)
+    }
+
+    fn page_images(&self, bytes: &[u8], page_index: u32) -> Result<Vec<PageImage>> {
+        self.reflow.page_images(bytes, page_index)
+    }
+}
+
+/// Archive path of a link relative to the document at `base`.
+fn resolve_href(base: &str, href: &str) -> String {
+    let href = href.split(['#', '?']).next().unwrap_or_default();
+    let mut parts: Vec<&str> = base.split('/').collect();
+    // Drop the document's own file name
+    parts.pop();
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
         }
-        Ok(self.text_renderer.render_page(&pages[idx], scale))
+    }
+    parts.join("/")
+}
+
+/// Value of an attribute in the text of an HTML start tag.
+fn html_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(pos) = rest.find(name) {
+        let before = rest[..pos].chars().next_back();
+        let after = rest[pos + name.len()..].trim_start();
+        rest = &rest[pos + name.len()..];
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let quote = value.chars().next()?;
+        return if quote == '"' || quote == '\'' {
+            value[1..].split(quote).next()
+        } else {
+            value.split_whitespace().next()
+        };
+    }
+    None
+}
+
+/// Decodes the common named entities and numeric character references.
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let decoded = rest.find(';').and_then(|end| {
+            let entity = &rest[1..end];
+            let ch = match entity {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => '\u{a0}',
+                "mdash" => '—',
+                "ndash" => '–',
+                "hellip" => '…',
+                "laquo" => '«',
+                "raquo" => '»',
+                _ => {
+                    let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((ch, end))
+        });
+        match decoded {
+            Some((ch, end)) => {
+                out.push(ch);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_html_styles_and_blocks() {
+        let html = r#"<html><head><title>Skip me</title><style>p { color: red }</style></head>
+            <body><h2>Chapter&nbsp;1</h2>
+            <p style="text-align: center">Some <b>bold</b> and <em>italic</em> text</p>
+            <blockquote><p>Quoted<br/>twice</p></blockquote>
+            <div class="epigraph">Words &#8212; author</div></body></html>"#;
+        let blocks = EpubRenderer::parse_html_blocks(html, |_| None);
+        let texts: Vec<String> = blocks.iter().map(Block::text).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Chapter\u{a0}1",
+                "Some bold and italic text",
+                "Quoted",
+                "twice",
+                "Words — author"
+            ]
+        );
+        assert_eq!(blocks[0].format.kind, BlockKind::Heading(2));
+        assert_eq!(blocks[1].format.align, Align::Center);
+        assert!(blocks[1].spans[1].style.bold);
+        assert!(blocks[1].spans[3].style.italic);
+        assert_eq!(blocks[2].format.indent, 1);
+        assert_eq!(blocks[3].format.indent, 1);
+        assert_eq!(blocks[4].format.kind, BlockKind::Epigraph);
+    }
+
+    #[test]
+    fn test_html_attribute() {
+        let tag = r#"p class='poem x' data-style="a" style="text-align:right""#;
+        assert_eq!(html_attribute(tag, "class"), Some("poem x"));
+        assert_eq!(html_attribute(tag, "style"), Some("text-align:right"));
+        assert_eq!(html_attribute(tag, "id"), None);
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(
+            decode_entities("a &amp; b &lt;&#x41;&#66;&gt; &bogus; &"),
+            "a & b <AB> &bogus; &"
+        );
     }
 }
@@ -1,88 +1,127 @@
 use anyhow::{Context, Result};
+use base64::Engine;
 use image::RgbaImage;
-use quick_xml::events::Event;
+use quick_xml::events::{BytesStart, Event};
 use quick_xml::Reader;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::Arc;
 
-use crate::config::constants::*;
+use super::page_text::{PageImage, PageText};
+use crate::config::settings::{PageColors, TypographySettings};
 
-use super::text_render::TextPageRenderer;
+use super::reflow_cache::ReflowCache;
+use super::rich_text::{decode_image, Align, BlockBuilder, BlockKind, Document};
 use super::traits::DocumentRenderer;
 
-/// Cached pagination result for a document.
-struct CachedDocument {
-    pages: Vec<Vec<String>>,
-    scale: f32,
-}
-
 pub struct Fb2Renderer {
-    text_renderer: TextPageRenderer,
-    cache: Mutex<HashMap<u64, CachedDocument>>,
+    reflow: ReflowCache,
 }
 
 impl Fb2Renderer {
-    fn parse_paragraphs(bytes: &[u8]) -> Result<Vec<String>> {
+    /// Sections are the top-level sections of the bodies.
+    fn parse_blocks(bytes: &[u8]) -> Result<Document> {
         let text = std::str::from_utf8(bytes).context("FB2 файл не является валидным UTF-8")?;
         let mut reader = Reader::from_str(text);
 
-        let mut paragraphs = Vec::new();
-        let mut current_text = String::new();
+        let binaries = Self::collect_binaries(text)?;
+        let mut images: HashMap<&str, Option<Arc<RgbaImage>>> = HashMap::new();
+        let mut load_image = |e: &BytesStart| {
+            let href = e
+                .attributes()
+                .flatten()
+                .find(|a| a.key.local_name().as_ref() == b"href")?
+                .unescape_value()
+                .ok()?;
+            let (id, data) = binaries.get_key_value(href.trim_start_matches('#'))?;
+            images
+                .entry(id.as_str())
+                .or_insert_with(|| {
+                    let data: String = data.split_whitespace().collect();
+                    let bytes = base64::engine::general_purpose::STANDARD
+                        .decode(data)
+                        .ok()?;
+                    decode_image(&bytes)
+                })
+                .clone()
+        };
+
+        let mut builder = BlockBuilder::default();
+        let mut sections = Vec::new();
+        let mut section_index = 0u32;
+        let mut seen_section = false;
         let mut in_body = false;
-        let mut in_p = false;
         let mut depth = 0u32;
+        // The book's own language, not the one of <src-title-info>
+        let mut in_title_info = false;
+        let mut in_lang = false;
+        let mut language = None;
 
         loop {
             match reader.read_event() {
                 Ok(Event::Start(ref e)) => {
-                    let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
                     match name.as_str() {
                         "body" => {
                             in_body = true;
                         }
-                        "p" if in_body => {
-                            in_p = true;
-                            current_text.clear();
-                        }
+                        "title-info" => in_title_info = true,
+                        "lang" if in_title_info => in_lang = true,
                         "section" if in_body => {
+                            builder.open(builder.format());
+                            if depth == 0 {
+                                // Blocks so far belong to the previous section
+                                sections.resize(builder.block_count(), section_index);
+                                if seen_section {
+                                    section_index += 1;
+                                }
+                                seen_section = true;
+                            }
                             depth += 1;
                         }
-                        "title" if in_body => {
-                            in_p = true;
-                            current_text.clear();
-                        }
-                        "empty-line" if in_body => {
-                            paragraphs.push(String::new());
+                        "image" if in_body => {
+                            if let Some(image) = load_image(e) {
+                                builder.image(image);
+                            }
                         }
+                        _ if in_body => Self::open_element(&mut builder, &name, depth),
                         _ => {}
                     }
                 }
+                Ok(Event::Empty(ref e)) if in_body => match e.local_name().as_ref() {
+                    b"empty-line" => builder.empty_line(),
+                    b"image" => {
+                        if let Some(image) = load_image(e) {
+                            builder.image(image);
+                        }
+                    }
+                    _ => {}
+                },
                 Ok(Event::End(ref e)) => {
-                    let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
                     match name.as_str() {
                         "body" => {
                             in_body = false;
                         }
-                        "p" | "title" if in_p => {
-                            in_p = false;
-                            let trimmed = current_text.trim().to_string();
-                            if !trimmed.is_empty() {
-                                paragraphs.push(trimmed);
-                            }
-                            current_text.clear();
-                        }
+                        "title-info" => in_title_info = false,
+                        "lang" => in_lang = false,
                         "section" if in_body && depth > 0 => {
+                            builder.close();
                             depth -= 1;
                         }
+                        _ if in_body => Self::close_element(&mut builder, &name),
                         _ => {}
                     }
                 }
-                Ok(Event::Text(ref e)) if in_p => {
+                Ok(Event::Text(ref e)) if in_lang => {
+                    language = e
+                        .unescape()
+                        .ok()
+                        .map(|t| t.trim().to_string())
+                        .filter(|t| !t.is_empty());
+                }
+                Ok(Event::Text(ref e)) if in_body => {
                     if let Ok(t) = e.unescape() {
-                        if !current_text.is_empty() {
-                            current_text.push(' ');
-                        }
-                        current_text.push_str(t.trim());
+                        builder.text(&t);
                     }
                 }
                 Ok(Event::Eof) => break,
@@ -93,76 +132,208 @@ impl Fb2Renderer {
             }
         }
 
-        Ok(paragraphs)
+        let blocks = builder.finish();
+        sections.resize(blocks.len(), section_index);
+        Ok(Document {
+            blocks,
+            sections,
+            language,
+        })
     }
 
-    fn bytes_hash(bytes: &[u8]) -> u64 {
-        use std::hash::{Hash, Hasher};
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        bytes.len().hash(&mut hasher);
-        if bytes.len() >= HASH_BUFFER_SIZE {
-            bytes[..HASH_BUFFER_SIZE].hash(&mut hasher);
-        } else {
-            bytes.hash(&mut hasher);
+    /// Base64 contents of the `<binary>` elements by id.
+    fn collect_binaries(text: &str) -> Result<HashMap<String, String>> {
+        let mut reader = Reader::from_str(text);
+        let mut binaries = HashMap::new();
+        let mut current: Option<String> = None;
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(ref e)) if e.local_name().as_ref() == b"binary" => {
+                    current = e
+                        .try_get_attribute("id")
+                        .ok()
+                        .flatten()
+                        .and_then(|a| a.unescape_value().ok())
+                        .map(|id| id.to_string());
+                }
+                Ok(Event::Text(ref e)) => {
+                    if let Some(id) = &current {
+                        let data = String::from_utf8_lossy(e.as_ref());
+                        binaries
+                            .entry(id.clone())
+                            .or_insert_with(String::new)
+                            .push_str(&data);
+                    }
+                }
+                Ok(Event::End(ref e)) if e.local_name().as_ref() == b"binary" => current = None,
+                Ok(Event::Eof) => break,
+                Err(e) => anyhow::bail!("Ошибка парсинга FB2: {}", e),
+                _ => {}
+            }
         }
-        hasher.finish()
+        Ok(binaries)
     }
 
-    fn get_pages(&self, bytes: &[u8], scale: f32) -> Result<Vec<Vec<String>>> {
-        let hash = Self::bytes_hash(bytes);
-
-        {
-            let cache = self.cache.lock().unwrap();
-            if let Some(cached) = cache.get(&hash) {
-                if (cached.scale - scale).abs() < SCALE_COMPARE_EPSILON {
-                    return Ok(cached.pages.clone());
-                }
+    /// Start of an element inside a body; `depth` is the section nesting.
+    fn open_element(builder: &mut BlockBuilder, name: &str, depth: u32) {
+        let mut format = builder.format();
+        match name {
+            "strong" => return builder.set_bold(true),
+            "emphasis" => return builder.set_italic(true),
+            "title" => {
+                format.kind = BlockKind::Heading((depth + 1).min(6) as u8);
+                format.align = Align::Center;
+            }
+            "subtitle" => {
+                format.kind = BlockKind::Heading(6);
+                format.align = Align::Center;
             }
+            "epigraph" => format.kind = BlockKind::Epigraph,
+            "cite" => format.indent += 1,
+            "poem" => format.kind = BlockKind::Verse,
+            "text-author" => format.align = Align::Right,
+            "empty-line" => return builder.empty_line(),
+            "p" | "v" | "stanza" => {}
+            _ => return,
         }
+        builder.open(format);
+    }
 
-        let paragraphs = Self::parse_paragraphs(bytes)?;
-        let pages = self.text_renderer.paginate(&paragraphs, scale);
-
-        {
-            let mut cache = self.cache.lock().unwrap();
-            cache.insert(
-                hash,
-                CachedDocument {
-                    pages: pages.clone(),
-                    scale,
-                },
-            );
+    fn close_element(builder: &mut BlockBuilder, name: &str) {
+        match name {
+            "strong" => builder.set_bold(false),
+            "emphasis" => builder.set_italic(false),
+            "stanza" => {
+                builder.close();
+                // Blank line between stanzas
+                builder.empty_line();
+            }
+            "title" | "subtitle" | "epigraph" | "cite" | "poem" | "text-author" | "p" | "v" => {
+                builder.close()
+            }
+            _ => {}
         }
-
-        Ok(pages)
     }
 }
 
 impl Default for Fb2Renderer {
     fn default() -> Self {
         Self {
-            text_renderer: TextPageRenderer::new(),
-            cache: Mutex::new(HashMap::new()),
+            reflow: ReflowCache::new(Box::new(Self::parse_blocks)),
         }
     }
 }
 
 impl DocumentRenderer for Fb2Renderer {
+    fn set_typography(&self, typography: TypographySettings) {
+        self.reflow.set_typography(typography);
+    }
+
+    fn set_colors(&self, colors: PageColors) -> bool {
+        self.reflow.set_colors(colors)
+    }
+
     fn get_page_count(&self, bytes: &[u8]) -> Result<u32> {
-        let pages = self.get_pages(bytes, 1.0)?;
-        Ok(pages.len() as u32)
+        self.reflow.page_count(bytes)
     }
 
     fn render_page(&self, bytes: &[u8], page_index: u32, scale: f32) -> Result<RgbaImage> {
-        let pages = self.get_pages(bytes, scale)?;
-        let idx = page_index as usize;
-        if idx >= pages.len() {
-            anyhow::bail!(
-                "Страница {} за пределами документа ({} стр.)",
-                page_index + 1,
-                pages.len()
-            );
-        }
-        Ok(self.text_renderer.render_page(&pages[idx], scale))
+        self.reflow.render_page(bytes, page_index, scale)
+    }
+
+    fn page_sections(&self, bytes: &[u8]) -> Result<Vec<u32>> {
+        self.reflow.page_sections(bytes)
+    }
+
+    fn page_positions(&self, bytes: &[u8]) -> Result<Vec<u64>> {
+        self.reflow.page_positions(bytes)
+    }
+
+    fn page_text(&self, bytes: &[u8], page_index: u32) -> Result<PageText> {
+        self.reflow.page_text(bytes, page_index)
+    }
+
+    fn page_images(&self, bytes: &[u8], page_index: u32) -> Result<Vec<PageImage>> {
+        self.reflow.page_images(bytes, page_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::rich_text::Block;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0">
+<description><title-info><book-title>Not shown</book-title><lang>en</lang></title-info>
+<src-title-info><lang>ru</lang></src-title-info></description>
+<body>
+<title><p>Book</p></title>
+<section>
+  <title><p>Chapter one</p></title>
+  <epigraph><p>Short saying</p><text-author>Someone</text-author></epigraph>
+  <p>Plain <strong>strong</strong> and <emphasis>soft</emphasis>.</p>
+  <empty-line/>
+  <poem><stanza><v>Line one</v><v>Line two</v></stanza></poem>
+</section>
+<section><cite><p>Quote</p></cite></section>
+</body>
+</FictionBook>"#;
+
+    #[test]
+    fn test_parse_blocks() {
+        let document = Fb2Renderer::parse_blocks(SAMPLE.as_bytes()).unwrap();
+        let (blocks, sections) = (document.blocks, document.sections);
+        assert_eq!(document.language.as_deref(), Some("en"));
+        let texts: Vec<String> = blocks.iter().map(Block::text).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Book",
+                "Chapter one",
+                "Short saying",
+                "Someone",
+                "Plain strong and soft.",
+                "",
+                "Line one",
+                "Line two",
+                "",
+                "Quote"
+            ]
+        );
+        assert_eq!(sections, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        assert_eq!(blocks[0].format.kind, BlockKind::Heading(1));
+        assert_eq!(blocks[1].format.kind, BlockKind::Heading(2));
+        assert_eq!(blocks[1].format.align, Align::Center);
+        assert_eq!(blocks[2].format.kind, BlockKind::Epigraph);
+        assert_eq!(blocks[3].format.align, Align::Right);
+        assert!(blocks[4].spans[1].style.bold);
+        assert!(blocks[4].spans[3].style.italic);
+        assert_eq!(blocks[6].format.kind, BlockKind::Verse);
+        assert_eq!(blocks[9].format.indent, 1);
+    }
+
+    #[test]
+    fn test_parse_images() {
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(RgbaImage::new(2, 3))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let data = base64::engine::general_purpose::STANDARD.encode(&png);
+        let (head, tail) = data.split_at(data.len() / 2);
+        let fb2 = format!(
+            r##"<FictionBook xmlns:l="http://www.w3.org/1999/xlink"><body><section>
+<p>Before</p><image l:href="#pic.png"/><p>After</p><image l:href="#missing"/>
+</section></body><binary id="pic.png" content-type="image/png">{head}
+{tail}</binary></FictionBook>"##
+        );
+        let blocks = Fb2Renderer::parse_blocks(fb2.as_bytes()).unwrap().blocks;
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(
+            blocks[1].image.as_ref().map(|i| i.dimensions()),
+            Some((2, 3))
+        );
+        assert_eq!(blocks[2].text(), "After");
     }
 }
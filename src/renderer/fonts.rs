@@ -0,0 +1,289 @@
+use ab_glyph::FontRef;
+use fontdb::{Database, Family, Query, Source, Style, Weight};
+use once_cell::sync::Lazy;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use unicode_script::{Script, UnicodeScript};
+
+use crate::config::constants::*;
+use crate::config::settings::AppSettings;
+
+use super::text_render::FontFace;
+
+static EMBEDDED_FONT: &[u8] = include_bytes!("../../libs/fonts/NotoSans-Regular.ttf");
+static EMBEDDED_FONT_BOLD: &[u8] = include_bytes!("../../libs/fonts/DejaVuSans-Bold.ttf");
+static EMBEDDED_FONT_ITALIC: &[u8] = include_bytes!("../../libs/fonts/DejaVuSans-Oblique.ttf");
+static EMBEDDED_FONT_BOLD_ITALIC: &[u8] =
+    include_bytes!("../../libs/fonts/DejaVuSans-BoldOblique.ttf");
+
+/// Fonts of the system and of the user fonts folder, scanned on first use.
+pub static SYSTEM_FONTS: Lazy<FontLibrary> = Lazy::new(|| {
+    let mut dirs = Vec::new();
+    if let Ok(dir) = AppSettings::fonts_dir() {
+        dirs.push(dir);
+    }
+    FontLibrary::scan(&dirs, true)
+});
+
+/// Index of a font in a [`FontSet`].
+pub type FontId = usize;
+
+/// A font face parsed for drawing and for shaping.
+pub struct LoadedFont {
+    pub glyphs: FontRef<'static>,
+    pub shaper: rustybuzz::Face<'static>,
+}
+
+impl LoadedFont {
+    fn new(data: &'static [u8], index: u32) -> Option<Self> {
+        Some(Self {
+            glyphs: FontRef::try_from_slice_and_index(data, index).ok()?,
+            shaper: rustybuzz::Face::from_slice(data, index)?,
+        })
+    }
+
+    fn has_glyph(&self, ch: char) -> bool {
+        self.shaper.glyph_index(ch).is_some()
+    }
+}
+
+/// Font files found on disk, by family.
+pub struct FontLibrary {
+    db: Database,
+    families: Vec<String>,
+    /// Contents of the font files in use. Faces borrow them, so they stay
+    /// loaded for the life of the process; each file is read once.
+    files: Mutex<HashMap<PathBuf, &'static [u8]>>,
+}
+
+impl FontLibrary {
+    /// Scans `dirs` and, if `system` is set, the system font folders.
+    pub fn scan(dirs: &[PathBuf], system: bool) -> Self {
+        let mut db = Database::new();
+        if system {
+            db.load_system_fonts();
+        }
+        for dir in dirs {
+            db.load_fonts_dir(dir);
+        }
+        let families: BTreeSet<String> = db
+            .faces()
+            .filter_map(|face| face.families.first())
+            .map(|(name, _)| name.clone())
+            .collect();
+        Self {
+            db,
+            families: families.into_iter().collect(),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Family names, sorted.
+    pub fn families(&self) -> &[String] {
+        &self.families
+    }
+
+    /// The face of `family` closest to `face`.
+    fn load(&self, family: &str, face: FontFace) -> Option<LoadedFont> {
+        let (bold, italic) = match face {
+            FontFace::Regular => (false, false),
+            FontFace::Bold => (true, false),
+            FontFace::Italic => (false, true),
+            FontFace::BoldItalic => (true, true),
+        };
+        let id = self.db.query(&Query {
+            families: &[Family::Name(family)],
+            weight: if bold { Weight::BOLD } else { Weight::NORMAL },
+            style: if italic { Style::Italic } else { Style::Normal },
+            ..Query::default()
+        })?;
+        let (source, index) = self.db.face_source(id)?;
+        let Source::File(path) = source else {
+            return None;
+        };
+        LoadedFont::new(self.file(&path)?, index)
+    }
+
+    fn file(&self, path: &Path) -> Option<&'static [u8]> {
+        let mut files = self.files.lock().unwrap();
+        if let Some(data) = files.get(path) {
+            return Some(data);
+        }
+        let data: &'static [u8] = Box::leak(std::fs::read(path).ok()?.into_boxed_slice());
+        files.insert(path.to_path_buf(), data);
+        Some(data)
+    }
+}
+
+/// Fonts text is set in: a reading family, with fallbacks for the
+/// characters it lacks. Fallback fonts are loaded when first needed.
+pub struct FontSet {
+    library: Option<&'static Lazy<FontLibrary>>,
+    fonts: RwLock<Vec<Arc<LoadedFont>>>,
+    /// Font of each face of the reading family.
+    reading: [FontId; 4],
+    /// Faces of fallback families, `None` if not installed.
+    fallbacks: Mutex<HashMap<(&'static str, FontFace), Option<FontId>>>,
+    /// Font found for characters the reading family lacks.
+    resolved: Mutex<HashMap<(char, FontFace), FontId>>,
+}
+
+impl FontSet {
+    /// The embedded faces alone.
+    pub fn embedded() -> Self {
+        let fonts = [
+            EMBEDDED_FONT,
+            EMBEDDED_FONT_BOLD,
+            EMBEDDED_FONT_ITALIC,
+            EMBEDDED_FONT_BOLD_ITALIC,
+        ]
+        .map(|data| Arc::new(LoadedFont::new(data, 0).expect("Failed to load embedded font")));
+        Self {
+            library: None,
+            fonts: RwLock::new(fonts.into()),
+            reading: [0, 1, 2, 3],
+            fallbacks: Mutex::new(HashMap::new()),
+            resolved: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `family` from `library`, or the embedded faces if it is `None` or
+    /// not installed, with fallbacks from `library`.
+    pub fn new(library: &'static Lazy<FontLibrary>, family: Option<&str>) -> Self {
+        let mut set = Self {
+            library: Some(library),
+            ..Self::embedded()
+        };
+        let Some(family) = family else {
+            return set;
+        };
+        let faces = [
+            FontFace::Regular,
+            FontFace::Bold,
+            FontFace::Italic,
+            FontFace::BoldItalic,
+        ];
+        let loaded: Option<Vec<LoadedFont>> = faces
+            .iter()
+            .map(|&face| library.load(family, face))
+            .collect();
+        if let Some(loaded) = loaded {
+            let fonts = set.fonts.get_mut().unwrap();
+            for (face, font) in faces.into_iter().zip(loaded) {
+                set.reading[face as usize] = fonts.len();
+                fonts.push(Arc::new(font));
+            }
+        }
+        set
+    }
+
+    pub fn font(&self, id: FontId) -> Arc<LoadedFont> {
+        Arc::clone(&self.fonts.read().unwrap()[id])
+    }
+
+    /// Font of the reading family for `face`.
+    pub fn reading(&self, face: FontFace) -> FontId {
+        self.reading[face as usize]
+    }
+
+    /// Font to draw `ch` in. Spaces, punctuation and marks shared by all
+    /// scripts stay in `previous`, the font of the character before, if it
+    /// has them; other characters missing from the reading family are
+    /// looked up in the fallbacks for their script, the embedded face and
+    /// the fallbacks for any script, in that order.
+    pub fn font_for(&self, ch: char, face: FontFace, previous: Option<FontId>) -> FontId {
+        let script = ch.script();
+        if let Some(previous) = previous {
+            if matches!(script, Script::Common | Script::Inherited)
+                && self.font(previous).has_glyph(ch)
+            {
+                return previous;
+            }
+        }
+        let reading = self.reading(face);
+        if self.font(reading).has_glyph(ch) {
+            return reading;
+        }
+        if let Some(&id) = self.resolved.lock().unwrap().get(&(ch, face)) {
+            return id;
+        }
+
+        let code = script.short_name();
+        let for_script = TEXT_FALLBACK_FONTS
+            .iter()
+            .filter(|(scripts, _)| scripts.contains(&code))
+            .flat_map(|(_, families)| families.iter());
+        let embedded = face as FontId;
+        let id = for_script
+            .filter_map(|family| self.fallback(family, face))
+            .chain(std::iter::once(embedded))
+            .chain(
+                TEXT_FALLBACK_FONTS_ANY
+                    .iter()
+                    .filter_map(|family| self.fallback(family, face)),
+            )
+            .find(|&id| self.font(id).has_glyph(ch))
+            .unwrap_or(reading);
+        self.resolved.lock().unwrap().insert((ch, face), id);
+        id
+    }
+
+    /// A face of a fallback family, loaded on first use.
+    fn fallback(&self, family: &'static str, face: FontFace) -> Option<FontId> {
+        let library = self.library?;
+        let mut fallbacks = self.fallbacks.lock().unwrap();
+        *fallbacks.entry((family, face)).or_insert_with(|| {
+            let font = library.load(family, face)?;
+            let mut fonts = self.fonts.write().unwrap();
+            fonts.push(Arc::new(font));
+            Some(fonts.len() - 1)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TEST_FONTS: Lazy<FontLibrary> =
+        Lazy::new(|| FontLibrary::scan(&[PathBuf::from("libs/fonts")], false));
+
+    #[test]
+    fn test_library_families() {
+        let families = TEST_FONTS.families();
+        assert!(families.iter().any(|f| f == "DejaVu Sans"));
+        assert!(families.iter().any(|f| f == "Noto Sans"));
+        assert!(families.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_reading_family() {
+        let set = FontSet::new(&TEST_FONTS, Some("DejaVu Sans"));
+        let regular = set.reading(FontFace::Regular);
+        assert!(regular >= 4, "expected a font from the library");
+        assert_eq!(set.font_for('a', FontFace::Regular, None), regular);
+
+        // Unknown families fall back to the embedded faces
+        let set = FontSet::new(&TEST_FONTS, Some("No Such Font"));
+        assert_eq!(set.reading(FontFace::Bold), FontFace::Bold as FontId);
+    }
+
+    #[test]
+    fn test_fallback_for_missing_glyphs() {
+        let set = FontSet::new(&TEST_FONTS, None);
+        assert_eq!(set.font_for('a', FontFace::Regular, None), 0);
+        // The embedded regular face has no Hebrew
+        let hebrew = set.font_for('ש', FontFace::Regular, None);
+        assert_ne!(hebrew, 0);
+        assert!(set.font(hebrew).has_glyph('ש'));
+        // A space stays in the font of the text before it
+        assert_eq!(set.font_for(' ', FontFace::Regular, Some(hebrew)), hebrew);
+        assert_eq!(set.font_for('a', FontFace::Regular, Some(hebrew)), 0);
+
+        // Without a library only the embedded faces are tried
+        let set = FontSet::embedded();
+        let bold = set.font_for('ש', FontFace::Bold, None);
+        assert_eq!(bold, FontFace::Bold as FontId);
+    }
+}
@@ -29,6 +29,13 @@ impl DocumentFormat {
         }
     }
 
+    /// Whether the text is laid out by the reader rather than the file,
+    /// so pages depend on the typography settings.
+    pub fn is_reflowable(&self) -> bool {
+        matches!(self, Self::Epub | Self::Fb2)
+    }
+
+    #[allow(dead_code)]
     pub fn supported_extensions() -> &'static [&'static str] {
         &["pdf", "epub", "fb2", "djvu", "djv"]
     }
@@ -110,6 +117,14 @@ mod tests {
         assert_eq!(DocumentFormat::Djvu.display_name(), "DJVU");
     }
 
+    #[test]
+    fn test_is_reflowable() {
+        assert!(DocumentFormat::Epub.is_reflowable());
+        assert!(DocumentFormat::Fb2.is_reflowable());
+        assert!(!DocumentFormat::Pdf.is_reflowable());
+        assert!(!DocumentFormat::Djvu.is_reflowable());
+    }
+
     #[test]
     fn test_supported_extensions() {
         let exts = DocumentFormat::supported_extensions();
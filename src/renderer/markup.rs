@@ -0,0 +1,98 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+
+/// A highlight as stored in a document file, located by its range in the
+/// page's text (the same indices as [`super::page_text::PageText`]).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Markup {
+    pub page_index: u32,
+    /// Inclusive character range.
+    pub start: usize,
+    pub end: usize,
+    /// Highlighted text; filled in when reading, ignored when writing.
+    pub text: String,
+    pub color: [u8; 3],
+    pub note: String,
+    pub created: DateTime<Utc>,
+    /// Last edit of the highlight or its note.
+    pub updated: DateTime<Utc>,
+}
+
+/// A note pinned to a page rather than to a piece of text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageNote {
+    pub page_index: u32,
+    pub text: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+/// Annotations read from a document file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentMarkups {
+    pub markups: Vec<Markup>,
+    pub notes: Vec<PageNote>,
+}
+
+/// Parses a PDF date string, `D:YYYYMMDDHHmmSSOHH'mm'` where everything
+/// after the year may be missing.
+pub fn parse_pdf_date(date: &str) -> Option<DateTime<Utc>> {
+    let date = date.trim();
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let digits = date.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 {
+        return None;
+    }
+    let field = |at: usize, default: u32| {
+        date.get(at..at + 2)
+            .filter(|_| at + 2 <= digits)
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(default)
+    };
+    let year = date[..4].parse().ok()?;
+    let local = NaiveDate::from_ymd_opt(year, field(4, 1), field(6, 1))?.and_hms_opt(
+        field(8, 0),
+        field(10, 0),
+        field(12, 0),
+    )?;
+
+    let zone = &date[digits..];
+    let sign = match zone.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => 0,
+    };
+    let offset = if sign == 0 {
+        0
+    } else {
+        let zone: String = zone[1..].chars().filter(char::is_ascii_digit).collect();
+        let hours: i32 = zone.get(..2).and_then(|h| h.parse().ok()).unwrap_or(0);
+        let minutes: i32 = zone.get(2..4).and_then(|m| m.parse().ok()).unwrap_or(0);
+        sign * (hours * 3600 + minutes * 60)
+    };
+    let offset = FixedOffset::east_opt(offset)?;
+    Some(
+        offset
+            .from_local_datetime(&local)
+            .single()?
+            .with_timezone(&Utc),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pdf_date() {
+        let expected = Utc.with_ymd_and_hms(2024, 3, 5, 10, 20, 30).unwrap();
+        assert_eq!(parse_pdf_date("D:20240305102030Z"), Some(expected));
+        assert_eq!(parse_pdf_date("D:20240305132030+03'00'"), Some(expected));
+        assert_eq!(parse_pdf_date("D:20240305052030-05'00"), Some(expected));
+        assert_eq!(
+            parse_pdf_date("D:2024"),
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap())
+        );
+        assert_eq!(parse_pdf_date("D:20241305"), None);
+        assert_eq!(parse_pdf_date("вчера"), None);
+    }
+}
@@ -1,10 +1,19 @@
 pub mod cache;
+pub mod crop;
 pub mod djvu;
 pub mod epub;
 pub mod fb2;
+pub mod fonts;
 pub mod format;
+pub mod markup;
+pub mod page_text;
 pub mod pdf;
+pub mod pdf_reflow;
+pub mod reflow_cache;
+pub mod rich_text;
+pub mod rotation;
 pub mod text_render;
+pub mod theme;
 pub mod traits;
 
 use std::collections::HashMap;
@@ -14,13 +23,17 @@ use traits::DocumentRenderer;
 
 pub struct RendererRegistry {
     renderers: HashMap<DocumentFormat, Box<dyn DocumentRenderer>>,
+    /// Renderers laying out the text of fixed-layout formats again
+    reflowed: HashMap<DocumentFormat, Box<dyn DocumentRenderer>>,
 }
 
 impl RendererRegistry {
     pub fn new() -> Self {
         let mut renderers: HashMap<DocumentFormat, Box<dyn DocumentRenderer>> = HashMap::new();
+        let mut reflowed: HashMap<DocumentFormat, Box<dyn DocumentRenderer>> = HashMap::new();
 
         if let Ok(r) = pdf::PdfRenderer::new() {
+            reflowed.insert(DocumentFormat::Pdf, Box::new(r.reflowed()));
             renderers.insert(DocumentFormat::Pdf, Box::new(r));
         }
 
@@ -32,17 +45,27 @@ impl RendererRegistry {
 
         // DJVU not yet supported — intentionally omitted
 
-        Self { renderers }
+        Self {
+            renderers,
+            reflowed,
+        }
     }
 
     pub fn get(&self, format: &DocumentFormat) -> Option<&dyn DocumentRenderer> {
         self.renderers.get(format).map(|r| r.as_ref())
     }
 
+    /// Renderer of the format with its text reflowed, if it can be.
+    pub fn get_reflowed(&self, format: &DocumentFormat) -> Option<&dyn DocumentRenderer> {
+        self.reflowed.get(format).map(|r| r.as_ref())
+    }
+
+    #[allow(dead_code)]
     pub fn supports(&self, format: &DocumentFormat) -> bool {
         self.renderers.contains_key(format)
     }
 
+    #[allow(dead_code)]
     pub fn has_pdf(&self) -> bool {
         self.renderers.contains_key(&DocumentFormat::Pdf)
     }
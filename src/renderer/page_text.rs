@@ -0,0 +1,214 @@
+use image::RgbaImage;
+use std::sync::Arc;
+
+/// Marks the hyphen the layout adds where it splits a word; drawn as `-`,
+/// but left out of copied text together with the line break after it.
+pub const SOFT_HYPHEN: char = '\u{ad}';
+
+/// An image on a rendered page, at full resolution. `rect` is in page
+/// fractions like [`TextChar::rect`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageImage {
+    pub rect: [f32; 4],
+    pub image: Arc<RgbaImage>,
+}
+
+/// A character on a rendered page. `rect` is `[min_x, min_y, max_x, max_y]`
+/// in page fractions (0..1, origin top left), so it holds at any zoom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextChar {
+    pub ch: char,
+    pub rect: [f32; 4],
+}
+
+/// Characters of a page in reading order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageText {
+    pub chars: Vec<TextChar>,
+    /// Where each character is in reflowed text, as in
+    /// [`PageStart::position`](super::text_render::PageStart::position), so
+    /// it can be found again in another layout. Empty on fixed pages.
+    pub positions: Vec<u64>,
+}
+
+impl PageText {
+    pub fn position(&self, index: usize) -> Option<u64> {
+        self.positions.get(index).copied()
+    }
+
+    /// Inclusive range of the characters on the page between two positions,
+    /// e.g. the part of a highlight laid out on it.
+    pub fn range_of(&self, start: u64, end: u64) -> Option<(usize, usize)> {
+        let mut matching = self
+            .chars
+            .iter()
+            .zip(&self.positions)
+            .enumerate()
+            .filter(|(_, (c, &p))| !c.ch.is_control() && p >= start && p <= end)
+            .map(|(i, _)| i);
+        let first = matching.next()?;
+        Some((first, matching.next_back().unwrap_or(first)))
+    }
+
+    /// Index of the character closest to a point in page fractions.
+    pub fn char_at(&self, x: f32, y: f32) -> Option<usize> {
+        let distance = |rect: &[f32; 4]| {
+            let dx = (rect[0] - x).max(x - rect[2]).max(0.0);
+            // Vertical distance dominates, so the point picks a line first
+            let dy = (rect[1] - y).max(y - rect[3]).max(0.0);
+            dy * 10.0 + dx
+        };
+        self.chars
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.ch.is_control())
+            .min_by(|(_, a), (_, b)| distance(&a.rect).total_cmp(&distance(&b.rect)))
+            .map(|(i, _)| i)
+    }
+
+    /// Text of the inclusive range `start..=end`, on one line.
+    pub fn text(&self, start: usize, end: usize) -> String {
+        let (start, end) = (start.min(end), start.max(end));
+        let mut text = String::new();
+        let mut hyphenated = false;
+        for c in self.chars.iter().take(end + 1).skip(start) {
+            if c.ch == SOFT_HYPHEN {
+                hyphenated = true;
+                continue;
+            }
+            if std::mem::take(&mut hyphenated) && c.ch == '\n' {
+                continue;
+            }
+            let ch = if c.ch.is_whitespace() { ' ' } else { c.ch };
+            if ch.is_control() || (ch == ' ' && text.ends_with(' ')) {
+                continue;
+            }
+            text.push(ch);
+        }
+        text.trim().to_string()
+    }
+
+    /// One rectangle per line covered by the inclusive range `start..=end`.
+    pub fn line_rects(&self, start: usize, end: usize) -> Vec<[f32; 4]> {
+        let (start, end) = (start.min(end), start.max(end));
+        let mut rects: Vec<[f32; 4]> = Vec::new();
+        for c in self.chars.iter().take(end + 1).skip(start) {
+            let r = c.rect;
+            if c.ch.is_control() || r[2] <= r[0] || r[3] <= r[1] {
+                continue;
+            }
+            match rects.last_mut() {
+                // Same line: vertical centre inside the previous rectangle
+                Some(last) if (r[1] + r[3]) / 2.0 > last[1] && (r[1] + r[3]) / 2.0 < last[3] => {
+                    last[0] = last[0].min(r[0]);
+                    last[1] = last[1].min(r[1]);
+                    last[2] = last[2].max(r[2]);
+                    last[3] = last[3].max(r[3]);
+                }
+                _ => rects.push(r),
+            }
+        }
+        rects
+    }
+
+    /// Inclusive range of the characters whose centres lie in any of the
+    /// rectangles, e.g. the areas of a highlight annotation.
+    pub fn range_in(&self, rects: &[[f32; 4]]) -> Option<(usize, usize)> {
+        let inside = |c: &TextChar| {
+            let (x, y) = ((c.rect[0] + c.rect[2]) / 2.0, (c.rect[1] + c.rect[3]) / 2.0);
+            rects
+                .iter()
+                .any(|r| x >= r[0] && x <= r[2] && y >= r[1] && y <= r[3])
+        };
+        let mut matching = self
+            .chars
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.ch.is_control() && inside(c))
+            .map(|(i, _)| i);
+        let start = matching.next()?;
+        Some((start, matching.next_back().unwrap_or(start)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two lines, "ab" and "cd", each character 0.1 wide and 0.1 tall.
+    fn sample() -> PageText {
+        let c = |ch, x: f32, y: f32| TextChar {
+            ch,
+            rect: [x, y, x + 0.1, y + 0.1],
+        };
+        PageText {
+            chars: vec![
+                c('a', 0.0, 0.0),
+                c('b', 0.1, 0.0),
+                TextChar {
+                    ch: '\n',
+                    rect: [0.0; 4],
+                },
+                c('c', 0.0, 0.2),
+                c('d', 0.1, 0.2),
+            ],
+            positions: vec![0, 1, 1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn test_char_at() {
+        let text = sample();
+        assert_eq!(text.char_at(0.15, 0.05), Some(1));
+        assert_eq!(text.char_at(0.05, 0.25), Some(3));
+        // Past the end of a line picks its last character
+        assert_eq!(text.char_at(0.9, 0.22), Some(4));
+        assert_eq!(PageText::default().char_at(0.5, 0.5), None);
+    }
+
+    #[test]
+    fn test_range_of_positions() {
+        let text = sample();
+        assert_eq!(text.range_of(1, 2), Some((1, 3)));
+        // Clipped to the page
+        assert_eq!(text.range_of(2, 100), Some((3, 4)));
+        assert_eq!(text.range_of(5, 8), None);
+    }
+
+    #[test]
+    fn test_text_across_lines() {
+        let text = sample();
+        assert_eq!(text.text(1, 3), "b c");
+        assert_eq!(text.text(4, 0), "ab cd");
+    }
+
+    #[test]
+    fn test_text_joins_hyphenated_word() {
+        let mut text = sample();
+        text.chars.insert(
+            2,
+            TextChar {
+                ch: SOFT_HYPHEN,
+                rect: [0.2, 0.0, 0.25, 0.1],
+            },
+        );
+        assert_eq!(text.text(0, 5), "abcd");
+    }
+
+    #[test]
+    fn test_line_rects() {
+        let rects = sample().line_rects(0, 4);
+        assert_eq!(rects.len(), 2);
+        assert_eq!(rects[0], [0.0, 0.0, 0.2, 0.1]);
+        assert_eq!(rects[1], [0.0, 0.2, 0.2, 0.3]);
+    }
+
+    #[test]
+    fn test_range_in() {
+        let text = sample();
+        let rects = text.line_rects(1, 3);
+        assert_eq!(text.range_in(&rects), Some((1, 3)));
+        assert_eq!(text.range_in(&[[0.0, 0.15, 0.05, 0.35]]), Some((3, 3)));
+        assert_eq!(text.range_in(&[[0.5, 0.5, 0.6, 0.6]]), None);
+    }
+}
@@ -3,9 +3,14 @@ use image::{DynamicImage, RgbaImage};
 use once_cell::sync::Lazy;
 use pdfium_render::prelude::*;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Mutex;
 
 use crate::config::constants::*;
 
+use super::markup::{parse_pdf_date, DocumentMarkups, Markup, PageNote};
+use super::page_text::{PageText, TextChar};
+use super::pdf_reflow::PdfReflowRenderer;
 use super::traits::DocumentRenderer;
 
 static EMBEDDED_PDFIUM: &[u8] = include_bytes!("../../libs/pdfium.dll");
@@ -30,7 +35,9 @@ static PDFIUM_DLL_PATH: Lazy<Result<PathBuf, String>> = Lazy::new(|| {
 });
 
 pub struct PdfRenderer {
-    pdfium: Pdfium,
+    pdfium: Rc<Pdfium>,
+    // Quarter turns clockwise pages are drawn at
+    rotation: Mutex<u8>,
 }
 
 impl PdfRenderer {
@@ -46,9 +53,31 @@ impl PdfRenderer {
         .context("Failed to load pdfium library")?;
 
         Ok(Self {
-            pdfium: Pdfium::new(bindings),
+            pdfium: Rc::new(Pdfium::new(bindings)),
+            rotation: Mutex::new(0),
         })
     }
+
+    /// Renderer of the same documents with their text reflowed, sharing
+    /// the loaded library.
+    pub fn reflowed(&self) -> PdfReflowRenderer {
+        PdfReflowRenderer::new(Rc::clone(&self.pdfium))
+    }
+}
+
+impl PdfRenderer {
+    fn page<'a>(document: &'a PdfDocument<'_>, page_index: u32) -> Result<PdfPage<'a>> {
+        let page_index_u16: u16 = page_index.try_into().map_err(|_| {
+            anyhow::anyhow!(
+                "Page index {} exceeds maximum supported (65535)",
+                page_index
+            )
+        })?;
+        document
+            .pages()
+            .get(page_index_u16)
+            .context("Page index out of bounds")
+    }
 }
 
 impl DocumentRenderer for PdfRenderer {
@@ -61,29 +90,34 @@ impl DocumentRenderer for PdfRenderer {
         Ok(document.pages().len() as u32)
     }
 
+    /// pdfium turns pages as it draws them.
+    fn set_rotation(&self, quarter_turns: u8) -> bool {
+        *self.rotation.lock().unwrap() = quarter_turns;
+        true
+    }
+
     fn render_page(&self, bytes: &[u8], page_index: u32, scale: f32) -> Result<RgbaImage> {
         let document = self
             .pdfium
             .load_pdf_from_byte_slice(bytes, None)
             .context("Failed to load PDF")?;
 
-        let pages = document.pages();
-        let page_index_u16: u16 = page_index.try_into().map_err(|_| {
-            anyhow::anyhow!(
-                "Page index {} exceeds maximum supported (65535)",
-                page_index
-            )
-        })?;
-        let page = pages
-            .get(page_index_u16)
-            .context("Page index out of bounds")?;
+        let page = Self::page(&document, page_index)?;
 
         let width = (page.width().value * scale) as i32;
         let height = (page.height().value * scale) as i32;
+        let rotation = match *self.rotation.lock().unwrap() % 4 {
+            1 => PdfPageRenderRotation::Degrees90,
+            2 => PdfPageRenderRotation::Degrees180,
+            3 => PdfPageRenderRotation::Degrees270,
+            _ => PdfPageRenderRotation::None,
+        };
 
+        // The target size turns with the page
         let config = PdfRenderConfig::new()
             .set_target_width(width)
-            .set_target_height(height);
+            .set_target_height(height)
+            .rotate(rotation, true);
 
         let bitmap = page
             .render_with_config(&config)
@@ -92,4 +126,233 @@ impl DocumentRenderer for PdfRenderer {
         let dynamic_image: DynamicImage = bitmap.as_image();
         Ok(dynamic_image.to_rgba8())
     }
+
+    fn page_sizes(&self, bytes: &[u8]) -> Result<Vec<[f32; 2]>> {
+        let document = self
+            .pdfium
+            .load_pdf_from_byte_slice(bytes, None)
+            .context("Failed to load PDF")?;
+
+        let sizes = document
+            .pages()
+            .page_sizes()
+            .context("Failed to read page sizes")?;
+        Ok(sizes
+            .iter()
+            .map(|rect| [rect.width().value, rect.height().value])
+            .collect())
+    }
+
+    fn page_text(&self, bytes: &[u8], page_index: u32) -> Result<PageText> {
+        let document = self
+            .pdfium
+            .load_pdf_from_byte_slice(bytes, None)
+            .context("Failed to load PDF")?;
+        let page = Self::page(&document, page_index)?;
+        Self::text_of(&page)
+    }
+
+    fn write_markups(&self, bytes: &[u8], markups: &[Markup]) -> Result<Vec<u8>> {
+        let document = self
+            .pdfium
+            .load_pdf_from_byte_slice(bytes, None)
+            .context("Failed to load PDF")?;
+
+        let mut markups: Vec<&Markup> = markups.iter().collect();
+        markups.sort_by_key(|m| m.page_index);
+        for on_page in markups.chunk_by(|a, b| a.page_index == b.page_index) {
+            let mut page = Self::page(&document, on_page[0].page_index)?;
+            let text = Self::text_of(&page)?;
+            let (width, height) = (page.width().value, page.height().value);
+            // Page fractions (origin top left) to PDF points (origin bottom left)
+            let to_pdf = |r: [f32; 4]| {
+                PdfRect::new_from_values(
+                    (1.0 - r[3]) * height,
+                    r[0] * width,
+                    (1.0 - r[1]) * height,
+                    r[2] * width,
+                )
+            };
+
+            for markup in on_page {
+                let lines: Vec<PdfRect> = text
+                    .line_rects(markup.start, markup.end)
+                    .into_iter()
+                    .map(to_pdf)
+                    .collect();
+                let Some(first) = lines.first() else {
+                    continue;
+                };
+                let bounds = lines.iter().fold(*first, |a, b| {
+                    PdfRect::new(
+                        a.bottom().min(b.bottom()),
+                        a.left().min(b.left()),
+                        a.top().max(b.top()),
+                        a.right().max(b.right()),
+                    )
+                });
+                let [r, g, b] = markup.color;
+                let color = PdfColor::new(r, g, b, 255);
+
+                let annotations = page.annotations_mut();
+                let mut highlight = annotations
+                    .create_highlight_annotation()
+                    .context("Failed to add highlight annotation")?;
+                highlight.set_bounds(bounds)?;
+                for line in &lines {
+                    highlight
+                        .attachment_points_mut()
+                        .create_attachment_point_at_end(PdfQuadPoints::from_rect(line))?;
+                }
+                highlight.set_stroke_color(color)?;
+                highlight.set_creation_date(markup.created)?;
+                highlight.set_modification_date(markup.updated)?;
+                if markup.note.is_empty() {
+                    continue;
+                }
+                highlight.set_contents(&markup.note)?;
+
+                // Sticky note at the end of the first line, for readers that
+                // do not show a highlight's contents
+                let left = first.right().value.min(width - PDF_NOTE_ICON_SIZE);
+                let top = first.top().value;
+                let mut note = annotations
+                    .create_text_annotation(&markup.note)
+                    .context("Failed to add note annotation")?;
+                note.set_bounds(PdfRect::new_from_values(
+                    top - PDF_NOTE_ICON_SIZE,
+                    left,
+                    top,
+                    left + PDF_NOTE_ICON_SIZE,
+                ))?;
+                note.set_stroke_color(color)?;
+                note.set_creation_date(markup.created)?;
+                note.set_modification_date(markup.updated)?;
+            }
+        }
+
+        document
+            .save_to_bytes()
+            .context("Failed to save annotated PDF")
+    }
+
+    fn read_markups(&self, bytes: &[u8]) -> Result<DocumentMarkups> {
+        let document = self
+            .pdfium
+            .load_pdf_from_byte_slice(bytes, None)
+            .context("Failed to load PDF")?;
+
+        let mut result = DocumentMarkups::default();
+        for (page_index, page) in document.pages().iter().enumerate() {
+            let page_index = page_index as u32;
+            let (width, height) = (page.width().value, page.height().value);
+            if width <= 0.0 || height <= 0.0 || page.annotations().is_empty() {
+                continue;
+            }
+            let text = Self::text_of(&page)?;
+            let to_fractions = |r: PdfRect| {
+                [
+                    r.left().value / width,
+                    1.0 - r.top().value / height,
+                    r.right().value / width,
+                    1.0 - r.bottom().value / height,
+                ]
+            };
+
+            let mut notes = Vec::new();
+            for annotation in page.annotations().iter() {
+                let contents = annotation.contents().unwrap_or_default();
+                // Files without dates count as edited before anything in the app
+                let created = annotation
+                    .creation_date()
+                    .and_then(|d| parse_pdf_date(&d))
+                    .unwrap_or_default();
+                let updated = annotation
+                    .modification_date()
+                    .and_then(|d| parse_pdf_date(&d))
+                    .unwrap_or(created);
+                if let Some(highlight) = annotation.as_highlight_annotation() {
+                    let mut areas: Vec<[f32; 4]> = highlight
+                        .attachment_points()
+                        .iter()
+                        .map(|quad| to_fractions(quad.to_rect()))
+                        .collect();
+                    if areas.is_empty() {
+                        areas.extend(highlight.bounds().ok().map(to_fractions));
+                    }
+                    let Some((start, end)) = text.range_in(&areas) else {
+                        continue;
+                    };
+                    let color = highlight
+                        .stroke_color()
+                        .or_else(|_| highlight.fill_color())
+                        .map(|c| [c.red(), c.green(), c.blue()])
+                        .unwrap_or(PDF_HIGHLIGHT_DEFAULT_RGB);
+                    result.markups.push(Markup {
+                        page_index,
+                        start,
+                        end,
+                        text: text.text(start, end),
+                        color,
+                        note: contents.trim().to_string(),
+                        created,
+                        updated,
+                    });
+                } else if annotation.annotation_type() == PdfPageAnnotationType::Text
+                    && !contents.trim().is_empty()
+                {
+                    notes.push(PageNote {
+                        page_index,
+                        text: contents.trim().to_string(),
+                        created,
+                        updated,
+                    });
+                }
+            }
+
+            // Sticky notes repeating a highlight's note are the ones written with it
+            notes.retain(|note| {
+                !result
+                    .markups
+                    .iter()
+                    .any(|m| m.page_index == page_index && m.note == note.text)
+            });
+            result.notes.extend(notes);
+        }
+        Ok(result)
+    }
+}
+
+impl PdfRenderer {
+    /// Characters of a page in page fractions, origin top left.
+    pub(super) fn text_of(page: &PdfPage<'_>) -> Result<PageText> {
+        let (width, height) = (page.width().value, page.height().value);
+        if width <= 0.0 || height <= 0.0 {
+            return Ok(PageText::default());
+        }
+
+        let text = page.text().context("Failed to read page text")?;
+        let chars = text
+            .chars()
+            .iter()
+            .filter_map(|c| {
+                let ch = c.unicode_char()?;
+                // Generated line breaks have no bounds; keep them as separators
+                let rect = match c.loose_bounds() {
+                    Ok(b) => [
+                        b.left().value / width,
+                        1.0 - b.top().value / height,
+                        b.right().value / width,
+                        1.0 - b.bottom().value / height,
+                    ],
+                    Err(_) => [0.0; 4],
+                };
+                Some(TextChar { ch, rect })
+            })
+            .collect();
+        Ok(PageText {
+            chars,
+            positions: Vec::new(),
+        })
+    }
 }
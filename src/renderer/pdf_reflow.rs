@@ -0,0 +1,445 @@
+//! Text of PDF pages laid out again like an EPUB or FB2 book, so pages too
+//! large for the window can be read without scrolling sideways.
+
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use pdfium_render::prelude::*;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::config::constants::*;
+use crate::config::settings::{PageColors, TypographySettings};
+
+use super::page_text::{PageImage, PageText, SOFT_HYPHEN};
+use super::pdf::PdfRenderer;
+use super::reflow_cache::ReflowCache;
+use super::rich_text::{BlockBuilder, BlockFormat, BlockKind, Document};
+use super::traits::DocumentRenderer;
+
+/// Lays out the text of PDF pages with the typography of EPUB and FB2
+/// books. Sections are the pages of the file, so reflowed pages map back
+/// to the pages they came from.
+pub struct PdfReflowRenderer {
+    reflow: ReflowCache,
+}
+
+/// An image on a PDF page; `top` is in page fractions, origin top left.
+struct Figure {
+    top: f32,
+    image: Arc<RgbaImage>,
+}
+
+/// A line of text on a PDF page; `rect` as in [`super::page_text::TextChar`].
+struct Line {
+    text: String,
+    rect: [f32; 4],
+    /// Ends with a hyphen that splits a word.
+    hyphenated: bool,
+}
+
+/// Lines of a page joined into a paragraph or a heading.
+struct Paragraph {
+    text: String,
+    heading: bool,
+    /// Bounds of all lines so far.
+    rect: [f32; 4],
+    last: Line,
+}
+
+impl Line {
+    fn new(ch: char, rect: [f32; 4]) -> Self {
+        Self {
+            text: ch.to_string(),
+            rect,
+            hyphenated: false,
+        }
+    }
+
+    fn height(&self) -> f32 {
+        self.rect[3] - self.rect[1]
+    }
+
+    /// Whether a character at `rect` sits on this line rather than below
+    /// or above it.
+    fn holds(&self, rect: [f32; 4]) -> bool {
+        let middle = (rect[1] + rect[3]) / 2.0;
+        middle >= self.rect[1] && middle <= self.rect[3]
+    }
+
+    fn push(&mut self, ch: char, rect: Option<[f32; 4]>) {
+        self.text.push(ch);
+        self.hyphenated = false;
+        if let Some(r) = rect {
+            self.rect = [
+                self.rect[0].min(r[0]),
+                self.rect[1].min(r[1]),
+                self.rect[2].max(r[2]),
+                self.rect[3].max(r[3]),
+            ];
+        }
+    }
+}
+
+impl Paragraph {
+    fn new(line: Line, heading: bool) -> Self {
+        Self {
+            text: line.text.trim().to_string(),
+            heading,
+            rect: line.rect,
+            last: line,
+        }
+    }
+
+    /// Whether `line` goes on with this paragraph, given the usual height
+    /// of a line on the page.
+    fn continued_by(&self, line: &Line, heading: bool, line_height: f32) -> bool {
+        let width = self.rect[2] - self.rect[0];
+        let gap = line.rect[1] - self.last.rect[3];
+        heading == self.heading
+            && gap <= line_height * PDF_REFLOW_PARAGRAPH_GAP
+            // Going up the page means the next column
+            && line.rect[1] >= self.last.rect[1]
+            // A short line ends a paragraph, an indented one starts the next
+            && self.last.rect[2] >= self.rect[2] - width * PDF_REFLOW_SHORT_LINE
+            && line.rect[0] <= self.rect[0] + line_height
+    }
+
+    fn push(&mut self, line: Line) {
+        let text = line.text.trim();
+        let hyphen = self
+            .text
+            .strip_suffix('-')
+            .is_some_and(|before| before.ends_with(char::is_alphabetic));
+        if self.last.hyphenated {
+            // Soft hyphen, already left out of the text
+        } else if hyphen && text.starts_with(char::is_lowercase) {
+            self.text.pop();
+        } else if !hyphen {
+            // A hyphen before a capital is part of a compound word
+            self.text.push(' ');
+        }
+        self.text.push_str(text);
+        self.rect = [
+            self.rect[0].min(line.rect[0]),
+            self.rect[1].min(line.rect[1]),
+            self.rect[2].max(line.rect[2]),
+            self.rect[3].max(line.rect[3]),
+        ];
+        self.last = line;
+    }
+
+    fn finish(self, builder: &mut BlockBuilder) {
+        let kind = if self.heading {
+            BlockKind::Heading(2)
+        } else {
+            BlockKind::Paragraph
+        };
+        builder.open(BlockFormat {
+            kind,
+            ..BlockFormat::default()
+        });
+        builder.text(&self.text);
+        builder.close();
+    }
+}
+
+/// Lines of a page in the order of its text. Besides at line breaks, a new
+/// line starts where a character is above or below the current one.
+fn page_lines(text: &PageText) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut current: Option<Line> = None;
+    for c in &text.chars {
+        // Generated spaces and line breaks have no bounds
+        let rect = (c.rect[2] > c.rect[0] && c.rect[3] > c.rect[1]).then_some(c.rect);
+        match c.ch {
+            '\n' | '\r' => lines.extend(current.take()),
+            SOFT_HYPHEN | '\u{2}' => {
+                if let Some(line) = &mut current {
+                    line.hyphenated = true;
+                }
+            }
+            ch if ch.is_control() => {}
+            ch => match (&mut current, rect) {
+                (Some(line), None) => line.push(ch, None),
+                (Some(line), Some(rect)) if line.holds(rect) => line.push(ch, Some(rect)),
+                (_, Some(rect)) => lines.extend(current.replace(Line::new(ch, rect))),
+                (None, None) => {}
+            },
+        }
+    }
+    lines.extend(current);
+    lines.retain(|line| !line.text.trim().is_empty());
+    lines
+}
+
+/// Adds the text of a page to `builder` as paragraphs and headings, with
+/// the figures between them where they stand on the page.
+fn page_blocks(builder: &mut BlockBuilder, text: &PageText, mut figures: Vec<Figure>) {
+    let mut lines = page_lines(text);
+    // Page numbers at the top or bottom of the page
+    let is_number = |line: &Line| line.text.trim().chars().all(|c| c.is_ascii_digit());
+    if lines.last().is_some_and(is_number) {
+        lines.pop();
+    }
+    if lines.first().is_some_and(is_number) {
+        lines.remove(0);
+    }
+
+    let mut heights: Vec<f32> = lines.iter().map(Line::height).collect();
+    heights.sort_by(f32::total_cmp);
+    let line_height = heights.get(heights.len() / 2).copied().unwrap_or(0.0);
+
+    figures.sort_by(|a, b| a.top.total_cmp(&b.top));
+    let mut figures = figures.into_iter().peekable();
+    let mut paragraph: Option<Paragraph> = None;
+    for line in lines {
+        let heading = line.height() > line_height * PDF_REFLOW_HEADING_SCALE;
+        match &mut paragraph {
+            Some(p) if p.continued_by(&line, heading, line_height) => p.push(line),
+            _ => {
+                if let Some(p) = paragraph.take() {
+                    p.finish(builder);
+                }
+                while let Some(figure) = figures.next_if(|f| f.top <= line.rect[1]) {
+                    builder.image(figure.image);
+                }
+                paragraph = Some(Paragraph::new(line, heading));
+            }
+        }
+    }
+    if let Some(p) = paragraph {
+        p.finish(builder);
+    }
+    for figure in figures {
+        builder.image(figure.image);
+    }
+}
+
+/// Index of the reflowed page to show for the PDF page `page_index`: the
+/// first one starting on it, or the one running onto it if none does.
+pub fn reflowed_page_index(page_sections: &[u32], page_index: u32) -> u32 {
+    let first = page_sections.partition_point(|&s| s < page_index);
+    if page_sections.get(first) == Some(&page_index) {
+        first as u32
+    } else {
+        first.saturating_sub(1) as u32
+    }
+}
+
+impl PdfReflowRenderer {
+    pub(super) fn new(pdfium: Rc<Pdfium>) -> Self {
+        Self {
+            reflow: ReflowCache::new(Box::new(move |bytes| Self::parse_blocks(&pdfium, bytes))),
+        }
+    }
+
+    /// Sections are the pages of the file.
+    fn parse_blocks(pdfium: &Pdfium, bytes: &[u8]) -> Result<Document> {
+        let document = pdfium
+            .load_pdf_from_byte_slice(bytes, None)
+            .context("Failed to load PDF")?;
+
+        let mut builder = BlockBuilder::default();
+        let mut sections = Vec::new();
+        for (page_index, page) in document.pages().iter().enumerate() {
+            let text = PdfRenderer::text_of(&page)?;
+            page_blocks(&mut builder, &text, Self::figures(&document, &page));
+            sections.resize(builder.block_count(), page_index as u32);
+        }
+
+        let blocks = builder.finish();
+        let last = sections.last().copied().unwrap_or(0);
+        sections.resize(blocks.len(), last);
+        Ok(Document {
+            blocks,
+            sections,
+            language: None,
+        })
+    }
+
+    /// Images on a page wide enough to be figures rather than decoration.
+    fn figures(document: &PdfDocument<'_>, page: &PdfPage<'_>) -> Vec<Figure> {
+        let (width, height) = (page.width().value, page.height().value);
+        if width <= 0.0 || height <= 0.0 {
+            return Vec::new();
+        }
+        page.objects()
+            .iter()
+            .filter_map(|object| {
+                let image = object.as_image_object()?;
+                let bounds = object.bounds().ok()?.to_rect();
+                if bounds.width().value < width * PDF_REFLOW_FIGURE_MIN {
+                    return None;
+                }
+                // Masks and colour spaces applied, if pdfium can
+                let image = image
+                    .get_processed_image(document)
+                    .or_else(|_| image.get_raw_image())
+                    .ok()?;
+                Some(Figure {
+                    top: 1.0 - bounds.top().value / height,
+                    image: Arc::new(image.to_rgba8()),
+                })
+            })
+            .collect()
+    }
+}
+
+impl DocumentRenderer for PdfReflowRenderer {
+    fn set_typography(&self, typography: TypographySettings) {
+        self.reflow.set_typography(typography);
+    }
+
+    fn set_colors(&self, colors: PageColors) -> bool {
+        self.reflow.set_colors(colors)
+    }
+
+    fn get_page_count(&self, bytes: &[u8]) -> Result<u32> {
+        self.reflow.page_count(bytes)
+    }
+
+    fn render_page(&self, bytes: &[u8], page_index: u32, scale: f32) -> Result<RgbaImage> {
+        self.reflow.render_page(bytes, page_index, scale)
+    }
+
+    fn page_sections(&self, bytes: &[u8]) -> Result<Vec<u32>> {
+        self.reflow.page_sections(bytes)
+    }
+
+    fn page_positions(&self, bytes: &[u8]) -> Result<Vec<u64>> {
+        self.reflow.page_positions(bytes)
+    }
+
+    fn page_text(&self, bytes: &[u8], page_index: u32) -> Result<PageText> {
+        self.reflow.page_text(bytes, page_index)
+    }
+
+    fn page_images(&self, bytes: &[u8], page_index: u32) -> Result<Vec<PageImage>> {
+        self.reflow.page_images(bytes, page_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::page_text::TextChar;
+    use crate::renderer::rich_text::Block;
+
+    const SIZE: f32 = 0.015;
+
+    /// Sets `text` on a line at `left`, `top` in characters `size` high and
+    /// half as wide, followed by a line break.
+    fn line(page: &mut PageText, text: &str, left: f32, top: f32, size: f32) {
+        for (i, ch) in text.chars().enumerate() {
+            let x = left + i as f32 * size / 2.0;
+            page.chars.push(TextChar {
+                ch,
+                rect: [x, top, x + size / 2.0, top + size],
+            });
+        }
+        page.chars.push(TextChar {
+            ch: '\n',
+            rect: [0.0; 4],
+        });
+    }
+
+    fn blocks(page: &PageText, figures: Vec<Figure>) -> Vec<Block> {
+        let mut builder = BlockBuilder::default();
+        page_blocks(&mut builder, page, figures);
+        builder.finish()
+    }
+
+    fn texts(blocks: &[Block]) -> Vec<String> {
+        blocks.iter().map(Block::text).collect()
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        let mut page = PageText::default();
+        line(&mut page, "The first line of a para-", 0.1, 0.10, SIZE);
+        line(&mut page, "graph and then a well-", 0.1, 0.12, SIZE);
+        line(&mut page, "Known end.", 0.1, 0.14, SIZE);
+        line(&mut page, "After a gap.", 0.1, 0.20, SIZE);
+        line(&mut page, "Indented start of one", 0.13, 0.22, SIZE);
+        line(&mut page, "more paragraph.", 0.1, 0.24, SIZE);
+        line(&mut page, "12", 0.5, 0.95, SIZE);
+        assert_eq!(
+            texts(&blocks(&page, Vec::new())),
+            vec![
+                "The first line of a paragraph and then a well-Known end.",
+                "After a gap.",
+                "Indented start of one more paragraph.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_headings_and_columns() {
+        let mut page = PageText::default();
+        line(&mut page, "Title", 0.1, 0.05, SIZE * 2.0);
+        line(&mut page, "Left column text", 0.1, 0.10, SIZE);
+        line(&mut page, "goes on here.", 0.1, 0.12, SIZE);
+        line(&mut page, "Right column text", 0.55, 0.10, SIZE);
+        let blocks = blocks(&page, Vec::new());
+        assert_eq!(
+            texts(&blocks),
+            vec![
+                "Title",
+                "Left column text goes on here.",
+                "Right column text"
+            ]
+        );
+        assert_eq!(blocks[0].format.kind, BlockKind::Heading(2));
+        assert_eq!(blocks[1].format.kind, BlockKind::Paragraph);
+    }
+
+    #[test]
+    fn test_lines_without_breaks() {
+        let mut page = PageText::default();
+        line(&mut page, "One line", 0.1, 0.10, SIZE);
+        page.chars.pop();
+        line(&mut page, "and the hy", 0.1, 0.12, SIZE);
+        page.chars.pop();
+        page.chars.push(TextChar {
+            ch: SOFT_HYPHEN,
+            rect: [0.0; 4],
+        });
+        line(&mut page, "phen", 0.1, 0.14, SIZE);
+        let lines: Vec<String> = page_lines(&page).into_iter().map(|l| l.text).collect();
+        assert_eq!(lines, vec!["One line", "and the hy", "phen"]);
+        assert_eq!(
+            texts(&blocks(&page, Vec::new())),
+            vec!["One line and the hyphen"]
+        );
+    }
+
+    #[test]
+    fn test_figures() {
+        let mut page = PageText::default();
+        line(&mut page, "Above the figure.", 0.1, 0.10, SIZE);
+        line(&mut page, "Below the figure.", 0.1, 0.50, SIZE);
+        let figure = |top| Figure {
+            top,
+            image: Arc::new(RgbaImage::new(2, 3)),
+        };
+        let blocks = blocks(&page, vec![figure(0.9), figure(0.2)]);
+        assert_eq!(
+            texts(&blocks),
+            vec!["Above the figure.", "", "Below the figure.", ""]
+        );
+        assert!(blocks[1].image.is_some());
+        assert!(blocks[3].image.is_some());
+    }
+
+    #[test]
+    fn test_reflowed_page_index() {
+        // No reflowed page starts on PDF page index 2
+        let sections = [0, 0, 1, 3, 3];
+        assert_eq!(reflowed_page_index(&sections, 0), 0);
+        assert_eq!(reflowed_page_index(&sections, 1), 2);
+        assert_eq!(reflowed_page_index(&sections, 2), 2);
+        assert_eq!(reflowed_page_index(&sections, 3), 3);
+        assert_eq!(reflowed_page_index(&sections, 9), 4);
+        assert_eq!(reflowed_page_index(&[], 5), 0);
+    }
+}
@@ -0,0 +1,185 @@
+//! Pagination shared by the renderers that lay out a document's text with
+//! the typography settings: EPUB, FB2 and reflowed PDF.
+
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::constants::*;
+use crate::config::settings::{PageColors, TypographySettings};
+
+use super::page_text::{PageImage, PageText};
+use super::rich_text::Document;
+use super::text_render::{TextLayout, TextLine, TextPageRenderer};
+
+/// Reads the blocks of a document from its file.
+pub type ParseFn = Box<dyn Fn(&[u8]) -> Result<Document>>;
+
+/// Cached pagination result for a document.
+struct CachedDocument {
+    pages: Vec<Vec<TextLine>>,
+    // Section each page starts in, see `DocumentRenderer::page_sections`
+    page_sections: Vec<u32>,
+    // Position of each page's first line, see `DocumentRenderer::page_positions`
+    page_positions: Vec<u64>,
+    layout: TextLayout,
+    scale: f32,
+}
+
+/// Documents parsed by `parse` and paginated with the current typography,
+/// by file. Laid out again when the typography changes.
+pub struct ReflowCache {
+    parse: ParseFn,
+    text_renderer: Mutex<TextPageRenderer>,
+    cache: Mutex<HashMap<u64, CachedDocument>>,
+    typography: Mutex<TypographySettings>,
+    colors: Mutex<PageColors>,
+}
+
+impl ReflowCache {
+    pub fn new(parse: ParseFn) -> Self {
+        Self {
+            parse,
+            text_renderer: Mutex::new(TextPageRenderer::new()),
+            cache: Mutex::new(HashMap::new()),
+            typography: Mutex::new(TypographySettings::default()),
+            colors: Mutex::new(PageColors::DAY),
+        }
+    }
+
+    pub fn set_typography(&self, typography: TypographySettings) {
+        let mut current = self.typography.lock().unwrap();
+        if *current != typography {
+            if current.font_family != typography.font_family {
+                *self.text_renderer.lock().unwrap() =
+                    TextPageRenderer::with_font(typography.font_family.as_deref());
+            }
+            *current = typography;
+            self.cache.lock().unwrap().clear();
+        }
+    }
+
+    /// Pages are always drawn in the theme colours.
+    pub fn set_colors(&self, colors: PageColors) -> bool {
+        *self.colors.lock().unwrap() = colors;
+        true
+    }
+
+    pub fn page_count(&self, bytes: &[u8]) -> Result<u32> {
+        self.with_layout(bytes, |c| c.pages.len() as u32)
+    }
+
+    pub fn render_page(&self, bytes: &[u8], page_index: u32, scale: f32) -> Result<RgbaImage> {
+        let hash = self.paginate(bytes, scale)?;
+        let cache = self.cache.lock().unwrap();
+        let cached = cache.get(&hash).context("Разметка документа не найдена")?;
+        let Some(lines) = cached.pages.get(page_index as usize) else {
+            anyhow::bail!(
+                "Страница {} за пределами документа ({} стр.)",
+                page_index + 1,
+                cached.pages.len()
+            );
+        };
+        Ok(self.text_renderer.lock().unwrap().render_page(
+            lines,
+            &cached.layout,
+            *self.colors.lock().unwrap(),
+            scale,
+        ))
+    }
+
+    pub fn page_sections(&self, bytes: &[u8]) -> Result<Vec<u32>> {
+        self.with_layout(bytes, |c| c.page_sections.clone())
+    }
+
+    pub fn page_positions(&self, bytes: &[u8]) -> Result<Vec<u64>> {
+        self.with_layout(bytes, |c| c.page_positions.clone())
+    }
+
+    pub fn page_text(&self, bytes: &[u8], page_index: u32) -> Result<PageText> {
+        self.with_page(bytes, page_index, |renderer, lines, layout| {
+            renderer.page_text(lines, layout)
+        })
+    }
+
+    pub fn page_images(&self, bytes: &[u8], page_index: u32) -> Result<Vec<PageImage>> {
+        self.with_page(bytes, page_index, |renderer, lines, layout| {
+            renderer.page_images(lines, layout)
+        })
+    }
+
+    /// Reads the lines of a page from any cached pagination, the default if
+    /// there is no such page; positions are page fractions, so the scale it
+    /// was made at does not matter.
+    fn with_page<T: Default>(
+        &self,
+        bytes: &[u8],
+        page_index: u32,
+        read: impl FnOnce(&TextPageRenderer, &[TextLine], &TextLayout) -> T,
+    ) -> Result<T> {
+        self.with_layout(bytes, |c| {
+            c.pages
+                .get(page_index as usize)
+                .map(|lines| read(&self.text_renderer.lock().unwrap(), lines, &c.layout))
+                .unwrap_or_default()
+        })
+    }
+
+    fn bytes_hash(bytes: &[u8]) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        bytes.len().hash(&mut hasher);
+        if bytes.len() >= HASH_BUFFER_SIZE {
+            bytes[..HASH_BUFFER_SIZE].hash(&mut hasher);
+        } else {
+            bytes.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Lays the document out at `scale` unless the cached layout is at
+    /// that scale already. Returns the document's cache key.
+    fn paginate(&self, bytes: &[u8], scale: f32) -> Result<u64> {
+        let hash = Self::bytes_hash(bytes);
+        if let Some(cached) = self.cache.lock().unwrap().get(&hash) {
+            if (cached.scale - scale).abs() < SCALE_COMPARE_EPSILON {
+                return Ok(hash);
+            }
+        }
+
+        let document = (self.parse)(bytes)?;
+        let layout = TextLayout::new(&self.typography.lock().unwrap(), &document);
+        let (pages, page_starts) =
+            self.text_renderer
+                .lock()
+                .unwrap()
+                .paginate_indexed(&document.blocks, &layout, scale);
+        let page_sections = TextPageRenderer::page_sections(&page_starts, &document.sections);
+        let page_positions = page_starts.iter().map(|p| p.position()).collect();
+
+        self.cache.lock().unwrap().insert(
+            hash,
+            CachedDocument {
+                pages,
+                page_sections,
+                page_positions,
+                layout,
+                scale,
+            },
+        );
+        Ok(hash)
+    }
+
+    /// Reads the cached layout of the document, laying it out if there is
+    /// none. Pagination does not depend on scale, so any cached entry will do.
+    fn with_layout<T>(&self, bytes: &[u8], read: impl FnOnce(&CachedDocument) -> T) -> Result<T> {
+        let hash = Self::bytes_hash(bytes);
+        if !self.cache.lock().unwrap().contains_key(&hash) {
+            self.paginate(bytes, 1.0)?;
+        }
+        let cache = self.cache.lock().unwrap();
+        let cached = cache.get(&hash).context("Разметка документа не найдена")?;
+        Ok(read(cached))
+    }
+}
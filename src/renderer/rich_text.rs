@@ -0,0 +1,294 @@
+//! Styled document model shared by the EPUB and FB2 parsers and laid out
+//! by [`super::text_render::TextPageRenderer`].
+
+use image::RgbaImage;
+use std::sync::Arc;
+
+use super::page_text::SOFT_HYPHEN;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SpanStyle {
+    pub bold: bool,
+    pub italic: bool,
+}
+
+/// A run of text in one style. Whitespace inside is already collapsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: SpanStyle,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockKind {
+    #[default]
+    Paragraph,
+    /// Level 1..=6, as in HTML.
+    Heading(u8),
+    /// Set in italics on the right part of the page.
+    Epigraph,
+    /// A line of a poem; lines of a stanza have no spacing between them.
+    Verse,
+}
+
+/// How a block is set, inherited by the blocks nested in it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockFormat {
+    pub kind: BlockKind,
+    pub align: Align,
+    /// Nesting level of quotes, each shifting the block right.
+    pub indent: u8,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Block {
+    pub format: BlockFormat,
+    /// Empty for a blank line or an image.
+    pub spans: Vec<Span>,
+    /// An illustration set on its own, scaled to the text width.
+    pub image: Option<Arc<RgbaImage>>,
+}
+
+impl Block {
+    /// An unstyled paragraph.
+    #[allow(dead_code)]
+    pub fn plain(text: &str) -> Self {
+        let mut builder = BlockBuilder::default();
+        builder.text(text);
+        builder.finish().into_iter().next().unwrap_or_default()
+    }
+
+    #[allow(dead_code)]
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|s| s.text.as_str()).collect()
+    }
+}
+
+/// A parsed book, ready for layout.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub blocks: Vec<Block>,
+    /// Section (EPUB spine item, FB2 top-level section) of each block.
+    pub sections: Vec<u32>,
+    /// Language tag from the book's metadata, e.g. "ru" or "en-US".
+    pub language: Option<String>,
+}
+
+/// Collects text and markup events from a parser into blocks.
+///
+/// Block elements call [`open`](Self::open) and [`close`](Self::close) with
+/// their format; text between them becomes one block in the format on top
+/// of the stack. Inline elements toggle bold and italic.
+#[derive(Default)]
+pub struct BlockBuilder {
+    blocks: Vec<Block>,
+    spans: Vec<Span>,
+    formats: Vec<BlockFormat>,
+    bold: u32,
+    italic: u32,
+    pending_space: bool,
+}
+
+impl BlockBuilder {
+    /// Format of the innermost open block, to derive a nested one from.
+    pub fn format(&self) -> BlockFormat {
+        self.formats.last().copied().unwrap_or_default()
+    }
+
+    /// Blocks finished so far.
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn open(&mut self, format: BlockFormat) {
+        self.flush();
+        self.formats.push(format);
+    }
+
+    pub fn close(&mut self) {
+        self.flush();
+        self.formats.pop();
+    }
+
+    /// Ends the current line without leaving the block.
+    pub fn line_break(&mut self) {
+        self.flush();
+    }
+
+    /// A blank line, e.g. between stanzas.
+    pub fn empty_line(&mut self) {
+        self.flush();
+        self.blocks.push(Block {
+            format: self.format(),
+            ..Block::default()
+        });
+    }
+
+    /// An image between the blocks around it.
+    pub fn image(&mut self, image: Arc<RgbaImage>) {
+        self.flush();
+        self.blocks.push(Block {
+            format: self.format(),
+            spans: Vec::new(),
+            image: Some(image),
+        });
+    }
+
+    pub fn set_bold(&mut self, on: bool) {
+        self.bold = if on {
+            self.bold + 1
+        } else {
+            self.bold.saturating_sub(1)
+        };
+    }
+
+    pub fn set_italic(&mut self, on: bool) {
+        self.italic = if on {
+            self.italic + 1
+        } else {
+            self.italic.saturating_sub(1)
+        };
+    }
+
+    pub fn text(&mut self, text: &str) {
+        let style = SpanStyle {
+            bold: self.bold > 0,
+            italic: self.italic > 0,
+        };
+        for ch in text.chars() {
+            // Hyphenation points are found again when the text is laid out
+            if ch == SOFT_HYPHEN {
+                continue;
+            }
+            if ch.is_whitespace() && ch != '\u{a0}' {
+                self.pending_space = !self.spans.is_empty();
+                continue;
+            }
+            if std::mem::take(&mut self.pending_space) {
+                self.push(' ', style);
+            }
+            self.push(ch, style);
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<Block> {
+        self.flush();
+        self.blocks
+    }
+
+    fn push(&mut self, ch: char, style: SpanStyle) {
+        match self.spans.last_mut() {
+            Some(span) if span.style == style => span.text.push(ch),
+            _ => self.spans.push(Span {
+                text: ch.to_string(),
+                style,
+            }),
+        }
+    }
+
+    fn flush(&mut self) {
+        self.pending_space = false;
+        if !self.spans.is_empty() {
+            self.blocks.push(Block {
+                format: self.format(),
+                spans: std::mem::take(&mut self.spans),
+                image: None,
+            });
+        }
+    }
+}
+
+/// Decodes an embedded image (PNG, JPEG, GIF, ...); `None` if it is broken
+/// or in an unsupported format such as SVG.
+pub fn decode_image(bytes: &[u8]) -> Option<Arc<RgbaImage>> {
+    let image = image::load_from_memory(bytes).ok()?.to_rgba8();
+    (image.width() > 0 && image.height() > 0).then(|| Arc::new(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_whitespace_collapsed_across_spans() {
+        let mut b = BlockBuilder::default();
+        b.text("  Hello \n ");
+        b.set_bold(true);
+        b.text(" big ");
+        b.set_bold(false);
+        b.text(" world  ");
+        let blocks = b.finish();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].text(), "Hello big world");
+        assert_eq!(blocks[0].spans.len(), 3);
+        assert_eq!(blocks[0].spans[1].text, " big");
+        assert!(blocks[0].spans[1].style.bold);
+    }
+
+    #[test]
+    fn test_nested_formats() {
+        let mut b = BlockBuilder::default();
+        let quote = BlockFormat {
+            indent: 1,
+            ..BlockFormat::default()
+        };
+        b.open(quote);
+        b.text("quoted");
+        b.line_break();
+        b.text("second line");
+        b.close();
+        b.text("after");
+        let blocks = b.finish();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].format, quote);
+        assert_eq!(blocks[1].format, quote);
+        assert_eq!(blocks[2].format, BlockFormat::default());
+    }
+
+    #[test]
+    fn test_empty_line_keeps_format() {
+        let mut b = BlockBuilder::default();
+        let verse = BlockFormat {
+            kind: BlockKind::Verse,
+            ..BlockFormat::default()
+        };
+        b.open(verse);
+        b.empty_line();
+        b.close();
+        let blocks = b.finish();
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].spans.is_empty());
+        assert_eq!(blocks[0].format.kind, BlockKind::Verse);
+    }
+
+    #[test]
+    fn test_image_splits_paragraph() {
+        let mut b = BlockBuilder::default();
+        b.text("before");
+        b.image(Arc::new(RgbaImage::new(4, 2)));
+        b.text("after");
+        let blocks = b.finish();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[1].image.as_ref().map(|i| i.width()), Some(4));
+        assert_eq!(blocks[2].text(), "after");
+    }
+
+    #[test]
+    fn test_decode_image() {
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(RgbaImage::new(3, 5))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let decoded = decode_image(&png).unwrap();
+        assert_eq!(decoded.dimensions(), (3, 5));
+        assert!(decode_image(b"<svg/>").is_none());
+    }
+}
@@ -0,0 +1,78 @@
+use image::{imageops, RgbaImage};
+
+/// Page turned by `quarter_turns` quarter turns clockwise.
+pub fn rotate(image: &RgbaImage, quarter_turns: u8) -> RgbaImage {
+    match quarter_turns % 4 {
+        1 => imageops::rotate90(image),
+        2 => imageops::rotate180(image),
+        3 => imageops::rotate270(image),
+        _ => image.clone(),
+    }
+}
+
+/// Maps a rectangle in page fractions to fractions of the page turned by
+/// `quarter_turns` quarter turns clockwise.
+pub fn rotate_rect(rect: [f32; 4], quarter_turns: u8) -> [f32; 4] {
+    let [left, top, right, bottom] = rect;
+    match quarter_turns % 4 {
+        1 => [1.0 - bottom, left, 1.0 - top, right],
+        2 => [1.0 - right, 1.0 - bottom, 1.0 - left, 1.0 - top],
+        3 => [top, 1.0 - right, bottom, 1.0 - left],
+        _ => rect,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    /// A 40x20 white page with a black 10x5 block in its top left corner.
+    fn page() -> RgbaImage {
+        RgbaImage::from_fn(40, 20, |x, y| {
+            if x < 10 && y < 5 {
+                BLACK
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        })
+    }
+
+    /// Bounds of the black pixels, in page fractions.
+    fn black_rect(image: &RgbaImage) -> [f32; 4] {
+        let (width, height) = (image.width() as f32, image.height() as f32);
+        let black: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(_, _, p)| **p == BLACK)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        let min_x = black.iter().map(|p| p.0).min().unwrap() as f32;
+        let max_x = black.iter().map(|p| p.0).max().unwrap() as f32 + 1.0;
+        let min_y = black.iter().map(|p| p.1).min().unwrap() as f32;
+        let max_y = black.iter().map(|p| p.1).max().unwrap() as f32 + 1.0;
+        [min_x / width, min_y / height, max_x / width, max_y / height]
+    }
+
+    #[test]
+    fn test_rotate() {
+        let page = page();
+        assert_eq!(rotate(&page, 1).dimensions(), (20, 40));
+        assert_eq!(rotate(&page, 2).dimensions(), (40, 20));
+        assert_eq!(rotate(&page, 4), page);
+    }
+
+    #[test]
+    fn test_rotate_rect_follows_image() {
+        let page = page();
+        let rect = black_rect(&page);
+        for turns in 0..4 {
+            assert_eq!(
+                rotate_rect(rect, turns),
+                black_rect(&rotate(&page, turns)),
+                "{turns} quarter turns"
+            );
+        }
+    }
+}
@@ -1,149 +1,1021 @@
-use ab_glyph::{Font, FontRef, PxScale, PxScaleFont, ScaleFont};
+use ab_glyph::{point, Font, FontRef, GlyphId, PxScale, ScaleFont};
+use hypher::Lang;
+use image::imageops::{self, FilterType};
 use image::{Rgba, RgbaImage};
-use imageproc::drawing::draw_text_mut;
+use rustybuzz::{Direction, UnicodeBuffer};
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::sync::Arc;
+use unicode_bidi::BidiInfo;
 
 use crate::config::constants::*;
+use crate::config::settings::{PageColors, TypographySettings};
 
-static EMBEDDED_FONT: &[u8] = include_bytes!("../../libs/fonts/NotoSans-Regular.ttf");
+use super::fonts::{FontId, FontSet, SYSTEM_FONTS};
+use super::page_text::{PageImage, PageText, TextChar, SOFT_HYPHEN};
+use super::rich_text::{Align, Block, BlockKind, Document, SpanStyle};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FontFace {
+    #[default]
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+impl FontFace {
+    fn of(style: SpanStyle) -> Self {
+        match (style.bold, style.italic) {
+            (false, false) => Self::Regular,
+            (true, false) => Self::Bold,
+            (false, true) => Self::Italic,
+            (true, true) => Self::BoldItalic,
+        }
+    }
+}
+
+/// Page and line breaking options for one document; lengths are unscaled
+/// pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextLayout {
+    /// Patterns words are hyphenated with; `None` disables hyphenation.
+    pub hyphenation: Option<Lang>,
+    pub justify: bool,
+    pub font_size: f32,
+    pub line_height: f32,
+    pub paragraph_spacing: f32,
+    pub margin: f32,
+    pub page_width: f32,
+    pub page_height: f32,
+}
+
+impl Default for TextLayout {
+    /// The default page, without hyphenation or justification.
+    fn default() -> Self {
+        let typography = TypographySettings {
+            hyphenation: false,
+            justify: false,
+            ..TypographySettings::default()
+        };
+        Self::new(&typography, &Document::default())
+    }
+}
+
+impl TextLayout {
+    /// Options for a document. Hyphenation follows the language in its
+    /// metadata, or the script of its text if the metadata has none.
+    pub fn new(typography: &TypographySettings, document: &Document) -> Self {
+        let lang = match &document.language {
+            Some(tag) => hyphenation_patterns(tag),
+            None => guess_language(&document.blocks),
+        };
+        // Settings files may be edited by hand
+        let page_size = |size: u32| size.clamp(TEXT_PAGE_SIZE_MIN, TEXT_PAGE_SIZE_MAX) as f32;
+        let page_width = page_size(typography.page_width);
+        let page_height = page_size(typography.page_height);
+        let font_size = typography
+            .font_size
+            .clamp(TEXT_FONT_SIZE_MIN, TEXT_FONT_SIZE_MAX);
+        let line_height = font_size
+            * typography
+                .line_spacing
+                .clamp(TEXT_LINE_SPACING_MIN, TEXT_LINE_SPACING_MAX);
+        let paragraph_spacing = typography
+            .paragraph_spacing
+            .clamp(0.0, TEXT_PARAGRAPH_SPACING_MAX);
+        Self {
+            hyphenation: lang.filter(|_| typography.hyphenation),
+            justify: typography.justify,
+            font_size,
+            line_height,
+            paragraph_spacing: line_height * paragraph_spacing,
+            // Leave at least half of the page for the text
+            margin: (typography.margin as f32).min(page_width.min(page_height) / 4.0),
+            page_width,
+            page_height,
+        }
+    }
+
+    fn text_width(&self) -> f32 {
+        self.page_width - 2.0 * self.margin
+    }
+
+    fn text_height(&self) -> f32 {
+        self.page_height - 2.0 * self.margin
+    }
+}
+
+/// Where a page starts: a block and a byte offset in its text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PageStart {
+    pub block: usize,
+    pub offset: usize,
+}
+
+impl PageStart {
+    /// The start as one number that orders the same way, for
+    /// [`DocumentRenderer::page_positions`](super::traits::DocumentRenderer::page_positions).
+    pub fn position(self) -> u64 {
+        ((self.block as u64) << 32) | self.offset as u64
+    }
+}
+
+/// Patterns for a language tag such as "ru" or "en-GB".
+fn hyphenation_patterns(tag: &str) -> Option<Lang> {
+    let code = tag.trim().get(..2)?.to_ascii_lowercase();
+    Lang::from_iso(code.as_bytes().try_into().ok()?)
+}
+
+/// Russian or English, whichever script dominates the start of the text.
+fn guess_language(blocks: &[Block]) -> Option<Lang> {
+    let (mut cyrillic, mut latin) = (0, 0);
+    let chars = blocks
+        .iter()
+        .flat_map(|b| &b.spans)
+        .flat_map(|s| s.text.chars())
+        .take(TEXT_LANGUAGE_SAMPLE);
+    for ch in chars {
+        if is_cyrillic(ch) {
+            cyrillic += 1;
+        } else if ch.is_ascii_alphabetic() {
+            latin += 1;
+        }
+    }
+    match (cyrillic, latin) {
+        (0, 0) => None,
+        (c, l) if c >= l => Some(Lang::Russian),
+        _ => Some(Lang::English),
+    }
+}
+
+fn is_cyrillic(ch: char) -> bool {
+    matches!(ch, '\u{400}'..='\u{4ff}')
+}
+
+/// Whether the patterns of `lang` apply to a word, e.g. not English
+/// patterns to a Russian word in an English book.
+fn in_script(lang: Lang, word: &str) -> bool {
+    match lang {
+        Lang::Russian => word.chars().all(is_cyrillic),
+        _ => word.chars().all(|c| c.is_ascii_alphabetic()),
+    }
+}
+
+/// A shaped glyph; lengths are unscaled pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glyph {
+    pub id: u16,
+    /// Pen position from the left edge of the run.
+    pub x: f32,
+    /// Upward shift, e.g. of a combining mark.
+    pub y: f32,
+    pub advance: f32,
+    /// Byte offset in the run text of the first character it shows.
+    pub cluster: usize,
+}
+
+/// Text in one face and direction on a line, with its glyphs in visual
+/// order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRun {
+    pub text: String,
+    pub face: FontFace,
+    /// Font of the glyphs: a face of the reading family or a fallback.
+    pub font: FontId,
+    pub rtl: bool,
+    /// Byte offset of `text` in the text of its block.
+    pub start: usize,
+    /// Left edge in unscaled pixels from the left margin.
+    pub x: f32,
+    pub glyphs: Vec<Glyph>,
+}
+
+/// An image taking the place of a line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineImage {
+    pub image: Arc<RgbaImage>,
+    /// Left edge in unscaled pixels from the left margin.
+    pub x: f32,
+    pub width: f32,
+}
+
+/// A laid out line; lengths are unscaled pixels, `top` from the top margin.
+/// Runs are in logical order; their `x` places them in display order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextLine {
+    /// Index of the block the line belongs to.
+    pub block: usize,
+    pub runs: Vec<TextRun>,
+    pub top: f32,
+    pub height: f32,
+    pub font_size: f32,
+    pub image: Option<LineImage>,
+}
+
+impl TextLine {
+    /// Text of the line in logical order.
+    #[allow(dead_code)]
+    pub fn text(&self) -> String {
+        self.runs.iter().map(|r| r.text.as_str()).collect()
+    }
+}
+
+/// Pages being filled by [`TextPageRenderer::paginate_indexed`].
+#[derive(Default)]
+struct PageBuilder {
+    text_height: f32,
+    pages: Vec<Vec<TextLine>>,
+    page_starts: Vec<PageStart>,
+    current: Vec<TextLine>,
+    current_start: PageStart,
+    y: f32,
+}
+
+impl PageBuilder {
+    /// Starts a new page unless the current one is empty.
+    fn break_page(&mut self) {
+        if !self.current.is_empty() {
+            self.pages.push(std::mem::take(&mut self.current));
+            self.page_starts.push(self.current_start);
+        }
+        self.y = 0.0;
+    }
+
+    /// Adds a line of `height` starting at `start` at the current position,
+    /// on a new page if it does not fit.
+    fn push(&mut self, start: PageStart, height: f32, line: impl FnOnce(f32) -> TextLine) {
+        if self.y + height > self.text_height {
+            self.break_page();
+        }
+        if self.current.is_empty() {
+            self.current_start = start;
+        }
+        self.current.push(line(self.y.trunc()));
+        self.y += height;
+    }
+}
+
+/// A block's text with the face and font of each part.
+struct Paragraph {
+    text: String,
+    /// Consecutive byte ranges covering `text`.
+    faces: Vec<(Range<usize>, FontFace, FontId)>,
+}
+
+impl Paragraph {
+    fn of(block: &Block, forced: SpanStyle, fonts: &FontSet) -> Self {
+        let mut text = String::new();
+        let mut faces: Vec<(Range<usize>, FontFace, FontId)> = Vec::new();
+        for span in &block.spans {
+            let face = FontFace::of(SpanStyle {
+                bold: span.style.bold || forced.bold,
+                italic: span.style.italic || forced.italic,
+            });
+            for (offset, ch) in span.text.char_indices() {
+                let start = text.len() + offset;
+                let end = start + ch.len_utf8();
+                let previous = faces.last().filter(|(_, f, _)| *f == face);
+                let font = fonts.font_for(ch, face, previous.map(|(_, _, font)| *font));
+                match faces.last_mut() {
+                    Some((last, f, id)) if *f == face && *id == font => last.end = end,
+                    _ => faces.push((start..end, face, font)),
+                }
+            }
+            text.push_str(&span.text);
+        }
+        Self { text, faces }
+    }
+
+    /// Parts of `range` in one face and font each.
+    fn face_runs(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (Range<usize>, FontFace, FontId)> + '_ {
+        self.faces.iter().filter_map(move |(r, face, font)| {
+            let part = r.start.max(range.start)..r.end.min(range.end);
+            (part.start < part.end).then_some((part, *face, *font))
+        })
+    }
+
+    /// Font of the character that ends at byte `end`.
+    fn font_before(&self, end: usize) -> FontId {
+        self.faces
+            .iter()
+            .find(|(r, _, _)| r.start < end && end <= r.end)
+            .map_or(0, |(_, _, font)| *font)
+    }
+}
+
+/// Text between two line break opportunities (UAX #14).
+#[derive(Debug, Clone, PartialEq)]
+struct Word {
+    /// Byte range in the paragraph, without the whitespace after it.
+    range: Range<usize>,
+    width: f32,
+    /// Width of the whitespace after the word.
+    space: f32,
+    /// Ends with a hyphen added by hyphenation.
+    hyphen: bool,
+}
+
+/// How a block is placed, derived from its format.
+struct BlockLayout {
+    font_scale: f32,
+    forced: SpanStyle,
+    left: f32,
+    width: f32,
+    align: Align,
+    space_before: f32,
+    space_after: f32,
+    hyphenation: Option<Lang>,
+    justify: bool,
+}
+
+impl BlockLayout {
+    fn of(block: &Block, text_width: f32, options: &TextLayout) -> Self {
+        let format = block.format;
+        let mut layout = Self {
+            font_scale: 1.0,
+            forced: SpanStyle::default(),
+            left: format.indent as f32 * TEXT_INDENT_STEP,
+            width: 0.0,
+            align: format.align,
+            space_before: 0.0,
+            space_after: options.paragraph_spacing,
+            hyphenation: options.hyphenation,
+            justify: options.justify && format.align == Align::Left,
+        };
+        match format.kind {
+            BlockKind::Paragraph => {}
+            BlockKind::Heading(level) => {
+                layout.hyphenation = None;
+                layout.justify = false;
+                let level = (level.clamp(1, 6) - 1) as usize;
+                layout.font_scale = TEXT_HEADING_SCALES[level];
+                layout.forced.bold = true;
+                layout.space_before = TEXT_HEADING_SPACING;
+            }
+            BlockKind::Epigraph => {
+                layout.forced.italic = true;
+                layout.left += text_width * TEXT_EPIGRAPH_INDENT;
+            }
+            BlockKind::Verse => {
+                layout.hyphenation = None;
+                layout.justify = false;
+                layout.left += TEXT_INDENT_STEP;
+                layout.space_after = 0.0;
+            }
+        }
+        layout.left = layout.left.min(text_width / 2.0);
+        layout.width = text_width - layout.left;
+        layout
+    }
+}
 
 pub struct TextPageRenderer {
-    font: FontRef<'static>,
+    fonts: FontSet,
 }
 
 impl TextPageRenderer {
     pub fn new() -> Self {
-        let font = FontRef::try_from_slice(EMBEDDED_FONT).expect("Failed to load embedded font");
-        Self { font }
-    }
-
-    /// Paginate text paragraphs into virtual pages.
-    /// Returns a Vec of pages, where each page is a Vec of lines (strings).
-    pub fn paginate(&self, paragraphs: &[String], scale: f32) -> Vec<Vec<String>> {
-        let font_size = TEXT_FONT_SIZE * scale;
-        let line_height = TEXT_LINE_HEIGHT * scale;
-        let paragraph_spacing = TEXT_PARAGRAPH_SPACING * scale;
-        let margin = TEXT_PAGE_MARGIN as f32 * scale;
-        let page_height = TEXT_PAGE_HEIGHT as f32 * scale;
-        let page_width = TEXT_PAGE_WIDTH as f32 * scale;
-        let usable_width = page_width - 2.0 * margin;
-        let usable_height = page_height - 2.0 * margin;
-
-        let px_scale = PxScale::from(font_size);
-        let scaled_font = self.font.as_scaled(px_scale);
-
-        let mut pages: Vec<Vec<String>> = Vec::new();
-        let mut current_page: Vec<String> = Vec::new();
-        let mut y = 0.0f32;
-
-        for paragraph in paragraphs {
-            let lines = Self::wrap_text(paragraph, usable_width, &scaled_font);
-
-            for (i, line) in lines.iter().enumerate() {
-                let extra = if i == lines.len() - 1 {
-                    paragraph_spacing
-                } else {
-                    0.0
+        Self::with_font(None)
+    }
+
+    /// Sets text in `family`, or in the embedded font if it is `None` or
+    /// not installed.
+    pub fn with_font(family: Option<&str>) -> Self {
+        Self {
+            fonts: FontSet::new(&SYSTEM_FONTS, family),
+        }
+    }
+
+    /// Pixel scale of a font at a body font size. `PxScale` sets the
+    /// ascent-to-descent height, which differs between font families, so
+    /// each font is matched to the em size of the regular reading face.
+    fn px_scale(&self, font: FontId, font_size: f32) -> PxScale {
+        let regular = self.fonts.reading(FontFace::Regular);
+        PxScale::from(font_size * self.em_per_height(regular) / self.em_per_height(font))
+    }
+
+    fn em_per_height(&self, font: FontId) -> f32 {
+        let font = &self.fonts.font(font).glyphs;
+        font.units_per_em().unwrap_or(1.0) / font.height_unscaled()
+    }
+
+    /// Shapes text in one font, applying kerning, ligatures and mark
+    /// positioning. Returns the glyphs in visual order and their total
+    /// advance, in pixels at `font_size`. The direction is guessed from the
+    /// text unless `rtl` is given.
+    fn shape(
+        &self,
+        text: &str,
+        font: FontId,
+        font_size: f32,
+        rtl: Option<bool>,
+    ) -> (Vec<Glyph>, f32) {
+        let font = self.fonts.font(font);
+        let shaper = &font.shaper;
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(text);
+        match rtl {
+            Some(true) => buffer.set_direction(Direction::RightToLeft),
+            Some(false) => buffer.set_direction(Direction::LeftToRight),
+            None => {}
+        }
+        buffer.guess_segment_properties();
+        let output = rustybuzz::shape(shaper, &[], buffer);
+
+        // Every font has the em size of the regular face, see `px_scale`
+        let em = font_size * self.em_per_height(self.fonts.reading(FontFace::Regular));
+        let px = em / shaper.units_per_em() as f32;
+
+        let mut pen = 0.0;
+        let glyphs = output
+            .glyph_infos()
+            .iter()
+            .zip(output.glyph_positions())
+            .map(|(info, pos)| {
+                let glyph = Glyph {
+                    id: info.glyph_id as u16,
+                    x: pen + pos.x_offset as f32 * px,
+                    y: pos.y_offset as f32 * px,
+                    advance: pos.x_advance as f32 * px,
+                    cluster: info.cluster as usize,
                 };
+                pen += glyph.advance;
+                glyph
+            })
+            .collect();
+        (glyphs, pen)
+    }
+
+    /// Shaped width of a range of a paragraph, in pixels at `font_size`.
+    fn measure(&self, paragraph: &Paragraph, range: Range<usize>, font_size: f32) -> f32 {
+        paragraph
+            .face_runs(range)
+            .map(|(part, _, font)| self.shape(&paragraph.text[part], font, font_size, None).1)
+            .sum()
+    }
 
-                if y + line_height > usable_height && !current_page.is_empty() {
-                    pages.push(current_page);
-                    current_page = Vec::new();
-                    y = 0.0;
+    /// Paginate blocks into virtual pages of laid out lines.
+    #[allow(dead_code)]
+    pub fn paginate(&self, blocks: &[Block], scale: f32) -> Vec<Vec<TextLine>> {
+        self.paginate_indexed(blocks, &TextLayout::default(), scale)
+            .0
+    }
+
+    /// Like [`paginate`](Self::paginate), but also returns where each page
+    /// starts.
+    pub fn paginate_indexed(
+        &self,
+        blocks: &[Block],
+        options: &TextLayout,
+        scale: f32,
+    ) -> (Vec<Vec<TextLine>>, Vec<PageStart>) {
+        let text_width = options.text_width();
+        let text_height = options.text_height();
+
+        let mut pages = PageBuilder {
+            text_height,
+            ..PageBuilder::default()
+        };
+        for (block_index, block) in blocks.iter().enumerate() {
+            let start = |offset| PageStart {
+                block: block_index,
+                offset,
+            };
+            let layout = BlockLayout::of(block, text_width, options);
+            let font_size = options.font_size * layout.font_scale;
+            let line_height = options.line_height * layout.font_scale;
+            if !pages.current.is_empty() {
+                pages.y += layout.space_before;
+            }
+
+            if let Some(image) = &block.image {
+                let (w, h) = (image.width() as f32, image.height() as f32);
+                // Never wider than the text; large images are fitted to a page of their own
+                let mut fit = (layout.width / w).min(1.0);
+                let own_page = h * fit > text_height * TEXT_IMAGE_OWN_PAGE;
+                if own_page {
+                    fit = (layout.width / w).min(text_height / h);
+                    pages.break_page();
                 }
+                let (width, height) = (w * fit, h * fit);
+                pages.push(start(0), height, |top| TextLine {
+                    block: block_index,
+                    runs: Vec::new(),
+                    top,
+                    height,
+                    font_size,
+                    image: Some(LineImage {
+                        image: Arc::clone(image),
+                        x: layout.left + (layout.width - width) / 2.0,
+                        width,
+                    }),
+                });
+                if own_page {
+                    pages.break_page();
+                } else {
+                    pages.y += layout.space_after;
+                }
+                continue;
+            }
 
-                current_page.push(line.clone());
-                y += line_height + extra;
+            let lines = self.layout_block(block, &layout, font_size, scale);
+            let last = lines.len() - 1;
+            for (i, (offset, runs)) in lines.into_iter().enumerate() {
+                pages.push(start(offset), line_height, |top| TextLine {
+                    block: block_index,
+                    runs,
+                    top,
+                    height: line_height,
+                    font_size,
+                    image: None,
+                });
+                if i == last {
+                    pages.y += layout.space_after;
+                }
             }
         }
+        pages.break_page();
 
-        if !current_page.is_empty() {
-            pages.push(current_page);
+        if pages.pages.is_empty() {
+            let placeholder = Block::plain("(Пустой документ)");
+            let layout = BlockLayout::of(&placeholder, text_width, options);
+            let lines = self.layout_block(&placeholder, &layout, options.font_size, scale);
+            for (_, runs) in lines {
+                pages.push(PageStart::default(), options.line_height, |top| TextLine {
+                    block: 0,
+                    runs,
+                    top,
+                    height: options.line_height,
+                    font_size: options.font_size,
+                    image: None,
+                });
+            }
+            pages.break_page();
         }
 
-        if pages.is_empty() {
-            pages.push(vec!["(Пустой документ)".to_string()]);
-        }
+        (pages.pages, pages.page_starts)
+    }
 
-        pages
+    /// Maps page starts to the section each page begins in.
+    pub fn page_sections(page_starts: &[PageStart], block_sections: &[u32]) -> Vec<u32> {
+        page_starts
+            .iter()
+            .map(|p| block_sections.get(p.block).copied().unwrap_or(0))
+            .collect()
     }
 
     /// Render a single page (given its lines) to an RgbaImage.
-    pub fn render_page(&self, lines: &[String], scale: f32) -> RgbaImage {
-        let width = (TEXT_PAGE_WIDTH as f32 * scale) as u32;
-        let height = (TEXT_PAGE_HEIGHT as f32 * scale) as u32;
-        let margin = (TEXT_PAGE_MARGIN as f32 * scale) as i32;
-        let font_size = TEXT_FONT_SIZE * scale;
-        let line_height = TEXT_LINE_HEIGHT * scale;
-
-        let mut image = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
-        let color = Rgba([0, 0, 0, 255]);
-        let px_scale = PxScale::from(font_size);
-
-        for (i, line) in lines.iter().enumerate() {
-            let x = margin;
-            let y = margin + (i as f32 * line_height) as i32;
-            draw_text_mut(&mut image, color, x, y, px_scale, &self.font, line);
+    pub fn render_page(
+        &self,
+        lines: &[TextLine],
+        options: &TextLayout,
+        colors: PageColors,
+        scale: f32,
+    ) -> RgbaImage {
+        let width = (options.page_width * scale) as u32;
+        let height = (options.page_height * scale) as u32;
+        let margin = options.margin;
+
+        let [r, g, b] = colors.background;
+        let mut image = RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]));
+        let [r, g, b] = colors.foreground;
+        let color = Rgba([r, g, b, 255]);
+
+        for line in lines {
+            if let Some(placed) = &line.image {
+                let resized = imageops::resize(
+                    placed.image.as_ref(),
+                    ((placed.width * scale) as u32).max(1),
+                    ((line.height * scale) as u32).max(1),
+                    FilterType::Triangle,
+                );
+                let x = ((margin + placed.x) * scale) as i64;
+                let y = ((margin + line.top) * scale) as i64;
+                imageops::overlay(&mut image, &resized, x, y);
+                continue;
+            }
+            let px_scale = |font| self.px_scale(font, line.font_size * scale);
+            let ascent = |font| {
+                self.fonts
+                    .font(font)
+                    .glyphs
+                    .as_scaled(px_scale(font))
+                    .ascent()
+            };
+            // Faces differ in ascent; line their baselines up
+            let ascent = line.runs.iter().map(|r| ascent(r.font)).fold(0.0, f32::max);
+            let baseline = ((margin + line.top) * scale + ascent).round();
+            for run in &line.runs {
+                let left = (margin + run.x) * scale;
+                for glyph in &run.glyphs {
+                    let position = point(left + glyph.x * scale, baseline - glyph.y * scale);
+                    let glyph =
+                        GlyphId(glyph.id).with_scale_and_position(px_scale(run.font), position);
+                    draw_glyph(&mut image, &self.fonts.font(run.font).glyphs, glyph, color);
+                }
+            }
         }
 
         image
     }
 
-    fn wrap_text(
-        text: &str,
-        max_width: f32,
-        scaled_font: &PxScaleFont<&FontRef<'_>>,
-    ) -> Vec<String> {
-        let mut lines = Vec::new();
-        let mut current_line = String::new();
-        let mut current_width = 0.0f32;
+    /// Character positions of a page laid out by [`render_page`](Self::render_page).
+    /// Lines are separated by a `'\n'` without bounds. The separators and
+    /// the hyphens of split words take the position of the character
+    /// before them.
+    pub fn page_text(&self, lines: &[TextLine], options: &TextLayout) -> PageText {
+        let width = options.page_width;
+        let height = options.page_height;
+        let margin = options.margin;
 
-        for word in text.split_whitespace() {
-            let word_width = Self::measure_text(word, scaled_font);
+        let mut chars = Vec::new();
+        let mut positions = Vec::new();
+        for line in lines.iter().filter(|l| l.image.is_none()) {
+            if !chars.is_empty() {
+                chars.push(TextChar {
+                    ch: '\n',
+                    rect: [0.0; 4],
+                });
+                positions.push(positions.last().copied().unwrap_or_default());
+            }
+            let top = margin + line.top;
+            for run in &line.runs {
+                let x = margin + run.x;
+                for (ch, offset, left, right) in Self::char_extents(run) {
+                    let added_hyphen =
+                        ch == SOFT_HYPHEN && offset + ch.len_utf8() == run.text.len();
+                    positions.push(match positions.last() {
+                        Some(&last) if added_hyphen => last,
+                        _ => PageStart {
+                            block: line.block,
+                            offset: run.start + offset,
+                        }
+                        .position(),
+                    });
+                    chars.push(TextChar {
+                        ch,
+                        rect: [
+                            (x + left) / width,
+                            top / height,
+                            (x + right) / width,
+                            (top + line.height) / height,
+                        ],
+                    });
+                }
+            }
+        }
+        PageText { chars, positions }
+    }
 
-            if current_line.is_empty() {
-                current_line = word.to_string();
-                current_width = word_width;
-            } else {
-                let space_width = Self::measure_text(" ", scaled_font);
-                if current_width + space_width + word_width <= max_width {
-                    current_line.push(' ');
-                    current_line.push_str(word);
-                    current_width += space_width + word_width;
+    /// Characters of a run in logical order with their byte offset in the
+    /// run text and their horizontal extent from the run's left edge.
+    /// Characters drawn as one glyph, such as a ligature or a letter with
+    /// its marks, share its width.
+    fn char_extents(run: &TextRun) -> Vec<(char, usize, f32, f32)> {
+        let mut clusters: BTreeMap<usize, (f32, f32)> = BTreeMap::new();
+        for glyph in &run.glyphs {
+            let extent = clusters
+                .entry(glyph.cluster)
+                .or_insert((f32::INFINITY, f32::NEG_INFINITY));
+            extent.0 = extent.0.min(glyph.x);
+            extent.1 = extent.1.max(glyph.x + glyph.advance);
+        }
+
+        let mut extents = Vec::new();
+        let mut clusters = clusters.into_iter().peekable();
+        while let Some((start, (left, right))) = clusters.next() {
+            let end = clusters.peek().map_or(run.text.len(), |(next, _)| *next);
+            let chars: Vec<(usize, char)> = run.text[start..end].char_indices().collect();
+            let step = (right - left) / chars.len().max(1) as f32;
+            for (i, (offset, ch)) in chars.into_iter().enumerate() {
+                // Right to left, the first character is on the right
+                let x = if run.rtl {
+                    right - (i + 1) as f32 * step
+                } else {
+                    left + i as f32 * step
+                };
+                extents.push((ch, start + offset, x, x + step));
+            }
+        }
+        extents
+    }
+
+    /// Images of a page laid out by [`render_page`](Self::render_page).
+    pub fn page_images(&self, lines: &[TextLine], options: &TextLayout) -> Vec<PageImage> {
+        let width = options.page_width;
+        let height = options.page_height;
+        let margin = options.margin;
+        lines
+            .iter()
+            .filter_map(|line| {
+                let placed = line.image.as_ref()?;
+                let (x, top) = (margin + placed.x, margin + line.top);
+                Some(PageImage {
+                    rect: [
+                        x / width,
+                        top / height,
+                        (x + placed.width) / width,
+                        (top + line.height) / height,
+                    ],
+                    image: Arc::clone(&placed.image),
+                })
+            })
+            .collect()
+    }
+
+    /// Breaks a block into lines at UAX #14 opportunities, hyphenating
+    /// where allowed, and shapes each line into runs in the UAX #9 display
+    /// order, placed within the block's width. Returns the byte offset each
+    /// line starts at with its runs.
+    fn layout_block(
+        &self,
+        block: &Block,
+        layout: &BlockLayout,
+        font_size: f32,
+        scale: f32,
+    ) -> Vec<(usize, Vec<TextRun>)> {
+        // Shape at the target size, store unscaled
+        let size = font_size * scale;
+        let paragraph = Paragraph::of(block, layout.forced, &self.fonts);
+        let text = paragraph.text.as_str();
+        let measure = |range: Range<usize>| self.measure(&paragraph, range, size) / scale;
+        let hyphen = |end: usize| self.shape("-", paragraph.font_before(end), size, None).1 / scale;
+
+        let words = Self::split_words(text, measure);
+        let lines = Self::wrap_words(words, layout.width, |word, room| {
+            Self::hyphenate(text, word, room, layout.hyphenation?, measure, hyphen)
+        });
+
+        let bidi = BidiInfo::new(text, None);
+        // "Left" means the start of the line, the right side in RTL text
+        let align = match layout.align {
+            Align::Left if bidi.paragraphs.first().is_some_and(|p| p.level.is_rtl()) => {
+                Align::Right
+            }
+            align => align,
+        };
+
+        let last = lines.len() - 1;
+        lines
+            .iter()
+            .enumerate()
+            .map(|(n, words)| {
+                let (Some(first), Some(end)) = (words.first(), words.last()) else {
+                    return (0, Vec::new());
+                };
+                let range = first.range.start..end.range.end;
+                let start = range.start;
+                // The last line of a paragraph keeps normal spaces
+                let spaces = text[range.clone()].matches(' ').count();
+                let extra = if layout.justify && n < last && spaces > 0 {
+                    let natural: f32 =
+                        words.iter().map(|w| w.width + w.space).sum::<f32>() - end.space;
+                    ((layout.width - natural) / spaces as f32).max(0.0)
                 } else {
-                    lines.push(current_line);
-                    current_line = word.to_string();
-                    current_width = word_width;
+                    0.0
+                };
+
+                let (mut runs, width) =
+                    self.shape_line(&paragraph, &bidi, range, end.hyphen, extra, size, scale);
+                let offset = match align {
+                    Align::Left => 0.0,
+                    Align::Center => (layout.width - width) / 2.0,
+                    Align::Right => layout.width - width,
+                }
+                .max(0.0);
+                for run in &mut runs {
+                    run.x += layout.left + offset;
+                }
+                (start, runs)
+            })
+            .collect()
+    }
+
+    /// Shapes a line into runs of one face and direction, placed from the
+    /// left in visual order with `extra` added to every space. Returns the
+    /// runs in logical order and the width of the line.
+    #[allow(clippy::too_many_arguments)]
+    fn shape_line(
+        &self,
+        paragraph: &Paragraph,
+        bidi: &BidiInfo,
+        range: Range<usize>,
+        hyphen: bool,
+        extra: f32,
+        size: f32,
+        scale: f32,
+    ) -> (Vec<TextRun>, f32) {
+        let Some(para) = bidi.paragraphs.first() else {
+            return (Vec::new(), 0.0);
+        };
+        let (levels, level_runs) = bidi.visual_runs(para, range.clone());
+
+        let mut runs: Vec<(usize, TextRun)> = Vec::new();
+        let mut x = 0.0;
+        for level_run in level_runs {
+            let rtl = levels[level_run.start].is_rtl();
+            let mut parts: Vec<_> = paragraph.face_runs(level_run).collect();
+            if rtl {
+                parts.reverse();
+            }
+            for (part, face, font) in parts {
+                let mut text = paragraph.text[part.clone()].to_string();
+                let (mut glyphs, _) = self.shape(&text, font, size, Some(rtl));
+                if hyphen && part.end == range.end {
+                    let pen: f32 = glyphs.iter().map(|g| g.advance).sum();
+                    let (dash, _) = self.shape("-", font, size, Some(false));
+                    glyphs.extend(dash.into_iter().map(|g| Glyph {
+                        x: pen + g.x,
+                        cluster: text.len(),
+                        ..g
+                    }));
+                    text.push(SOFT_HYPHEN);
+                }
+
+                let mut shift = 0.0;
+                for glyph in &mut glyphs {
+                    glyph.x = glyph.x / scale + shift;
+                    glyph.y /= scale;
+                    glyph.advance /= scale;
+                    if text[glyph.cluster..].starts_with(' ') {
+                        glyph.advance += extra;
+                        shift += extra;
+                    }
                 }
+                let width: f32 = glyphs.iter().map(|g| g.advance).sum();
+                runs.push((
+                    part.start,
+                    TextRun {
+                        text,
+                        face,
+                        font,
+                        rtl,
+                        start: part.start,
+                        x,
+                        glyphs,
+                    },
+                ));
+                x += width;
             }
         }
+        runs.sort_by_key(|(start, _)| *start);
+        (runs.into_iter().map(|(_, run)| run).collect(), x)
+    }
 
-        if !current_line.is_empty() {
-            lines.push(current_line);
+    /// Splits text at its line break opportunities (UAX #14): after spaces
+    /// and hyphens, between ideographs, and so on.
+    fn split_words(text: &str, measure: impl Fn(Range<usize>) -> f32) -> Vec<Word> {
+        let mut words = Vec::new();
+        let mut start = 0;
+        for (end, _) in unicode_linebreak::linebreaks(text) {
+            let content_end = start + text[start..end].trim_end().len();
+            if content_end > start {
+                words.push(Word {
+                    range: start..content_end,
+                    width: measure(start..content_end),
+                    space: measure(content_end..end),
+                    hyphen: false,
+                });
+            }
+            start = end;
         }
+        words
+    }
 
-        if lines.is_empty() {
-            lines.push(String::new());
+    /// Greedy line breaking; returns the words of each line. A word that
+    /// does not fit may be split by `hyphenate(word, room)` into a head no
+    /// wider than `room` and the rest. A word wider than the line that
+    /// cannot be split gets a line of its own. Always returns at least one
+    /// line.
+    fn wrap_words(
+        words: Vec<Word>,
+        max_width: f32,
+        hyphenate: impl Fn(&Word, f32) -> Option<(Word, Word)>,
+    ) -> Vec<Vec<Word>> {
+        let mut lines = Vec::new();
+        let mut line: Vec<Word> = Vec::new();
+        let mut line_width = 0.0f32;
+        let mut queue = VecDeque::from(words);
+
+        while let Some(word) = queue.pop_front() {
+            if line_width + word.width <= max_width {
+                line_width += word.width + word.space;
+                line.push(word);
+                continue;
+            }
+            if let Some((head, tail)) = hyphenate(&word, max_width - line_width) {
+                line.push(head);
+                queue.push_front(tail);
+            } else if line.is_empty() {
+                line.push(word);
+            } else {
+                queue.push_front(word);
+            }
+            lines.push(std::mem::take(&mut line));
+            line_width = 0.0;
         }
 
+        if !line.is_empty() || lines.is_empty() {
+            lines.push(line);
+        }
         lines
     }
 
-    fn measure_text(text: &str, scaled_font: &PxScaleFont<&FontRef<'_>>) -> f32 {
-        text.chars()
-            .map(|c| {
-                let glyph_id = scaled_font.glyph_id(c);
-                scaled_font.h_advance(glyph_id)
+    /// Splits a word at the last hyphenation point that leaves a head,
+    /// with `hyphen(end)` for its hyphen, no wider than `room`. Punctuation
+    /// around the word stays with its parts; words with other characters
+    /// inside are kept whole.
+    fn hyphenate(
+        text: &str,
+        word: &Word,
+        room: f32,
+        lang: Lang,
+        measure: impl Fn(Range<usize>) -> f32,
+        hyphen: impl Fn(usize) -> f32,
+    ) -> Option<(Word, Word)> {
+        let Range { start, end } = word.range;
+        let content = &text[start..end];
+        let not_letter = |c: char| !c.is_alphabetic();
+        let core = content.trim_matches(not_letter);
+        if core.is_empty() || !in_script(lang, core) {
+            return None;
+        }
+
+        let mut points = Vec::new();
+        let mut at = start + content.len() - content.trim_start_matches(not_letter).len();
+        for syllable in hypher::hyphenate(core, lang) {
+            at += syllable.len();
+            points.push(at);
+        }
+        // The end of the word is not a hyphenation point
+        points.pop();
+
+        points.into_iter().rev().find_map(|at| {
+            let width = measure(start..at) + hyphen(at);
+            (width <= room).then(|| {
+                let head = Word {
+                    range: start..at,
+                    width,
+                    space: 0.0,
+                    hyphen: true,
+                };
+                let tail = Word {
+                    range: at..end,
+                    width: measure(at..end),
+                    ..word.clone()
+                };
+                (head, tail)
             })
-            .sum()
+        })
     }
 }
 
+/// Blends a glyph's coverage onto the image in `color`.
+fn draw_glyph(image: &mut RgbaImage, font: &FontRef<'_>, glyph: ab_glyph::Glyph, color: Rgba<u8>) {
+    let Some(outlined) = font.outline_glyph(glyph) else {
+        return;
+    };
+    let bounds = outlined.px_bounds();
+    let (width, height) = image.dimensions();
+    outlined.draw(|gx, gy, coverage| {
+        let x = bounds.min.x as i32 + gx as i32;
+        let y = bounds.min.y as i32 + gy as i32;
+        if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+            return;
+        }
+        let pixel = image.get_pixel_mut(x as u32, y as u32);
+        let coverage = coverage.clamp(0.0, 1.0);
+        for (channel, target) in pixel.0.iter_mut().zip(color.0).take(3) {
+            *channel =
+                (*channel as f32 * (1.0 - coverage) + target as f32 * coverage).round() as u8;
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::renderer::rich_text::{BlockBuilder, BlockFormat};
 
     fn renderer() -> TextPageRenderer {
-        TextPageRenderer::new()
+        TextPageRenderer {
+            fonts: FontSet::embedded(),
+        }
+    }
+
+    fn plain(texts: impl IntoIterator<Item = String>) -> Vec<Block> {
+        texts.into_iter().map(|t| Block::plain(&t)).collect()
+    }
+
+    fn formatted(format: BlockFormat, text: &str) -> Block {
+        Block {
+            format,
+            ..Block::plain(text)
+        }
     }
 
     #[test]
@@ -151,29 +1023,27 @@ mod tests {
         let r = renderer();
         let pages = r.paginate(&[], 1.0);
         assert_eq!(pages.len(), 1);
-        assert_eq!(pages[0][0], "(Пустой документ)");
+        assert_eq!(pages[0][0].text(), "(Пустой документ)");
     }
 
     #[test]
     fn test_paginate_single_paragraph() {
         let r = renderer();
-        let pages = r.paginate(&["Hello world".to_string()], 1.0);
+        let pages = r.paginate(&[Block::plain("Hello world")], 1.0);
         assert!(!pages.is_empty());
-        assert!(pages[0].iter().any(|l| l.contains("Hello")));
+        assert!(pages[0].iter().any(|l| l.text().contains("Hello")));
     }
 
     #[test]
     fn test_paginate_creates_multiple_pages() {
         let r = renderer();
         // Create enough text to overflow one page
-        let long_paragraphs: Vec<String> = (0..200)
-            .map(|i| {
-                format!(
-                    "Paragraph number {} with some extra text to fill the page.",
-                    i
-                )
-            })
-            .collect();
+        let long_paragraphs = plain((0..200).map(|i| {
+            format!(
+                "Paragraph number {} with some extra text to fill the page.",
+                i
+            )
+        }));
         let pages = r.paginate(&long_paragraphs, 1.0);
         assert!(
             pages.len() > 1,
@@ -185,9 +1055,7 @@ mod tests {
     #[test]
     fn test_paginate_scale_affects_pages() {
         let r = renderer();
-        let paragraphs: Vec<String> = (0..100)
-            .map(|i| format!("Line {} with content.", i))
-            .collect();
+        let paragraphs = plain((0..100).map(|i| format!("Line {} with content.", i)));
         let pages_small = r.paginate(&paragraphs, 0.5);
         let pages_large = r.paginate(&paragraphs, 2.0);
         // Larger scale = larger text = more pages
@@ -199,93 +1067,549 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_paginate_indexed_page_starts() {
+        let r = renderer();
+        let paragraphs =
+            plain((0..200).map(|i| format!("Paragraph {} with enough words to take a line.", i)));
+        let (pages, starts) = r.paginate_indexed(&paragraphs, &TextLayout::default(), 1.0);
+        assert_eq!(pages.len(), starts.len());
+        assert_eq!(starts[0], PageStart::default());
+        assert!(starts.windows(2).all(|w| w[0] < w[1]));
+        // Each page starts with a line from its start paragraph
+        for (page, &start) in pages.iter().zip(&starts) {
+            let first_line = page[0].text();
+            assert!(paragraphs[start.block]
+                .text()
+                .starts_with(first_line.split(' ').next().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_typography_sizes() {
+        let typography = TypographySettings {
+            font_size: 10.0,
+            line_spacing: 2.0,
+            paragraph_spacing: 1.0,
+            margin: 500,
+            page_width: 600,
+            page_height: 900,
+            ..TypographySettings::default()
+        };
+        let layout = TextLayout::new(&typography, &Document::default());
+        assert_eq!(layout.line_height, 20.0);
+        assert_eq!(layout.paragraph_spacing, 20.0);
+        // Margins never take more than half of the page
+        assert_eq!(layout.margin, 150.0);
+
+        let r = renderer();
+        let image = r.render_page(&[], &layout, PageColors::DAY, 1.0);
+        assert_eq!((image.width(), image.height()), (600, 900));
+    }
+
+    #[test]
+    fn test_page_positions_survive_relayout() {
+        let r = renderer();
+        let paragraphs =
+            plain((0..100).map(|i| format!("Paragraph {} with enough words to take a line.", i)));
+        let small = TextLayout::default();
+        let large = TextLayout {
+            font_size: small.font_size * 2.0,
+            line_height: small.line_height * 2.0,
+            ..small
+        };
+        let (_, small_starts) = r.paginate_indexed(&paragraphs, &small, 1.0);
+        let (large_pages, large_starts) = r.paginate_indexed(&paragraphs, &large, 1.0);
+        assert!(large_starts.len() > small_starts.len());
+
+        // The page of the larger layout holding the start of a page of the
+        // smaller one shows the same paragraph start
+        let positions: Vec<u64> = large_starts.iter().map(|p| p.position()).collect();
+        for start in small_starts.iter().filter(|s| s.offset == 0) {
+            let page = positions.partition_point(|&p| p <= start.position()) - 1;
+            let first_words = format!("Paragraph {} ", start.block);
+            assert!(large_pages[page]
+                .iter()
+                .any(|l| l.text().starts_with(&first_words)));
+        }
+    }
+
+    #[test]
+    fn test_highlight_survives_font_change() {
+        let r = renderer();
+        let paragraphs =
+            plain((0..60).map(|i| format!("Paragraph {} with enough words to take a line.", i)));
+        let small = TextLayout::default();
+        let large = TextLayout {
+            font_size: small.font_size * 1.5,
+            line_height: small.line_height * 1.5,
+            ..small
+        };
+
+        // Anchor a highlight to the positions of a page's words
+        let (small_pages, _) = r.paginate_indexed(&paragraphs, &small, 1.0);
+        let page = r.page_text(&small_pages[1], &small);
+        let words = "with enough words";
+        let start = (0..page.chars.len())
+            .find(|&i| page.text(i, i + words.len() - 1) == words)
+            .unwrap();
+        let end = start + words.len() - 1;
+        let anchor = [page.position(start).unwrap(), page.position(end).unwrap()];
+
+        // The page of the larger layout holding the anchor shows the same words
+        let (large_pages, large_starts) = r.paginate_indexed(&paragraphs, &large, 1.0);
+        let positions: Vec<u64> = large_starts.iter().map(|p| p.position()).collect();
+        let index = positions.partition_point(|&p| p <= anchor[0]) - 1;
+        let relaid = r.page_text(&large_pages[index], &large);
+        let (start, end) = relaid.range_of(anchor[0], anchor[1]).unwrap();
+        assert_eq!(relaid.text(start, end), "with enough words");
+        assert_ne!(small_pages.len(), large_pages.len());
+    }
+
+    #[test]
+    fn test_page_sections() {
+        let starts = [0, 2, 5].map(|block| PageStart { block, offset: 0 });
+        let sections = TextPageRenderer::page_sections(&starts, &[0, 0, 1, 1, 1, 2]);
+        assert_eq!(sections, vec![0, 1, 2]);
+        let first = [PageStart::default()];
+        assert_eq!(TextPageRenderer::page_sections(&first, &[]), vec![0]);
+    }
+
+    #[test]
+    fn test_styled_runs() {
+        let r = renderer();
+        let mut b = BlockBuilder::default();
+        b.text("plain ");
+        b.set_bold(true);
+        b.text("bold");
+        b.set_bold(false);
+        b.set_italic(true);
+        b.text("tail and more");
+        let pages = r.paginate(&b.finish(), 1.0);
+        let runs = &pages[0][0].runs;
+        let faces: Vec<FontFace> = runs.iter().map(|r| r.face).collect();
+        assert_eq!(
+            faces,
+            vec![FontFace::Regular, FontFace::Bold, FontFace::Italic]
+        );
+        // "boldtail" is one word in two faces, without a space between
+        assert_eq!(runs[0].text, "plain");
+        assert_eq!(runs[1].text, " bold");
+        assert_eq!(runs[2].text, "tail and more");
+        assert!(runs[1].x < runs[2].x);
+    }
+
+    #[test]
+    fn test_heading_bold_and_larger() {
+        let r = renderer();
+        let heading = formatted(
+            BlockFormat {
+                kind: BlockKind::Heading(1),
+                ..BlockFormat::default()
+            },
+            "Title",
+        );
+        let pages = r.paginate(&[heading, Block::plain("Body")], 1.0);
+        let (title, body) = (&pages[0][0], &pages[0][1]);
+        assert_eq!(title.runs[0].face, FontFace::Bold);
+        assert!(title.font_size > body.font_size);
+        assert!(title.height > body.height);
+    }
+
+    #[test]
+    fn test_alignment_and_indent() {
+        let r = renderer();
+        let with = |kind, align, indent| {
+            let block = formatted(
+                BlockFormat {
+                    kind,
+                    align,
+                    indent,
+                },
+                "Short",
+            );
+            r.paginate(&[block], 1.0)[0][0].runs[0].x
+        };
+        let left = with(BlockKind::Paragraph, Align::Left, 0);
+        let center = with(BlockKind::Paragraph, Align::Center, 0);
+        let right = with(BlockKind::Paragraph, Align::Right, 0);
+        assert_eq!(left, 0.0);
+        assert!(left < center && center < right);
+        assert_eq!(
+            with(BlockKind::Paragraph, Align::Left, 2),
+            2.0 * TEXT_INDENT_STEP
+        );
+        assert!(with(BlockKind::Epigraph, Align::Left, 0) > 2.0 * TEXT_INDENT_STEP);
+    }
+
+    #[test]
+    fn test_verse_lines_without_spacing() {
+        let r = renderer();
+        let verse = BlockFormat {
+            kind: BlockKind::Verse,
+            ..BlockFormat::default()
+        };
+        let pages = r.paginate(&[formatted(verse, "One"), formatted(verse, "Two")], 1.0);
+        assert_eq!(
+            pages[0][1].top - pages[0][0].top,
+            TextLayout::default().line_height
+        );
+        let pages = r.paginate(&plain(["One".into(), "Two".into()]), 1.0);
+        assert!(pages[0][1].top - pages[0][0].top > TextLayout::default().line_height);
+    }
+
     #[test]
     fn test_render_page_dimensions() {
         let r = renderer();
-        let lines = vec!["Test line".to_string()];
-        let image = r.render_page(&lines, 1.0);
-        assert_eq!(image.width(), TEXT_PAGE_WIDTH);
-        assert_eq!(image.height(), TEXT_PAGE_HEIGHT);
+        let lines = r.paginate(&[Block::plain("Test line")], 1.0).remove(0);
+        let image = r.render_page(&lines, &TextLayout::default(), PageColors::DAY, 1.0);
+        assert_eq!(image.width(), TEXT_PAGE_WIDTH_DEFAULT);
+        assert_eq!(image.height(), TEXT_PAGE_HEIGHT_DEFAULT);
     }
 
     #[test]
     fn test_render_page_scaled_dimensions() {
         let r = renderer();
-        let lines = vec!["Test".to_string()];
-        let image = r.render_page(&lines, 2.0);
-        assert_eq!(image.width(), TEXT_PAGE_WIDTH * 2);
-        assert_eq!(image.height(), TEXT_PAGE_HEIGHT * 2);
+        let lines = r.paginate(&[Block::plain("Test")], 2.0).remove(0);
+        let image = r.render_page(&lines, &TextLayout::default(), PageColors::DAY, 2.0);
+        assert_eq!(image.width(), TEXT_PAGE_WIDTH_DEFAULT * 2);
+        assert_eq!(image.height(), TEXT_PAGE_HEIGHT_DEFAULT * 2);
     }
 
     #[test]
     fn test_render_page_white_background() {
         let r = renderer();
-        let lines = vec![];
-        let image = r.render_page(&lines, 1.0);
+        let image = r.render_page(&[], &TextLayout::default(), PageColors::DAY, 1.0);
         // Corner pixel should be white
         let pixel = image.get_pixel(0, 0);
         assert_eq!(pixel, &Rgba([255, 255, 255, 255]));
     }
 
     #[test]
-    fn test_wrap_text_short() {
+    fn test_render_page_theme_colors() {
         let r = renderer();
-        let px_scale = PxScale::from(TEXT_FONT_SIZE);
-        let scaled_font = r.font.as_scaled(px_scale);
-        let lines = TextPageRenderer::wrap_text("Hello", 1000.0, &scaled_font);
-        assert_eq!(lines.len(), 1);
-        assert_eq!(lines[0], "Hello");
+        let colors = PageColors {
+            foreground: [200, 200, 200],
+            background: [20, 20, 20],
+        };
+        let lines = r.paginate(&[Block::plain("Ночь")], 1.0).remove(0);
+        let image = r.render_page(&lines, &TextLayout::default(), colors, 1.0);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([20, 20, 20, 255]));
+        // Fully covered glyph pixels take the text colour
+        assert!(image.pixels().any(|p| p == &Rgba([200, 200, 200, 255])));
+    }
+
+    /// Width of a range of text at 10px a character.
+    fn fixed_width(text: &str) -> impl Fn(Range<usize>) -> f32 + '_ {
+        |range| text[range].chars().count() as f32 * 10.0
+    }
+
+    fn hyphen_width(_: usize) -> f32 {
+        10.0
+    }
+
+    fn sized(widths: &[f32]) -> Vec<Word> {
+        widths
+            .iter()
+            .map(|&width| Word {
+                range: 0..0,
+                width,
+                space: 5.0,
+                hyphen: false,
+            })
+            .collect()
+    }
+
+    fn line_lengths(lines: &[Vec<Word>]) -> Vec<usize> {
+        lines.iter().map(Vec::len).collect()
+    }
+
+    fn no_hyphenation(_: &Word, _: f32) -> Option<(Word, Word)> {
+        None
     }
 
     #[test]
-    fn test_wrap_text_empty() {
-        let r = renderer();
-        let px_scale = PxScale::from(TEXT_FONT_SIZE);
-        let scaled_font = r.font.as_scaled(px_scale);
-        let lines = TextPageRenderer::wrap_text("", 1000.0, &scaled_font);
-        assert_eq!(lines.len(), 1);
-        assert_eq!(lines[0], "");
+    fn test_wrap_words_short() {
+        let lines = TextPageRenderer::wrap_words(sized(&[50.0]), 1000.0, no_hyphenation);
+        assert_eq!(line_lengths(&lines), vec![1]);
     }
 
     #[test]
-    fn test_wrap_text_wraps_long_line() {
-        let r = renderer();
-        let px_scale = PxScale::from(TEXT_FONT_SIZE);
-        let scaled_font = r.font.as_scaled(px_scale);
-        let long_text = "word ".repeat(100);
-        let lines = TextPageRenderer::wrap_text(&long_text.trim(), 200.0, &scaled_font);
+    fn test_wrap_words_empty() {
+        let lines = TextPageRenderer::wrap_words(Vec::new(), 1000.0, no_hyphenation);
+        assert_eq!(line_lengths(&lines), vec![0]);
+    }
+
+    #[test]
+    fn test_wrap_words_wraps_long_line() {
+        let lines = TextPageRenderer::wrap_words(sized(&[40.0; 100]), 200.0, no_hyphenation);
         assert!(
             lines.len() > 1,
             "Expected wrapping, got {} lines",
             lines.len()
         );
+        assert_eq!(lines[0].len(), 4);
+        assert_eq!(lines.iter().map(Vec::len).sum::<usize>(), 100);
+    }
+
+    #[test]
+    fn test_wrap_words_overlong_word() {
+        let lines =
+            TextPageRenderer::wrap_words(sized(&[10.0, 500.0, 10.0]), 200.0, no_hyphenation);
+        assert_eq!(line_lengths(&lines), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn test_split_words_at_break_opportunities() {
+        let text = "Hello, wide world — 漢字";
+        let words = TextPageRenderer::split_words(text, fixed_width(text));
+        let parts: Vec<&str> = words.iter().map(|w| &text[w.range.clone()]).collect();
+        assert_eq!(parts, vec!["Hello,", "wide", "world", "—", "漢", "字"]);
+        assert_eq!(words[0].width, 60.0);
+        assert_eq!(words[0].space, 10.0);
+        assert_eq!(words[4].space, 0.0);
+    }
+
+    /// Splits the only word of `text` with room for `room` pixels.
+    fn split(text: &str, room: f32, lang: Lang) -> Option<(String, String)> {
+        let word = TextPageRenderer::split_words(text, fixed_width(text)).remove(0);
+        let (head, tail) =
+            TextPageRenderer::hyphenate(text, &word, room, lang, fixed_width(text), hyphen_width)?;
+        assert!(head.hyphen && !tail.hyphen);
+        assert_eq!(head.width, fixed_width(text)(head.range.clone()) + 10.0);
+        Some((text[head.range].to_string(), text[tail.range].to_string()))
+    }
+
+    #[test]
+    fn test_hyphenate_last_fitting_point() {
+        let parts = |a: &str, b: &str| Some((a.to_string(), b.to_string()));
+        // ex-ten-sive; the hyphen takes a character's width
+        assert_eq!(
+            split("extensive", 60.0, Lang::English),
+            parts("exten", "sive")
+        );
+        assert_eq!(
+            split("extensive", 59.0, Lang::English),
+            parts("ex", "tensive")
+        );
+        assert_eq!(split("extensive", 20.0, Lang::English), None);
+    }
+
+    #[test]
+    fn test_hyphenate_keeps_punctuation() {
+        assert_eq!(
+            split("«переход»,", 1000.0, Lang::Russian),
+            Some(("«пере".to_string(), "ход»,".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_hyphenate_only_matching_script() {
+        let hyphenate = |text, lang| split(text, 1000.0, lang).is_some();
+        assert!(hyphenate("переход", Lang::Russian));
+        assert!(!hyphenate("переход", Lang::English));
+        assert!(!hyphenate("extensive", Lang::Russian));
+        assert!(!hyphenate("кто-то", Lang::Russian));
+    }
+
+    #[test]
+    fn test_wrap_words_hyphenates_into_free_space() {
+        let text = "an extensive list";
+        let measure = fixed_width(text);
+        let words = TextPageRenderer::split_words(text, &measure);
+        let lines = TextPageRenderer::wrap_words(words, 100.0, |word, room| {
+            TextPageRenderer::hyphenate(text, word, room, Lang::English, &measure, hyphen_width)
+        });
+        let texts: Vec<Vec<&str>> = lines
+            .iter()
+            .map(|line| line.iter().map(|w| &text[w.range.clone()]).collect())
+            .collect();
+        assert_eq!(texts, vec![vec!["an", "exten"], vec!["sive", "list"]]);
+        assert!(lines[0][1].hyphen);
+    }
+
+    #[test]
+    fn test_text_layout_language() {
+        let typography = TypographySettings::default();
+        let document = |language: Option<&str>, text: &str| Document {
+            blocks: vec![Block::plain(text)],
+            language: language.map(str::to_string),
+            ..Document::default()
+        };
+        let lang = |doc: &Document| TextLayout::new(&typography, doc).hyphenation;
+        assert_eq!(lang(&document(Some("ru-RU"), "")), Some(Lang::Russian));
+        assert_eq!(lang(&document(Some("en"), "")), Some(Lang::English));
+        // Metadata wins over the script of the text
+        assert_eq!(lang(&document(Some("de"), "Привет")), None);
+        assert_eq!(lang(&document(None, "Привет, world")), Some(Lang::Russian));
+        assert_eq!(lang(&document(None, "Hello, мир")), Some(Lang::English));
+        assert_eq!(lang(&document(None, "1984")), None);
+
+        let off = TypographySettings {
+            hyphenation: false,
+            justify: true,
+            ..TypographySettings::default()
+        };
+        let layout = TextLayout::new(&off, &document(Some("ru"), ""));
+        assert_eq!(layout.hyphenation, None);
+        assert!(layout.justify);
+    }
+
+    /// Right edge of each line of a laid out page, in page fractions.
+    fn line_ends(r: &TextPageRenderer, lines: &[TextLine]) -> Vec<f32> {
+        let text = r.page_text(lines, &TextLayout::default());
+        text.chars
+            .split(|c| c.ch == '\n')
+            .map(|line| line.last().map_or(0.0, |c| c.rect[2]))
+            .collect()
+    }
+
+    fn long_paragraph() -> Block {
+        Block::plain(&"Съешь же ещё этих мягких французских булок, да выпей чаю. ".repeat(12))
+    }
+
+    #[test]
+    fn test_justified_lines_end_flush() {
+        let r = renderer();
+        let layout = TextLayout {
+            hyphenation: None,
+            justify: true,
+            ..TextLayout::default()
+        };
+        let (pages, _) = r.paginate_indexed(&[long_paragraph()], &layout, 1.0);
+        let ends = line_ends(&r, &pages[0]);
+        let right = (TEXT_PAGE_WIDTH_DEFAULT - TEXT_PAGE_MARGIN_DEFAULT) as f32
+            / TEXT_PAGE_WIDTH_DEFAULT as f32;
+        assert!(ends.len() > 2);
+        for end in &ends[..ends.len() - 1] {
+            assert!((end - right).abs() < 0.002, "line ends at {}", end);
+        }
+        assert!(ends[ends.len() - 1] < right - 0.01);
+
+        let ragged = r.paginate(&[long_paragraph()], 1.0);
+        let ends = line_ends(&r, &ragged[0]);
+        assert!(ends[..ends.len() - 1]
+            .iter()
+            .any(|end| (end - right).abs() > 0.01));
+    }
+
+    #[test]
+    fn test_hyphenated_layout_keeps_text() {
+        let r = renderer();
+        let layout = TextLayout {
+            hyphenation: Some(Lang::Russian),
+            justify: true,
+            ..TextLayout::default()
+        };
+        let block = long_paragraph();
+        let (pages, _) = r.paginate_indexed(std::slice::from_ref(&block), &layout, 1.0);
+        let lines = &pages[0];
+        assert!(lines.iter().any(|l| l.text().ends_with(SOFT_HYPHEN)));
+        let text = r.page_text(lines, &TextLayout::default());
+        assert_eq!(text.text(0, text.chars.len() - 1), block.text().trim());
+    }
+
+    fn width(r: &TextPageRenderer, text: &str) -> f32 {
+        r.shape(text, 0, TEXT_FONT_SIZE_DEFAULT, None).1
+    }
+
+    #[test]
+    fn test_shape_width_positive() {
+        assert!(width(&renderer(), "Hello") > 0.0);
+    }
+
+    #[test]
+    fn test_shape_empty() {
+        let r = renderer();
+        let (glyphs, width) = r.shape("", 0, TEXT_FONT_SIZE_DEFAULT, None);
+        assert!(glyphs.is_empty());
+        assert_eq!(width, 0.0);
+    }
+
+    #[test]
+    fn test_shape_longer_is_wider() {
+        let r = renderer();
+        assert!(width(&r, "Hello World") > width(&r, "Hi"));
+    }
+
+    #[test]
+    fn test_shape_kerning() {
+        let r = renderer();
+        assert!(width(&r, "AV") < width(&r, "A") + width(&r, "V"));
+    }
+
+    #[test]
+    fn test_shape_combining_mark() {
+        let r = renderer();
+        // A base letter with a combining accent takes the letter's width
+        let (glyphs, w) = r.shape("e\u{301}", 0, TEXT_FONT_SIZE_DEFAULT, None);
+        assert!((w - width(&r, "e")).abs() < 0.01);
+        assert!(glyphs.iter().all(|g| g.cluster == 0));
+    }
+
+    #[test]
+    fn test_cjk_wraps_without_spaces() {
+        let r = renderer();
+        let (pages, _) = r.paginate_indexed(
+            &[Block::plain(&"漢字仮名交じり文".repeat(20))],
+            &TextLayout::default(),
+            1.0,
+        );
+        let ends = line_ends(&r, &pages[0]);
+        let right = (TEXT_PAGE_WIDTH_DEFAULT - TEXT_PAGE_MARGIN_DEFAULT) as f32
+            / TEXT_PAGE_WIDTH_DEFAULT as f32;
+        assert!(ends.len() > 1);
+        assert!(ends.iter().all(|&end| end <= right + 1e-4));
     }
 
     #[test]
-    fn test_measure_text_positive() {
+    fn test_bidi_reorders_runs() {
         let r = renderer();
-        let px_scale = PxScale::from(TEXT_FONT_SIZE);
-        let scaled_font = r.font.as_scaled(px_scale);
-        let width = TextPageRenderer::measure_text("Hello", &scaled_font);
-        assert!(width > 0.0);
+        let lines = r.paginate(&[Block::plain("abc אבג def")], 1.0).remove(0);
+        let runs = &lines[0].runs;
+        let hebrew = runs.iter().find(|run| run.rtl).expect("no RTL run");
+        assert_eq!(hebrew.text, "אבג");
+        assert!(runs
+            .iter()
+            .filter(|run| !run.rtl)
+            .all(|run| !run.text.contains('א')));
+
+        // Text stays in logical order, the first Hebrew letter on the right
+        let text = r.page_text(&lines, &TextLayout::default());
+        assert_eq!(text.text(0, text.chars.len() - 1), "abc אבג def");
+        let x = |ch| text.chars.iter().find(|c| c.ch == ch).unwrap().rect[0];
+        assert!(x('א') > x('ב') && x('ב') > x('ג'));
+        assert!(x('a') < x('ג') && x('ב') < x('d'));
     }
 
     #[test]
-    fn test_measure_text_empty() {
+    fn test_rtl_paragraph_aligned_right() {
         let r = renderer();
-        let px_scale = PxScale::from(TEXT_FONT_SIZE);
-        let scaled_font = r.font.as_scaled(px_scale);
-        let width = TextPageRenderer::measure_text("", &scaled_font);
-        assert!((width - 0.0).abs() < f32::EPSILON);
+        let lines = r.paginate(&[Block::plain("שלום עולם")], 1.0).remove(0);
+        let ends = line_ends(&r, &lines);
+        let text = r.page_text(&lines, &TextLayout::default());
+        let right = (TEXT_PAGE_WIDTH_DEFAULT - TEXT_PAGE_MARGIN_DEFAULT) as f32
+            / TEXT_PAGE_WIDTH_DEFAULT as f32;
+        let rightmost = text.chars.iter().map(|c| c.rect[2]).fold(0.0, f32::max);
+        assert!((rightmost - right).abs() < 0.002, "{:?}", ends);
     }
 
     #[test]
-    fn test_measure_text_longer_is_wider() {
+    fn test_page_text_positions() {
         let r = renderer();
-        let px_scale = PxScale::from(TEXT_FONT_SIZE);
-        let scaled_font = r.font.as_scaled(px_scale);
-        let w1 = TextPageRenderer::measure_text("Hi", &scaled_font);
-        let w2 = TextPageRenderer::measure_text("Hello World", &scaled_font);
-        assert!(w2 > w1);
+        let lines = r
+            .paginate(&plain(["Hello".into(), "world".into()]), 1.0)
+            .remove(0);
+        let text = r.page_text(&lines, &TextLayout::default());
+        assert_eq!(text.chars.len(), 11);
+        assert_eq!(text.text(0, 10), "Hello world");
+
+        let h = text.chars[0].rect;
+        let w = text.chars[6].rect;
+        assert!(
+            (h[0] - TEXT_PAGE_MARGIN_DEFAULT as f32 / TEXT_PAGE_WIDTH_DEFAULT as f32).abs() < 1e-6
+        );
+        assert!(w[1] > h[1]);
+        assert_eq!(text.char_at(w[0] + 0.001, w[1] + 0.001), Some(6));
     }
 }
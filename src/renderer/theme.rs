@@ -0,0 +1,84 @@
+use image::RgbaImage;
+
+use crate::config::settings::PageColors;
+
+/// Recolours a page rendered dark on light into `colors`.
+///
+/// For dark themes the lightness of each pixel is inverted first, keeping
+/// its hue and saturation, so the paper turns dark while pictures keep
+/// their colours. Black and white are then mapped onto the darker and the
+/// lighter colour of the theme, channel by channel.
+pub fn recolor(image: &mut RgbaImage, colors: PageColors) {
+    if colors == PageColors::DAY {
+        return;
+    }
+    let dark = colors.is_dark();
+    let (low, high) = if dark {
+        (colors.background, colors.foreground)
+    } else {
+        (colors.foreground, colors.background)
+    };
+    let ramps: [[u8; 256]; 3] = std::array::from_fn(|channel| {
+        let (low, high) = (low[channel] as i32, high[channel] as i32);
+        std::array::from_fn(|value| (low + (high - low) * value as i32 / 255) as u8)
+    });
+
+    for pixel in image.pixels_mut() {
+        let [r, g, b, _] = &mut pixel.0;
+        let mut rgb = [*r, *g, *b];
+        if dark {
+            // Moving every channel by the same amount keeps the hue;
+            // max + min is twice the HSL lightness
+            let max = rgb.into_iter().max().unwrap_or(0) as i32;
+            let min = rgb.into_iter().min().unwrap_or(0) as i32;
+            let shift = 255 - max - min;
+            rgb = rgb.map(|c| (c as i32 + shift) as u8);
+        }
+        [*r, *g, *b] = std::array::from_fn(|channel| ramps[channel][rgb[channel] as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn recolored(pixel: [u8; 3], colors: PageColors) -> [u8; 3] {
+        let [r, g, b] = pixel;
+        let mut image = RgbaImage::from_pixel(1, 1, Rgba([r, g, b, 255]));
+        recolor(&mut image, colors);
+        let [r, g, b, a] = image.get_pixel(0, 0).0;
+        assert_eq!(a, 255);
+        [r, g, b]
+    }
+
+    const NIGHT: PageColors = PageColors {
+        foreground: [200, 200, 200],
+        background: [20, 20, 20],
+    };
+    const SEPIA: PageColors = PageColors {
+        foreground: [90, 70, 50],
+        background: [244, 236, 216],
+    };
+
+    #[test]
+    fn test_day_is_unchanged() {
+        assert_eq!(recolored([12, 200, 90], PageColors::DAY), [12, 200, 90]);
+    }
+
+    #[test]
+    fn test_paper_and_ink_take_theme_colors() {
+        assert_eq!(recolored([255, 255, 255], NIGHT), NIGHT.background);
+        assert_eq!(recolored([0, 0, 0], NIGHT), NIGHT.foreground);
+        assert_eq!(recolored([255, 255, 255], SEPIA), SEPIA.background);
+        assert_eq!(recolored([0, 0, 0], SEPIA), SEPIA.foreground);
+    }
+
+    #[test]
+    fn test_night_keeps_hue() {
+        // Dark red turns light red, not cyan
+        let [r, g, b] = recolored([128, 0, 0], NIGHT);
+        assert!(r > g && g == b, "got {:?}", [r, g, b]);
+        assert!(r > 128);
+    }
+}
@@ -1,7 +1,71 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use image::RgbaImage;
 
+use crate::config::settings::{PageColors, TypographySettings};
+
+use super::markup::{DocumentMarkups, Markup};
+use super::page_text::{PageImage, PageText};
+
 pub trait DocumentRenderer {
     fn get_page_count(&self, bytes: &[u8]) -> Result<u32>;
     fn render_page(&self, bytes: &[u8], page_index: u32, scale: f32) -> Result<RgbaImage>;
+
+    /// Typesetting options for reflowable formats, which lay the document
+    /// out again when they change.
+    fn set_typography(&self, _typography: TypographySettings) {}
+
+    /// Colours of the reading theme. Returns whether the renderer draws
+    /// pages in them; pages of those that do not are recoloured afterwards
+    /// (see [`super::theme::recolor`]).
+    fn set_colors(&self, _colors: PageColors) -> bool {
+        false
+    }
+
+    /// Quarter turns clockwise to draw pages at. Returns whether the
+    /// renderer turns pages itself; pages of those that do not are turned
+    /// afterwards (see [`super::rotation::rotate`]).
+    fn set_rotation(&self, _quarter_turns: u8) -> bool {
+        false
+    }
+
+    /// Size of each page at scale 1, as `render_page` draws it, so pages can
+    /// be laid out before they are rendered. Empty if it is not known.
+    fn page_sizes(&self, _bytes: &[u8]) -> Result<Vec<[f32; 2]>> {
+        Ok(Vec::new())
+    }
+
+    /// Section (EPUB spine item, FB2 top-level section) each page starts in.
+    /// Empty for fixed-layout formats.
+    fn page_sections(&self, _bytes: &[u8]) -> Result<Vec<u32>> {
+        Ok(Vec::new())
+    }
+
+    /// Where each page starts in the document, in increasing order. Unlike
+    /// page numbers, positions stay meaningful when the typography changes,
+    /// so the reader can find the page that now holds a position.
+    /// Empty for fixed-layout formats.
+    fn page_positions(&self, _bytes: &[u8]) -> Result<Vec<u64>> {
+        Ok(Vec::new())
+    }
+
+    /// Characters of a page with their positions, for text selection.
+    /// Empty if the format has no text layer.
+    fn page_text(&self, _bytes: &[u8], _page_index: u32) -> Result<PageText> {
+        Ok(PageText::default())
+    }
+
+    /// Images placed on a page, for opening them at full size.
+    fn page_images(&self, _bytes: &[u8], _page_index: u32) -> Result<Vec<PageImage>> {
+        Ok(Vec::new())
+    }
+
+    /// Copy of the document with the markups saved as annotations in the file.
+    fn write_markups(&self, _bytes: &[u8], _markups: &[Markup]) -> Result<Vec<u8>> {
+        bail!("This format cannot store annotations")
+    }
+
+    /// Annotations already stored in the document file.
+    fn read_markups(&self, _bytes: &[u8]) -> Result<DocumentMarkups> {
+        bail!("This format cannot store annotations")
+    }
 }
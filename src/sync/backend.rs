@@ -0,0 +1,80 @@
+use anyhow::Result;
+
+/// Object loaded from a sync backend together with its version tag.
+#[derive(Debug, Clone)]
+pub struct RemoteObject {
+    pub data: Vec<u8>,
+    pub etag: Option<String>,
+}
+
+/// Listing entry returned by [`SyncBackend::list`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub etag: Option<String>,
+}
+
+/// Precondition attached to a write for optimistic concurrency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    /// Unconditional write.
+    #[allow(dead_code)]
+    None,
+    /// The object must not exist yet (`If-None-Match: *`).
+    Absent,
+    /// The object must still have this ETag (`If-Match`).
+    Matches(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("Remote object was changed by another device")]
+    PreconditionFailed,
+}
+
+impl SyncError {
+    pub fn is_precondition_failed(err: &anyhow::Error) -> bool {
+        matches!(
+            err.downcast_ref::<SyncError>(),
+            Some(SyncError::PreconditionFailed)
+        )
+    }
+}
+
+/// Transport for synced objects. Keys are flat names relative to the backend root.
+pub trait SyncBackend: Send + Sync {
+    /// Human-readable location, shown in the UI.
+    fn describe(&self) -> String;
+
+    /// Returns `None` if the object does not exist.
+    fn get(&self, key: &str) -> Result<Option<RemoteObject>>;
+
+    /// Writes the object and returns its new ETag, if the backend reports one.
+    /// Fails with [`SyncError::PreconditionFailed`] if the precondition does not hold.
+    fn put(&self, key: &str, data: &[u8], precondition: &Precondition) -> Result<Option<String>>;
+
+    /// Lists objects directly under the backend root.
+    fn list(&self) -> Result<Vec<ObjectInfo>>;
+
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_precondition_failed() {
+        let err = anyhow::Error::new(SyncError::PreconditionFailed);
+        assert!(SyncError::is_precondition_failed(&err));
+
+        let other = anyhow::anyhow!("network down");
+        assert!(!SyncError::is_precondition_failed(&other));
+    }
+
+    #[test]
+    fn test_is_precondition_failed_with_context() {
+        let err = anyhow::Error::new(SyncError::PreconditionFailed).context("Failed to save");
+        assert!(SyncError::is_precondition_failed(&err));
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, SubsecRound, Utc};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::constants::*;
+use crate::library::progress::ReadingProgress;
+
+const SNAPSHOT_PREFIX: &str = "progress-";
+const SNAPSHOT_SUFFIX: &str = ".snapshot";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// A stored copy of the progress object, exactly as it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    pub created: DateTime<Utc>,
+}
+
+/// Rotating local history of the progress object.
+pub struct BackupStore {
+    dir: PathBuf,
+    keep: usize,
+    min_interval: chrono::Duration,
+}
+
+impl BackupStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            keep: BACKUP_KEEP_COUNT,
+            min_interval: chrono::Duration::seconds(BACKUP_INTERVAL_SECS),
+        }
+    }
+
+    /// `<data dir>/backups`, kept out of the synced folder on purpose.
+    pub fn default_dir() -> Result<PathBuf> {
+        let proj_dirs = directories::ProjectDirs::from("", "", PROJECT_NAME)
+            .context("Failed to get project directories")?;
+        Ok(proj_dirs.data_dir().join(BACKUP_DIR_NAME))
+    }
+
+    /// Newest first.
+    pub fn list(&self) -> Result<Vec<Snapshot>> {
+        let mut snapshots = Vec::new();
+        if !self.dir.exists() {
+            return Ok(snapshots);
+        }
+
+        for entry in fs::read_dir(&self.dir).context("Failed to read backup directory")? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+                continue;
+            };
+            let Some(stamp) = name
+                .strip_prefix(SNAPSHOT_PREFIX)
+                .and_then(|n| n.strip_suffix(SNAPSHOT_SUFFIX))
+            else {
+                continue;
+            };
+            if let Ok(created) = NaiveDateTime::parse_from_str(stamp, TIMESTAMP_FORMAT) {
+                snapshots.push(Snapshot {
+                    path,
+                    created: created.and_utc(),
+                });
+            }
+        }
+
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.created));
+        Ok(snapshots)
+    }
+
+    pub fn read(&self, snapshot: &Snapshot) -> Result<Vec<u8>> {
+        fs::read(&snapshot.path).context("Failed to read backup")
+    }
+
+    /// Stores `data` and drops the oldest snapshots beyond the limit.
+    pub fn snapshot(&self, data: &[u8]) -> Result<Snapshot> {
+        fs::create_dir_all(&self.dir).context("Failed to create backup directory")?;
+
+        // File names keep milliseconds only
+        let mut created = Utc::now().trunc_subsecs(3);
+        // Timestamps are the identity of a snapshot; never reuse one
+        if let Some(newest) = self.list()?.first() {
+            if created <= newest.created {
+                created = newest.created + chrono::Duration::milliseconds(1);
+            }
+        }
+        let path = self.dir.join(format!(
+            "{}{}{}",
+            SNAPSHOT_PREFIX,
+            created.format(TIMESTAMP_FORMAT),
+            SNAPSHOT_SUFFIX
+        ));
+        fs::write(&path, data).context("Failed to write backup")?;
+
+        for old in self.list()?.into_iter().skip(self.keep) {
+            let _ = fs::remove_file(&old.path);
+        }
+
+        Ok(Snapshot { path, created })
+    }
+
+    /// Snapshots `data` unless the newest snapshot is recent enough.
+    pub fn maybe_snapshot(&self, data: &[u8]) -> Result<Option<Snapshot>> {
+        if let Some(newest) = self.list()?.first() {
+            if Utc::now() - newest.created < self.min_interval {
+                return Ok(None);
+            }
+        }
+        self.snapshot(data).map(Some)
+    }
+}
+
+/// How a single book differs between two progress states.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookChange {
+    /// Present only in the snapshot.
+    Added {
+        page: u32,
+    },
+    /// Present only in the current progress.
+    Removed {
+        page: u32,
+    },
+    PageChanged {
+        from: u32,
+        to: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookDiff {
+    pub file_name: String,
+    pub change: BookChange,
+}
+
+/// Per-book differences from `current` to `snapshot`, sorted by file name.
+pub fn diff_progress(current: &ReadingProgress, snapshot: &ReadingProgress) -> Vec<BookDiff> {
+    let mut diffs = Vec::new();
+
+    for (hash, book) in &snapshot.books {
+        let change = match current.books.get(hash) {
+            None => BookChange::Added {
+                page: book.current_page,
+            },
+            Some(cur) if cur.current_page != book.current_page => BookChange::PageChanged {
+                from: cur.current_page,
+                to: book.current_page,
+            },
+            Some(_) => continue,
+        };
+        diffs.push(BookDiff {
+            file_name: book.file_name.clone(),
+            change,
+        });
+    }
+
+    for (hash, book) in &current.books {
+        if !snapshot.books.contains_key(hash) {
+            diffs.push(BookDiff {
+                file_name: book.file_name.clone(),
+                change: BookChange::Removed {
+                    page: book.current_page,
+                },
+            });
+        }
+    }
+
+    diffs.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(name: &str) -> (BackupStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("docreader_test_backup_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        (BackupStore::new(dir.clone()), dir)
+    }
+
+    #[test]
+    fn test_snapshot_and_list() {
+        let (store, dir) = temp_store("list");
+        assert!(store.list().unwrap().is_empty());
+
+        let first = store.snapshot(b"one").unwrap();
+        let second = store.snapshot(b"two").unwrap();
+        assert!(second.created > first.created);
+
+        let list = store.list().unwrap();
+        assert_eq!(list, vec![second.clone(), first]);
+        assert_eq!(store.read(&second).unwrap(), b"two");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let (mut store, dir) = temp_store("rotation");
+        store.keep = 3;
+
+        for i in 0..5u8 {
+            store.snapshot(&[i]).unwrap();
+        }
+        let list = store.list().unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(store.read(&list[0]).unwrap(), vec![4]);
+        assert_eq!(store.read(&list[2]).unwrap(), vec![2]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_maybe_snapshot_respects_interval() {
+        let (store, dir) = temp_store("interval");
+
+        assert!(store.maybe_snapshot(b"a").unwrap().is_some());
+        assert!(store.maybe_snapshot(b"b").unwrap().is_none());
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_diff_progress() {
+        let mut current = ReadingProgress::new("d".to_string());
+        current.add_book("h1".into(), "A.pdf".into(), "/a".into(), 100);
+        current.add_book("h2".into(), "B.pdf".into(), "/b".into(), 100);
+        current.add_book("h3".into(), "C.pdf".into(), "/c".into(), 100);
+        current.update_book_progress("h1", 50);
+
+        let mut snapshot = ReadingProgress::new("d".to_string());
+        snapshot.add_book("h1".into(), "A.pdf".into(), "/a".into(), 100);
+        snapshot.add_book("h2".into(), "B.pdf".into(), "/b".into(), 100);
+        snapshot.add_book("h4".into(), "D.pdf".into(), "/d".into(), 100);
+        snapshot.update_book_progress("h1", 20);
+
+        let diffs = diff_progress(&current, &snapshot);
+        assert_eq!(
+            diffs,
+            vec![
+                BookDiff {
+                    file_name: "A.pdf".into(),
+                    change: BookChange::PageChanged { from: 50, to: 20 },
+                },
+                BookDiff {
+                    file_name: "C.pdf".into(),
+                    change: BookChange::Removed { page: 1 },
+                },
+                BookDiff {
+                    file_name: "D.pdf".into(),
+                    change: BookChange::Added { page: 1 },
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,329 @@
+//! Passphrase encryption of progress objects.
+//!
+//! Envelope layout (version 1):
+//!
+//! ```text
+//! "DRENC" | version: u8 | m_cost: u32 LE | t_cost: u32 LE | p_cost: u32 LE
+//!         | salt: [u8; 16] | nonce: [u8; 24] | XChaCha20-Poly1305 ciphertext
+//! ```
+//!
+//! The key is derived with Argon2id from the passphrase and salt; the whole
+//! header is authenticated as associated data.
+
+use anyhow::Result;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::config::constants::*;
+
+const MAGIC: &[u8] = b"DRENC";
+const ENVELOPE_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 12 + SALT_LEN + NONCE_LEN;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("Wrong passphrase or corrupted encrypted file")]
+    WrongPassphrase,
+    #[error("File is encrypted but no passphrase is set")]
+    PassphraseRequired,
+    #[error("Unsupported encryption envelope version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Malformed encryption envelope")]
+    Malformed,
+}
+
+impl CryptoError {
+    pub fn find(err: &anyhow::Error) -> Option<&CryptoError> {
+        err.downcast_ref::<CryptoError>()
+    }
+}
+
+/// Argon2id cost parameters, stored in every envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: ENCRYPTION_KDF_MEMORY_KIB,
+            iterations: ENCRYPTION_KDF_ITERATIONS,
+            parallelism: ENCRYPTION_KDF_PARALLELISM,
+        }
+    }
+}
+
+pub struct ProgressCipher {
+    passphrase: String,
+    params: KdfParams,
+    // Derived keys by salt, one per device object in the per-device layout;
+    // key derivation is deliberately slow
+    keys: RefCell<HashMap<([u8; SALT_LEN], KdfParams), Key>>,
+    // Salt and parameters of the last key used, reused for writes
+    current: RefCell<Option<([u8; SALT_LEN], KdfParams)>>,
+}
+
+impl ProgressCipher {
+    pub fn new(passphrase: &str) -> Self {
+        Self::with_params(passphrase, KdfParams::default())
+    }
+
+    pub fn with_params(passphrase: &str, params: KdfParams) -> Self {
+        Self {
+            passphrase: passphrase.to_string(),
+            params,
+            keys: RefCell::new(HashMap::new()),
+            current: RefCell::new(None),
+        }
+    }
+
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        // Reuse the salt of the last derived key; the nonce is fresh every time
+        let current = *self.current.borrow();
+        let (salt, params, key) = match current.and_then(|c| Some((c, self.cached_key(c)?))) {
+            Some(((salt, params), key)) => (salt, params, key),
+            None => {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                let key = self.derive_key(&salt, self.params)?;
+                (salt, self.params, key)
+            }
+        };
+        self.remember(salt, params, key);
+
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut out = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        out.extend_from_slice(MAGIC);
+        out.push(ENVELOPE_VERSION);
+        out.extend_from_slice(&params.memory_kib.to_le_bytes());
+        out.extend_from_slice(&params.iterations.to_le_bytes());
+        out.extend_from_slice(&params.parallelism.to_le_bytes());
+        out.extend_from_slice(&salt);
+        out.extend_from_slice(&nonce);
+
+        let ciphertext = XChaCha20Poly1305::new(&key)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &out,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if !Self::is_encrypted(data) {
+            return Err(CryptoError::Malformed.into());
+        }
+        let version = *data.get(MAGIC.len()).ok_or(CryptoError::Malformed)?;
+        if version != ENVELOPE_VERSION {
+            return Err(CryptoError::UnsupportedVersion(version).into());
+        }
+        if data.len() < HEADER_LEN {
+            return Err(CryptoError::Malformed.into());
+        }
+
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes(data[offset..offset + 4].try_into().expect("4-byte slice"))
+        };
+        let params_offset = MAGIC.len() + 1;
+        let params = KdfParams {
+            memory_kib: read_u32(params_offset),
+            iterations: read_u32(params_offset + 4),
+            parallelism: read_u32(params_offset + 8),
+        };
+        // Refuse headers that would make key derivation exhaust memory or time
+        if params.memory_kib > ENCRYPTION_KDF_MAX_MEMORY_KIB
+            || params.iterations > ENCRYPTION_KDF_MAX_ITERATIONS
+            || params.parallelism > ENCRYPTION_KDF_MAX_PARALLELISM
+        {
+            return Err(CryptoError::Malformed.into());
+        }
+        let salt_offset = params_offset + 12;
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&data[salt_offset..salt_offset + SALT_LEN]);
+        let nonce = XNonce::from_slice(&data[salt_offset + SALT_LEN..HEADER_LEN]);
+
+        let key = match self.cached_key((salt, params)) {
+            Some(key) => key,
+            None => self.derive_key(&salt, params)?,
+        };
+
+        let plaintext = XChaCha20Poly1305::new(&key)
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: &data[HEADER_LEN..],
+                    aad: &data[..HEADER_LEN],
+                },
+            )
+            .map_err(|_| CryptoError::WrongPassphrase)?;
+
+        // Only cache keys proven correct, so writes keep the file's salt
+        self.remember(salt, params, key);
+        Ok(plaintext)
+    }
+
+    fn cached_key(&self, entry: ([u8; SALT_LEN], KdfParams)) -> Option<Key> {
+        self.keys.borrow().get(&entry).copied()
+    }
+
+    fn remember(&self, salt: [u8; SALT_LEN], params: KdfParams, key: Key) {
+        let mut keys = self.keys.borrow_mut();
+        // More salts than devices means keys that are no longer in use
+        if keys.len() >= ENCRYPTION_KEY_CACHE_SIZE && !keys.contains_key(&(salt, params)) {
+            keys.clear();
+        }
+        keys.insert((salt, params), key);
+        *self.current.borrow_mut() = Some((salt, params));
+    }
+
+    fn derive_key(&self, salt: &[u8], params: KdfParams) -> Result<Key> {
+        let argon_params = Params::new(
+            params.memory_kib,
+            params.iterations,
+            params.parallelism,
+            Some(32),
+        )
+        .map_err(|_| CryptoError::Malformed)?;
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, argon_params)
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_cipher(passphrase: &str) -> ProgressCipher {
+    // Cheap parameters keep debug-build tests fast
+    ProgressCipher::with_params(
+        passphrase,
+        KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let cipher = test_cipher("correct horse");
+        let data = cipher.encrypt(b"{\"books\":{}}").unwrap();
+        assert!(ProgressCipher::is_encrypted(&data));
+        assert!(!data.windows(5).any(|w| w == b"books"));
+
+        // A fresh instance has to derive the key from the header
+        let other = test_cipher("correct horse");
+        assert_eq!(other.decrypt(&data).unwrap(), b"{\"books\":{}}");
+    }
+
+    #[test]
+    fn test_nonce_changes_between_writes() {
+        let cipher = test_cipher("pass");
+        let a = cipher.encrypt(b"same").unwrap();
+        let b = cipher.encrypt(b"same").unwrap();
+        assert_ne!(a, b);
+        // Salt is reused
+        assert_eq!(a[..HEADER_LEN - NONCE_LEN], b[..HEADER_LEN - NONCE_LEN]);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let data = test_cipher("right").encrypt(b"secret").unwrap();
+        let err = test_cipher("wrong").decrypt(&data).unwrap_err();
+        assert!(matches!(
+            CryptoError::find(&err),
+            Some(CryptoError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_header_rejected() {
+        let mut data = test_cipher("pass").encrypt(b"secret").unwrap();
+        // Flip a salt byte: authenticated as associated data
+        data[MAGIC.len() + 13] ^= 1;
+        assert!(test_cipher("pass").decrypt(&data).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut data = test_cipher("pass").encrypt(b"secret").unwrap();
+        data[MAGIC.len()] = 99;
+        let err = test_cipher("pass").decrypt(&data).unwrap_err();
+        assert!(matches!(
+            CryptoError::find(&err),
+            Some(CryptoError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn test_keys_cached_per_salt() {
+        let a = test_cipher("pass").encrypt(b"first").unwrap();
+        let b = test_cipher("pass").encrypt(b"second").unwrap();
+        let cipher = test_cipher("pass");
+        cipher.decrypt(&a).unwrap();
+        cipher.decrypt(&b).unwrap();
+        assert_eq!(cipher.keys.borrow().len(), 2);
+        // Both decrypt again without deriving a third key
+        assert_eq!(cipher.decrypt(&a).unwrap(), b"first");
+        assert_eq!(cipher.keys.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_excessive_memory_cost_rejected() {
+        let mut data = test_cipher("pass").encrypt(b"secret").unwrap();
+        let offset = MAGIC.len() + 1;
+        data[offset..offset + 4]
+            .copy_from_slice(&(ENCRYPTION_KDF_MAX_MEMORY_KIB + 1).to_le_bytes());
+        let err = test_cipher("pass").decrypt(&data).unwrap_err();
+        assert!(matches!(
+            CryptoError::find(&err),
+            Some(CryptoError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn test_excessive_time_cost_rejected() {
+        let offset = MAGIC.len() + 1;
+        for (field, limit) in [
+            (4, ENCRYPTION_KDF_MAX_ITERATIONS),
+            (8, ENCRYPTION_KDF_MAX_PARALLELISM),
+        ] {
+            let mut data = test_cipher("pass").encrypt(b"secret").unwrap();
+            data[offset + field..offset + field + 4].copy_from_slice(&(limit + 1).to_le_bytes());
+            let err = test_cipher("pass").decrypt(&data).unwrap_err();
+            assert!(matches!(
+                CryptoError::find(&err),
+                Some(CryptoError::Malformed)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_plaintext_not_detected() {
+        assert!(!ProgressCipher::is_encrypted(b"{\"version\":1}"));
+        assert!(test_cipher("pass").decrypt(b"{}").is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Client for the KOReader progress sync server (kosync).
+//!
+//! kosync keys documents by KOReader's partial-MD5 digest and stores one
+//! position per document: a page number for fixed-layout files or an
+//! xpointer for reflowable ones, plus a percentage.
+
+use anyhow::{bail, Context, Result};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::config::constants::HTTP_TIMEOUT_SECS;
+use crate::renderer::format::DocumentFormat;
+
+const KOSYNC_ACCEPT: &str = "application/vnd.koreader.v1+json";
+
+/// Position record as stored by the kosync server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KosyncProgress {
+    pub document: String,
+    pub progress: String,
+    pub percentage: f64,
+    pub device: String,
+    pub device_id: String,
+    /// Unix time of the update, set by the server.
+    #[serde(default, skip_serializing)]
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Clone)]
+pub struct KosyncClient {
+    agent: ureq::Agent,
+    server: String,
+    username: String,
+    userkey: String,
+}
+
+impl KosyncClient {
+    /// `userkey` is the MD5 of the account password, as KOReader stores it.
+    pub fn new(server: &str, username: &str, userkey: &str) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(HTTP_TIMEOUT_SECS))
+            .build();
+
+        Self {
+            agent,
+            server: server.trim().trim_end_matches('/').to_string(),
+            username: username.to_string(),
+            userkey: userkey.to_string(),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        self.agent
+            .request(method, &format!("{}{}", self.server, path))
+            .set("Accept", KOSYNC_ACCEPT)
+            .set("x-auth-user", &self.username)
+            .set("x-auth-key", &self.userkey)
+    }
+
+    /// Checks the credentials against the server.
+    #[allow(dead_code)]
+    pub fn authorize(&self) -> Result<()> {
+        match self.request("GET", "/users/auth").call() {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(401, _)) => bail!("kosync: invalid username or password"),
+            Err(e) => Err(e).context("kosync auth request failed"),
+        }
+    }
+
+    /// Returns the last position stored for `document`, if any.
+    pub fn get_progress(&self, document: &str) -> Result<Option<KosyncProgress>> {
+        let path = format!("/syncs/progress/{}", document);
+        let body = match self.request("GET", &path).call() {
+            Ok(resp) => resp
+                .into_string()
+                .context("Failed to read kosync response")?,
+            Err(ureq::Error::Status(401, _)) => bail!("kosync: invalid username or password"),
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(e).context("kosync progress request failed"),
+        };
+
+        // The server answers `{}` for unknown documents
+        let value: serde_json::Value =
+            serde_json::from_str(&body).context("Invalid kosync response")?;
+        if value.get("progress").is_none() {
+            return Ok(None);
+        }
+        let progress = serde_json::from_value(value).context("Invalid kosync response")?;
+        Ok(Some(progress))
+    }
+
+    pub fn update_progress(&self, progress: &KosyncProgress) -> Result<()> {
+        let body = serde_json::to_string(progress).context("Failed to serialize progress")?;
+        match self
+            .request("PUT", "/syncs/progress")
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+        {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(401, _)) => bail!("kosync: invalid username or password"),
+            Err(e) => Err(e).context("kosync update request failed"),
+        }
+    }
+}
+
+/// MD5 hex of the password, the form kosync expects in `x-auth-key`.
+pub fn hash_password(password: &str) -> String {
+    format!("{:x}", Md5::digest(password.as_bytes()))
+}
+
+/// Converts our page position to kosync `(progress, percentage)`.
+///
+/// `page_sections` maps each page to the EPUB spine item / FB2 section it
+/// starts in; it is empty for fixed-layout formats.
+pub fn to_kosync_position(
+    format: DocumentFormat,
+    page: u32,
+    total_pages: u32,
+    page_sections: &[u32],
+) -> (String, f64) {
+    let percentage = if total_pages == 0 {
+        0.0
+    } else {
+        page as f64 / total_pages as f64
+    };
+
+    let section = page_sections
+        .get(page.saturating_sub(1) as usize)
+        .copied()
+        .unwrap_or(0)
+        + 1;
+    let progress = match format {
+        DocumentFormat::Epub => format!("/body/DocFragment[{}]/body", section),
+        DocumentFormat::Fb2 => format!("/FictionBook/body/section[{}]", section),
+        _ => page.to_string(),
+    };
+
+    (progress, percentage)
+}
+
+/// Converts a kosync position back to a 1-based page number.
+///
+/// Page-number progress is used as is. For xpointers the percentage gives the
+/// page, kept inside the section the xpointer names: our pagination differs
+/// from KOReader's, but the section is stable across both.
+pub fn from_kosync_position(
+    progress: &str,
+    percentage: f64,
+    total_pages: u32,
+    page_sections: &[u32],
+) -> u32 {
+    let total = total_pages.max(1);
+
+    if let Ok(page) = progress.trim().parse::<u32>() {
+        return page.clamp(1, total);
+    }
+
+    let mut page = ((percentage.clamp(0.0, 1.0) * total as f64).round() as u32).clamp(1, total);
+
+    if let Some(section) = parse_xpointer_section(progress) {
+        let first = page_sections.iter().position(|&s| s >= section);
+        let last = page_sections.iter().rposition(|&s| s <= section);
+        match (first, last) {
+            (Some(first), Some(last)) if first <= last => {
+                page = page.clamp(first as u32 + 1, last as u32 + 1);
+            }
+            // The section starts and ends inside a single page
+            (_, Some(last)) => page = last as u32 + 1,
+            _ => {}
+        }
+    }
+
+    page.clamp(1, total)
+}
+
+/// Extracts the 0-based section index from `DocFragment[n]` or `section[n]`.
+fn parse_xpointer_section(xpointer: &str) -> Option<u32> {
+    for marker in ["DocFragment[", "body/section["] {
+        if let Some(start) = xpointer.find(marker) {
+            let rest = &xpointer[start + marker.len()..];
+            let end = rest.find(']')?;
+            let index: u32 = rest[..end].parse().ok()?;
+            return index.checked_sub(1);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::test_server::{TestResponse, TestServer};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_hash_password() {
+        assert_eq!(hash_password("secret"), "5ebe2294ecd0e0f08eab7690d2a6ee69");
+    }
+
+    #[test]
+    fn test_pdf_position_roundtrip() {
+        let (progress, percentage) = to_kosync_position(DocumentFormat::Pdf, 25, 100, &[]);
+        assert_eq!(progress, "25");
+        assert!((percentage - 0.25).abs() < 1e-9);
+        assert_eq!(from_kosync_position(&progress, percentage, 100, &[]), 25);
+        assert_eq!(from_kosync_position("500", 1.0, 100, &[]), 100);
+    }
+
+    #[test]
+    fn test_epub_xpointer() {
+        let sections = [0, 0, 1, 1, 1, 2];
+        let (progress, _) = to_kosync_position(DocumentFormat::Epub, 4, 6, &sections);
+        assert_eq!(progress, "/body/DocFragment[2]/body");
+
+        let (progress, _) = to_kosync_position(DocumentFormat::Fb2, 6, 6, &sections);
+        assert_eq!(progress, "/FictionBook/body/section[3]");
+    }
+
+    #[test]
+    fn test_from_xpointer_clamps_to_section() {
+        let sections = [0, 0, 1, 1, 1, 2];
+        // Percentage alone would give page 6, the xpointer says section 2
+        let page = from_kosync_position(
+            "/body/DocFragment[2]/body/div/p[3]/text().15",
+            0.95,
+            6,
+            &sections,
+        );
+        assert_eq!(page, 5);
+
+        let page = from_kosync_position("/body/DocFragment[3]/body", 0.1, 6, &sections);
+        assert_eq!(page, 6);
+    }
+
+    #[test]
+    fn test_from_xpointer_section_inside_page() {
+        // Section 1 starts and ends inside page 2
+        let sections = [0, 0, 2];
+        let page = from_kosync_position("/FictionBook/body/section[2]/p[1]", 0.9, 3, &sections);
+        assert_eq!(page, 2);
+    }
+
+    #[test]
+    fn test_from_unknown_progress_uses_percentage() {
+        assert_eq!(from_kosync_position("garbage", 0.5, 10, &[]), 5);
+        assert_eq!(from_kosync_position("", 0.0, 10, &[]), 1);
+    }
+
+    #[test]
+    fn test_client_roundtrip() {
+        let stored: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let server_stored = Arc::clone(&stored);
+        let server = TestServer::start(move |req| {
+            if req.header("x-auth-user") != Some("reader")
+                || req.header("x-auth-key") != Some(&hash_password("secret"))
+            {
+                return TestResponse::new(401);
+            }
+            assert_eq!(req.header("accept"), Some(KOSYNC_ACCEPT));
+            match (req.method.as_str(), req.path.as_str()) {
+                ("GET", "/users/auth") => {
+                    TestResponse::new(200).with_body(r#"{"authorized":"OK"}"#)
+                }
+                ("PUT", "/syncs/progress") => {
+                    let mut value: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+                    value["timestamp"] = 1_700_000_000.into();
+                    *server_stored.lock().unwrap() = Some(value.to_string());
+                    TestResponse::new(200).with_body(r#"{"document":"abc","timestamp":1700000000}"#)
+                }
+                ("GET", "/syncs/progress/abc") => {
+                    let body = server_stored
+                        .lock()
+                        .unwrap()
+                        .clone()
+                        .unwrap_or_else(|| "{}".to_string());
+                    TestResponse::new(200).with_body(body)
+                }
+                _ => TestResponse::new(404),
+            }
+        });
+
+        let client = KosyncClient::new(&server.base_url, "reader", &hash_password("secret"));
+        client.authorize().unwrap();
+        assert!(client.get_progress("abc").unwrap().is_none());
+
+        let progress = KosyncProgress {
+            document: "abc".to_string(),
+            progress: "12".to_string(),
+            percentage: 0.12,
+            device: "DocReader".to_string(),
+            device_id: "device-1".to_string(),
+            timestamp: None,
+        };
+        client.update_progress(&progress).unwrap();
+
+        let remote = client.get_progress("abc").unwrap().unwrap();
+        assert_eq!(remote.progress, "12");
+        assert_eq!(remote.device_id, "device-1");
+        assert_eq!(remote.timestamp, Some(1_700_000_000));
+
+        let bad = KosyncClient::new(&server.base_url, "reader", "wrong");
+        assert!(bad.authorize().is_err());
+        assert!(bad.get_progress("abc").is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::backend::{ObjectInfo, Precondition, RemoteObject, SyncBackend, SyncError};
+
+/// Backend over a local directory, usually one synced by a desktop cloud client.
+pub struct LocalFileBackend {
+    root: PathBuf,
+}
+
+impl LocalFileBackend {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    #[allow(dead_code)]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Content hash used as the ETag: cloud clients do not preserve mtimes reliably.
+    fn content_etag(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    fn current_etag(path: &Path) -> Result<Option<String>> {
+        match fs::read(path) {
+            Ok(data) => Ok(Some(Self::content_etag(&data))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context("Failed to read file"),
+        }
+    }
+}
+
+impl SyncBackend for LocalFileBackend {
+    fn describe(&self) -> String {
+        self.root.to_string_lossy().to_string()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<RemoteObject>> {
+        let path = self.root.join(key);
+        match fs::read(&path) {
+            Ok(data) => {
+                let etag = Some(Self::content_etag(&data));
+                Ok(Some(RemoteObject { data, etag }))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context("Failed to read file"),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8], precondition: &Precondition) -> Result<Option<String>> {
+        let path = self.root.join(key);
+
+        // Ensure parent directory exists
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create directory")?;
+        }
+
+        // Best effort: a cloud client may still replace the file between
+        // this check and the rename below.
+        let current = Self::current_etag(&path)?;
+        let holds = match precondition {
+            Precondition::None => true,
+            Precondition::Absent => current.is_none(),
+            Precondition::Matches(etag) => current.as_deref() == Some(etag.as_str()),
+        };
+        if !holds {
+            return Err(SyncError::PreconditionFailed.into());
+        }
+
+        // Write to temporary file first (atomic write)
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        fs::write(&temp_path, data).context("Failed to write temporary file")?;
+
+        // Atomic rename
+        fs::rename(&temp_path, &path).context("Failed to rename temporary file")?;
+
+        Ok(Some(Self::content_etag(data)))
+    }
+
+    fn list(&self) -> Result<Vec<ObjectInfo>> {
+        let mut objects = Vec::new();
+
+        if !self.root.exists() {
+            return Ok(objects);
+        }
+
+        for entry in fs::read_dir(&self.root).context("Failed to read directory")? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let key = entry.file_name().to_string_lossy().to_string();
+            let etag = Self::current_etag(&entry.path())?;
+            objects.push(ObjectInfo { key, etag });
+        }
+
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.root.join(key)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).context("Failed to delete file"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_backend(name: &str) -> (LocalFileBackend, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("docreader_test_local_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        (LocalFileBackend::new(dir.clone()), dir)
+    }
+
+    #[test]
+    fn test_get_missing() {
+        let (backend, dir) = temp_backend("missing");
+        assert!(backend.get("nope.json").unwrap().is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_put_and_get() {
+        let (backend, dir) = temp_backend("put_get");
+
+        let etag = backend
+            .put("a.json", b"hello", &Precondition::None)
+            .unwrap();
+        let obj = backend.get("a.json").unwrap().unwrap();
+        assert_eq!(obj.data, b"hello");
+        assert_eq!(obj.etag, etag);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_put_if_match() {
+        let (backend, dir) = temp_backend("if_match");
+
+        let etag = backend
+            .put("a.json", b"v1", &Precondition::Absent)
+            .unwrap()
+            .unwrap();
+        backend
+            .put("a.json", b"v2", &Precondition::Matches(etag.clone()))
+            .unwrap();
+
+        // Stale ETag must be rejected
+        let err = backend
+            .put("a.json", b"v3", &Precondition::Matches(etag))
+            .unwrap_err();
+        assert!(SyncError::is_precondition_failed(&err));
+        assert_eq!(backend.get("a.json").unwrap().unwrap().data, b"v2");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_put_absent_rejects_existing() {
+        let (backend, dir) = temp_backend("absent");
+
+        backend.put("a.json", b"v1", &Precondition::None).unwrap();
+        let err = backend
+            .put("a.json", b"v2", &Precondition::Absent)
+            .unwrap_err();
+        assert!(SyncError::is_precondition_failed(&err));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_list_and_delete() {
+        let (backend, dir) = temp_backend("list");
+
+        backend.put("b.json", b"b", &Precondition::None).unwrap();
+        backend.put("a.json", b"a", &Precondition::None).unwrap();
+
+        let keys: Vec<String> = backend.list().unwrap().into_iter().map(|o| o.key).collect();
+        assert_eq!(keys, vec!["a.json", "b.json"]);
+
+        backend.delete("a.json").unwrap();
+        backend.delete("a.json").unwrap();
+        assert_eq!(backend.list().unwrap().len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
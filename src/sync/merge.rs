@@ -1,3 +1,6 @@
+use crate::config::roaming::RoamingPreferences;
+use crate::library::annotations::merge_annotations;
+use crate::library::devices;
 use crate::library::progress::ReadingProgress;
 
 #[cfg(test)]
@@ -14,9 +17,28 @@ impl ProgressMerger {
             match merged.books.get(book_hash) {
                 Some(local_book) => {
                     // Book exists in both - take the one with most recent last_read
-                    if remote_book.last_read > local_book.last_read {
-                        merged.books.insert(book_hash.clone(), remote_book.clone());
+                    let bookmarks =
+                        merge_annotations(&local_book.bookmarks, &remote_book.bookmarks);
+                    let highlights =
+                        merge_annotations(&local_book.highlights, &remote_book.highlights);
+                    let (mut book, other) = if remote_book.last_read > local_book.last_read {
+                        (remote_book.clone(), local_book)
+                    } else {
+                        (local_book.clone(), remote_book)
+                    };
+                    book.bookmarks = bookmarks;
+                    book.highlights = highlights;
+                    // View settings changed later than the book was read win
+                    if other.view_modified > book.view_modified {
+                        book.take_view(other);
                     }
+                    // Fields only a newer version knows survive an older winner
+                    for (key, value) in &other.extra {
+                        book.extra
+                            .entry(key.clone())
+                            .or_insert_with(|| value.clone());
+                    }
+                    merged.books.insert(book_hash.clone(), book);
                 }
                 None => {
                     // Book only exists in remote - add it
@@ -25,6 +47,23 @@ impl ProgressMerger {
             }
         }
 
+        merged.preferences = RoamingPreferences::merge(&local.preferences, &remote.preferences);
+        merged.devices = devices::merge_devices(&local.devices, &remote.devices);
+
+        // Keep the newest schema and any fields only the other side knows
+        if remote.version > merged.version {
+            merged.version = remote.version;
+            merged.minor_version = remote.minor_version;
+        } else if remote.version == merged.version {
+            merged.minor_version = merged.minor_version.max(remote.minor_version);
+        }
+        for (key, value) in &remote.extra {
+            merged
+                .extra
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+
         // Update last_modified to now
         merged.last_modified = chrono::Utc::now();
 
@@ -52,6 +91,7 @@ mod tests {
                 total_pages: 100,
                 current_page: 50,
                 last_read: earlier,
+                ..Default::default()
             },
         );
 
@@ -65,6 +105,7 @@ mod tests {
                 total_pages: 100,
                 current_page: 75,
                 last_read: now,
+                ..Default::default()
             },
         );
 
@@ -86,6 +127,7 @@ mod tests {
                 total_pages: 200,
                 current_page: 10,
                 last_read: Utc::now(),
+                ..Default::default()
             },
         );
 
@@ -108,6 +150,7 @@ mod tests {
                 total_pages: 100,
                 current_page: 80,
                 last_read: now,
+                ..Default::default()
             },
         );
 
@@ -121,6 +164,7 @@ mod tests {
                 total_pages: 100,
                 current_page: 30,
                 last_read: earlier,
+                ..Default::default()
             },
         );
 
@@ -140,6 +184,7 @@ mod tests {
                 total_pages: 50,
                 current_page: 25,
                 last_read: Utc::now(),
+                ..Default::default()
             },
         );
 
@@ -175,6 +220,7 @@ mod tests {
                 total_pages: 100,
                 current_page: 10,
                 last_read: earlier,
+                ..Default::default()
             },
         );
         local.books.insert(
@@ -186,6 +232,7 @@ mod tests {
                 total_pages: 200,
                 current_page: 100,
                 last_read: now,
+                ..Default::default()
             },
         );
 
@@ -199,6 +246,7 @@ mod tests {
                 total_pages: 100,
                 current_page: 50,
                 last_read: now,
+                ..Default::default()
             },
         );
         remote.books.insert(
@@ -210,6 +258,7 @@ mod tests {
                 total_pages: 300,
                 current_page: 5,
                 last_read: now,
+                ..Default::default()
             },
         );
 
@@ -222,4 +271,144 @@ mod tests {
         // book3: remote only, added
         assert_eq!(merged.books.get("book3").unwrap().current_page, 5);
     }
+
+    #[test]
+    fn test_merge_keeps_newer_schema_and_unknown_fields() {
+        let local = ReadingProgress::new("device1".to_string());
+        let mut remote = ReadingProgress::new("device2".to_string());
+        remote.minor_version = local.minor_version + 1;
+        remote
+            .extra
+            .insert("future".to_string(), serde_json::json!(true));
+
+        let merged = ProgressMerger::merge(&local, &remote);
+        assert_eq!(merged.minor_version, remote.minor_version);
+        assert_eq!(merged.extra.get("future"), Some(&serde_json::json!(true)));
+    }
+
+    #[test]
+    fn test_merge_keeps_unknown_book_fields() {
+        let now = Utc::now();
+        let mut local = ReadingProgress::new("device1".to_string());
+        local.add_book("h1".into(), "A.pdf".into(), "/a".into(), 100);
+        let mut remote = local.clone();
+
+        // The newer device wrote a field, the older one read the book since
+        let book = remote.books.get_mut("h1").unwrap();
+        book.extra
+            .insert("future".to_string(), serde_json::json!({"a": 1}));
+        book.last_read = now;
+        let book = local.books.get_mut("h1").unwrap();
+        book.extra
+            .insert("shared".to_string(), serde_json::json!(1));
+        book.current_page = 30;
+        book.last_read = now + Duration::minutes(1);
+        remote
+            .books
+            .get_mut("h1")
+            .unwrap()
+            .extra
+            .insert("shared".to_string(), serde_json::json!(2));
+
+        let merged = ProgressMerger::merge(&local, &remote);
+        let book = merged.books.get("h1").unwrap();
+        assert_eq!(book.current_page, 30);
+        assert_eq!(book.extra.get("future"), Some(&serde_json::json!({"a": 1})));
+        // The winner's own value stays
+        assert_eq!(book.extra.get("shared"), Some(&serde_json::json!(1)));
+    }
+
+    #[test]
+    fn test_merge_view_settings_apart_from_reading() {
+        use crate::config::settings::ZoomMode;
+        use crate::library::progress::BookZoom;
+
+        let now = Utc::now();
+        let mut local = ReadingProgress::new("device1".to_string());
+        local.add_book("h1".into(), "A.pdf".into(), "/a".into(), 100);
+        let mut remote = local.clone();
+
+        // Zoomed here, then the book was read further elsewhere
+        let zoom = BookZoom {
+            mode: ZoomMode::FitWidth,
+            level: 1.5,
+        };
+        let book = local.books.get_mut("h1").unwrap();
+        book.zoom = Some(zoom);
+        book.view_modified = Some(now + Duration::minutes(1));
+        let book = remote.books.get_mut("h1").unwrap();
+        book.current_page = 40;
+        book.view_modified = Some(now);
+        book.last_read = now + Duration::minutes(2);
+
+        let merged = ProgressMerger::merge(&local, &remote);
+        let book = merged.books.get("h1").unwrap();
+        assert_eq!(book.current_page, 40);
+        assert_eq!(book.zoom, Some(zoom));
+        assert_eq!(book.view_modified, Some(now + Duration::minutes(1)));
+    }
+
+    #[test]
+    fn test_merge_keeps_scroll_offset_of_reading() {
+        use crate::library::progress::ScrollPosition;
+
+        let now = Utc::now();
+        let mut local = ReadingProgress::new("device1".to_string());
+        local.add_book("h1".into(), "A.pdf".into(), "/a".into(), 100);
+        let mut remote = local.clone();
+
+        // Scrolling switched on here, read further while scrolled elsewhere
+        let book = local.books.get_mut("h1").unwrap();
+        book.scroll = Some(ScrollPosition { page_offset: 0.1 });
+        book.view_modified = Some(now + Duration::minutes(1));
+        let book = remote.books.get_mut("h1").unwrap();
+        book.current_page = 40;
+        book.scroll = Some(ScrollPosition { page_offset: 0.5 });
+        book.view_modified = Some(now);
+        book.last_read = now + Duration::minutes(2);
+
+        let merged = ProgressMerger::merge(&local, &remote);
+        let book = merged.books.get("h1").unwrap();
+        assert_eq!(book.current_page, 40);
+        assert_eq!(book.scroll, Some(ScrollPosition { page_offset: 0.5 }));
+    }
+
+    #[test]
+    fn test_merge_combines_preferences() {
+        use crate::config::roaming::{AUTO_SAVE_INTERVAL, ZOOM_LEVEL};
+
+        let mut local = ReadingProgress::new("device1".to_string());
+        local.preferences.set(AUTO_SAVE_INTERVAL, 10.into());
+        let mut remote = ReadingProgress::new("device2".to_string());
+        remote.preferences.set(ZOOM_LEVEL, 1.5.into());
+
+        let merged = ProgressMerger::merge(&local, &remote);
+        assert_eq!(merged.preferences.get(ZOOM_LEVEL).unwrap().value, 1.5);
+        assert_eq!(
+            merged.preferences.get(AUTO_SAVE_INTERVAL).unwrap().value,
+            10
+        );
+    }
+
+    #[test]
+    fn test_merge_unions_bookmarks() {
+        let now = Utc::now();
+        let mut local = ReadingProgress::new("device1".to_string());
+        local.add_book("h1".into(), "A.pdf".into(), "/a".into(), 100);
+        let mut remote = local.clone();
+
+        let book = local.books.get_mut("h1").unwrap();
+        book.add_bookmark(10, None, "Локальная".to_string());
+        book.last_read = now;
+        let book = remote.books.get_mut("h1").unwrap();
+        book.add_bookmark(20, None, "Удалённая".to_string());
+        book.current_page = 20;
+        book.last_read = now + Duration::minutes(1);
+
+        let merged = ProgressMerger::merge(&local, &remote);
+        let book = merged.books.get("h1").unwrap();
+        assert_eq!(book.current_page, 20);
+        let labels: Vec<_> = book.bookmarks().map(|b| b.label.as_str()).collect();
+        assert_eq!(labels, vec!["Локальная", "Удалённая"]);
+    }
 }
@@ -1,3 +1,12 @@
+pub mod backend;
+pub mod backup;
+pub mod crypto;
+pub mod kosync;
+pub mod local;
 pub mod merge;
+pub mod s3;
 pub mod storage;
+#[cfg(test)]
+mod test_server;
 pub mod watcher;
+pub mod webdav;
@@ -1,77 +1,365 @@
 use anyhow::{Context, Result};
-use std::fs;
-use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 
+use crate::config::constants::{DEVICE_PROGRESS_PREFIX, QUARANTINE_SUFFIX};
+use crate::library::migration::{self, SchemaError};
 use crate::library::progress::ReadingProgress;
 
+use super::backend::{Precondition, RemoteObject, SyncBackend};
+use super::backup::{BackupStore, Snapshot};
+use super::crypto::{CryptoError, ProgressCipher};
+use super::local::LocalFileBackend;
+use super::merge::ProgressMerger;
+
+/// How progress objects are laid out in the backend.
+enum Layout {
+    /// All devices read and write one shared object.
+    Shared { key: String },
+    /// Each device writes its own object; loading merges all of them.
+    PerDevice { device_id: String },
+}
+
 pub struct ProgressStorage {
-    file_path: PathBuf,
+    backend: Arc<dyn SyncBackend>,
+    layout: Layout,
+    // ETags of objects as we last read or wrote them; `None` if the object was absent
+    seen: RefCell<HashMap<String, Option<String>>>,
+    // Set when progress is encrypted with a passphrase
+    cipher: Option<ProgressCipher>,
+    backups: Option<BackupStore>,
 }
 
 impl ProgressStorage {
     pub fn new(file_path: PathBuf) -> Self {
-        Self { file_path }
+        let root = file_path
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
+        let key = file_path
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Self::with_backend(Arc::new(LocalFileBackend::new(root)), key)
     }
 
-    pub fn load(&self) -> Result<ReadingProgress> {
-        if !self.file_path.exists() {
-            return Err(anyhow::anyhow!("Progress file does not exist"));
+    pub fn with_backend(backend: Arc<dyn SyncBackend>, key: impl Into<String>) -> Self {
+        Self {
+            backend,
+            layout: Layout::Shared { key: key.into() },
+            seen: RefCell::new(HashMap::new()),
+            cipher: None,
+            backups: None,
         }
+    }
 
-        let content =
-            fs::read_to_string(&self.file_path).context("Failed to read progress file")?;
+    /// Storage where every device owns a separate `progress-<device_id>.json` object.
+    pub fn per_device(backend: Arc<dyn SyncBackend>, device_id: &str) -> Self {
+        Self {
+            backend,
+            layout: Layout::PerDevice {
+                device_id: device_id.to_string(),
+            },
+            seen: RefCell::new(HashMap::new()),
+            cipher: None,
+            backups: None,
+        }
+    }
 
-        let progress: ReadingProgress =
-            serde_json::from_str(&content).context("Failed to parse progress file")?;
+    /// Encrypts everything written from now on. Plaintext files are still
+    /// read, so existing progress is migrated on the next save.
+    pub fn with_cipher(mut self, cipher: ProgressCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
 
-        Ok(progress)
+    /// Keeps rotating snapshots of what this device writes.
+    pub fn with_backups(mut self, backups: BackupStore) -> Self {
+        self.backups = Some(backups);
+        self
+    }
+
+    pub fn load(&self) -> Result<ReadingProgress> {
+        match &self.layout {
+            Layout::Shared { key } => {
+                let object = self.fetch(key)?.context("Progress file does not exist")?;
+                self.parse(&object)
+            }
+            Layout::PerDevice { device_id } => self.load_all_devices(device_id),
+        }
+    }
+
+    /// Loads the remote progress only if it differs from the version we last saw.
+    pub fn load_if_changed(&self) -> Result<Option<ReadingProgress>> {
+        match &self.layout {
+            Layout::Shared { key } => {
+                let known = self.seen.borrow().get(key).cloned().flatten();
+                match self.fetch(key)? {
+                    Some(object) if object.etag.is_none() || object.etag != known => {
+                        Ok(Some(self.parse(&object)?))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            Layout::PerDevice { .. } => {
+                let listing = self.device_listing()?;
+                let changed = {
+                    let seen = self.seen.borrow();
+                    let seen_count = seen.values().filter(|etag| etag.is_some()).count();
+                    listing.len() != seen_count
+                        || listing.iter().any(|(key, etag)| {
+                            etag.is_none() || seen.get(key).cloned().flatten() != *etag
+                        })
+                };
+                if changed {
+                    self.load().map(Some)
+                } else {
+                    Ok(None)
+                }
+            }
+        }
     }
 
+    /// Loads progress, creating it if missing. A corrupt object is moved
+    /// aside first and replaced with the newest readable snapshot.
     pub fn load_or_create(&self, device_id: &str) -> Result<ReadingProgress> {
         match self.load() {
             Ok(progress) => Ok(progress),
-            Err(_) => {
-                let progress = ReadingProgress::new(device_id.to_string());
+            // Never replace a file we merely failed to decrypt
+            Err(e) if CryptoError::find(&e).is_some() || SchemaError::find(&e).is_some() => Err(e),
+            Err(e) => {
+                let key = self.own_key();
+                if let Some(object) = self.fetch(&key)? {
+                    if self.parse(&object).is_ok() {
+                        // Our own object is fine, the failure was elsewhere
+                        return Err(e);
+                    }
+                    self.quarantine(&key, &object)?;
+                }
+
+                let mut progress = self
+                    .latest_backup()
+                    .unwrap_or_else(|| ReadingProgress::new(device_id.to_string()));
+                progress.device_id = device_id.to_string();
                 self.save(&progress)?;
                 Ok(progress)
             }
         }
     }
 
+    /// Writes progress, failing with `SyncError::PreconditionFailed` if the
+    /// remote copy changed since we last read or wrote it.
     pub fn save(&self, progress: &ReadingProgress) -> Result<()> {
-        // Ensure parent directory exists
-        if let Some(parent) = self.file_path.parent() {
-            fs::create_dir_all(parent).context("Failed to create progress directory")?;
+        migration::ensure_writable(progress)?;
+        let key = self.own_key();
+        let data = self.serialize(progress)?;
+
+        let precondition = match self.seen.borrow().get(&key) {
+            Some(Some(etag)) => Precondition::Matches(etag.clone()),
+            _ => Precondition::Absent,
+        };
+
+        let etag = self
+            .backend
+            .put(&key, &data, &precondition)
+            .context("Failed to write progress file")?;
+        self.seen.borrow_mut().insert(key, etag);
+
+        if let Some(backups) = &self.backups {
+            if let Err(e) = backups.maybe_snapshot(&data) {
+                eprintln!("Failed to back up progress: {:#}", e);
+            }
         }
 
-        // Write to temporary file first (atomic write)
-        let temp_path = self.file_path.with_extension("json.tmp");
+        Ok(())
+    }
 
-        let json =
-            serde_json::to_string_pretty(progress).context("Failed to serialize progress")?;
+    /// Overwrites this device's object regardless of its current content,
+    /// e.g. to re-encrypt it after the passphrase changed.
+    pub fn replace(&self, progress: &ReadingProgress) -> Result<()> {
+        self.fetch(&self.own_key())?;
+        self.save(progress)
+    }
 
-        fs::write(&temp_path, &json).context("Failed to write temporary file")?;
+    /// Deletes the progress object of a retired device. Only the per-device
+    /// layout keeps data per device; the shared object is left alone.
+    pub fn remove_device(&self, device_id: &str) -> Result<()> {
+        let Layout::PerDevice { device_id: own } = &self.layout else {
+            return Ok(());
+        };
+        if own == device_id {
+            anyhow::bail!("Cannot remove this device's own progress");
+        }
+        let key = Self::device_key(device_id);
+        self.backend
+            .delete(&key)
+            .context("Failed to delete device progress")?;
+        self.seen.borrow_mut().remove(&key);
+        Ok(())
+    }
 
-        // Atomic rename
-        fs::rename(&temp_path, &self.file_path).context("Failed to rename temporary file")?;
+    /// Snapshots, newest first; empty if backups are off.
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>> {
+        match &self.backups {
+            Some(backups) => backups.list(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn read_snapshot(&self, snapshot: &Snapshot) -> Result<ReadingProgress> {
+        let backups = self.backups.as_ref().context("Backups are disabled")?;
+        let data = backups.read(snapshot)?;
+        self.parse(&RemoteObject { data, etag: None })
+    }
 
+    /// Snapshots the given progress right away, e.g. before a restore.
+    pub fn snapshot_now(&self, progress: &ReadingProgress) -> Result<()> {
+        let Some(backups) = &self.backups else {
+            return Ok(());
+        };
+        backups.snapshot(&self.serialize(progress)?)?;
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn file_path(&self) -> &Path {
-        &self.file_path
+    pub fn backend(&self) -> Arc<dyn SyncBackend> {
+        Arc::clone(&self.backend)
     }
 
     #[allow(dead_code)]
     pub fn exists(&self) -> bool {
-        self.file_path.exists()
+        matches!(self.backend.get(&self.own_key()), Ok(Some(_)))
+    }
+
+    /// Key of the object this device writes to.
+    fn own_key(&self) -> String {
+        match &self.layout {
+            Layout::Shared { key } => key.clone(),
+            Layout::PerDevice { device_id } => Self::device_key(device_id),
+        }
+    }
+
+    fn device_key(device_id: &str) -> String {
+        format!("{}{}.json", DEVICE_PROGRESS_PREFIX, device_id)
+    }
+
+    fn device_listing(&self) -> Result<Vec<(String, Option<String>)>> {
+        Ok(self
+            .backend
+            .list()
+            .context("Failed to list progress files")?
+            .into_iter()
+            .filter(|o| o.key.starts_with(DEVICE_PROGRESS_PREFIX) && o.key.ends_with(".json"))
+            .map(|o| (o.key, o.etag))
+            .collect())
+    }
+
+    fn load_all_devices(&self, device_id: &str) -> Result<ReadingProgress> {
+        let own_key = Self::device_key(device_id);
+        let mut keys: Vec<String> = self.device_listing()?.into_iter().map(|(k, _)| k).collect();
+        if keys.is_empty() {
+            anyhow::bail!("Progress file does not exist");
+        }
+        // Own object first, so it is the base of the merge
+        keys.sort_by_key(|k| *k != own_key);
+
+        self.seen.borrow_mut().clear();
+        let mut merged: Option<ReadingProgress> = None;
+        for key in keys {
+            let Some(object) = self.fetch(&key)? else {
+                continue;
+            };
+            let progress = match self.parse(&object) {
+                Ok(p) => p,
+                Err(e) if key == own_key => return Err(e),
+                Err(e) => {
+                    eprintln!("Skipping unreadable progress object {}: {:#}", key, e);
+                    continue;
+                }
+            };
+            // Another device on a newer schema must not make our own object read-only
+            if key != own_key && migration::ensure_writable(&progress).is_err() {
+                eprintln!("Skipping progress object {} from a newer schema", key);
+                continue;
+            }
+            merged = Some(match merged {
+                Some(acc) => ProgressMerger::merge(&acc, &progress),
+                None => progress,
+            });
+        }
+
+        let mut merged = merged.context("Progress file does not exist")?;
+        merged.device_id = device_id.to_string();
+        Ok(merged)
+    }
+
+    fn fetch(&self, key: &str) -> Result<Option<RemoteObject>> {
+        let object = self
+            .backend
+            .get(key)
+            .context("Failed to read progress file")?;
+        self.seen.borrow_mut().insert(
+            key.to_string(),
+            object.as_ref().and_then(|o| o.etag.clone()),
+        );
+        Ok(object)
+    }
+
+    fn serialize(&self, progress: &ReadingProgress) -> Result<Vec<u8>> {
+        let json =
+            serde_json::to_string_pretty(progress).context("Failed to serialize progress")?;
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(json.as_bytes()),
+            None => Ok(json.into_bytes()),
+        }
+    }
+
+    /// Copies an unreadable object next to the original so it can be inspected.
+    fn quarantine(&self, key: &str, object: &RemoteObject) -> Result<()> {
+        let quarantine_key = format!(
+            "{}{}{}",
+            key,
+            QUARANTINE_SUFFIX,
+            chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+        );
+        self.backend
+            .put(&quarantine_key, &object.data, &Precondition::Absent)
+            .context("Failed to quarantine corrupt progress file")?;
+        eprintln!("Corrupt progress file moved to {}", quarantine_key);
+        Ok(())
+    }
+
+    fn latest_backup(&self) -> Option<ReadingProgress> {
+        let backups = self.backups.as_ref()?;
+        backups
+            .list()
+            .ok()?
+            .iter()
+            .find_map(|snapshot| self.read_snapshot(snapshot).ok())
+    }
+
+    fn parse(&self, object: &RemoteObject) -> Result<ReadingProgress> {
+        let decrypted;
+        let data = if ProgressCipher::is_encrypted(&object.data) {
+            let cipher = self
+                .cipher
+                .as_ref()
+                .ok_or(CryptoError::PassphraseRequired)?;
+            decrypted = cipher.decrypt(&object.data)?;
+            &decrypted
+        } else {
+            &object.data
+        };
+        let content = std::str::from_utf8(data).context("Failed to read progress file")?;
+        migration::parse_progress(content)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sync::backend::SyncError;
     use std::fs;
 
     fn temp_storage(name: &str) -> (ProgressStorage, PathBuf) {
@@ -160,4 +448,201 @@ mod tests {
 
         let _ = fs::remove_dir_all(&dir);
     }
+
+    #[test]
+    fn test_save_conflict_after_external_change() {
+        let (storage, dir) = temp_storage("conflict");
+
+        let progress = storage.load_or_create("d1").unwrap();
+
+        // Another device writes the file behind our back
+        let other = ProgressStorage::new(dir.join("progress.json"));
+        let mut remote = other.load().unwrap();
+        remote.add_book("h1".to_string(), "A.pdf".to_string(), "/a".to_string(), 10);
+        other.save(&remote).unwrap();
+
+        let err = storage.save(&progress).unwrap_err();
+        assert!(SyncError::is_precondition_failed(&err));
+
+        // After reloading, the save goes through
+        let reloaded = storage.load().unwrap();
+        assert_eq!(reloaded.books.len(), 1);
+        storage.save(&reloaded).unwrap();
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_load_if_changed() {
+        let (storage, dir) = temp_storage("if_changed");
+
+        storage.load_or_create("d1").unwrap();
+        assert!(storage.load_if_changed().unwrap().is_none());
+
+        let other = ProgressStorage::new(dir.join("progress.json"));
+        let mut remote = other.load().unwrap();
+        remote.add_book("h1".to_string(), "A.pdf".to_string(), "/a".to_string(), 10);
+        other.save(&remote).unwrap();
+
+        let changed = storage.load_if_changed().unwrap().unwrap();
+        assert!(changed.books.contains_key("h1"));
+        assert!(storage.load_if_changed().unwrap().is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_webdav_backend_roundtrip() {
+        use crate::sync::test_server::MemoryDav;
+        use crate::sync::webdav::WebDavBackend;
+
+        let (server, dav) = MemoryDav::start();
+        let backend = WebDavBackend::new(&format!("{}/remote.php/dav", server.base_url), "", "");
+        let storage = ProgressStorage::with_backend(Arc::new(backend), "reading_progress.json");
+
+        let mut progress = storage.load_or_create("d1").unwrap();
+        progress.add_book("h1".to_string(), "A.pdf".to_string(), "/a".to_string(), 10);
+        storage.save(&progress).unwrap();
+
+        assert!(dav
+            .contents("/remote.php/dav/reading_progress.json")
+            .is_some());
+        assert_eq!(storage.load().unwrap().books.len(), 1);
+    }
+
+    #[test]
+    fn test_per_device_layout_merges_devices() {
+        use crate::sync::s3::{S3Backend, S3Config};
+        use crate::sync::test_server::MemoryS3;
+
+        let (server, s3) = MemoryS3::start();
+        let backend: Arc<dyn SyncBackend> = Arc::new(S3Backend::new(S3Config {
+            endpoint: server.base_url.clone(),
+            bucket: "books".to_string(),
+            prefix: "sync".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: "key".to_string(),
+            secret_key: "secret".to_string(),
+        }));
+
+        let laptop = ProgressStorage::per_device(Arc::clone(&backend), "laptop");
+        let desktop = ProgressStorage::per_device(Arc::clone(&backend), "desktop");
+
+        let mut p1 = laptop.load_or_create("laptop").unwrap();
+        p1.add_book("h1".to_string(), "A.pdf".to_string(), "/a".to_string(), 10);
+        laptop.save(&p1).unwrap();
+
+        let mut p2 = desktop.load_or_create("desktop").unwrap();
+        assert!(p2.books.contains_key("h1"));
+        p2.add_book("h2".to_string(), "B.pdf".to_string(), "/b".to_string(), 20);
+        desktop.save(&p2).unwrap();
+
+        assert!(s3.contents("books", "sync/progress-laptop.json").is_some());
+        assert!(s3.contents("books", "sync/progress-desktop.json").is_some());
+
+        // Laptop sees the desktop's new book, but not its own write
+        let merged = laptop.load_if_changed().unwrap().unwrap();
+        assert_eq!(merged.books.len(), 2);
+        assert_eq!(merged.device_id, "laptop");
+        assert!(laptop.load_if_changed().unwrap().is_none());
+        laptop.save(&merged).unwrap();
+        assert!(laptop.load_if_changed().unwrap().is_none());
+
+        laptop.remove_device("desktop").unwrap();
+        assert!(s3.contents("books", "sync/progress-desktop.json").is_none());
+        assert!(laptop.remove_device("laptop").is_err());
+    }
+
+    #[test]
+    fn test_encrypted_storage_migrates_plaintext() {
+        use crate::sync::crypto::test_cipher;
+
+        let (plain, dir) = temp_storage("encrypted");
+        let path = dir.join("progress.json");
+        let mut progress = ReadingProgress::new("device1".to_string());
+        progress.add_book(
+            "h1".to_string(),
+            "Secret Title.pdf".to_string(),
+            "/a.pdf".to_string(),
+            10,
+        );
+        plain.save(&progress).unwrap();
+
+        // Plaintext is still readable, the next save encrypts it
+        let storage = ProgressStorage::new(path.clone()).with_cipher(test_cipher("pass"));
+        let loaded = storage.load().unwrap();
+        storage.save(&loaded).unwrap();
+        let raw = fs::read(&path).unwrap();
+        assert!(ProgressCipher::is_encrypted(&raw));
+        assert!(!String::from_utf8_lossy(&raw).contains("Secret Title"));
+
+        let reopened = ProgressStorage::new(path.clone()).with_cipher(test_cipher("pass"));
+        assert_eq!(reopened.load().unwrap().books.len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_wrong_passphrase_is_not_overwritten() {
+        use crate::sync::crypto::test_cipher;
+
+        let (_, dir) = temp_storage("wrong_passphrase");
+        let path = dir.join("progress.json");
+        let storage = ProgressStorage::new(path.clone()).with_cipher(test_cipher("right"));
+        storage
+            .save(&ReadingProgress::new("device1".to_string()))
+            .unwrap();
+        let before = fs::read(&path).unwrap();
+
+        let wrong = ProgressStorage::new(path.clone()).with_cipher(test_cipher("wrong"));
+        let err = wrong.load_or_create("device1").unwrap_err();
+        assert!(matches!(
+            CryptoError::find(&err),
+            Some(CryptoError::WrongPassphrase)
+        ));
+
+        let missing = ProgressStorage::new(path.clone());
+        let err = missing.load_or_create("device1").unwrap_err();
+        assert!(matches!(
+            CryptoError::find(&err),
+            Some(CryptoError::PassphraseRequired)
+        ));
+
+        assert_eq!(fs::read(&path).unwrap(), before);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_corrupt_file_is_quarantined_and_restored_from_backup() {
+        let (_, dir) = temp_storage("quarantine");
+        let path = dir.join("progress.json");
+        let storage =
+            ProgressStorage::new(path.clone()).with_backups(BackupStore::new(dir.join("backups")));
+
+        let mut progress = storage.load_or_create("device1").unwrap();
+        progress.add_book("h1".to_string(), "A.pdf".to_string(), "/a".to_string(), 10);
+        progress.update_book_progress("h1", 7);
+        storage.snapshot_now(&progress).unwrap();
+
+        fs::write(&path, b"{ truncated by a cloud client").unwrap();
+
+        let reopened =
+            ProgressStorage::new(path.clone()).with_backups(BackupStore::new(dir.join("backups")));
+        let recovered = reopened.load_or_create("device1").unwrap();
+        assert_eq!(recovered.books.get("h1").unwrap().current_page, 7);
+
+        let quarantined: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().contains(QUARANTINE_SUFFIX))
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(
+            fs::read(quarantined[0].path()).unwrap(),
+            b"{ truncated by a cloud client"
+        );
+        assert_eq!(reopened.load().unwrap().books.len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,373 @@
+//! Minimal in-process HTTP server used as a stand-in for remote sync services in tests.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+pub struct TestRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl TestRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|s| s.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query.split('&').find_map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k) == name).then(|| percent_decode(v))
+        })
+    }
+}
+
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+type Handler = dyn Fn(&TestRequest) -> TestResponse + Send + Sync;
+
+pub struct TestServer {
+    pub base_url: String,
+}
+
+impl TestServer {
+    pub fn start(handler: impl Fn(&TestRequest) -> TestResponse + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    break;
+                };
+                let handler = Arc::clone(&handler);
+                std::thread::spawn(move || {
+                    let _ = Self::serve(stream, handler.as_ref());
+                });
+            }
+        });
+
+        Self {
+            base_url: format!("http://{}", addr),
+        }
+    }
+
+    fn serve(stream: TcpStream, handler: &Handler) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default().to_string();
+        let (path, query) = match target.split_once('?') {
+            Some((p, q)) => (p.to_string(), q.to_string()),
+            None => (target, String::new()),
+        };
+
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+        }
+
+        let length = headers
+            .get("content-length")
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body)?;
+
+        let response = handler(&TestRequest {
+            method,
+            path,
+            query,
+            headers,
+            body,
+        });
+
+        let mut out = stream;
+        write!(out, "HTTP/1.1 {} Test\r\n", response.status)?;
+        for (name, value) in &response.headers {
+            write!(out, "{}: {}\r\n", name, value)?;
+        }
+        write!(
+            out,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            response.body.len()
+        )?;
+        out.write_all(&response.body)?;
+        out.flush()
+    }
+}
+
+/// In-memory WebDAV collection: GET, HEAD, PUT, DELETE, MKCOL and PROPFIND with ETags.
+#[derive(Default)]
+pub struct MemoryDav {
+    files: Mutex<HashMap<String, (Vec<u8>, String)>>,
+    counter: Mutex<u64>,
+}
+
+impl MemoryDav {
+    pub fn start() -> (TestServer, Arc<MemoryDav>) {
+        let dav = Arc::new(MemoryDav::default());
+        let handler_dav = Arc::clone(&dav);
+        let server = TestServer::start(move |req| handler_dav.handle(req));
+        (server, dav)
+    }
+
+    /// Simulates a write from another client.
+    pub fn put_external(&self, path: &str, data: &[u8]) {
+        let etag = self.next_etag();
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), (data.to_vec(), etag));
+    }
+
+    pub fn contents(&self, path: &str) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(path).map(|(d, _)| d.clone())
+    }
+
+    fn next_etag(&self) -> String {
+        let mut counter = self.counter.lock().unwrap();
+        *counter += 1;
+        format!("\"{}\"", counter)
+    }
+
+    fn handle(&self, req: &TestRequest) -> TestResponse {
+        let mut files = self.files.lock().unwrap();
+        let current = files.get(&req.path).cloned();
+
+        match req.method.as_str() {
+            "GET" | "HEAD" => match current {
+                Some((data, etag)) => {
+                    let resp = TestResponse::new(200).with_header("ETag", &etag);
+                    if req.method == "GET" {
+                        resp.with_body(data)
+                    } else {
+                        resp
+                    }
+                }
+                None => TestResponse::new(404),
+            },
+            "PUT" => {
+                let current_etag = current.map(|(_, e)| e);
+                if let Some(expected) = req.header("if-match") {
+                    if current_etag.as_deref() != Some(expected) {
+                        return TestResponse::new(412);
+                    }
+                }
+                if req.header("if-none-match") == Some("*") && current_etag.is_some() {
+                    return TestResponse::new(412);
+                }
+                let etag = self.next_etag();
+                files.insert(req.path.clone(), (req.body.clone(), etag.clone()));
+                TestResponse::new(if current_etag.is_some() { 204 } else { 201 })
+                    .with_header("ETag", &etag)
+            }
+            "DELETE" => match files.remove(&req.path) {
+                Some(_) => TestResponse::new(204),
+                None => TestResponse::new(404),
+            },
+            "MKCOL" => TestResponse::new(201),
+            "PROPFIND" => {
+                let dir = if req.path.ends_with('/') {
+                    req.path.clone()
+                } else {
+                    format!("{}/", req.path)
+                };
+                let mut xml = String::from(
+                    r#"<?xml version="1.0" encoding="utf-8"?><d:multistatus xmlns:d="DAV:">"#,
+                );
+                xml.push_str(&format!(
+                    "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>",
+                    dir
+                ));
+                let mut paths: Vec<_> = files
+                    .iter()
+                    .filter(|(p, _)| p.starts_with(&dir) && !p[dir.len()..].contains('/'))
+                    .collect();
+                paths.sort_by(|a, b| a.0.cmp(b.0));
+                for (path, (_, etag)) in paths {
+                    xml.push_str(&format!(
+                        "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:getetag>{}</d:getetag><d:resourcetype/></d:prop></d:propstat></d:response>",
+                        path.replace(' ', "%20"),
+                        etag.replace('"', "&quot;")
+                    ));
+                }
+                xml.push_str("</d:multistatus>");
+                TestResponse::new(207)
+                    .with_header("Content-Type", "application/xml")
+                    .with_body(xml)
+            }
+            _ => TestResponse::new(405),
+        }
+    }
+}
+
+type ObjectMap = HashMap<(String, String), (Vec<u8>, String)>;
+
+/// In-memory S3 bucket store: path-style GET, PUT (conditional), DELETE and ListObjectsV2.
+/// Signatures are not verified, only recorded.
+#[derive(Default)]
+pub struct MemoryS3 {
+    objects: Mutex<ObjectMap>,
+    counter: Mutex<u64>,
+    last_authorization: Mutex<String>,
+}
+
+impl MemoryS3 {
+    pub fn start() -> (TestServer, Arc<MemoryS3>) {
+        let s3 = Arc::new(MemoryS3::default());
+        let handler_s3 = Arc::clone(&s3);
+        let server = TestServer::start(move |req| handler_s3.handle(req));
+        (server, s3)
+    }
+
+    pub fn put_external(&self, bucket: &str, key: &str, data: &[u8]) {
+        let etag = self.next_etag();
+        self.objects
+            .lock()
+            .unwrap()
+            .insert((bucket.to_string(), key.to_string()), (data.to_vec(), etag));
+    }
+
+    pub fn contents(&self, bucket: &str, key: &str) -> Option<Vec<u8>> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(&(bucket.to_string(), key.to_string()))
+            .map(|(d, _)| d.clone())
+    }
+
+    pub fn last_authorization(&self) -> String {
+        self.last_authorization.lock().unwrap().clone()
+    }
+
+    fn next_etag(&self) -> String {
+        let mut counter = self.counter.lock().unwrap();
+        *counter += 1;
+        format!("\"{:032x}\"", *counter)
+    }
+
+    fn handle(&self, req: &TestRequest) -> TestResponse {
+        if let Some(auth) = req.header("authorization") {
+            *self.last_authorization.lock().unwrap() = auth.to_string();
+        }
+
+        let path = percent_decode(req.path.trim_start_matches('/'));
+        let (bucket, key) = match path.split_once('/') {
+            Some((b, k)) => (b.to_string(), k.to_string()),
+            None => (path.clone(), String::new()),
+        };
+        let mut objects = self.objects.lock().unwrap();
+
+        if key.is_empty() && req.method == "GET" {
+            let prefix = req.query_param("prefix").unwrap_or_default();
+            let mut keys: Vec<_> = objects
+                .iter()
+                .filter(|((b, k), _)| *b == bucket && k.starts_with(&prefix))
+                .map(|((_, k), (_, etag))| (k.clone(), etag.clone()))
+                .collect();
+            keys.sort();
+            let mut xml = String::from(
+                r#"<?xml version="1.0" encoding="UTF-8"?><ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+            );
+            for (k, etag) in keys {
+                xml.push_str(&format!(
+                    "<Contents><Key>{}</Key><ETag>{}</ETag></Contents>",
+                    k,
+                    etag.replace('"', "&quot;")
+                ));
+            }
+            xml.push_str("<IsTruncated>false</IsTruncated></ListBucketResult>");
+            return TestResponse::new(200).with_body(xml);
+        }
+
+        let id = (bucket, key);
+        let current = objects.get(&id).cloned();
+        match req.method.as_str() {
+            "GET" => match current {
+                Some((data, etag)) => TestResponse::new(200)
+                    .with_header("ETag", &etag)
+                    .with_body(data),
+                None => TestResponse::new(404),
+            },
+            "PUT" => {
+                let current_etag = current.map(|(_, e)| e);
+                if let Some(expected) = req.header("if-match") {
+                    if current_etag.as_deref() != Some(expected) {
+                        return TestResponse::new(412);
+                    }
+                }
+                if req.header("if-none-match") == Some("*") && current_etag.is_some() {
+                    return TestResponse::new(412);
+                }
+                let etag = self.next_etag();
+                objects.insert(id, (req.body.clone(), etag.clone()));
+                TestResponse::new(200).with_header("ETag", &etag)
+            }
+            "DELETE" => {
+                objects.remove(&id);
+                TestResponse::new(204)
+            }
+            _ => TestResponse::new(405),
+        }
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
@@ -1,19 +1,34 @@
 use anyhow::{Context, Result};
 use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::config::constants::FILE_WATCHER_POLL_SECS;
 
+use super::backend::{ObjectInfo, SyncBackend};
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum SyncEvent {
     FileModified,
     FileDeleted,
 }
 
+enum WatcherKind {
+    Notify {
+        _watcher: RecommendedWatcher,
+        rx: Receiver<Result<Event, notify::Error>>,
+    },
+    Polling {
+        rx: Receiver<SyncEvent>,
+        stop: Arc<AtomicBool>,
+    },
+}
+
 pub struct SyncWatcher {
-    _watcher: RecommendedWatcher,
-    rx: Receiver<Result<Event, notify::Error>>,
+    kind: WatcherKind,
 }
 
 impl SyncWatcher {
@@ -36,27 +51,153 @@ impl SyncWatcher {
         }
 
         Ok(Self {
-            _watcher: watcher,
-            rx,
+            kind: WatcherKind::Notify {
+                _watcher: watcher,
+                rx,
+            },
         })
     }
 
-    pub fn poll_changes(&mut self) -> Option<SyncEvent> {
-        match self.rx.try_recv() {
-            Ok(Ok(event)) => {
-                use notify::EventKind;
-                match event.kind {
-                    EventKind::Modify(_) | EventKind::Create(_) => Some(SyncEvent::FileModified),
-                    EventKind::Remove(_) => Some(SyncEvent::FileDeleted),
-                    _ => None,
+    /// Watches a remote backend by periodically listing it on a background thread.
+    pub fn polling(backend: Arc<dyn SyncBackend>, interval: Duration) -> Self {
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+
+        std::thread::spawn(move || {
+            let mut last: Option<Vec<ObjectInfo>> = None;
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                match backend.list() {
+                    Ok(listing) => {
+                        if let Some(previous) = &last {
+                            if let Some(event) = Self::diff_listings(previous, &listing) {
+                                if tx.send(event).is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        last = Some(listing);
+                    }
+                    Err(e) => {
+                        eprintln!("Sync poll error: {:#}", e);
+                    }
                 }
+                std::thread::sleep(interval);
             }
-            Ok(Err(e)) => {
-                eprintln!("Watch error: {:?}", e);
-                None
-            }
-            Err(TryRecvError::Empty) => None,
-            Err(TryRecvError::Disconnected) => None,
+        });
+
+        Self {
+            kind: WatcherKind::Polling { rx, stop },
+        }
+    }
+
+    fn diff_listings(previous: &[ObjectInfo], current: &[ObjectInfo]) -> Option<SyncEvent> {
+        if previous == current {
+            return None;
+        }
+        let removed = previous
+            .iter()
+            .any(|p| !current.iter().any(|c| c.key == p.key));
+        if removed && current.len() < previous.len() {
+            Some(SyncEvent::FileDeleted)
+        } else {
+            Some(SyncEvent::FileModified)
+        }
+    }
+
+    pub fn poll_changes(&mut self) -> Option<SyncEvent> {
+        match &self.kind {
+            WatcherKind::Notify { rx, .. } => match rx.try_recv() {
+                Ok(Ok(event)) => {
+                    use notify::EventKind;
+                    match event.kind {
+                        EventKind::Modify(_) | EventKind::Create(_) => {
+                            Some(SyncEvent::FileModified)
+                        }
+                        EventKind::Remove(_) => Some(SyncEvent::FileDeleted),
+                        _ => None,
+                    }
+                }
+                Ok(Err(e)) => {
+                    eprintln!("Watch error: {:?}", e);
+                    None
+                }
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => None,
+            },
+            WatcherKind::Polling { rx, .. } => rx.try_recv().ok(),
         }
     }
 }
+
+impl Drop for SyncWatcher {
+    fn drop(&mut self) {
+        if let WatcherKind::Polling { stop, .. } = &self.kind {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::backend::Precondition;
+    use crate::sync::local::LocalFileBackend;
+    use std::time::Instant;
+
+    fn info(key: &str, etag: &str) -> ObjectInfo {
+        ObjectInfo {
+            key: key.to_string(),
+            etag: Some(etag.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_diff_listings_unchanged() {
+        let a = vec![info("p.json", "1")];
+        assert_eq!(SyncWatcher::diff_listings(&a, &a.clone()), None);
+    }
+
+    #[test]
+    fn test_diff_listings_modified() {
+        let a = vec![info("p.json", "1")];
+        let b = vec![info("p.json", "2")];
+        assert_eq!(
+            SyncWatcher::diff_listings(&a, &b),
+            Some(SyncEvent::FileModified)
+        );
+    }
+
+    #[test]
+    fn test_diff_listings_deleted() {
+        let a = vec![info("p.json", "1")];
+        assert_eq!(
+            SyncWatcher::diff_listings(&a, &[]),
+            Some(SyncEvent::FileDeleted)
+        );
+    }
+
+    #[test]
+    fn test_polling_detects_change() {
+        let dir = std::env::temp_dir().join("docreader_test_watcher_poll");
+        let _ = std::fs::remove_dir_all(&dir);
+        let backend = Arc::new(LocalFileBackend::new(dir.clone()));
+        backend.put("p.json", b"v1", &Precondition::None).unwrap();
+
+        let mut watcher = SyncWatcher::polling(backend.clone(), Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(100));
+        backend.put("p.json", b"v2", &Precondition::None).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut event = None;
+        while event.is_none() && Instant::now() < deadline {
+            event = watcher.poll_changes();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(event, Some(SyncEvent::FileModified));
+
+        drop(watcher);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
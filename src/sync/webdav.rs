@@ -0,0 +1,362 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::io::Read;
+use std::time::Duration;
+
+use crate::config::constants::HTTP_TIMEOUT_SECS;
+
+use super::backend::{ObjectInfo, Precondition, RemoteObject, SyncBackend, SyncError};
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:getetag/><d:resourcetype/></d:prop></d:propfind>"#;
+
+/// Backend over a WebDAV collection (Nextcloud, Yandex Disk WebDAV, ...).
+pub struct WebDavBackend {
+    agent: ureq::Agent,
+    base_url: String,
+    authorization: Option<String>,
+}
+
+impl WebDavBackend {
+    /// `url` points to the collection holding the synced files.
+    pub fn new(url: &str, username: &str, password: &str) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(HTTP_TIMEOUT_SECS))
+            .build();
+
+        let mut base_url = url.trim().to_string();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+
+        let authorization = if username.is_empty() {
+            None
+        } else {
+            let credentials = format!("{}:{}", username, password);
+            Some(format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            ))
+        };
+
+        Self {
+            agent,
+            base_url,
+            authorization,
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}{}", self.base_url, encode_path_segment(key))
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+        match &self.authorization {
+            Some(auth) => request.set("Authorization", auth),
+            None => request,
+        }
+    }
+
+    fn head_etag(&self, key: &str) -> Result<Option<String>> {
+        match self.request("HEAD", &self.url(key)).call() {
+            Ok(resp) => Ok(resp.header("ETag").map(|s| s.to_string())),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(e).context("WebDAV HEAD failed"),
+        }
+    }
+
+    fn make_collection(&self) -> Result<()> {
+        match self.request("MKCOL", &self.base_url).call() {
+            // 405: the collection already exists
+            Ok(_) | Err(ureq::Error::Status(405, _)) => Ok(()),
+            Err(e) => Err(e).context("WebDAV MKCOL failed"),
+        }
+    }
+
+    /// Extracts `(href, etag, is_collection)` entries from a PROPFIND multistatus body.
+    fn parse_multistatus(xml: &str) -> Result<Vec<(String, Option<String>, bool)>> {
+        let mut reader = Reader::from_str(xml);
+        let mut entries = Vec::new();
+
+        let mut href = String::new();
+        let mut etag: Option<String> = None;
+        let mut is_collection = false;
+        let mut current = String::new();
+
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(ref e)) => {
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                    match name.as_str() {
+                        "response" => {
+                            href.clear();
+                            etag = None;
+                            is_collection = false;
+                        }
+                        "collection" => is_collection = true,
+                        _ => {}
+                    }
+                    current = name;
+                }
+                Ok(Event::Empty(ref e)) if e.local_name().as_ref() == b"collection" => {
+                    is_collection = true;
+                }
+                Ok(Event::Text(ref e)) => {
+                    let text = e.unescape().context("Invalid PROPFIND response")?;
+                    match current.as_str() {
+                        "href" => href.push_str(text.trim()),
+                        "getetag" => etag = Some(text.trim().to_string()),
+                        _ => {}
+                    }
+                }
+                Ok(Event::End(ref e)) => {
+                    if e.local_name().as_ref() == b"response" {
+                        entries.push((href.clone(), etag.take(), is_collection));
+                    }
+                    current.clear();
+                }
+                Ok(Event::Eof) => break,
+                Err(e) => return Err(e).context("Invalid PROPFIND response"),
+                _ => {}
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+impl SyncBackend for WebDavBackend {
+    fn describe(&self) -> String {
+        self.base_url.clone()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<RemoteObject>> {
+        match self.request("GET", &self.url(key)).call() {
+            Ok(resp) => {
+                let etag = resp.header("ETag").map(|s| s.to_string());
+                let mut data = Vec::new();
+                resp.into_reader()
+                    .read_to_end(&mut data)
+                    .context("Failed to read WebDAV response")?;
+                Ok(Some(RemoteObject { data, etag }))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(e).context("WebDAV GET failed"),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8], precondition: &Precondition) -> Result<Option<String>> {
+        // `Ok(None)` means the parent collection is missing (409)
+        let send = || -> Result<Option<ureq::Response>> {
+            let request = self
+                .request("PUT", &self.url(key))
+                .set("Content-Type", "application/octet-stream");
+            let request = match precondition {
+                Precondition::None => request,
+                Precondition::Absent => request.set("If-None-Match", "*"),
+                Precondition::Matches(etag) => request.set("If-Match", etag),
+            };
+            match request.send_bytes(data) {
+                Ok(resp) => Ok(Some(resp)),
+                Err(ureq::Error::Status(409, _)) => Ok(None),
+                Err(ureq::Error::Status(412, _)) => Err(SyncError::PreconditionFailed.into()),
+                Err(e) => Err(e).context("WebDAV PUT failed"),
+            }
+        };
+
+        let resp = match send()? {
+            Some(resp) => resp,
+            None => {
+                self.make_collection()?;
+                send()?.context("WebDAV PUT failed: collection is missing")?
+            }
+        };
+
+        match resp.header("ETag") {
+            Some(etag) => Ok(Some(etag.to_string())),
+            // Some servers omit the ETag on PUT
+            None => self.head_etag(key),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<ObjectInfo>> {
+        let resp = match self
+            .request("PROPFIND", &self.base_url)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml")
+            .send_string(PROPFIND_BODY)
+        {
+            Ok(resp) => resp,
+            Err(ureq::Error::Status(404, _)) => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("WebDAV PROPFIND failed"),
+        };
+        let xml = resp
+            .into_string()
+            .context("Failed to read WebDAV response")?;
+
+        let mut objects: Vec<ObjectInfo> = Self::parse_multistatus(&xml)?
+            .into_iter()
+            .filter(|(href, _, is_collection)| !is_collection && !href.ends_with('/'))
+            .filter_map(|(href, etag, _)| {
+                let name = href.rsplit('/').next()?;
+                Some(ObjectInfo {
+                    key: decode_path_segment(name),
+                    etag,
+                })
+            })
+            .collect();
+
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match self.request("DELETE", &self.url(key)).call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(e) => Err(e).context("WebDAV DELETE failed"),
+        }
+    }
+}
+
+/// Percent-encodes a single path segment (RFC 3986 unreserved characters are kept).
+pub(crate) fn encode_path_segment(segment: &str) -> String {
+    let mut out = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+fn decode_path_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::test_server::MemoryDav;
+
+    fn backend(base_url: &str) -> WebDavBackend {
+        WebDavBackend::new(&format!("{}/dav/books", base_url), "user", "secret")
+    }
+
+    #[test]
+    fn test_put_and_get() {
+        let (server, dav) = MemoryDav::start();
+        let backend = backend(&server.base_url);
+
+        let etag = backend
+            .put("progress.json", b"{}", &Precondition::Absent)
+            .unwrap();
+        assert!(etag.is_some());
+        assert_eq!(dav.contents("/dav/books/progress.json").unwrap(), b"{}");
+
+        let obj = backend.get("progress.json").unwrap().unwrap();
+        assert_eq!(obj.data, b"{}");
+        assert_eq!(obj.etag, etag);
+    }
+
+    #[test]
+    fn test_get_missing() {
+        let (server, _dav) = MemoryDav::start();
+        assert!(backend(&server.base_url)
+            .get("none.json")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_if_match_conflict() {
+        let (server, dav) = MemoryDav::start();
+        let backend = backend(&server.base_url);
+
+        let etag = backend
+            .put("progress.json", b"v1", &Precondition::None)
+            .unwrap()
+            .unwrap();
+        dav.put_external("/dav/books/progress.json", b"other device");
+
+        let err = backend
+            .put("progress.json", b"v2", &Precondition::Matches(etag))
+            .unwrap_err();
+        assert!(SyncError::is_precondition_failed(&err));
+        assert_eq!(
+            dav.contents("/dav/books/progress.json").unwrap(),
+            b"other device"
+        );
+    }
+
+    #[test]
+    fn test_list_skips_collection() {
+        let (server, dav) = MemoryDav::start();
+        let backend = backend(&server.base_url);
+
+        dav.put_external("/dav/books/b.json", b"b");
+        dav.put_external("/dav/books/a file.json", b"a");
+        dav.put_external("/dav/other/c.json", b"c");
+
+        let objects = backend.list().unwrap();
+        let keys: Vec<&str> = objects.iter().map(|o| o.key.as_str()).collect();
+        assert_eq!(keys, vec!["a file.json", "b.json"]);
+        assert!(objects.iter().all(|o| o.etag.is_some()));
+    }
+
+    #[test]
+    fn test_delete() {
+        let (server, dav) = MemoryDav::start();
+        let backend = backend(&server.base_url);
+
+        dav.put_external("/dav/books/a.json", b"a");
+        backend.delete("a.json").unwrap();
+        backend.delete("a.json").unwrap();
+        assert!(dav.contents("/dav/books/a.json").is_none());
+    }
+
+    #[test]
+    fn test_parse_multistatus_namespaced() {
+        let xml = r#"<?xml version="1.0"?>
+            <D:multistatus xmlns:D="DAV:">
+              <D:response><D:href>/remote/</D:href>
+                <D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop></D:propstat>
+              </D:response>
+              <D:response><D:href>/remote/reading_progress.json</D:href>
+                <D:propstat><D:prop><D:getetag>"abc"</D:getetag></D:prop></D:propstat>
+              </D:response>
+            </D:multistatus>"#;
+        let entries = WebDavBackend::parse_multistatus(xml).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].2);
+        assert_eq!(entries[1].0, "/remote/reading_progress.json");
+        assert_eq!(entries[1].1.as_deref(), Some("\"abc\""));
+    }
+
+    #[test]
+    fn test_path_segment_roundtrip() {
+        let encoded = encode_path_segment("Книга 1.json");
+        assert!(!encoded.contains(' '));
+        assert_eq!(decode_path_segment(&encoded), "Книга 1.json");
+    }
+}
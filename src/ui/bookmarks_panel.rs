@@ -0,0 +1,149 @@
+use egui::Ui;
+
+use crate::library::annotations::Highlight;
+use crate::library::bookmarks::Bookmark;
+
+pub struct BookmarksPanel;
+
+pub enum BookmarkAction {
+    Jump(u32),
+    Rename {
+        id: String,
+        label: String,
+    },
+    Delete(String),
+    ExportMarkdown,
+    ExportJson,
+    /// Write the highlights into a copy of the PDF.
+    ExportPdf,
+    /// Read the annotations already in the PDF.
+    ImportPdf,
+}
+
+impl BookmarksPanel {
+    /// Bookmarks and highlights come with the page they are on now.
+    /// `editing` holds the id and label buffer of the bookmark being renamed;
+    /// `export_status` is the result of the last export; `pdf` enables the
+    /// actions that work on the PDF file itself.
+    pub fn show(
+        ui: &mut Ui,
+        bookmarks: &[(u32, &Bookmark)],
+        highlights: &[(u32, &Highlight)],
+        current_page: u32,
+        editing: &mut Option<(String, String)>,
+        export_status: Option<&str>,
+        pdf: bool,
+    ) -> Option<BookmarkAction> {
+        let mut action = None;
+
+        ui.heading("Закладки");
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if bookmarks.is_empty() {
+                ui.label("Нет закладок. Ctrl+B добавляет закладку на текущей странице.");
+            }
+            for &(page, bookmark) in bookmarks {
+                let is_editing = editing.as_ref().is_some_and(|(id, _)| *id == bookmark.id);
+                if is_editing {
+                    let mut finished = false;
+                    if let Some((_, label)) = editing.as_mut() {
+                        let response = ui.text_edit_singleline(label);
+                        response.request_focus();
+                        if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                            action = Some(BookmarkAction::Rename {
+                                id: bookmark.id.clone(),
+                                label: label.trim().to_string(),
+                            });
+                            finished = true;
+                        } else if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                            finished = true;
+                        }
+                    }
+                    if finished {
+                        *editing = None;
+                    }
+                    continue;
+                }
+
+                ui.horizontal(|ui| {
+                    let label = format!("стр. {} — {}", page, bookmark.label);
+                    if ui.selectable_label(page == current_page, label).clicked() {
+                        action = Some(BookmarkAction::Jump(page));
+                    }
+                    if ui
+                        .small_button("✏")
+                        .on_hover_text("Переименовать")
+                        .clicked()
+                    {
+                        *editing = Some((bookmark.id.clone(), bookmark.label.clone()));
+                    }
+                    if ui.small_button("🗑").on_hover_text("Удалить").clicked() {
+                        action = Some(BookmarkAction::Delete(bookmark.id.clone()));
+                    }
+                });
+            }
+
+            ui.add_space(8.0);
+            ui.heading("Выделения");
+            ui.separator();
+            if highlights.is_empty() {
+                ui.label("Выделите текст на странице мышью, чтобы сохранить цитату.");
+            }
+            for &(page, highlight) in highlights {
+                let [r, g, b] = highlight.color.rgb();
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::from_rgb(r, g, b), "▌");
+                    let label = format!("стр. {} — {}", page, highlight.text);
+                    let response = ui.selectable_label(page == current_page, label);
+                    let response = if highlight.note.is_empty() {
+                        response
+                    } else {
+                        response.on_hover_text(&highlight.note)
+                    };
+                    if response.clicked() {
+                        action = Some(BookmarkAction::Jump(page));
+                    }
+                });
+            }
+            if !highlights.is_empty() {
+                ui.horizontal(|ui| {
+                    ui.label("Экспорт:");
+                    if ui.button("Markdown").clicked() {
+                        action = Some(BookmarkAction::ExportMarkdown);
+                    }
+                    if ui.button("JSON").clicked() {
+                        action = Some(BookmarkAction::ExportJson);
+                    }
+                });
+            }
+            if pdf {
+                ui.horizontal(|ui| {
+                    ui.label("PDF:");
+                    if !highlights.is_empty()
+                        && ui
+                            .button("Записать в копию")
+                            .on_hover_text(
+                                "Сохранить выделения и заметки как аннотации в копии PDF",
+                            )
+                            .clicked()
+                    {
+                        action = Some(BookmarkAction::ExportPdf);
+                    }
+                    if ui
+                        .button("Импорт")
+                        .on_hover_text("Загрузить аннотации из файла PDF и его копии")
+                        .clicked()
+                    {
+                        action = Some(BookmarkAction::ImportPdf);
+                    }
+                });
+            }
+            if let Some(status) = export_status {
+                ui.label(status);
+            }
+        });
+
+        action
+    }
+}
@@ -1,28 +1,294 @@
-use egui::Ui;
+use egui::{Color32, Painter, Pos2, Rect, Ui};
+use std::ops::RangeInclusive;
+
+use crate::config::constants::*;
+use crate::library::annotations::Highlight;
+use crate::renderer::cache::CachedPage;
+use crate::renderer::page_text::{PageImage, PageText};
 
 pub struct DocumentViewer;
 
+/// What is drawn over the page texture.
+pub struct PageOverlay<'a> {
+    pub text: &'a PageText,
+    pub images: &'a [PageImage],
+    pub highlights: &'a [Highlight],
+    /// Inclusive character range, in either order.
+    pub selection: Option<(usize, usize)>,
+}
+
+pub enum ViewerAction {
+    /// A drag started on this character.
+    SelectStart(usize),
+    /// The drag moved over this character.
+    SelectTo(usize),
+    /// The drag ended at this screen position.
+    SelectEnd(Pos2),
+    /// A click without dragging, on the character closest to it.
+    Click {
+        char_index: Option<usize>,
+        pos: Pos2,
+    },
+    /// A click on the illustration with this index.
+    OpenImage(usize),
+}
+
+/// Where the reader is in a continuous scroll of pages.
+pub struct ContinuousView {
+    /// Page at the top of the viewer, from 1.
+    pub page: u32,
+    /// How far the top of the viewer is into that page, in page heights.
+    pub offset: f32,
+    /// Pages in view or close to it, which should be rendered.
+    pub pages: RangeInclusive<u32>,
+}
+
 impl DocumentViewer {
     pub fn show(
         ui: &mut Ui,
         texture: Option<&egui::TextureHandle>,
+        overlay: PageOverlay<'_>,
         _current_page: u32,
         _total_pages: u32,
-    ) {
+    ) -> Option<ViewerAction> {
+        let mut action = None;
+
         if let Some(tex) = texture {
+            let selectable = !overlay.text.chars.is_empty();
+            let clickable = selectable || !overlay.images.is_empty();
             egui::ScrollArea::both()
                 .auto_shrink([false, false])
+                // Dragging selects text instead of scrolling
+                .drag_to_scroll(!selectable)
                 .show(ui, |ui| {
                     // Texture is rendered at native pixel density;
                     // display in logical points so it appears at the correct size
                     let ppp = ui.ctx().pixels_per_point();
                     let size = tex.size_vec2() / ppp;
-                    ui.image((tex.id(), size));
+                    let sense = if selectable {
+                        egui::Sense::click_and_drag()
+                    } else if clickable {
+                        egui::Sense::click()
+                    } else {
+                        egui::Sense::hover()
+                    };
+                    let response = ui.add(egui::Image::new((tex.id(), size)).sense(sense));
+                    let rect = response.rect;
+                    let to_screen = |r: [f32; 4]| {
+                        Rect::from_min_max(
+                            rect.min + egui::vec2(r[0], r[1]) * rect.size(),
+                            rect.min + egui::vec2(r[2], r[3]) * rect.size(),
+                        )
+                    };
+
+                    let painter = ui.painter_at(rect);
+                    for highlight in overlay.highlights {
+                        paint_highlight(&painter, overlay.text, rect, highlight);
+                    }
+                    if let Some((start, end)) = overlay.selection {
+                        let [r, g, b, a] = SELECTION_COLOR;
+                        let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+                        fill_text_range(&painter, overlay.text, rect, start, end, color);
+                    }
+
+                    let pointer = response
+                        .interact_pointer_pos()
+                        .or(ui.ctx().pointer_latest_pos());
+                    let image_at = |pos: Pos2| {
+                        overlay
+                            .images
+                            .iter()
+                            .position(|image| to_screen(image.rect).contains(pos))
+                    };
+                    // An ongoing selection drag may pass over images
+                    let dragging = response.dragged() || response.drag_stopped();
+                    let image = pointer.filter(|_| clickable && !dragging);
+                    if let Some(index) = image.and_then(image_at) {
+                        if response.hovered() {
+                            ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                        }
+                        if response.clicked() {
+                            action = Some(ViewerAction::OpenImage(index));
+                        }
+                        return;
+                    }
+
+                    if !selectable {
+                        return;
+                    }
+                    if response.hovered() {
+                        ui.ctx().set_cursor_icon(egui::CursorIcon::Text);
+                    }
+                    let char_at = |pos: Pos2| {
+                        let p = (pos - rect.min) / rect.size();
+                        overlay.text.char_at(p.x, p.y)
+                    };
+                    if response.drag_started() {
+                        action = pointer.and_then(char_at).map(ViewerAction::SelectStart);
+                    } else if response.drag_stopped() {
+                        action = pointer.map(ViewerAction::SelectEnd);
+                    } else if response.dragged() {
+                        action = pointer.and_then(char_at).map(ViewerAction::SelectTo);
+                    } else if response.clicked() {
+                        action = pointer.map(|pos| ViewerAction::Click {
+                            char_index: char_at(pos),
+                            pos,
+                        });
+                    }
                 });
         } else {
             ui.centered_and_justified(|ui| {
                 ui.label("Выберите книгу из библиотеки");
             });
         }
+
+        action
+    }
+
+    /// Shows the pages of a spread side by side, left to right. Pages not
+    /// rendered yet are drawn as blank sheets of their size in `page_sizes`
+    /// (at 100% zoom).
+    pub fn show_spread(
+        ui: &mut Ui,
+        pages: &[(u32, Option<CachedPage>)],
+        page_sizes: &[egui::Vec2],
+        zoom: f32,
+        highlights: &[&Highlight],
+    ) {
+        egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                let ppp = ui.ctx().pixels_per_point();
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 0.0;
+                    for (page, cached) in pages {
+                        let size = match cached {
+                            Some(cached) => cached.texture.size_vec2() / ppp,
+                            None => page_sizes
+                                .get(page.saturating_sub(1) as usize)
+                                .map_or(egui::Vec2::ZERO, |size| *size * zoom),
+                        };
+                        let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+                        paint_page(ui, rect, *page, cached.as_ref(), highlights);
+                    }
+                });
+            });
+    }
+
+    /// Shows all pages one under another, `page_sizes` at 100% zoom. Pages
+    /// not rendered yet are drawn as blank sheets.
+    pub fn show_continuous(
+        ui: &mut Ui,
+        page_sizes: &[egui::Vec2],
+        zoom: f32,
+        scroll_to: Option<(u32, f32)>,
+        highlights: &[&Highlight],
+        page: &mut dyn FnMut(u32) -> Option<CachedPage>,
+    ) -> Option<ContinuousView> {
+        if page_sizes.is_empty() {
+            return None;
+        }
+
+        // Page rectangles relative to the top of the scroll, centred later
+        let mut layout = Vec::with_capacity(page_sizes.len());
+        let mut top = 0.0;
+        for size in page_sizes {
+            layout.push(Rect::from_min_size(egui::pos2(0.0, top), *size * zoom));
+            top += size.y * zoom + CONTINUOUS_PAGE_GAP;
+        }
+        let width = layout.iter().map(|r| r.width()).fold(0.0, f32::max);
+        let height = top - CONTINUOUS_PAGE_GAP;
+
+        let mut area = egui::ScrollArea::both()
+            .id_salt("continuous")
+            .auto_shrink([false, false]);
+        if let Some((page, offset)) = scroll_to {
+            let index = (page.max(1) as usize - 1).min(layout.len() - 1);
+            let rect = layout[index];
+            area = area.vertical_scroll_offset(rect.min.y + offset * rect.height());
+        }
+
+        let output = area.show_viewport(ui, |ui, viewport| {
+            ui.set_min_size(egui::vec2(width, height));
+            let content_width = width.max(viewport.width());
+            let origin = ui.max_rect().min;
+            let to_screen = |rect: Rect| {
+                rect.translate(
+                    origin.to_vec2() + egui::vec2((content_width - rect.width()) / 2.0, 0.0),
+                )
+            };
+            // Pages from the first ending below `from` to the last starting above `to`
+            let pages_between = |from: f32, to: f32| {
+                let first = layout.partition_point(|r| r.max.y <= from);
+                let last = layout.partition_point(|r| r.min.y < to).saturating_sub(1);
+                first.min(last)..=last
+            };
+
+            for index in pages_between(viewport.min.y, viewport.max.y) {
+                let rect = to_screen(layout[index]);
+                let number = index as u32 + 1;
+                paint_page(ui, rect, number, page(number).as_ref(), highlights);
+            }
+
+            let top = layout
+                .partition_point(|r| r.min.y <= viewport.min.y)
+                .saturating_sub(1);
+            let offset = (viewport.min.y - layout[top].min.y) / layout[top].height();
+            let margin = viewport.height() * CONTINUOUS_RENDER_MARGIN;
+            let pages = pages_between(viewport.min.y - margin, viewport.max.y + margin);
+            let first = *pages.start();
+            let last = (*pages.end()).min(first + CONTINUOUS_MAX_PAGES as usize - 1);
+            ContinuousView {
+                page: top as u32 + 1,
+                offset: offset.clamp(0.0, 1.0),
+                pages: first as u32 + 1..=last as u32 + 1,
+            }
+        });
+        Some(output.inner)
+    }
+}
+
+/// Draws a rendered page with its highlights, or a blank sheet.
+fn paint_page(
+    ui: &Ui,
+    rect: Rect,
+    page: u32,
+    cached: Option<&CachedPage>,
+    highlights: &[&Highlight],
+) {
+    let painter = ui.painter_at(rect);
+    let Some(cached) = cached else {
+        painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+        return;
+    };
+    let uv = Rect::from_min_max(Pos2::ZERO, egui::pos2(1.0, 1.0));
+    painter.image(cached.texture.id(), rect, uv, Color32::WHITE);
+    for highlight in highlights.iter().filter(|h| h.page == page) {
+        paint_highlight(&painter, &cached.text, rect, highlight);
+    }
+}
+
+fn paint_highlight(painter: &Painter, text: &PageText, rect: Rect, highlight: &Highlight) {
+    let [r, g, b] = highlight.color.rgb();
+    let color = Color32::from_rgba_unmultiplied(r, g, b, HIGHLIGHT_ALPHA);
+    let (start, end) = (highlight.start as usize, highlight.end as usize);
+    fill_text_range(painter, text, rect, start, end, color);
+}
+
+/// Fills the lines of a character range of a page drawn in `rect`.
+fn fill_text_range(
+    painter: &Painter,
+    text: &PageText,
+    rect: Rect,
+    start: usize,
+    end: usize,
+    color: Color32,
+) {
+    for [left, top, right, bottom] in text.line_rects(start, end) {
+        let line = Rect::from_min_max(
+            rect.min + egui::vec2(left, top) * rect.size(),
+            rect.min + egui::vec2(right, bottom) * rect.size(),
+        );
+        painter.rect_filled(line, 0.0, color);
     }
 }
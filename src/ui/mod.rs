@@ -1,3 +1,4 @@
+pub mod bookmarks_panel;
 pub mod document_viewer;
 pub mod sidebar;
 pub mod toolbar;
@@ -1,7 +1,29 @@
 use egui::Ui;
 
+use crate::config::constants::CROP_MARGIN_MAX;
+use crate::config::settings::ZoomMode;
+use crate::library::progress::{Crop, Spread};
+use crate::renderer::crop::CropMargins;
+
 pub struct Toolbar;
 
+/// How the pages of a fixed-layout book are shown.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PageView {
+    Single,
+    Continuous,
+    Spread(Spread),
+}
+
+/// How the pages of a fixed-layout book are arranged, cropped and turned.
+#[derive(Clone, Copy, PartialEq)]
+pub struct PageLayout {
+    pub view: PageView,
+    pub crop: Option<Crop>,
+    /// Quarter turns clockwise of the current page.
+    pub rotation: u8,
+}
+
 #[allow(dead_code)]
 pub enum ToolbarAction {
     PrevPage,
@@ -10,15 +32,36 @@ pub enum ToolbarAction {
     ZoomIn,
     ZoomOut,
     ZoomReset,
+    FitWidth,
+    FitPage,
+    ToggleBookmark,
+    ToggleBookmarksPanel,
+    ToggleContinuous,
+    SetSpread(Option<Spread>),
+    SetCrop(Option<Crop>),
+    ToggleReflow,
+    /// Turn the current page by the given quarter turns clockwise.
+    RotatePage(u8),
+    /// Turn every page by the given quarter turns clockwise.
+    RotateDocument(u8),
+    ResetRotation,
 }
 
 impl Toolbar {
+    /// `layout` is how pages are shown, `None` for reflowable books, which
+    /// have no fixed pages to arrange. `reflow` is whether the text of a
+    /// PDF is reflowed, `None` for other formats.
+    #[allow(clippy::too_many_arguments)]
     pub fn show(
         ui: &mut Ui,
-        _current_page: u32,
         total_pages: u32,
         zoom: f32,
+        zoom_mode: ZoomMode,
         page_input: &mut String,
+        bookmarked: bool,
+        bookmarks_open: bool,
+        layout: Option<PageLayout>,
+        reflow: Option<bool>,
     ) -> Option<ToolbarAction> {
         let mut action = None;
 
@@ -46,6 +89,20 @@ impl Toolbar {
 
             ui.separator();
 
+            let (icon, hint) = if bookmarked {
+                ("★", "Убрать закладку (Ctrl+B)")
+            } else {
+                ("☆", "Добавить закладку (Ctrl+B)")
+            };
+            if ui.button(icon).on_hover_text(hint).clicked() {
+                action = Some(ToolbarAction::ToggleBookmark);
+            }
+            if ui.selectable_label(bookmarks_open, "Закладки").clicked() {
+                action = Some(ToolbarAction::ToggleBookmarksPanel);
+            }
+
+            ui.separator();
+
             if ui.button("-").clicked() {
                 action = Some(ToolbarAction::ZoomOut);
             }
@@ -56,8 +113,165 @@ impl Toolbar {
             if ui.button("100%").clicked() {
                 action = Some(ToolbarAction::ZoomReset);
             }
+            if ui
+                .selectable_label(zoom_mode == ZoomMode::FitWidth, "По ширине")
+                .clicked()
+            {
+                action = Some(ToolbarAction::FitWidth);
+            }
+            if ui
+                .selectable_label(zoom_mode == ZoomMode::FitPage, "Страница целиком")
+                .clicked()
+            {
+                action = Some(ToolbarAction::FitPage);
+            }
+
+            if let Some(PageLayout {
+                view,
+                crop,
+                rotation,
+            }) = layout
+            {
+                ui.separator();
+                if ui
+                    .selectable_label(view == PageView::Continuous, "Лента")
+                    .on_hover_text("Непрерывная прокрутка страниц")
+                    .clicked()
+                {
+                    action = Some(ToolbarAction::ToggleContinuous);
+                }
+                let spread = match view {
+                    PageView::Spread(spread) => Some(spread),
+                    _ => None,
+                };
+                if ui
+                    .selectable_label(spread.is_some(), "Разворот")
+                    .on_hover_text("Две страницы рядом")
+                    .clicked()
+                {
+                    let toggled = spread.is_none().then(Spread::default);
+                    action = Some(ToolbarAction::SetSpread(toggled));
+                }
+                if let Some(spread) = spread {
+                    if ui
+                        .selectable_label(spread.cover_alone, "Обложка отдельно")
+                        .clicked()
+                    {
+                        action = Some(ToolbarAction::SetSpread(Some(Spread {
+                            cover_alone: !spread.cover_alone,
+                            ..spread
+                        })));
+                    }
+                    if ui.selectable_label(spread.rtl, "Справа налево").clicked() {
+                        action = Some(ToolbarAction::SetSpread(Some(Spread {
+                            rtl: !spread.rtl,
+                            ..spread
+                        })));
+                    }
+                }
+
+                ui.menu_button("Поля", |ui| {
+                    if let Some(crop) = Self::crop_menu(ui, crop) {
+                        action = Some(ToolbarAction::SetCrop(crop));
+                    }
+                });
+                ui.menu_button("Поворот", |ui| {
+                    if let Some(rotate) = Self::rotation_menu(ui, rotation) {
+                        action = Some(rotate);
+                        ui.close_menu();
+                    }
+                });
+            }
+
+            if let Some(reflow) = reflow {
+                ui.separator();
+                if ui
+                    .selectable_label(reflow, "Перекомпоновка")
+                    .on_hover_text("Текст страниц по ширине окна")
+                    .clicked()
+                {
+                    action = Some(ToolbarAction::ToggleReflow);
+                }
+            }
         });
 
         action
     }
+
+    /// Commands turning the current page or all pages by 90°.
+    fn rotation_menu(ui: &mut Ui, rotation: u8) -> Option<ToolbarAction> {
+        ui.label(format!("Текущая страница: {}°", rotation as u32 * 90));
+        let commands = [
+            ("Страницу по часовой (Ctrl+R)", ToolbarAction::RotatePage(1)),
+            ("Страницу против часовой", ToolbarAction::RotatePage(3)),
+            (
+                "Все страницы по часовой (Ctrl+Shift+R)",
+                ToolbarAction::RotateDocument(1),
+            ),
+            (
+                "Все страницы против часовой",
+                ToolbarAction::RotateDocument(3),
+            ),
+            ("Сбросить поворот", ToolbarAction::ResetRotation),
+        ];
+        let mut chosen = None;
+        for (label, command) in commands {
+            if ui.button(label).clicked() {
+                chosen = Some(command);
+            }
+        }
+        chosen
+    }
+
+    /// Choice of margin cropping; returns the new one if it changed.
+    fn crop_menu(ui: &mut Ui, crop: Option<Crop>) -> Option<Option<Crop>> {
+        let mut chosen = None;
+        if ui.radio(crop.is_none(), "Не обрезать").clicked() {
+            chosen = Some(None);
+        }
+        if ui
+            .radio(crop == Some(Crop::Auto), "Обрезать по содержимому")
+            .clicked()
+        {
+            chosen = Some(Some(Crop::Auto));
+        }
+        let manual = matches!(crop, Some(Crop::Manual { .. }));
+        if ui.radio(manual, "Вручную").clicked() && !manual {
+            chosen = Some(Some(Crop::Manual {
+                odd: CropMargins::default(),
+                even: CropMargins::default(),
+            }));
+        }
+        let Some(Crop::Manual { mut odd, mut even }) = crop else {
+            return chosen;
+        };
+
+        let mut changed = false;
+        for (label, margins) in [
+            ("Нечётные страницы", &mut odd),
+            ("Чётные страницы", &mut even),
+        ] {
+            ui.separator();
+            ui.label(label);
+            for (side, value) in [
+                ("Слева", &mut margins.left),
+                ("Сверху", &mut margins.top),
+                ("Справа", &mut margins.right),
+                ("Снизу", &mut margins.bottom),
+            ] {
+                let slider = egui::Slider::new(value, 0.0..=CROP_MARGIN_MAX)
+                    .text(side)
+                    .custom_formatter(|v, _| format!("{:.0}%", v * 100.0))
+                    .custom_parser(|s| {
+                        let percent = s.trim().trim_end_matches('%').trim();
+                        percent.parse::<f64>().ok().map(|v| v / 100.0)
+                    });
+                changed |= ui.add(slider).changed();
+            }
+        }
+        if changed {
+            chosen = Some(Some(Crop::Manual { odd, even }));
+        }
+        chosen
+    }
 }